@@ -0,0 +1,48 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use wgalib::parser::cigar::{cigar_eqx_to_m, parse_cigar_to_units};
+use wgalib::parser::paf::{PAFReader, PafRecord};
+
+// cigar of `units` units with lengths of 1 to 5 digits, as long alignments of wfmash
+fn gen_cigar(units: usize) -> String {
+    let ops = ['=', 'X', '=', 'I', '=', 'D'];
+    (0..units)
+        .map(|i| {
+            format!(
+                "{}{}",
+                1 + (i * 7919) % 10usize.pow(1 + (i % 5) as u32),
+                ops[i % 6]
+            )
+        })
+        .collect()
+}
+
+fn gen_paf(cigar: &str) -> PafRecord {
+    let paf = format!(
+        "q\t1000000000\t0\t1\t+\tt\t1000000000\t0\t1\t1\t1\t60\tcg:Z:{}\n",
+        cigar
+    );
+    PAFReader::new(paf.as_bytes())
+        .records()
+        .next()
+        .unwrap()
+        .unwrap()
+}
+
+fn bench_cigar_units(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cigar_units");
+    for units in [1_000, 100_000] {
+        let cigar = gen_cigar(units);
+        group.throughput(Throughput::Bytes(cigar.len() as u64));
+        group.bench_with_input(BenchmarkId::new("eqx_to_m", units), &cigar, |b, cigar| {
+            b.iter(|| cigar_eqx_to_m(black_box(cigar)).unwrap())
+        });
+        let rec = gen_paf(&cigar);
+        group.bench_with_input(BenchmarkId::new("paf_units", units), &rec, |b, rec| {
+            b.iter(|| parse_cigar_to_units(black_box(rec)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_cigar_units);
+criterion_main!(benches);
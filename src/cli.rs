@@ -1,7 +1,19 @@
-use crate::parser::common::{DotplotMode, DotplotoutFormat, FileFormat};
-use clap::ArgAction;
-use clap::{command, Parser, Subcommand};
+use crate::errors::{WGAError, EXIT_USAGE};
+use crate::log::{LogFormat, WarnCategory};
+use crate::parser::common::{
+    AlnOutFormat, BestHit, BlocksOutFormat, ChunkStrategy, CigarStyle, DotplotMode,
+    DotplotoutFormat, FastaMaskMode, FileFormat, GroupBy, InvMode, LiftFrom, MaskMode, OddChar,
+    PafSortBy, PafVersion, UnknownBase, ValidateOutFormat,
+};
+use crate::tools::filter::{DUP_REDUNDANCY, SPLIT_BEST_FRACTION};
+use crate::tools::index::INDEX_MAX_MEM_MB;
+use crate::utils::{DEFAULT_COMPRESSION_LEVEL, ORDERED_IN_FLIGHT};
+use clap::error::ErrorKind;
+use clap::{ArgAction, ArgGroup, ArgMatches, Args, Command, CommandFactory};
+use clap::{FromArgMatches, Parser, Subcommand};
 use clap_complete::Shell;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(name = "wgatools")]
@@ -17,18 +29,57 @@ help_template =
 ) // change template more!
 ]
 pub struct Cli {
-    /// Output file ("-" for stdout), file name ending in .gz/.bz2/.xz will be compressed automatically
+    /// Output file ("-" for stdout), file name ending in .gz/.bz2/.xz/.zst will be compressed automatically
     #[arg(long, short, global = true, default_value = "-", help_heading = Some("GLOBAL"))]
     pub outfile: String,
     /// Bool, if rewrite output file [default: false]
     #[arg(long, short, global = true, default_value = "false", help_heading = Some("GLOBAL"))]
     pub rewrite: bool,
-    /// Threads, default 1
+    /// Threads, also compress `.gz`/`.xz`/`.zst` output and decompress BGZF input in parallel, not `.bz2` or plain gzip
     #[arg(long, short, global = true, default_value = "1", help_heading = Some("GLOBAL"))]
     pub threads: usize,
+    /// Compression level of `.gz`/`.bz2`/`.xz` output in 1-9, or `.zst` output in the levels of zstd, negative ones for faster compression and up to 22
+    #[arg(long, global = true, default_value_t = DEFAULT_COMPRESSION_LEVEL, allow_negative_numbers = true, help_heading = Some("GLOBAL"))]
+    pub compression_level: i32,
     /// Logging level [-v: Info, -vv: Debug, -vvv: Trace, defalut: Warn].
     #[arg(short, long, global = true, action = ArgAction::Count, help_heading = Some("GLOBAL"))]
     pub verbose: u8,
+    /// Only log errors, no warnings or info
+    #[arg(long, global = true, conflicts_with = "verbose", help_heading = Some("GLOBAL"))]
+    pub quiet: bool,
+    /// Drop warnings of categories split by ',': overwrite, index, args, empty, bad-record, input, result
+    #[arg(long, global = true, value_delimiter = ',', hide_possible_values = true, help_heading = Some("GLOBAL"))]
+    pub suppress_warnings: Vec<WarnCategory>,
+    /// Config file with default options [default: ~/.config/wgatools.toml]
+    #[arg(long, global = true, help_heading = Some("GLOBAL"))]
+    pub config: Option<String>,
+    /// Output buffer size in KiB, a larger one reduces write syscalls on network file systems
+    #[arg(long, global = true, default_value = "1024", help_heading = Some("GLOBAL"))]
+    pub buffer_size: usize,
+    /// Log format, `json` emits one object per line and a final summary
+    #[arg(long, global = true, default_value = "text", help_heading = Some("GLOBAL"))]
+    pub log_format: LogFormat,
+    /// Print time spent per stage (parse, cigar, fetch, call, write) at exit
+    #[arg(long, global = true, help_heading = Some("GLOBAL"))]
+    pub profile: bool,
+    /// Pad s-line columns of MAF output to the same width in each block, like the UCSC pretty format
+    #[arg(long, global = true, help_heading = Some("GLOBAL"))]
+    pub maf_pretty: bool,
+    /// Exit with error if input has no records instead of a warning and empty output, and for `validate` if any record is invalid
+    #[arg(long, global = true, help_heading = Some("GLOBAL"))]
+    pub strict: bool,
+    /// Skip malformed records with a warning instead of exiting with error, e.g. MAF blocks with s-lines of different lengths
+    #[arg(long, global = true, help_heading = Some("GLOBAL"))]
+    pub skip_bad_records: bool,
+    /// Write output files in place, rather than to temporary files renamed on success and removed on error
+    #[arg(long, global = true, help_heading = Some("GLOBAL"))]
+    pub no_atomic: bool,
+    /// Name of the running subcommand, for logging
+    #[arg(skip)]
+    pub subcommand: String,
+    /// Input of the running subcommand, for logging
+    #[arg(skip)]
+    pub input: Option<String>,
     /// Subcommands
     #[command(subcommand)]
     pub command: Commands,
@@ -38,14 +89,7 @@ pub struct Cli {
 pub enum Commands {
     /// Convert MAF format to PAF format
     #[command(visible_alias = "m2p", name = "maf2paf")]
-    Maf2Paf {
-        /// Input MAF File, None for STDIN
-        #[arg(required = false)]
-        input: Option<String>,
-        /// Query name when multiple query in MAF, None for first query
-        #[arg(required = false, short, long)]
-        query_name: Option<String>,
-    },
+    Maf2Paf(Box<Maf2PafArgs>),
     /// Convert MAF format to Chain format
     #[command(visible_alias = "m2c", name = "maf2chain")]
     Maf2Chain {
@@ -53,8 +97,49 @@ pub enum Commands {
         #[arg(required = false)]
         input: Option<String>,
         /// Query name when multiple query in MAF, None for first query
-        #[arg(required = false, short, long)]
+        #[arg(required = false, short, long, conflicts_with_all = ["query_regex", "split"])]
         query_name: Option<String>,
+        /// Regex of query names, the first matched query of each block is used and blocks without it are skipped,
+        /// or only matched queries are written with `--split`
+        #[arg(required = false, long)]
+        query_regex: Option<String>,
+        /// Write all queries into one chain file per query genome, `-o` is the output dir
+        #[arg(required = false, long, default_value = "false")]
+        split: bool,
+        /// Separator of genome and sequence in query names for `--split`, like `.` of `Genome.Chr`
+        #[arg(required = false, long, default_value = ".")]
+        genome_sep: char,
+        /// Write chains usable by liftOver directly: on the positive target strand, checked,
+        /// sorted by target and score, with unique ids from 1
+        #[arg(
+            required = false,
+            long,
+            default_value = "false",
+            conflicts_with = "split"
+        )]
+        liftover_ready: bool,
+        /// How to handle characters other than IUPAC codes and `-` in MAF sequences, e.g. `.` or `*`
+        #[arg(required = false, long, value_enum, default_value = "treat-as-n")]
+        odd_char: OddChar,
+    },
+    /// Concatenate MAF blocks into per-genome alignment matrix as FASTA/PHYLIP, e.g. for phylogenetics
+    #[command(visible_alias = "m2a", name = "maf2aln")]
+    Maf2Aln {
+        /// Input MAF Files such as pseudo MAFs, None for STDIN
+        #[arg(required = false)]
+        input: Vec<String>,
+        /// Output format
+        #[arg(required = false, long, value_enum, default_value = "fasta")]
+        out_format: AlnOutFormat,
+        /// Separator between genome and sequence name, e.g. `#` for PanSN
+        #[arg(required = false, long, default_value = ".")]
+        genome_sep: char,
+        /// Genomes as rows in order, comma separated, None for all in order of appearance
+        #[arg(required = false, long, value_delimiter = ',')]
+        genomes: Vec<String>,
+        /// Min genomes in a block to be used
+        #[arg(required = false, long, default_value = "1")]
+        min_genomes: usize,
     },
     /// Convert PAF format to MAF format
     #[command(visible_alias = "p2m", name = "paf2maf")]
@@ -68,6 +153,18 @@ pub enum Commands {
         /// Input query FASTA File, required
         #[arg(required = true, long, short)]
         query: String,
+        /// Convert each record back and verify coordinates, strand and CIGAR
+        #[arg(required = false, long, default_value = "false")]
+        verify: bool,
+        /// How to handle characters out of IUPAC nucleotide codes in FASTA
+        #[arg(required = false, long, value_enum, default_value = "error")]
+        unknown_base: UnknownBase,
+        /// Names of PAF tags kept in a-line, comma separated, e.g. `gi,bi`, None for all but `cg`
+        #[arg(required = false, long, value_delimiter = ',')]
+        keep_tags: Vec<String>,
+        /// Only convert records on target regions like `chr1`, `chr1:0-1000`, `chr1:1000-` or BED files `a.bed`, clipped to each of them
+        #[arg(required = false, long, value_delimiter = ',')]
+        target_region: Vec<String>,
     },
     /// Convert PAF format to Chain format
     #[command(visible_alias = "p2c", name = "paf2chain")]
@@ -83,11 +180,17 @@ pub enum Commands {
         #[arg(required = false)]
         input: Option<String>,
         /// Input target FASTA File, required
-        #[arg(required = true, long, short)]
+        #[arg(required = true, long, short = 'g')]
         target: String,
         /// Input query FASTA File, required
         #[arg(required = true, long, short)]
         query: String,
+        /// How to handle characters out of IUPAC nucleotide codes in FASTA
+        #[arg(required = false, long, value_enum, default_value = "error")]
+        unknown_base: UnknownBase,
+        /// Only convert records on target regions like `chr1`, `chr1:0-1000`, `chr1:1000-` or BED files `a.bed`, clipped to each of them
+        #[arg(required = false, long, value_delimiter = ',')]
+        target_region: Vec<String>,
     },
     /// Convert Chain format to PAF format
     #[command(visible_alias = "c2p", name = "chain2paf")]
@@ -95,6 +198,163 @@ pub enum Commands {
         /// Input Chain File, None for STDIN
         #[arg(required = false)]
         input: Option<String>,
+        /// Sort PAF records before writing
+        #[arg(required = false, long, value_enum, default_value = "none")]
+        sort_by: PafSortBy,
+        /// Operations of aligned bases in CIGAR, `eqx` splits `M` into `=` and `X` by sequences
+        #[arg(required = false, long, value_enum, default_value = "m")]
+        cigar_style: CigarStyle,
+        /// Input target FASTA File, required by `--cigar-style eqx`
+        #[arg(required_if_eq("cigar_style", "eqx"), long, short = 'g')]
+        target: Option<String>,
+        /// Input query FASTA File, required by `--cigar-style eqx`
+        #[arg(required_if_eq("cigar_style", "eqx"), long, short)]
+        query: Option<String>,
+    },
+    /// Rewrite Chain file, split chains at large gaps
+    #[command(visible_alias = "c2c", name = "chain2chain")]
+    Chain2Chain {
+        /// Input Chain File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Split chains at gaps longer than it in target or query, like `chainSplit` before netting
+        #[arg(required = false, long)]
+        split: Option<u64>,
+    },
+    /// Convert Chain format to bigChain and bigLink input of `bedToBigBed`
+    #[command(visible_alias = "c2bc", name = "chain2bigchain")]
+    Chain2BigChain {
+        /// Input Chain File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Output bigLink file of ungapped blocks, required
+        #[arg(required = true, long, short)]
+        link: String,
+        /// Directory to write autoSql schemas `bigChain.as` and `bigLink.as`
+        #[arg(required = false, long)]
+        as_dir: Option<String>,
+    },
+    /// Convert UCSC AXT format to MAF format
+    #[command(visible_alias = "x2m", name = "axt2maf")]
+    Axt2Maf {
+        /// Input AXT File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Target sizes as chrom.sizes or indexed FASTA, required
+        #[arg(required = true, long, short = 'g')]
+        target: String,
+        /// Query sizes as chrom.sizes or indexed FASTA, required
+        #[arg(required = true, long, short)]
+        query: String,
+        /// Max records held in memory while converting in parallel
+        #[arg(required = false, long, default_value_t = ORDERED_IN_FLIGHT)]
+        in_flight: usize,
+    },
+    /// Convert UCSC AXT format to PAF format
+    #[command(visible_alias = "x2p", name = "axt2paf")]
+    Axt2Paf {
+        /// Input AXT File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Target sizes as chrom.sizes or indexed FASTA, required
+        #[arg(required = true, long, short = 'g')]
+        target: String,
+        /// Query sizes as chrom.sizes or indexed FASTA, required
+        #[arg(required = true, long, short)]
+        query: String,
+        /// Sort PAF records before writing
+        #[arg(required = false, long, value_enum, default_value = "none")]
+        sort_by: PafSortBy,
+        /// Operations of aligned bases in CIGAR, `m` collapses `=` and `X` into `M`
+        #[arg(required = false, long, value_enum, default_value = "eqx")]
+        cigar_style: CigarStyle,
+        /// Max records held in memory while converting in parallel, all records are held by `--sort-by`
+        #[arg(required = false, long, default_value_t = ORDERED_IN_FLIGHT)]
+        in_flight: usize,
+    },
+    /// Convert MUMmer Delta format to MAF format
+    #[command(visible_alias = "d2m", name = "delta2maf")]
+    Delta2Maf {
+        /// Input Delta File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Input target FASTA File, the reference path in delta header if not set
+        #[arg(required = false, long, short = 'g')]
+        target: Option<String>,
+        /// Input query FASTA File, the query path in delta header if not set
+        #[arg(required = false, long, short)]
+        query: Option<String>,
+        /// How to handle characters out of IUPAC nucleotide codes in FASTA
+        #[arg(required = false, long, value_enum, default_value = "error")]
+        unknown_base: UnknownBase,
+    },
+    /// Convert MUMmer Delta format to PAF format
+    #[command(visible_alias = "d2p", name = "delta2paf")]
+    Delta2Paf {
+        /// Input Delta File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Sort PAF records before writing
+        #[arg(required = false, long, value_enum, default_value = "none")]
+        sort_by: PafSortBy,
+    },
+    /// Convert MAF format to bigMaf input of `bedToBigBed`
+    #[command(visible_alias = "m2bm", name = "maf2bigmaf")]
+    Maf2BigMaf {
+        /// Input MAF File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Reference database name to strip from target name as `DB.chrom`, like `mafToBigMaf`
+        #[arg(required = false, long)]
+        ref_db: Option<String>,
+        /// Directory to write autoSql schema `bigMaf.as`
+        #[arg(required = false, long)]
+        as_dir: Option<String>,
+    },
+    /// Export gapless alignment blocks of MAF/PAF file as TSV/BED
+    #[command(visible_alias = "bl", name = "blocks")]
+    Blocks {
+        /// Input Alignment File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Input File format, auto for detection from the first record
+        #[arg(required = false, long, short, default_value = "auto")]
+        format: FileFormat,
+        /// Output format, BED is on target with `query:start-end` as name
+        #[arg(required = false, long, default_value = "tsv")]
+        out_format: BlocksOutFormat,
+        /// Query name when multiple query in MAF, None for first query
+        #[arg(required = false, short, long)]
+        query_name: Option<String>,
+    },
+    /// Export gap-free matched segments of MAF/PAF/Chain file as TSV, with record index
+    #[command(visible_alias = "sg", name = "segments")]
+    Segments {
+        /// Input Alignment File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Input File format, auto for detection from the first record
+        #[arg(required = false, long, short, default_value = "auto")]
+        format: FileFormat,
+        /// Query name when multiple query in MAF, None for first query
+        #[arg(required = false, short, long)]
+        query_name: Option<String>,
+    },
+    /// Export alignment records of MAF/PAF/Chain file as BEDPE with identity, e.g. for circos/ribbon plots
+    #[command(visible_alias = "bp", name = "bedpe")]
+    Bedpe {
+        /// Input Alignment File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Input File format, auto for detection from the first record
+        #[arg(required = false, long, short, default_value = "auto")]
+        format: FileFormat,
+        /// One line per gap-free segment instead of per record
+        #[arg(required = false, long)]
+        split: bool,
+        /// Query name when multiple query in MAF, None for first query
+        #[arg(required = false, short, long)]
+        query_name: Option<String>,
     },
     /// Build index for MAF file
     #[command(visible_alias = "mi", name = "maf-index")]
@@ -102,6 +362,12 @@ pub enum Commands {
         /// Input MAF File
         #[arg(required = true)]
         input: String,
+        /// Memory cap of intervals in MiB, intervals over it are spilled to `{index}.tmp`
+        #[arg(required = false, long, default_value_t = INDEX_MAX_MEM_MB)]
+        max_mem: usize,
+        /// Check the index instead of building it: format version, source file and offsets of all intervals
+        #[arg(required = false, long)]
+        check: bool,
     },
     /// Extract specific region from MAF file with index
     #[command(visible_alias = "me", name = "maf-ext")]
@@ -109,12 +375,18 @@ pub enum Commands {
         /// Input MAF File, None for STDIN
         #[arg(required = false)]
         input: Option<String>,
-        /// Input regions
-        #[arg(required = false, long, short, value_delimiter = ',')]
+        /// Input regions like `chr1`, `chr1:0-1000`, `chr1:1000-` or BED files `a.bed`
+        #[arg(required = false, long, short = 'g', value_delimiter = ',')]
         regions: Option<Vec<String>>,
-        /// Input regions file
+        /// Input regions BED file
         #[arg(required = false, long, short)]
         file: Option<String>,
+        /// Rebuild MAF index if it is stale
+        #[arg(required = false, long, default_value = "false")]
+        rebuild_index: bool,
+        /// Match sequence names of regions by substring or fuzzily if not exact, error if ambiguous
+        #[arg(required = false, long, default_value = "false")]
+        fuzzy: bool,
     },
     /// Chunk MAF file by length
     #[command(visible_alias = "ch", name = "chunk")]
@@ -128,35 +400,124 @@ pub enum Commands {
     },
     /// Call Variants from MAF/PAF file
     #[command(visible_alias = "c", name = "call")]
-    Call {
-        /// Input MAF/PAF File
+    Call(Box<CallArgs>),
+    /// Annotate variants with the alignment context of MAF blocks
+    #[command(visible_alias = "an", name = "annotate")]
+    Annotate {
+        /// Input VCF File such as output of `call` (plain or compressed), None for STDIN
         #[arg(required = false)]
         input: Option<String>,
-        /// Sample name
-        #[arg(
-            required = false,
-            long = "sample",
-            short = 'n',
-            default_value = "sample"
-        )]
+        /// Input MAF File the variants are called from
+        #[arg(required = true, long, short)]
+        maf: String,
+        /// Query name when multiple query in MAF, None for first query
+        #[arg(required = false, short, long)]
+        query_name: Option<String>,
+        /// Half size of the window around POS on target to calculate gap density
+        #[arg(required = false, long, short, default_value = "1000")]
+        window: u64,
+    },
+    /// Lift VCF between query and target coordinates through Chain/MAF/PAF, with REF checked
+    #[command(visible_alias = "vl", name = "vcf-lift")]
+    VcfLift {
+        /// Input VCF File (plain or compressed), None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Alignment File of Chain/MAF/PAF used to lift
+        #[arg(required = true, long, short)]
+        align: String,
+        /// Alignment File format, auto for detection from the first record
+        #[arg(required = false, long, short, default_value = "auto")]
+        format: FileFormat,
+        /// Destination FASTA File, REF alleles are checked and anchors are fetched from it
+        #[arg(required = true, long, short = 'g')]
+        fasta: String,
+        /// Side of alignment the input VCF is on
+        #[arg(required = false, long, value_enum, default_value = "query")]
+        from: LiftFrom,
+        /// Query name when multiple query in MAF, None for first query
+        #[arg(required = false, short, long)]
+        query_name: Option<String>,
+        /// Output VCF File of records failed to lift, with reason in INFO `LIFT_FAIL`
+        #[arg(required = false, long)]
+        reject: Option<String>,
+    },
+    /// Map positions between target and query through MAF/PAF/Chain, with strand and record span
+    #[command(visible_alias = "pm", name = "pos-map")]
+    PosMap {
+        /// Input Alignment File, None for STDIN, MAF index is used if exists
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Input File format, auto for detection from the first record
+        #[arg(required = false, long, short, default_value = "auto")]
+        format: FileFormat,
+        /// Positions to map as `target:name:pos` or `query:name:pos`, 1-based, comma-delimited
+        #[arg(required = true, long, value_delimiter = ',')]
+        at: Vec<String>,
+        /// Query name of target positions when multiple query in MAF, None for first query
+        #[arg(required = false, short, long)]
+        query_name: Option<String>,
+    },
+    /// Reconstruct query sequences by applying variants of `call` to reference
+    #[command(visible_alias = "cs", name = "consensus")]
+    Consensus {
+        /// Input VCF File produced by `call` (plain or compressed), or MAF File with `--maf`, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Input target FASTA File with index, required for VCF
+        #[arg(required = false, long, short = 'g')]
+        target: Option<String>,
+        /// Input is MAF, variants of each block are called and applied to its target sequence
+        #[arg(required = false, long, default_value = "false")]
+        maf: bool,
+        /// Regions to reconstruct for VCF like `chr1`, `chr1:0-1000`, `chr1:1000-` or BED files `a.bed`, whole sequences if not set
+        #[arg(required = false, long, value_delimiter = ',')]
+        regions: Option<Vec<String>>,
+        /// Sample of VCF whose GT picks the ALT to apply, the first sample if not set
+        #[arg(required = false, long)]
         sample: Option<String>,
-        /// If call SNP
-        #[arg(required = false, long = "snp", short = 's', default_value = "false")]
-        snp: bool,
-        /// SV length cutoff
-        #[arg(required = false, long = "svlen", short = 'l', default_value = "50")]
-        svlen: u64,
-        #[arg(required = false, long, short, default_value = "maf")]
+        /// Haplotype of GT to apply for VCF, 1-based, e.g. 2 for the `1` of `0|1`
+        #[arg(required = false, long, default_value = "1", value_parser = clap::value_parser!(u8).range(1..))]
+        haplotype: u8,
+    },
+    /// Subset records of MAF/PAF/Chain by fraction, head or regions, with header kept
+    #[command(visible_alias = "sa", name = "sample")]
+    Sample {
+        /// Input Alignment File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Input File format, auto for detection from the first record
+        #[arg(required = false, long, short, default_value = "auto")]
         format: FileFormat,
-        /// Input target FASTA File, required if input is PAF
+        /// Keep each record with this probability, in (0, 1]
+        #[arg(required = false, long)]
+        fraction: Option<f64>,
+        /// Random seed of `--fraction`, the same seed keeps the same records
+        #[arg(required = false, long, default_value = "0")]
+        seed: u64,
+        /// Keep the first N records picked, and stop reading after them
         #[arg(required = false, long)]
+        head: Option<usize>,
+        /// Keep records overlapping target regions like `chr1`, `chr1:0-1000`, `chr1:1000-` or BED files `a.bed`
+        #[arg(required = false, long, alias = "region", value_delimiter = ',')]
+        target_region: Option<Vec<String>>,
+    },
+    /// Re-align unaligned gap pairs of pairwise MAF blocks
+    #[cfg(feature = "polish")]
+    #[command(visible_alias = "po", name = "polish")]
+    Polish {
+        /// Input MAF File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Max length of gap on either side to re-align
+        #[arg(required = false, long, short = 'l', default_value = "1000")]
+        max_gap: usize,
+        /// Input target FASTA File with index, to re-align gaps between blocks
+        #[arg(required = false, long, short = 'g', requires = "query")]
         target: Option<String>,
-        /// Input query FASTA File, required if input is PAF
-        #[arg(required = false, long, short)]
+        /// Input query FASTA File with index, to re-align gaps between blocks
+        #[arg(required = false, long, short, requires = "target")]
         query: Option<String>,
-        /// query name when multiple query in MAF, None for first query
-        #[arg(required = false, long)]
-        query_name: Option<String>,
     },
     /// View MAF file in terminal
     #[command(visible_alias = "tv", name = "tview")]
@@ -167,76 +528,92 @@ pub enum Commands {
         /// Move step size
         #[arg(required = false, long, short, default_value = "10")]
         step: usize,
+        /// VCF file of variants to jump through by `n`/`p`, such as output of `call`
+        #[arg(required = false, long)]
+        vcf: Option<String>,
+        /// Rebuild MAF index if it is stale
+        #[arg(required = false, long, default_value = "false")]
+        rebuild_index: bool,
     },
     /// Statistics for Alignment file
     #[command(visible_alias = "st", name = "stat")]
-    Stat {
+    Stat(Box<StatArgs>),
+    /// One-page HTML report of statistics, target coverage and overview dotplot, needs network access to load vega scripts
+    #[command(visible_alias = "rp", name = "report")]
+    Report {
         /// Input Alignment File, None for STDIN
         #[arg(required = false)]
         input: Option<String>,
-        /// Input File format,
-        #[arg(required = false, long, short, default_value = "maf")]
+        /// Input File format, auto for detection from the first record
+        #[arg(required = false, long, short, default_value = "auto")]
         format: FileFormat,
-        /// Show each block's statistics, default: false
-        #[arg(required = false, long, short, default_value = "false")]
-        each: bool,
         /// Query name when multiple query in MAF, None for first query
         #[arg(required = false, short, long)]
         query_name: Option<String>,
+        /// Number of bins of coverage track for each target
+        #[arg(required = false, long, short, default_value = "500")]
+        bins: usize,
     },
-    /// Plot dotplot for Alignment file
-    #[command(visible_alias = "dp", name = "dotplot")]
-    Dotplot {
-        /// Input Alignment File, None for STDIN
-        #[arg(required = false)]
-        input: Option<String>,
-        /// Input File format,
-        #[arg(required = false, long, short, default_value = "maf")]
+    /// Compare two alignments of the same genome pair on target intervals aligned to the same query locus,
+    /// identity is over the columns of each interval and `M` columns of PAF count by the record match rate
+    #[command(visible_alias = "df", name = "diff")]
+    Diff {
+        /// Input Alignment File A
+        #[arg(required = true)]
+        input_a: String,
+        /// Input Alignment File B
+        #[arg(required = true)]
+        input_b: String,
+        /// Input File format of both files, auto for detection from the first record
+        #[arg(required = false, long, short, default_value = "auto")]
         format: FileFormat,
-        /// Output format
-        #[arg(required = false, long, default_value = "html")]
-        out_format: DotplotoutFormat,
-        /// Plot mode, BaseLevel or Overview
-        #[arg(required = false, long, short, default_value = "base-level")]
-        mode: DotplotMode,
-        /// do not show identity in Overview mode, default: false
-        #[arg(required = false, long, short = 'd', default_value = "false")]
-        no_identity: bool,
-        /// Skip segment with length less than cutoff in BaseLevel mode, default: 0
-        #[arg(required = false, long, short = 'l')]
-        length: Option<usize>,
         /// Query name when multiple query in MAF, None for first query
         #[arg(required = false, short, long)]
         query_name: Option<String>,
     },
+    /// Plot dotplot for Alignment file
+    #[command(visible_alias = "dp", name = "dotplot")]
+    Dotplot(Box<DotplotArgs>),
     /// Filter records for Alignment file
     #[command(visible_alias = "fl", name = "filter")]
-    Filter {
-        /// Input Alignment File, None for STDIN
-        #[arg(required = false)]
-        input: Option<String>,
-        /// Input File format,
-        #[arg(required = false, long, short, default_value = "maf")]
-        format: FileFormat,
-        /// Min block size
-        #[arg(required = false, long, short = 'b', default_value = "0")]
-        min_block_size: u64,
-        /// Min query size, usually for contigs
-        #[arg(required = false, long, short = 'q', default_value = "0")]
-        min_query_size: u64,
-        /// Min align size for query-target pair, only for all-to-all alignment paf
-        #[arg(required = false, long, short = 'a', default_value = None)]
-        min_align_size: Option<u64>,
-    },
-    /// Rename MAF records with prefix
+    Filter(Box<FilterArgs>),
+    /// Rename MAF/PAF records with prefix, map or regex
     #[command(visible_alias = "rn", name = "rename")]
+    #[command(group(
+        ArgGroup::new("rules")
+            .required(true)
+            .multiple(true)
+            .args(["prefixs", "genome_prefix", "map", "regex"])
+    ))]
     Rename {
-        /// Input MAF File, None for STDIN
+        /// Input MAF/PAF File, None for STDIN
         #[arg(required = false)]
         input: Option<String>,
+        /// Input File format, auto for detection from the first record
+        #[arg(required = false, long, short, default_value = "auto")]
+        format: FileFormat,
         /// prefix for rename, split by ',' ordered by input
-        #[arg(required = true, long, short, value_delimiter = ',')]
+        #[arg(
+            required = false,
+            long,
+            short,
+            value_delimiter = ',',
+            conflicts_with = "genome_prefix"
+        )]
         prefixs: Vec<String>,
+        /// prefix of each genome as `genome=prefix`, split by ',', for blocks of varying s-lines.
+        /// Genome is the part of name before the first `.`
+        #[arg(required = false, long, value_delimiter = ',')]
+        genome_prefix: Vec<String>,
+        /// TSV file of `old\tnew` names for exact replacement
+        #[arg(required = false, long, short)]
+        map: Option<String>,
+        /// sed-like substitution for names, e.g. `s/^chr/Chr/`, `\1` for capture group
+        #[arg(required = false, long, short = 'e')]
+        regex: Option<String>,
+        /// s-line indices to apply map and regex, split by ',', 0 for target, None for all
+        #[arg(required = false, long, short, value_delimiter = ',')]
+        index: Option<Vec<usize>>,
     },
     /// DEV: maf2sam
     #[command(visible_alias = "m2s", name = "maf2sam")]
@@ -245,12 +622,34 @@ pub enum Commands {
         #[arg(required = false)]
         input: Option<String>,
     },
+    /// Mask target FASTA regions not covered by any alignment of MAF/PAF, or covered ones
+    #[command(visible_alias = "mf", name = "maskfa")]
+    MaskFa {
+        /// Input Alignment File, None for STDIN
+        #[arg(required = false)]
+        input: Option<String>,
+        /// Input File format, auto for detection from the first record
+        #[arg(required = false, long, short, default_value = "auto")]
+        format: FileFormat,
+        /// Target FASTA File (plain or compressed) to mask, line layout is kept
+        #[arg(required = true, long, short = 'g')]
+        target: String,
+        /// Replace masked bases with `N` or lowercase them
+        #[arg(required = false, long, value_enum, default_value = "hard")]
+        mode: FastaMaskMode,
+        /// Mask aligned regions instead of unaligned ones
+        #[arg(required = false, long, default_value = "false")]
+        aligned: bool,
+    },
     /// Calculate coverage for PAF file
     #[command(visible_alias = "pc", name = "pafcov")]
     PafCov {
         /// Input PAF File, None for STDIN
         #[arg(required = false)]
         input: Option<String>,
+        /// Summarize coverage of targets by level of PanSN names (`sample#hap#chr`) instead of per-base BED
+        #[arg(required = false, long, value_enum, default_value = "sequence")]
+        group_by: GroupBy,
     },
     /// Generate pesudo-maf for divergence analysis from PAF file
     #[command(visible_alias = "pp", name = "pafpseudo")]
@@ -265,6 +664,19 @@ pub enum Commands {
         #[arg(required = false, long, short = 'g')]
         target: Option<String>,
     },
+    /// Window-averaged divergence and aligned fraction for pseudo MAFs from `pafpseudo`
+    #[command(visible_alias = "ps", name = "pmaf-stat")]
+    PmafStat {
+        /// Input directory of pseudo MAF files
+        #[arg(required = true)]
+        input: String,
+        /// Window size
+        #[arg(required = false, long, short, default_value = "10000")]
+        window: u64,
+        /// Min covered fraction of a window for a genome to be counted as aligned
+        #[arg(required = false, long, default_value = "0")]
+        min_cov: f64,
+    },
     // /// TEST: trim overlap for paf
     // #[command(visible_alias = "tr", name = "trimovp")]
     // TrimOvp {
@@ -291,6 +703,9 @@ pub enum Commands {
         /// Fixed output file, None for NOT FIX, `-` will mix newoutput & information
         #[arg(required = false, long, short)]
         fix: Option<String>,
+        /// Report format, JSON and TSV list each error of records with its kind
+        #[arg(required = false, long, value_enum, default_value = "text")]
+        out_format: ValidateOutFormat,
         // /// Carefully validate mode, will not fix any record, default: false
         // #[arg(required = false, long, short, default_value = "false")]
         // careful: bool,
@@ -307,6 +722,567 @@ pub enum Commands {
     // },
 }
 
+#[derive(Args, Debug)]
+pub struct Maf2PafArgs {
+    /// Input MAF File, None for STDIN
+    #[arg(required = false)]
+    pub input: Option<String>,
+    /// Query name when multiple query in MAF, None for first query
+    #[arg(required = false, short, long)]
+    pub query_name: Option<String>,
+    /// Convert each record back and verify coordinates, strand and sequence
+    #[arg(required = false, long, default_value = "false")]
+    pub verify: bool,
+    /// Restore PAF tags and MAPQ kept in a-line by `paf2maf`
+    #[arg(required = false, long, default_value = "false")]
+    pub sam_tags: bool,
+    /// How to handle characters other than IUPAC codes and `-` in MAF sequences, e.g. `.` or `*`
+    #[arg(required = false, long, value_enum, default_value = "treat-as-n")]
+    pub odd_char: OddChar,
+    /// Sort PAF records before writing
+    #[arg(required = false, long, value_enum, default_value = "none")]
+    pub sort_by: PafSortBy,
+    /// Naming of sequences, `pansn` renames `Genome.Chr` to `Genome#1#Chr`
+    #[arg(required = false, long, value_enum, default_value = "plain")]
+    pub paf_version: PafVersion,
+    /// Separator of PanSN names
+    #[arg(required = false, long, default_value = "#")]
+    pub pansn_sep: String,
+    /// Haplotype of PanSN names
+    #[arg(required = false, long, default_value = "1")]
+    pub pansn_hap: u32,
+    /// Operations of aligned bases in CIGAR, `m` collapses `=` and `X` into `M`
+    #[arg(required = false, long, value_enum, default_value = "eqx")]
+    pub cigar_style: CigarStyle,
+    /// Max records held in memory while converting in parallel, all records are held by `--sort-by`
+    #[arg(required = false, long, default_value_t = ORDERED_IN_FLIGHT)]
+    pub in_flight: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct CallArgs {
+    /// Input MAF/PAF File, None for STDIN, multiple MAF files (one per query) for a multi-sample VCF
+    #[arg(required = false)]
+    pub input: Vec<String>,
+    /// Sample name, split by ',' ordered by input [default: sample, or file names of multiple input]
+    #[arg(required = false, long = "sample", short = 'n', value_delimiter = ',')]
+    pub sample: Option<Vec<String>>,
+    /// Jointly genotype two MAF files of haplotype 1 and 2 of one sample, as phased GT like `1|0`
+    #[arg(required = false, long, default_value = "false")]
+    pub diploid: bool,
+    /// Ploidy of GT, 1 for haploid assemblies as `1`, `0` and `.` instead of `1|1`, `0|0` and `./.`
+    #[arg(required = false, long, default_value = "2", value_parser = clap::value_parser!(u8).range(1..=2))]
+    pub ploidy: u8,
+    /// If call SNP
+    #[arg(required = false, long = "snp", short = 's', default_value = "false")]
+    pub snp: bool,
+    /// SV length cutoff
+    #[arg(required = false, long = "svlen", short = 'l', default_value = "50")]
+    pub svlen: u64,
+    /// Output indels of at least N bp and up to `--svlen` as plain VCF indels without SVTYPE
+    #[arg(required = false, long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub min_indel: Option<u64>,
+    /// Drop SVs longer than N bp, e.g. multi-Mb events from misalignments
+    #[arg(required = false, long)]
+    pub max_svlen: Option<u64>,
+    /// Keep SVs longer than `--max-svlen` with FILTER `LongSV` instead of dropping them
+    #[arg(
+        required = false,
+        long,
+        default_value = "false",
+        requires = "max_svlen"
+    )]
+    pub filter_long_sv: bool,
+    /// Input File format, auto for detection from the first record of the first input
+    #[arg(required = false, long, short, default_value = "auto")]
+    pub format: FileFormat,
+    /// Input target FASTA File, required if input is PAF, contig lines of VCF header
+    /// are taken from its index `.fai` (built if absent for MAF)
+    #[arg(required = false, long)]
+    pub target: Option<String>,
+    /// Input query FASTA File, required if input is PAF
+    #[arg(required = false, long, short)]
+    pub query: Option<String>,
+    /// query name when multiple query in MAF, None for first query
+    #[arg(required = false, long)]
+    pub query_name: Option<String>,
+    /// Drop SNPs and small indels within N bp of an SV breakpoint (INS/DEL longer than `--svlen`) in the same block, 0 for no masking
+    #[arg(required = false, long, default_value = "0")]
+    pub mask_near_sv: u64,
+    /// How to report variants within inversion (negative strand block)
+    #[arg(required = false, long, value_enum, default_value = "nested")]
+    pub inv_mode: InvMode,
+    /// Merge SVs of the same type whose breakpoints and lengths are within N bp into one record with all query supports in QI, only for MAF
+    #[arg(required = false, long, default_value = "0")]
+    pub merge_dist: u64,
+    /// How to split long blocks to call in parallel, only for MAF
+    #[arg(required = false, long, value_enum, default_value = "none")]
+    pub chunk_strategy: ChunkStrategy,
+    /// Columns per chunk when splitting long blocks, 1000000 by default, only for MAF
+    #[arg(required = false, long)]
+    pub chunk_size: Option<usize>,
+    /// How to handle characters out of IUPAC nucleotide codes in FASTA, only for PAF
+    #[arg(required = false, long, value_enum, default_value = "error")]
+    pub unknown_base: UnknownBase,
+    /// Rebuild MAF index if it is stale
+    #[arg(required = false, long, default_value = "false")]
+    pub rebuild_index: bool,
+    /// Output gVCF with reference blocks of aligned invariant stretches and no-call blocks of unaligned regions, only for MAF
+    #[arg(required = false, long, default_value = "false")]
+    pub gvcf: bool,
+    /// How to handle characters other than IUPAC codes and `-` in MAF sequences, only for MAF
+    #[arg(required = false, long, value_enum, default_value = "treat-as-n")]
+    pub odd_char: OddChar,
+    /// Add INFO of the source block to each variant: BLOCK_ID (target span), BLOCK_IDENTITY and BLOCK_LEN, not for multiple input
+    #[arg(required = false, long, default_value = "false")]
+    pub block_info: bool,
+    /// Set FILTER to `LowQual` for variants whose query bases in q-line are below it (0-9, `F` as 10), PASS for others, only for MAF and not multiple input
+    #[arg(required = false, long, value_parser = clap::value_parser!(u8).range(0..=10))]
+    pub min_qline_qual: Option<u8>,
+    /// Skip blocks with more than N `N` bases in target or query, e.g. assembly gaps
+    #[arg(required = false, long)]
+    pub max_block_n: Option<u64>,
+    /// Skip blocks with a gap run longer than N in target or query
+    #[arg(required = false, long)]
+    pub max_gap_run: Option<u64>,
+    /// File of `##` meta-information lines added to the header, e.g. `##reference=`,
+    /// INFO/FORMAT/FILTER/ALT/contig lines replace generated ones of the same ID
+    #[arg(required = false, long)]
+    pub vcf_header_lines: Option<String>,
+    /// Sample metadata `key=value` split by ',', written as `##SAMPLE=<ID=sample,key=value,...>` of each sample
+    #[arg(required = false, long, value_delimiter = ',')]
+    pub sample_meta: Vec<String>,
+    /// Checkpoint file of targets done, a crashed run with the same one resumes and appends to output, only for a single MAF
+    #[arg(required = false, long)]
+    pub checkpoint: Option<String>,
+    /// Write one VCF per target chromosome `{chrom}.vcf` into the dir, each with only its contig line, only for a single MAF
+    #[arg(required = false, long, conflicts_with = "checkpoint")]
+    pub split_by_chrom: Option<String>,
+    /// Call breakends (BND) with MATEID at junctions of consecutive query segments on different targets or far apart, only for a single MAF
+    #[arg(required = false, long, default_value = "false", conflicts_with_all = ["checkpoint", "split_by_chrom"])]
+    pub between: bool,
+    /// Min distance on target between two ends of a junction on the same target to call BND
+    #[arg(required = false, long, default_value = "100000")]
+    pub bnd_distance: u64,
+}
+
+#[derive(Args, Debug)]
+pub struct StatArgs {
+    /// Input Alignment File, None for STDIN
+    #[arg(required = false)]
+    pub input: Option<String>,
+    /// Input File format, auto for detection from the first record
+    #[arg(required = false, long, short, default_value = "auto")]
+    pub format: FileFormat,
+    /// Show each block's statistics, default: false
+    #[arg(required = false, long, short, default_value = "false")]
+    pub each: bool,
+    /// Query name when multiple query in MAF, None for first query
+    #[arg(required = false, short, long)]
+    pub query_name: Option<String>,
+    /// Output nested JSON (target -> query -> statistics with block intervals), default: false
+    #[arg(required = false, long, default_value = "false")]
+    pub json_detailed: bool,
+    /// Output ins/del length histograms for each pair and all pairs instead, default: false
+    #[arg(required = false, long, default_value = "false")]
+    pub gap_hist: bool,
+    /// Ascending bin edges of gap length histograms, split by ','
+    #[arg(
+        required = false,
+        long,
+        value_delimiter = ',',
+        default_value = "1,2,5,10,20,50,100,200,500,1000,5000,10000"
+    )]
+    pub gap_bins: Vec<u64>,
+    /// How to handle characters other than IUPAC codes and `-` in MAF sequences, only for MAF
+    #[arg(required = false, long, value_enum, default_value = "treat-as-n")]
+    pub odd_char: OddChar,
+    /// Show statistics of target and each query s-line of each block, only for MAF, default: false
+    #[arg(required = false, long, default_value = "false")]
+    pub per_sline: bool,
+    /// Show N bases and longest gap runs of target and query of each block instead, only for MAF, default: false
+    #[arg(required = false, long, default_value = "false")]
+    pub qc: bool,
+    /// BED File of target regions with class in the 4th column, show statistics of each class instead
+    #[arg(required = false, long)]
+    pub strata: Option<String>,
+    /// Aggregate pairs by level of PanSN names (`sample#hap#chr`)
+    #[arg(required = false, long, value_enum, default_value = "sequence")]
+    pub group_by: GroupBy,
+    /// Only stat a fraction in (0, 1] of records picked randomly, and extrapolate totals from them, for a quick look at huge files
+    #[arg(
+        required = false,
+        long,
+        conflicts_with_all = ["each", "json_detailed", "gap_hist", "per_sline", "qc", "strata"]
+    )]
+    pub sample: Option<f64>,
+    /// Random seed of `--sample`, the same seed picks the same records
+    #[arg(required = false, long, default_value = "0")]
+    pub seed: u64,
+}
+
+#[derive(Args, Debug)]
+pub struct DotplotArgs {
+    /// Input Alignment File, None for STDIN
+    #[arg(required = false)]
+    pub input: Option<String>,
+    /// Input File format, auto for detection from the first record
+    #[arg(required = false, long, short, default_value = "auto")]
+    pub format: FileFormat,
+    /// Output format
+    #[arg(required = false, long, default_value = "html")]
+    pub out_format: DotplotoutFormat,
+    /// Plot mode, BaseLevel or Overview
+    #[arg(required = false, long, short, default_value = "base-level")]
+    pub mode: DotplotMode,
+    /// do not show identity in Overview mode, default: false
+    #[arg(required = false, long, short = 'd', default_value = "false")]
+    pub no_identity: bool,
+    /// Skip segment with length less than cutoff in BaseLevel mode, default: 0
+    #[arg(required = false, long, short = 'l')]
+    pub length: Option<usize>,
+    /// Query name when multiple query in MAF, None for first query
+    #[arg(required = false, short, long)]
+    pub query_name: Option<String>,
+    /// Only plot segments in one target region like `chr1`, `chr1:0-1000` or `chr1:1000-`, MAF index is used if exists
+    #[arg(required = false, long)]
+    pub target_region: Option<String>,
+    /// Only plot segments in one query region like `chr1`, `chr1:0-1000` or `chr1:1000-`
+    #[arg(required = false, long)]
+    pub query_region: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct FilterArgs {
+    /// Input Alignment File, None for STDIN
+    #[arg(required = false)]
+    pub input: Option<String>,
+    /// Input File format, auto for detection from the first record
+    #[arg(required = false, long, short, default_value = "auto")]
+    pub format: FileFormat,
+    /// Min block size
+    #[arg(required = false, long, short = 'b', default_value = "0")]
+    pub min_block_size: u64,
+    /// Min query size, usually for contigs
+    #[arg(required = false, long, short = 'q', default_value = "0")]
+    pub min_query_size: u64,
+    /// Min align size for query-target pair, only for all-to-all alignment paf
+    #[arg(required = false, long, short = 'a', default_value = None)]
+    pub min_align_size: Option<u64>,
+    /// Min chain score, only for chain
+    #[arg(required = false, long, default_value = "0")]
+    pub min_chain_score: f64,
+    /// Min chain target span, only for chain
+    #[arg(required = false, long, default_value = "0")]
+    pub min_target_span: u64,
+    /// Drop duplicated blocks with identical or contained target intervals for the same query, only for MAF
+    #[arg(required = false, long, default_value = "false")]
+    pub dedup: bool,
+    /// Report coverage and redundancy of each query instead of filtering, only for all-to-all alignment paf
+    #[arg(required = false, long, default_value = "false")]
+    pub pair_report: bool,
+    /// Flag a query of `--pair-report` as `split` if its best target takes less than this fraction of its aligned size
+    #[arg(required = false, long, default_value_t = SPLIT_BEST_FRACTION, requires = "pair_report")]
+    pub split_fraction: f64,
+    /// Flag a query of `--pair-report` as `duplicated` if its aligned bases are covered this many times on average
+    #[arg(required = false, long, default_value_t = DUP_REDUNDANCY, requires = "pair_report")]
+    pub dup_redundancy: f64,
+    /// BED File of regions to mask on target, e.g. centromeres
+    #[arg(required = false, long)]
+    pub mask: Option<String>,
+    /// How to apply masked regions, `trim` is not for chain and `softmask` is only for MAF
+    #[arg(
+        required = false,
+        long,
+        value_enum,
+        default_value = "drop",
+        requires = "mask"
+    )]
+    pub mask_mode: MaskMode,
+    /// Keep only the best alignment of each interval on query, target or both by identity × length, only for PAF
+    #[arg(required = false, long, value_enum)]
+    pub best_hit: Option<BestHit>,
+}
+
+impl Commands {
+    /// Whether the command reads all records of its input, so an input without records is reported
+    pub fn reads_records(&self) -> bool {
+        match self {
+            Commands::Maf2Sam { .. }
+            | Commands::MafExtract { .. }
+            | Commands::Tview { .. }
+            | Commands::PmafStat { .. }
+            | Commands::GenCompletion { .. } => false,
+            Commands::Consensus { maf, .. } => *maf,
+            _ => true,
+        }
+    }
+
+    /// Whether the command runs in a single thread, regardless of `--threads`
+    pub fn is_single_threaded(&self) -> bool {
+        match self {
+            Commands::Maf2Sam { .. }
+            | Commands::Maf2Aln { .. }
+            | Commands::MafIndex { .. }
+            | Commands::Tview { .. }
+            | Commands::Rename { .. }
+            | Commands::Chunk { .. }
+            | Commands::Consensus { .. }
+            | Commands::VcfLift { .. }
+            | Commands::PosMap { .. }
+            | Commands::Sample { .. }
+            | Commands::MaskFa { .. }
+            | Commands::Chain2BigChain { .. }
+            | Commands::Chain2Chain { .. }
+            | Commands::Maf2BigMaf { .. }
+            | Commands::GenCompletion { .. } => true,
+            Commands::Maf2Chain { split, .. } => *split,
+            // only dedup, pair filter and pair report run in parallel
+            Commands::Filter(args) => {
+                !(args.dedup || args.pair_report || args.min_align_size.is_some())
+            }
+            _ => false,
+        }
+    }
+}
+
 pub fn make_cli_parse() -> Cli {
-    Cli::parse()
+    parse_cli_from(std::env::args_os().collect()).unwrap_or_else(|e| exit_usage(e))
+}
+
+// short flags released before they clashed with global `-t` and `-r`, now `-g` is used
+const LEGACY_SHORTS: [(&str, &str, &str); 2] = [
+    ("chain2maf", "-t", "--target"),
+    ("maf-ext", "-r", "--regions"),
+];
+
+/// Replace legacy short flags after their subcommand with long ones, so old command lines keep working
+fn replace_legacy_shorts(cmd: &Command, mut args: Vec<OsString>) -> Vec<OsString> {
+    // the first subcommand given by name or alias
+    let Some((sub_idx, sc)) = args.iter().enumerate().skip(1).find_map(|(idx, arg)| {
+        cmd.find_subcommand(arg).map(|sc| (idx, sc))
+    }) else {
+        return args;
+    };
+    for (name, short, long) in LEGACY_SHORTS {
+        if sc.get_name() != name {
+            continue;
+        }
+        for arg in args[sub_idx + 1..].iter_mut() {
+            if *arg == "--" {
+                break;
+            }
+            let replaced = match arg.to_str() {
+                Some(value) if value == short => OsString::from(long),
+                // value attached like `-tref.fa`
+                Some(value) if value.len() > short.len() && value.starts_with(short) => {
+                    OsString::from(format!("{}={}", long, &value[short.len()..]))
+                }
+                _ => continue,
+            };
+            *arg = replaced;
+        }
+    }
+    args
+}
+
+/// Parse args with options in config file as defaults
+fn parse_cli_from(args: Vec<OsString>) -> Result<Cli, clap::Error> {
+    let mut cmd = Cli::command();
+    let args = replace_legacy_shorts(&cmd, args);
+    // lenient pre-pass for `--config` and the subcommand, args required but set in config
+    // are missing yet, so they are validated once with the config applied
+    let matches = cmd
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)?;
+    let config_path = match matches.get_one::<String>("config") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => default_config_path().filter(|path| path.is_file()),
+    };
+    if let Some(path) = config_path {
+        apply_config(&path, &mut cmd, &matches)
+            .map_err(|e| cmd.error(ErrorKind::InvalidValue, e))?;
+    }
+    let matches = cmd.try_get_matches_from(args)?;
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    if let Some((name, sub_m)) = matches.subcommand() {
+        cli.subcommand = name.to_string();
+        cli.input = sub_m
+            .try_get_raw("input")
+            .ok()
+            .flatten()
+            .and_then(|mut values| values.next())
+            .map(|value| value.to_string_lossy().into_owned());
+    }
+    Ok(cli)
+}
+
+// usage errors exit with `EXIT_USAGE` rather than 2 of clap, help and version are printed as usual
+fn exit_usage(e: clap::Error) -> ! {
+    if !e.use_stderr() {
+        e.exit()
+    }
+    let _ = e.print();
+    std::process::exit(EXIT_USAGE)
+}
+
+/// `$XDG_CONFIG_HOME/wgatools.toml` or `~/.config/wgatools.toml`
+fn default_config_path() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("wgatools.toml"))
+}
+
+/// Set options in config file as defaults of `cmd`, so the command line still wins.
+/// Top-level keys are global options, and tables named by subcommand are its options:
+///
+/// ```toml
+/// threads = 8
+/// [call]
+/// sample = "sample1"
+/// ```
+fn apply_config(path: &Path, cmd: &mut Command, matches: &ArgMatches) -> Result<(), WGAError> {
+    let config_err = |msg: String| WGAError::ParseConfig(path.display().to_string(), msg);
+    let content = std::fs::read_to_string(path)?;
+    let config = content
+        .parse::<toml::Table>()
+        .map_err(|e| config_err(e.to_string()))?;
+
+    let sub_name = matches.subcommand_name();
+    for (key, value) in &config {
+        match value {
+            toml::Value::Table(table) => {
+                // only apply options of the running subcommand
+                let sub_cmd = cmd
+                    .find_subcommand(key)
+                    .ok_or_else(|| config_err(format!("unknown subcommand `{}`", key)))?;
+                let name = sub_cmd.get_name().to_string();
+                if sub_name != Some(name.as_str()) {
+                    continue;
+                }
+                let sub_defaults = table
+                    .iter()
+                    .map(|(sub_key, sub_value)| config_defaults(sub_cmd, sub_key, sub_value))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| config_err(format!("[{}] {}", key, e)))?;
+                *cmd = std::mem::take(cmd).mut_subcommand(&name, |sub| {
+                    sub_defaults.into_iter().fold(sub, |sub, (id, defaults)| {
+                        sub.mut_arg(id, |arg| arg.default_values(defaults).required(false))
+                    })
+                });
+            }
+            _ => {
+                let (id, defaults) = config_defaults(cmd, key, value).map_err(config_err)?;
+                *cmd = std::mem::take(cmd)
+                    .mut_arg(id, |arg| arg.default_values(defaults).required(false));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Id and default values of the option of a config key-value
+fn config_defaults(
+    cmd: &Command,
+    key: &str,
+    value: &toml::Value,
+) -> Result<(String, Vec<String>), String> {
+    let id = key.replace('-', "_");
+    let arg = cmd
+        .get_arguments()
+        .find(|arg| arg.get_id() == id.as_str() && arg.get_long().is_some())
+        .ok_or_else(|| format!("unknown option `{}`", key))?;
+    let invalid = || format!("invalid value `{}` for option `{}`", value, key);
+
+    let defaults = match arg.get_action() {
+        ArgAction::SetTrue => vec![value.as_bool().ok_or_else(invalid)?.to_string()],
+        ArgAction::Count => vec![value.as_integer().ok_or_else(invalid)?.max(0).to_string()],
+        _ => {
+            let values = match value {
+                toml::Value::Array(values) => values.iter().collect::<Vec<_>>(),
+                value => vec![value],
+            };
+            values
+                .into_iter()
+                .map(|value| match value {
+                    toml::Value::String(value) => Ok(value.clone()),
+                    toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => {
+                        Ok(value.to_string())
+                    }
+                    _ => Err(invalid()),
+                })
+                .collect::<Result<Vec<_>, _>>()?
+        }
+    };
+    Ok((id, defaults))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_cli() {
+        Cli::command().debug_assert()
+    }
+
+    #[test]
+    fn required_args_from_config() {
+        let path =
+            std::env::temp_dir().join(format!("wgatools-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "[chunk]\nlength = 100\n").unwrap();
+        let args = |extra: &[&str]| {
+            ["wgatools", "chunk", "--config", path.to_str().unwrap()]
+                .iter()
+                .chain(extra)
+                .map(OsString::from)
+                .collect::<Vec<_>>()
+        };
+        let cli = parse_cli_from(args(&["in.maf"])).unwrap();
+        match cli.command {
+            Commands::Chunk { length, input } => {
+                assert_eq!(length, 100);
+                assert_eq!(input.as_deref(), Some("in.maf"));
+            }
+            _ => panic!("not chunk"),
+        }
+        // command line wins over config
+        let cli = parse_cli_from(args(&["-l", "5"])).unwrap();
+        assert!(matches!(cli.command, Commands::Chunk { length: 5, .. }));
+        // still validated after merging
+        std::fs::write(&path, "[chunk]\n").unwrap();
+        let err = parse_cli_from(args(&[])).err().map(|e| e.kind());
+        assert_eq!(err, Some(ErrorKind::MissingRequiredArgument));
+        // config options are not taken as positionals after `--`
+        std::fs::write(&path, "threads = 2\n[chunk]\nlength = 100\n").unwrap();
+        let cli = parse_cli_from(args(&["--", "in.maf"])).unwrap();
+        assert_eq!(cli.threads, 2);
+        assert_eq!(cli.input.as_deref(), Some("in.maf"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn legacy_short_flags() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        let cli = parse_cli_from(args(&[
+            "wgatools", "-t", "2", "c2m", "in.chain", "-t", "t.fa", "-q", "q.fa",
+        ]))
+        .unwrap();
+        assert_eq!(cli.threads, 2);
+        assert!(matches!(cli.command, Commands::Chain2Maf { target, .. } if target == "t.fa"));
+        let cli = parse_cli_from(args(&["wgatools", "maf-ext", "in.maf", "-rchr1,chr2"])).unwrap();
+        assert!(!cli.rewrite);
+        match cli.command {
+            Commands::MafExtract { regions, .. } => {
+                assert_eq!(regions, Some(vec!["chr1".to_string(), "chr2".to_string()]))
+            }
+            _ => panic!("not maf-ext"),
+        }
+        // global short flags of other subcommands are kept
+        let cli = parse_cli_from(args(&["wgatools", "chunk", "-l", "5", "-r"])).unwrap();
+        assert!(cli.rewrite);
+    }
 }
@@ -1,15 +1,24 @@
 use crate::errors::WGAError;
+use crate::log::{profile, record_skipped, Stage, WarnCategory};
+use crate::parser::axt::{AxtReader, AxtRecord};
 use crate::parser::chain::{ChainHeader, ChainReader, ChainRecord};
 use crate::parser::cigar::{
-    // parse_cigar_to_blocks,
-    parse_cigar_to_chain,
-    parse_cigar_to_insert,
-    parse_maf_seq_to_chain,
+    cigar_eqx_to_m, cigar_m_to_eqx, parse_cigar_to_blocks, parse_cigar_to_chain,
+    parse_cigar_to_insert, parse_cigar_to_units, parse_maf_seq_to_blocks, parse_maf_seq_to_chain,
+    parse_paf_to_cigar,
 };
-use crate::parser::common::{AlignRecord, Strand};
+use crate::parser::common::{
+    AlignRecord, AlnOutFormat, BlocksOutFormat, CigarStyle, PafSortBy, PanSN, Strand, UnknownBase,
+};
+use crate::parser::delta::DeltaReader;
 use crate::parser::maf::{MAFReader, MAFRecord, MAFSLine, MAFWriter};
-use crate::parser::paf::PAFReader;
-use crate::utils::reverse_complement;
+use crate::parser::paf::{paf_writer, PAFReader, PafRecord};
+use crate::parser::region::GenomeRegion;
+use crate::utils::{
+    check_bases, create_output_writer, par_collect_ordered, par_write_bounded, par_write_ordered,
+    reverse_complement_with, OutputWriter,
+};
+use log::{info, warn};
 use noodles::sam::header::record::value::map;
 use noodles::sam::header::record::value::map::header::SortOrder;
 use noodles::sam::record::ReadName;
@@ -21,73 +30,581 @@ use noodles::sam::{
     },
 };
 use rayon::prelude::*;
+use regex::Regex;
 use rust_htslib::faidx;
+use std::collections::HashMap;
+use std::fmt::Display;
 use std::io::{Read, Write};
 use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 /// Convert a MAF Reader to output a PAF file
+#[allow(clippy::too_many_arguments)]
 pub fn maf2paf<R: Read + Send>(
     mafreader: &mut MAFReader<R>,
     writer: &mut dyn Write,
     query_name: Option<&str>,
+    verify: bool,
+    sam_tags: bool,
+    sort_by: PafSortBy,
+    pansn: Option<&PanSN>,
+    cigar_style: CigarStyle,
+    in_flight: usize,
 ) -> Result<(), WGAError> {
-    // init csv writer for deserializing
-    let mut wtr = csv::WriterBuilder::new()
-        .delimiter(b'\t')
-        .has_headers(false)
-        .from_writer(writer);
-
-    // multi-threading
-    let pafrecords = mafreader
+    let convert = |mut mafrecord: MAFRecord| -> Result<_, WGAError> {
+        let mut pafrec = mafrecord.convert2paf(query_name)?;
+        let passed = !verify || verify_maf2paf(&mafrecord, &pafrec)?;
+        if cigar_style == CigarStyle::M {
+            set_cigar_style(&mut pafrec, cigar_style, None)?;
+        }
+        if sam_tags {
+            restore_sam_tags(&mafrecord, &mut pafrec);
+        }
+        if let Some(pansn) = pansn {
+            pafrec.query_name = pansn.rename(&pafrec.query_name);
+            pafrec.target_name = pansn.rename(&pafrec.target_name);
+        }
+        Ok((pafrec, passed))
+    };
+
+    // records are written in input order with bounded memory unless sorted
+    if sort_by == PafSortBy::None {
+        let failed_count = AtomicUsize::new(0);
+        par_write_bounded(mafreader.records(), writer, in_flight, |_, mafrecord| {
+            let (pafrec, passed) = convert(mafrecord)?;
+            if !passed {
+                failed_count.fetch_add(1, Ordering::Relaxed);
+            }
+            let mut buf = Vec::new();
+            let mut wtr = paf_writer(&mut buf);
+            wtr.serialize(pafrec)?;
+            wtr.flush()?;
+            drop(wtr);
+            Ok(buf)
+        })?;
+        let failed_count = failed_count.into_inner();
+        if failed_count > 0 {
+            return Err(WGAError::VerifyFailed(failed_count));
+        }
+        return Ok(());
+    }
+
+    // init csv writer for deserializing, restored tags vary in number
+    let mut wtr = paf_writer(writer);
+
+    // all records are needed to sort
+    let mut pafrecords = mafreader
         .records()
         .par_bridge()
-        .map(|record| -> Result<_, WGAError> {
-            let mut mafrecord = record?;
-            mafrecord.convert2paf(query_name)
-        })
+        .map(|record| convert(record?))
         .collect::<Result<Vec<_>, WGAError>>()?;
-    for pafrec in pafrecords {
-        wtr.serialize(pafrec)?;
+    pafrecords.par_sort_by(|(a, _), (b, _)| cmp_paf(a, b, sort_by));
+    let mut failed_count = 0;
+    profile(Stage::Write, || {
+        for (pafrec, passed) in pafrecords {
+            if !passed {
+                failed_count += 1;
+            }
+            wtr.serialize(pafrec)?;
+        }
+        wtr.flush()
+    })?;
+    if failed_count > 0 {
+        return Err(WGAError::VerifyFailed(failed_count));
     }
-    wtr.flush()?;
     Ok(())
 }
 
+// order of PAF records by names in natural order, then start and end,
+// ties are broken by the other side
+fn cmp_paf(a: &PafRecord, b: &PafRecord, sort_by: PafSortBy) -> std::cmp::Ordering {
+    let query = |rec: &PafRecord| (rec.query_start, rec.query_end);
+    let target = |rec: &PafRecord| (rec.target_start, rec.target_end);
+    let by_query =
+        || natord::compare(&a.query_name, &b.query_name).then_with(|| query(a).cmp(&query(b)));
+    let by_target =
+        || natord::compare(&a.target_name, &b.target_name).then_with(|| target(a).cmp(&target(b)));
+    match sort_by {
+        PafSortBy::Query => by_query().then_with(by_target),
+        PafSortBy::Target => by_target().then_with(by_query),
+        PafSortBy::None => std::cmp::Ordering::Equal,
+    }
+}
+
+// restore tags kept in a-line by `paf2maf` before generated tags of other names,
+// and MAPQ from score if it is in range
+fn restore_sam_tags(mafrec: &MAFRecord, pafrec: &mut PafRecord) {
+    if mafrec.score <= 255 {
+        pafrec.mapq = mafrec.score;
+    }
+    let tag_name = |tag: &str| tag.split(':').next().unwrap_or_default().to_string();
+    let kept_names = mafrec
+        .tags
+        .iter()
+        .map(|tag| tag_name(tag))
+        .collect::<Vec<_>>();
+    let generated = std::mem::replace(&mut pafrec.tags, mafrec.tags.clone());
+    pafrec.tags.extend(
+        generated
+            .into_iter()
+            .filter(|tag| !kept_names.contains(&tag_name(tag))),
+    );
+}
+
+// tags of PAF to keep in MAF a-line, CIGAR is restored from sequences,
+// and a tag with whitespace can not be kept
+fn paf_tags_for_maf(pafrec: &PafRecord) -> Vec<String> {
+    pafrec
+        .tags
+        .iter()
+        .filter(|tag| !tag.starts_with("cg:"))
+        .filter(|tag| {
+            let keep = !tag.contains(char::is_whitespace);
+            if !keep {
+                warn!(
+                    target: WarnCategory::Input.target(),
+                    "tag `{}` with whitespace is dropped in record {}",
+                    tag,
+                    pafrec.coord_context()
+                );
+            }
+            keep
+        })
+        .cloned()
+        .collect()
+}
+
+// push a problem if expected and found values are different
+fn check_field<T: PartialEq + Display>(
+    problems: &mut Vec<String>,
+    field: &str,
+    expected: T,
+    found: T,
+) {
+    if expected != found {
+        problems.push(format!("{}: expected {}, found {}", field, expected, found));
+    }
+}
+
+// push a problem for each field of (name, expected, found) with different values
+fn check_fields<T: PartialEq + Display>(problems: &mut Vec<String>, fields: &[(&str, T, T)]) {
+    for (field, expected, found) in fields {
+        check_field(problems, field, expected, found);
+    }
+}
+
+// log problems with record context, return true if no problem
+fn report_problems(pafrec: &PafRecord, problems: Vec<String>) -> bool {
+    if problems.is_empty() {
+        return true;
+    }
+    warn!(
+        target: WarnCategory::Result.target(),
+        "round-trip verify failed for {}:{}-{} {}:{}-{}({}): {}",
+        pafrec.target_name,
+        pafrec.target_start,
+        pafrec.target_end,
+        pafrec.query_name,
+        pafrec.query_start,
+        pafrec.query_end,
+        pafrec.strand,
+        problems.join("; ")
+    );
+    false
+}
+
+// target and query length consumed by cigar
+fn cigar_consumed_len(units: &[(char, u64)]) -> (u64, u64) {
+    units
+        .iter()
+        .fold((0, 0), |(t_len, q_len), &(op, len)| match op {
+            'M' | '=' | 'X' => (t_len + len, q_len + len),
+            'D' => (t_len + len, q_len),
+            'I' => (t_len, q_len + len),
+            _ => (t_len, q_len),
+        })
+}
+
+/// Verify a converted PAF record by converting it back to MAF s-lines:
+/// coordinates, strand, sequence columns and identity should be the same
+fn verify_maf2paf(mafrec: &MAFRecord, pafrec: &PafRecord) -> Result<bool, WGAError> {
+    let mut problems = Vec::new();
+    let t_sline = &mafrec.slines[0];
+    let q_sline = &mafrec.slines[mafrec.query_idx];
+
+    // query coordinates on forward strand
+    let (q_start, q_end) = match q_sline.strand {
+        Strand::Positive => (q_sline.start, q_sline.start + q_sline.align_size),
+        Strand::Negative => (
+            q_sline.size - q_sline.start - q_sline.align_size,
+            q_sline.size - q_sline.start,
+        ),
+    };
+    check_fields(
+        &mut problems,
+        &[
+            ("target name", &t_sline.name, &pafrec.target_name),
+            ("query name", &q_sline.name, &pafrec.query_name),
+        ],
+    );
+    check_fields(
+        &mut problems,
+        &[
+            ("target length", t_sline.size, pafrec.target_length),
+            ("target start", t_sline.start, pafrec.target_start),
+            (
+                "target end",
+                t_sline.start + t_sline.align_size,
+                pafrec.target_end,
+            ),
+            ("query length", q_sline.size, pafrec.query_length),
+            ("query start", q_start, pafrec.query_start),
+            ("query end", q_end, pafrec.query_end),
+        ],
+    );
+    check_field(&mut problems, "strand", q_sline.strand, pafrec.strand);
+
+    let mut t_seq = t_sline.seq.replace('-', "");
+    let mut q_seq = q_sline.seq.replace('-', "");
+    let (t_consumed, q_consumed) = cigar_consumed_len(&parse_cigar_to_units(pafrec)?);
+    check_fields(
+        &mut problems,
+        &[
+            ("target seq length", t_sline.align_size, t_seq.len() as u64),
+            ("query seq length", q_sline.align_size, q_seq.len() as u64),
+            (
+                "cigar target length",
+                pafrec.coord_sub(pafrec.target_end, pafrec.target_start)?,
+                t_consumed,
+            ),
+            (
+                "cigar query length",
+                pafrec.coord_sub(pafrec.query_end, pafrec.query_start)?,
+                q_consumed,
+            ),
+        ],
+    );
+
+    // rebuild alignment columns only if lengths are consistent
+    if problems.is_empty() {
+        parse_cigar_to_insert(pafrec, &mut t_seq, &mut q_seq)?;
+        // columns gapped in both are dropped in PAF
+        let (t_cols, q_cols): (String, String) = t_sline
+            .seq
+            .chars()
+            .zip(q_sline.seq.chars())
+            .filter(|(t, q)| *t != '-' || *q != '-')
+            .unzip();
+        if t_seq != t_cols || q_seq != q_cols {
+            problems.push("alignment columns changed after round-trip".to_string());
+        }
+        let matches = t_cols
+            .chars()
+            .zip(q_cols.chars())
+            .filter(|(t, q)| t == q)
+            .count() as u64;
+        check_fields(
+            &mut problems,
+            &[
+                ("matches", matches, pafrec.matches),
+                ("block length", t_cols.len() as u64, pafrec.block_length),
+            ],
+        );
+    }
+    Ok(report_problems(pafrec, problems))
+}
+
+/// Verify a converted MAF record by converting it back to PAF:
+/// coordinates, strand, sequence length and CIGAR should be the same
+fn verify_paf2maf(pafrec: &PafRecord, mafrec: &mut MAFRecord) -> Result<bool, WGAError> {
+    let mut problems = Vec::new();
+    let back = mafrec.convert2paf(None)?;
+
+    check_fields(
+        &mut problems,
+        &[
+            ("target name", &pafrec.target_name, &back.target_name),
+            ("query name", &pafrec.query_name, &back.query_name),
+        ],
+    );
+    check_fields(
+        &mut problems,
+        &[
+            ("target length", pafrec.target_length, back.target_length),
+            ("target start", pafrec.target_start, back.target_start),
+            ("target end", pafrec.target_end, back.target_end),
+            ("query length", pafrec.query_length, back.query_length),
+            ("query start", pafrec.query_start, back.query_start),
+            ("query end", pafrec.query_end, back.query_end),
+        ],
+    );
+    check_field(&mut problems, "strand", pafrec.strand, back.strand);
+
+    // fetched sequences should cover the whole alignment
+    let t_sline = &mafrec.slines[0];
+    let q_sline = &mafrec.slines[mafrec.query_idx];
+    let bases = |seq: &str| seq.chars().filter(|c| *c != '-').count() as u64;
+    check_fields(
+        &mut problems,
+        &[
+            ("target seq length", t_sline.align_size, bases(&t_sline.seq)),
+            ("query seq length", q_sline.align_size, bases(&q_sline.seq)),
+            (
+                "column count",
+                t_sline.seq.len() as u64,
+                q_sline.seq.len() as u64,
+            ),
+        ],
+    );
+
+    // M/=/X are all aligned columns, compare after merging them
+    let merge_aligned = |units: Vec<(char, u64)>| {
+        units.into_iter().fold(Vec::new(), |mut acc, (op, len)| {
+            let op = match op {
+                '=' | 'X' => 'M',
+                op => op,
+            };
+            match acc.last_mut() {
+                Some((last_op, last_len)) if *last_op == op => *last_len += len,
+                _ => acc.push((op, len)),
+            }
+            acc
+        })
+    };
+    let units = parse_cigar_to_units(pafrec)?;
+    let (t_consumed, q_consumed) = cigar_consumed_len(&units);
+    check_fields(
+        &mut problems,
+        &[
+            (
+                "cigar target length",
+                pafrec.coord_sub(pafrec.target_end, pafrec.target_start)?,
+                t_consumed,
+            ),
+            (
+                "cigar query length",
+                pafrec.coord_sub(pafrec.query_end, pafrec.query_start)?,
+                q_consumed,
+            ),
+        ],
+    );
+    // identity is comparable only if the original CIGAR distinguishes =/X
+    if !units.iter().any(|(op, _)| *op == 'M') {
+        let matches = units
+            .iter()
+            .filter(|(op, _)| *op == '=')
+            .map(|(_, len)| len)
+            .sum::<u64>();
+        check_field(&mut problems, "matches", matches, back.matches);
+    }
+    if merge_aligned(units) != merge_aligned(parse_cigar_to_units(&back)?) {
+        problems.push("CIGAR changed after round-trip".to_string());
+    }
+    Ok(report_problems(pafrec, problems))
+}
+
 /// Convert a MAF Reader to output a Chain file
 pub fn maf2chain<R: Read + Send>(
     mafreader: &mut MAFReader<R>,
-    writer: &mut Box<dyn Write>,
+    writer: &mut dyn Write,
     query_name: Option<&str>,
+    query_regex: Option<&Regex>,
 ) -> Result<(), WGAError> {
-    // iterate over records and give a self-increasing chain-id
-    for (id, record) in mafreader.records().enumerate() {
-        let mut record = record?;
+    // give a self-increasing chain-id by input order, generate chains in parallel
+    par_write_ordered(mafreader.records(), writer, |id, mut record| {
+        let mut buf = Vec::new();
+        if !select_maf_query(&mut record, query_name, query_regex)? {
+            record_skipped();
+            return Ok(buf);
+        }
+        write_maf_chain(&mut buf, &record, id)?;
+        Ok(buf)
+    })
+}
 
-        match query_name {
-            Some(qname) => {
-                record.set_query_idx_byname(qname)?;
-            }
-            None => {
-                // do nothing
+/// Convert a MAF Reader to a Chain file ready for liftOver: blocks on the negative target strand
+/// are flipped, chains are checked and sorted by target and score, with ids from 1 in output order
+pub fn maf2chain_liftover<R: Read + Send>(
+    mafreader: &mut MAFReader<R>,
+    writer: &mut dyn Write,
+    query_name: Option<&str>,
+    query_regex: Option<&Regex>,
+) -> Result<(), WGAError> {
+    // all chains must be known before sorting, only headers and data lines are kept
+    let chains = par_collect_ordered(mafreader.records(), |_, mut record| {
+        if !select_maf_query(&mut record, query_name, query_regex)? {
+            record_skipped();
+            return Ok(None);
+        }
+        // liftOver only accepts chains on the positive target strand
+        if record.target_strand() == Strand::Negative {
+            flip_maf_record(&mut record)?;
+        }
+        let mut header = ChainHeader::try_from(&record)?;
+        header.set_score(record.score as f64);
+        let mut data = Vec::new();
+        parse_maf_seq_to_chain(&record, &mut data)?;
+        check_liftover_chain(&header, &data)
+            .map_err(|msg| record.context_err(WGAError::InvalidRecord(msg)))?;
+        Ok(Some((header, data)))
+    })?;
+    let mut chains = chains.into_iter().flatten().collect::<Vec<_>>();
+    chains.par_sort_by(|(a, _), (b, _)| {
+        natord::compare(&a.target().name, &b.target().name)
+            .then_with(|| b.score().total_cmp(&a.score()))
+            .then_with(|| a.target().start.cmp(&b.target().start))
+            .then_with(|| natord::compare(&a.query().name, &b.query().name))
+            .then_with(|| a.query().start.cmp(&b.query().start))
+    });
+    let count = chains.len();
+    for (idx, (mut header, data)) in chains.into_iter().enumerate() {
+        header.chain_id = idx + 1;
+        write!(writer, "{}", header)?;
+        writer.write_all(&data)?;
+        writer.write_all(b"\n\n")?;
+    }
+    writer.flush()?;
+    info!("write {} chains", count);
+    Ok(())
+}
+
+// select the query of MAF record by name or the first one matched by regex,
+// false if no query is matched
+fn select_maf_query(
+    record: &mut MAFRecord,
+    query_name: Option<&str>,
+    query_regex: Option<&Regex>,
+) -> Result<bool, WGAError> {
+    match (query_name, query_regex) {
+        (Some(qname), _) => record.set_query_idx_byname(qname)?,
+        (None, Some(re)) => {
+            match (1..record.slines.len()).find(|idx| re.is_match(&record.slines[*idx].name)) {
+                Some(idx) => record.set_query_idx(idx),
+                None => return Ok(false),
             }
         }
+        (None, None) => {}
+    }
+    Ok(true)
+}
 
-        // transform record to Chain Header
-        let mut header = ChainHeader::try_from(&record)?;
+// reverse complement all lines of MAF record, so the target is on the other strand
+fn flip_maf_record(record: &mut MAFRecord) -> Result<(), WGAError> {
+    for sline in record.slines.iter_mut() {
+        let start = sline
+            .size
+            .checked_sub(sline.start + sline.align_size)
+            .ok_or_else(|| {
+                WGAError::InvalidRecord(format!(
+                    "`{}` ends after its size {}",
+                    sline.name, sline.size
+                ))
+            })?;
+        sline.seq = reverse_complement_with(&sline.seq, UnknownBase::PassThrough)?;
+        sline.qual = sline.qual.as_ref().map(|qual| qual.chars().rev().collect());
+        sline.start = start;
+        sline.strand = match sline.strand {
+            Strand::Positive => Strand::Negative,
+            Strand::Negative => Strand::Positive,
+        };
+    }
+    Ok(())
+}
 
-        // set chain id
-        header.chain_id = id;
+// check spans of chain header are in sequences and consistent with its data lines
+fn check_liftover_chain(header: &ChainHeader, data: &[u8]) -> Result<(), String> {
+    for (kind, info) in [("target", header.target()), ("query", header.query())] {
+        if info.start >= info.end {
+            return Err(format!("empty {} span of chain", kind));
+        }
+        if info.end > info.size {
+            return Err(format!(
+                "{} span {}-{} exceeds its size {}",
+                kind, info.start, info.end, info.size
+            ));
+        }
+    }
+    let data = String::from_utf8_lossy(data);
+    let lines = data.trim().lines().collect::<Vec<_>>();
+    let (mut t_len, mut q_len) = (0, 0);
+    for (idx, line) in lines.iter().enumerate() {
+        let fields = line
+            .split('\t')
+            .map(|f| f.parse::<u64>().map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let expected = if idx + 1 == lines.len() { 1 } else { 3 };
+        if fields.len() != expected || fields[0] == 0 {
+            return Err(format!("invalid chain data line `{}`", line));
+        }
+        t_len += fields[0] + fields.get(1).unwrap_or(&0);
+        q_len += fields[0] + fields.get(2).unwrap_or(&0);
+    }
+    let t_span = header.target().end - header.target().start;
+    let q_span = header.query().end - header.query().start;
+    if t_len != t_span || q_len != q_span {
+        return Err(format!(
+            "chain data covers {} target and {} query bases, but spans are {} and {}",
+            t_len, q_len, t_span, q_span
+        ));
+    }
+    Ok(())
+}
 
-        // write header without newline
-        writer.write_all(format!("{}", header).as_bytes())?;
+// write the chain of current query in MAF record
+fn write_maf_chain(writer: &mut dyn Write, record: &MAFRecord, id: usize) -> Result<(), WGAError> {
+    // transform record to Chain Header
+    let mut header = ChainHeader::try_from(record)?;
 
-        // nom the cigar string and write to file
-        parse_maf_seq_to_chain(&record, writer)?;
+    // set chain id
+    header.chain_id = id;
 
-        // additional newline for standard chain format
-        writer.write_all(b"\n\n")?;
+    // write header without newline
+    write!(writer, "{}", header)?;
+
+    // nom the cigar string and write to buffer
+    parse_maf_seq_to_chain(record, writer)?;
+
+    // additional newline for standard chain format
+    writer.write_all(b"\n\n")?;
+    Ok(())
+}
+
+/// Convert all queries of MAF records into one chain file per query genome in `out_dir`,
+/// named by the genome as the prefix of query name before `genome_sep`,
+/// only queries matched by `query_regex` if it is set
+pub fn maf2chain_split<R: Read + Send>(
+    mafreader: &mut MAFReader<R>,
+    out_dir: &Path,
+    genome_sep: char,
+    query_regex: Option<&Regex>,
+) -> Result<(), WGAError> {
+    // writer and next chain-id of each genome
+    let mut writers: HashMap<String, (OutputWriter, usize)> = HashMap::new();
+    for record in mafreader.records() {
+        let mut record = record?;
+        for idx in 1..record.slines.len() {
+            let name = &record.slines[idx].name;
+            if query_regex.is_some_and(|re| !re.is_match(name)) {
+                continue;
+            }
+            let genome = name.split(genome_sep).next().unwrap_or(name);
+            if !writers.contains_key(genome) {
+                // a genome name may not be a valid file name
+                let out_path = out_dir.join(format!("{}.chain", genome.replace('/', "_")));
+                let writer = create_output_writer(&out_path)?;
+                writers.insert(genome.to_string(), (writer, 0));
+            }
+            let (writer, id) = writers.get_mut(genome).unwrap();
+            record.set_query_idx(idx);
+            write_maf_chain(writer, &record, *id)?;
+            *id += 1;
+        }
     }
-    writer.flush()?;
+    for (writer, _) in writers.values_mut() {
+        writer.flush()?;
+    }
+    info!("write chains of {} genomes", writers.len());
     Ok(())
 }
 
@@ -124,34 +641,175 @@ pub fn maf2sam<R: Read + Send>(
     Ok(())
 }
 
-// /// Convert a PAF Reader to output a Blocks file
-// pub fn paf2blocks<R: Read + Send>(
-//     pafreader: &mut PAFReader<R>,
-//     writer: &mut dyn Write,
-// ) -> Result<(), WGAError> {
-//     // init writer and csv writer for deserializing
-//     let mut wtr = csv::WriterBuilder::new()
-//         .delimiter(b'\t')
-//         .from_writer(writer);
-
-//     // iterate over records
-//     for record in pafreader.records() {
-//         let record = record?;
-//         // nom the cigar string and write to file
-//         parse_cigar_to_blocks(&record, &mut wtr)?;
-//     }
-//     wtr.flush()?;
-//     Ok(())
-// }
+/// Convert a PAF Reader to output a Blocks file
+pub fn paf2blocks<R: Read + Send>(
+    pafreader: &mut PAFReader<R>,
+    writer: &mut dyn Write,
+    out_format: BlocksOutFormat,
+) -> Result<(), WGAError> {
+    write_blocks_header(writer, out_format)?;
+    // generate blocks in parallel and write in input order
+    par_write_ordered(pafreader.records(), writer, |_, record| {
+        let mut buf = Vec::new();
+        // nom the cigar string and write to buffer
+        for block in parse_cigar_to_blocks(&record)? {
+            block.write_line(&mut buf, out_format)?;
+        }
+        Ok(buf)
+    })
+}
+
+/// Convert a MAF Reader to output a Blocks file
+pub fn maf2blocks<R: Read + Send>(
+    mafreader: &mut MAFReader<R>,
+    writer: &mut dyn Write,
+    out_format: BlocksOutFormat,
+    query_name: Option<&str>,
+) -> Result<(), WGAError> {
+    write_blocks_header(writer, out_format)?;
+    par_write_ordered(mafreader.records(), writer, |_, mut record| {
+        let mut buf = Vec::new();
+        if let Some(qname) = query_name {
+            record.set_query_idx_byname(qname)?;
+        }
+        for block in parse_maf_seq_to_blocks(&record)? {
+            block.write_line(&mut buf, out_format)?;
+        }
+        Ok(buf)
+    })
+}
+
+// only TSV has a header line
+fn write_blocks_header(
+    writer: &mut dyn Write,
+    out_format: BlocksOutFormat,
+) -> Result<(), WGAError> {
+    if let BlocksOutFormat::Tsv = out_format {
+        writer.write_all(
+            b"query_name\tquery_start\tquery_end\ttarget_name\ttarget_start\ttarget_end\tstrand\n",
+        )?;
+    }
+    Ok(())
+}
+
+/// Concatenate columns of MAF blocks into one aligned row per genome, genome is
+/// the prefix of sequence name before `genome_sep`, and genomes missing in a block are filled with gaps.
+/// Rows are `genomes` in order if given, or all genomes in order of first appearance
+pub fn maf2aln<R: Read + Send>(
+    mafreaders: &mut [MAFReader<R>],
+    writer: &mut dyn Write,
+    out_format: AlnOutFormat,
+    genome_sep: char,
+    genomes: &[String],
+    min_genomes: usize,
+) -> Result<(), WGAError> {
+    let mut names = genomes.to_vec();
+    let mut rows: Vec<Vec<u8>> = vec![Vec::new(); names.len()];
+    let mut name_idx: HashMap<String, usize> = names
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| (name.clone(), idx))
+        .collect();
+    let fixed = !genomes.is_empty();
+    let (mut used_blocks, mut skipped_blocks, mut dup_slines) = (0usize, 0usize, 0usize);
+
+    for mafreader in mafreaders.iter_mut() {
+        for rec in mafreader.records() {
+            let rec = rec?;
+            // first s-line of each genome in the block
+            let mut block_rows: Vec<(usize, &[u8])> = Vec::new();
+            for sline in &rec.slines {
+                let genome = sline
+                    .name
+                    .split_once(genome_sep)
+                    .map_or(sline.name.as_str(), |(genome, _)| genome);
+                let idx = match name_idx.get(genome) {
+                    Some(idx) => *idx,
+                    None if fixed => continue,
+                    None => {
+                        // a new genome is missing in all previous columns
+                        names.push(genome.to_string());
+                        rows.push(vec![b'-'; rows.first().map_or(0, |row| row.len())]);
+                        name_idx.insert(genome.to_string(), names.len() - 1);
+                        names.len() - 1
+                    }
+                };
+                match block_rows.iter().any(|(exist, _)| *exist == idx) {
+                    true => dup_slines += 1,
+                    false => block_rows.push((idx, sline.seq.as_bytes())),
+                }
+            }
+            if block_rows.is_empty() || block_rows.len() < min_genomes {
+                skipped_blocks += 1;
+                continue;
+            }
+            used_blocks += 1;
+
+            // columns of gaps in all genomes are dropped
+            let align_len = block_rows[0].1.len();
+            if block_rows.iter().any(|(_, seq)| seq.len() != align_len) {
+                return Err(rec.context_err(WGAError::InvalidRecord(
+                    "s-lines of the block have different alignment lengths".to_string(),
+                )));
+            }
+            let keep = (0..align_len)
+                .filter(|col| block_rows.iter().any(|(_, seq)| seq[*col] != b'-'))
+                .collect::<Vec<_>>();
+            let mut filled = vec![false; rows.len()];
+            for (idx, seq) in block_rows {
+                rows[idx].extend(keep.iter().map(|col| seq[*col]));
+                filled[idx] = true;
+            }
+            for (row, _) in rows.iter_mut().zip(filled).filter(|(_, filled)| !filled) {
+                row.resize(row.len() + keep.len(), b'-');
+            }
+        }
+    }
+    info!(
+        "used blocks: {}, skipped blocks: {}, genomes: {}",
+        used_blocks,
+        skipped_blocks,
+        names.len()
+    );
+    if dup_slines > 0 {
+        warn!(
+            target: WarnCategory::Input.target(),
+            "{} s-lines of a genome already in their block are ignored, only the first one is used",
+            dup_slines
+        );
+    }
+
+    let aln_len = rows.first().map_or(0, |row| row.len());
+    if let AlnOutFormat::Phylip = out_format {
+        writeln!(writer, "{} {}", names.len(), aln_len)?;
+    }
+    for (name, row) in names.iter().zip(rows) {
+        match out_format {
+            AlnOutFormat::Fasta => {
+                writeln!(writer, ">{}", name)?;
+                for line in row.chunks(60) {
+                    writer.write_all(line)?;
+                    writer.write_all(b"\n")?;
+                }
+            }
+            AlnOutFormat::Phylip => {
+                write!(writer, "{} ", name)?;
+                writer.write_all(&row)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+    }
+    Ok(())
+}
 
 /// Convert a PAF Reader to output a Chain file
 pub fn paf2chain<R: Read + Send>(
     pafreader: &mut PAFReader<R>,
-    writer: &mut Box<dyn Write>,
+    writer: &mut dyn Write,
 ) -> Result<(), WGAError> {
-    // iterate over records and give a self-increasing chain-id
-    for (id, record) in pafreader.records().enumerate() {
-        let record = record?;
+    // give a self-increasing chain-id by input order, generate chains in parallel
+    par_write_ordered(pafreader.records(), writer, |id, record| {
+        let mut buf = Vec::new();
 
         // transform record to Chain Header
         let mut header = ChainHeader::try_from(&record)?;
@@ -160,108 +818,269 @@ pub fn paf2chain<R: Read + Send>(
         header.chain_id = id;
 
         // write header without newline
-        writer.write_all(format!("{}", header).as_bytes())?;
+        write!(buf, "{}", header)?;
 
-        // nom the cigar string and write to file
-        parse_cigar_to_chain(&record, writer)?;
+        // nom the cigar string and write to buffer
+        parse_cigar_to_chain(&record, &mut buf)?;
 
         // additional newline for standard chain format
-        writer.write_all(b"\n\n")?;
-    }
-    writer.flush()?;
-    Ok(())
+        buf.write_all(b"\n\n")?;
+        Ok(buf)
+    })
 }
 
 /// Convert a PAF Reader to output a MAF file
+#[allow(clippy::too_many_arguments)]
 pub fn paf2maf<R: Read + Send>(
     pafreader: &mut PAFReader<R>,
     writer: &mut dyn Write,
     t_fa_path: &str,
     q_fa_path: &str,
+    verify: bool,
+    unknown_base: UnknownBase,
+    keep_tags: &[String],
+    regions: &[GenomeRegion],
 ) -> Result<(), WGAError> {
     // get the target and query fasta reader
     let t_reader = faidx::Reader::from_path(t_fa_path)?;
     let q_reader = faidx::Reader::from_path(q_fa_path)?;
 
-    // init a MAFWriter
-    let mut mafwtr = MAFWriter::new(writer);
-
     // write header
     let header = format!(
         "#maf version=1.6 convert_from=paf t_seq_path={} q_seq_path={}",
         t_fa_path, q_fa_path
     );
-    mafwtr.write_header(header)?;
-
-    for pafrec in pafreader.records() {
-        let pafrec = pafrec?;
-        // get mapq as score
-        let score = pafrec.mapq;
-        // get target info
-        let t_name = &pafrec.target_name;
-        let t_start = pafrec.target_start;
-        let t_end = pafrec.target_end - 1;
-        let t_strand = pafrec.target_strand();
-        let t_alilen = pafrec.target_end - pafrec.target_start;
-        let t_size = pafrec.target_length;
-        // get query info
-        let q_name = &pafrec.query_name;
-        let q_strand = pafrec.query_strand();
-        let q_size = pafrec.query_length;
-        let q_alilen = pafrec.query_end - pafrec.query_start;
-        // NOTE: if negative strand, we should convert the start position
-        let q_start = match q_strand {
-            Strand::Positive => pafrec.query_start,
-            Strand::Negative => q_size - pafrec.query_end,
-        };
+    MAFWriter::new(&mut *writer).write_header(header)?;
 
-        // get seqs from indexed fasta files
-        let mut whole_t_seq =
-            t_reader.fetch_seq_string(t_name, t_start as usize, t_end as usize)?;
-        let mut whole_q_seq = q_reader.fetch_seq_string(
-            q_name,
-            pafrec.query_start as usize,
-            (pafrec.query_end - 1) as usize,
-        )?;
+    // sequences are fetched in order while reading, gaps are inserted in parallel
+    // records are clipped to each of `regions` if set, others out of them are skipped
+    let records = pafreader
+        .records()
+        .flat_map(|pafrec| clip_paf_to_regions(pafrec, regions))
+        .map(|pafrec| {
+            pafrec.and_then(|pafrec| {
+                let mut mafrec = paf2maf_ungapped(&pafrec, &t_reader, &q_reader, unknown_base)?;
+                // only selected tags are kept if any
+                if !keep_tags.is_empty() {
+                    mafrec.tags.retain(|tag| {
+                        let name = tag.split(':').next().unwrap_or_default();
+                        keep_tags.iter().any(|keep| keep == name)
+                    });
+                }
+                Ok((pafrec, mafrec))
+            })
+        });
+    let failed_count = AtomicUsize::new(0);
+    par_write_ordered(records, writer, |_, (pafrec, mut mafrec)| {
+        // nom the cigar string and insert the `-` to sequence
+        insert_paf_gaps(&pafrec, &mut mafrec)?;
+        if verify && !verify_paf2maf(&pafrec, &mut mafrec)? {
+            failed_count.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut buf = Vec::new();
+        MAFWriter::new(&mut buf).write_record(&mafrec)?;
+        Ok(buf)
+    })?;
+    let failed_count = failed_count.into_inner();
+    if failed_count > 0 {
+        return Err(WGAError::VerifyFailed(failed_count));
+    }
+    Ok(())
+}
+
+// records clipped to each of `regions` on target, the record itself if no region is set
+fn clip_paf_to_regions(
+    pafrec: Result<PafRecord, WGAError>,
+    regions: &[GenomeRegion],
+) -> Vec<Result<PafRecord, WGAError>> {
+    let pafrec = match pafrec {
+        Ok(pafrec) if !regions.is_empty() => pafrec,
+        pafrec => return vec![pafrec],
+    };
+    let mut clipped = Vec::new();
+    for region in regions {
+        match clip_paf_to_target(&pafrec, region) {
+            Ok(Some(rec)) => clipped.push(Ok(rec)),
+            Ok(None) => {}
+            Err(err) => return vec![Err(err)],
+        }
+    }
+    if clipped.is_empty() {
+        record_skipped();
+    }
+    clipped
+}
 
-        // reverse complement the query sequence if it is on the negative strand
-        match q_strand {
-            Strand::Positive => {}
-            Strand::Negative => {
-                whole_q_seq = reverse_complement(&whole_q_seq)?;
+/// Clip a PAF record to `[start, end)` of `region` on target by CIGAR, so only sequences of
+/// the region are fetched. The clipped record starts and ends with aligned bases, and keeps
+/// no tags but the new CIGAR. None if no aligned base is in the region
+pub fn clip_paf_to_target(
+    pafrec: &PafRecord,
+    region: &GenomeRegion,
+) -> Result<Option<PafRecord>, WGAError> {
+    let start = region.start.max(pafrec.target_start);
+    let end = region.end.min(pafrec.target_end);
+    if pafrec.target_name != region.name || start >= end {
+        return Ok(None);
+    }
+    // (op, len, target position, query offset in the direction of alignment) of kept pieces
+    let mut pieces = Vec::new();
+    let (mut t_pos, mut q_off) = (pafrec.target_start, 0);
+    for (op, len) in parse_cigar_to_units(pafrec)? {
+        match op {
+            'M' | '=' | 'X' | 'D' => {
+                let (piece_start, piece_end) = (t_pos.max(start), (t_pos + len).min(end));
+                if piece_start < piece_end {
+                    let q_piece = match op {
+                        'D' => q_off,
+                        _ => q_off + piece_start - t_pos,
+                    };
+                    pieces.push((op, piece_end - piece_start, piece_start, q_piece));
+                }
+                t_pos += len;
+                if op != 'D' {
+                    q_off += len;
+                }
+            }
+            'I' => {
+                // an insertion is kept if bases on both sides are in the region
+                if start < t_pos && t_pos < end {
+                    pieces.push((op, len, t_pos, q_off));
+                }
+                q_off += len;
             }
+            _ => {}
         }
-        // nom the cigar string and insert the `-` to sequence
-        parse_cigar_to_insert(&pafrec, &mut whole_t_seq, &mut whole_q_seq)?;
-        // get s-lines
-        let t_sline = MAFSLine {
-            mode: 's',
-            name: t_name.to_string(),
-            start: t_start,
-            align_size: t_alilen,
-            strand: t_strand,
-            size: t_size,
-            seq: whole_t_seq,
-        };
-        let q_sline = MAFSLine {
-            mode: 's',
-            name: q_name.to_string(),
-            start: q_start,
-            align_size: q_alilen,
-            strand: q_strand,
-            size: q_size,
-            seq: whole_q_seq,
-        };
-        // get maf record
-        let mafrec = MAFRecord {
-            score,
-            slines: vec![t_sline, q_sline],
-            query_idx: 1,
-        };
-        // write maf record
-        mafwtr.write_record(&mafrec)?;
     }
-    Ok(())
+    // indels at both ends are trimmed
+    let is_aligned = |piece: &&(char, u64, u64, u64)| matches!(piece.0, 'M' | '=' | 'X');
+    let (Some(first), Some(last)) = (
+        pieces.iter().position(|piece| is_aligned(&piece)),
+        pieces.iter().rposition(|piece| is_aligned(&piece)),
+    ) else {
+        return Ok(None);
+    };
+    let pieces = &pieces[first..=last];
+    let (_, _, t_start, q_begin) = pieces[0];
+    let (_, last_len, last_t, last_q) = pieces[pieces.len() - 1];
+    let (t_end, q_finish) = (last_t + last_len, last_q + last_len);
+    let (query_start, query_end) = match pafrec.strand {
+        Strand::Positive => (pafrec.query_start + q_begin, pafrec.query_start + q_finish),
+        Strand::Negative => (pafrec.query_end - q_finish, pafrec.query_end - q_begin),
+    };
+    let mut cigar = String::from("cg:Z:");
+    let (mut matches, mut block_length) = (0, 0);
+    for &(op, len, _, _) in pieces {
+        cigar.push_str(&format!("{}{}", len, op));
+        block_length += len;
+        if matches!(op, 'M' | '=') {
+            matches += len;
+        }
+    }
+    Ok(Some(PafRecord {
+        query_name: pafrec.query_name.clone(),
+        query_length: pafrec.query_length,
+        query_start,
+        query_end,
+        strand: pafrec.strand,
+        target_name: pafrec.target_name.clone(),
+        target_length: pafrec.target_length,
+        target_start: t_start,
+        target_end: t_end,
+        matches,
+        block_length,
+        mapq: pafrec.mapq,
+        tags: vec![cigar],
+    }))
+}
+
+/// Build a MAF record of PAF record with ungapped sequences fetched from indexed FASTA,
+/// gaps should be inserted by [`insert_paf_gaps`]
+pub fn paf2maf_ungapped(
+    pafrec: &PafRecord,
+    t_reader: &faidx::Reader,
+    q_reader: &faidx::Reader,
+    unknown_base: UnknownBase,
+) -> Result<MAFRecord, WGAError> {
+    // get target info
+    let t_name = &pafrec.target_name;
+    let t_start = pafrec.target_start;
+    let t_end = pafrec.coord_sub(pafrec.target_end, 1)?;
+    let t_alilen = pafrec.coord_sub(pafrec.target_end, pafrec.target_start)?;
+    // get query info
+    let q_name = &pafrec.query_name;
+    let q_strand = pafrec.query_strand();
+    let q_size = pafrec.query_length;
+    let q_alilen = pafrec.coord_sub(pafrec.query_end, pafrec.query_start)?;
+    // NOTE: if negative strand, we should convert the start position
+    let q_start = match q_strand {
+        Strand::Positive => pafrec.query_start,
+        Strand::Negative => pafrec.coord_sub(q_size, pafrec.query_end)?,
+    };
+
+    // get seqs from indexed fasta files
+    let (t_seq, q_seq) = profile(Stage::Fetch, || {
+        Ok::<_, WGAError>((
+            t_reader.fetch_seq_string(t_name, t_start as usize, t_end as usize)?,
+            q_reader.fetch_seq_string(
+                q_name,
+                pafrec.query_start as usize,
+                pafrec.coord_sub(pafrec.query_end, 1)? as usize,
+            )?,
+        ))
+    })?;
+
+    // handle unknown characters, and reverse complement the query sequence if it is on the negative strand
+    let t_seq = check_bases(t_seq, unknown_base)?;
+    let q_seq = match q_strand {
+        Strand::Positive => check_bases(q_seq, unknown_base)?,
+        Strand::Negative => reverse_complement_with(&q_seq, unknown_base)?,
+    };
+
+    let t_sline = MAFSLine {
+        mode: 's',
+        name: t_name.to_string(),
+        start: t_start,
+        align_size: t_alilen,
+        strand: pafrec.target_strand(),
+        size: pafrec.target_length,
+        seq: t_seq,
+        qual: None,
+    };
+    let q_sline = MAFSLine {
+        mode: 's',
+        name: q_name.to_string(),
+        start: q_start,
+        align_size: q_alilen,
+        strand: q_strand,
+        size: q_size,
+        seq: q_seq,
+        qual: None,
+    };
+    // get mapq as score, tags are kept for `maf2paf --sam-tags`
+    Ok(MAFRecord {
+        score: pafrec.mapq,
+        slines: vec![t_sline, q_sline],
+        query_idx: 1,
+        tags: paf_tags_for_maf(pafrec),
+    })
+}
+
+/// Insert `-` into sequences of MAF record built by [`paf2maf_ungapped`] by CIGAR
+pub fn insert_paf_gaps(pafrec: &PafRecord, mafrec: &mut MAFRecord) -> Result<(), WGAError> {
+    let (t_sline, q_sline) = match mafrec.slines.as_mut_slice() {
+        [t_sline, q_sline] => (t_sline, q_sline),
+        slines => {
+            return Err(WGAError::SLineCountNotMatch(
+                slines.len(),
+                2,
+                pafrec.coord_context(),
+            ))
+        }
+    };
+    parse_cigar_to_insert(pafrec, &mut t_sline.seq, &mut q_sline.seq)?;
+    // a CIGAR not matching the spans gives sequences of different lengths
+    mafrec.check_seq_len().map_err(|e| pafrec.context_err(e))
 }
 
 /// Convert a Chain Reader to output a MAF file
@@ -270,90 +1089,153 @@ pub fn chain2maf<R: Read + Send>(
     writer: &mut dyn Write,
     t_fa_path: &str,
     q_fa_path: &str,
+    unknown_base: UnknownBase,
+    regions: &[GenomeRegion],
 ) -> Result<(), WGAError> {
+    if !regions.is_empty() {
+        return chain2maf_regions(
+            chainreader,
+            writer,
+            t_fa_path,
+            q_fa_path,
+            unknown_base,
+            regions,
+        );
+    }
     // get the target and query fasta reader
     let t_reader = faidx::Reader::from_path(t_fa_path)?;
     let q_reader = faidx::Reader::from_path(q_fa_path)?;
 
-    // init a MAFWriter
-    let mut mafwtr = MAFWriter::new(writer);
-
     // write header
     let header = format!(
         "#maf version=1.6 convert_from=chain t_seq_path={} q_seq_path={}",
         t_fa_path, q_fa_path
     );
-    mafwtr.write_header(header)?;
+    MAFWriter::new(&mut *writer).write_header(header)?;
 
-    for chainrec in chainreader.records()? {
+    // sequences are fetched in order while reading, gaps are inserted in parallel
+    let records = chainreader.records()?.map(|chainrec| {
         let chainrec = chainrec?;
-        // 255 as score
-        let score = 255;
-        // get target info
-        let t_name = chainrec.target_name();
-        let t_start = chainrec.target_start();
-        let t_end = chainrec.target_end() - 1;
-        let t_strand = chainrec.target_strand();
-        let t_alilen = chainrec.target_end() - chainrec.target_start();
-        let t_size = chainrec.target_length();
-        // get query info
-        let q_name = chainrec.query_name();
-        let q_strand = chainrec.query_strand();
-        let q_size = chainrec.query_length();
-        let q_alilen = chainrec.query_end() - chainrec.query_start();
-        // NOTE: if negative strand, we should convert the start position
-        let q_start = match q_strand {
-            Strand::Positive => chainrec.query_start(),
-            Strand::Negative => q_size - chainrec.query_end(),
-        };
+        let mafrec = chain2maf_ungapped(&chainrec, &t_reader, &q_reader, unknown_base)?;
+        Ok::<_, WGAError>((chainrec, mafrec))
+    });
+    par_write_ordered(records, writer, |_, (chainrec, mut mafrec)| {
+        // read chain dataline and insert the `-` to sequence
+        if let [t_sline, q_sline] = mafrec.slines.as_mut_slice() {
+            parse_chain_to_insert(&chainrec, &mut t_sline.seq, &mut q_sline.seq)?;
+        }
+        let mut buf = Vec::new();
+        MAFWriter::new(&mut buf).write_record(&mafrec)?;
+        Ok(buf)
+    })
+}
 
-        // get seqs from indexed fasta files
-        let mut whole_t_seq =
-            t_reader.fetch_seq_string(t_name, t_start as usize, t_end as usize)?;
-        let mut whole_q_seq = q_reader.fetch_seq_string(
-            q_name,
-            chainrec.query_start() as usize,
-            (chainrec.query_end() - 1) as usize,
-        )?;
+// convert chains clipped to each of `regions` on target, chains are clipped as PAF records
+fn chain2maf_regions<R: Read + Send>(
+    chainreader: &mut ChainReader<R>,
+    writer: &mut dyn Write,
+    t_fa_path: &str,
+    q_fa_path: &str,
+    unknown_base: UnknownBase,
+    regions: &[GenomeRegion],
+) -> Result<(), WGAError> {
+    let t_reader = faidx::Reader::from_path(t_fa_path)?;
+    let q_reader = faidx::Reader::from_path(q_fa_path)?;
+    let header = format!(
+        "#maf version=1.6 convert_from=chain t_seq_path={} q_seq_path={}",
+        t_fa_path, q_fa_path
+    );
+    MAFWriter::new(&mut *writer).write_header(header)?;
 
-        // reverse complement the query sequence if it is on the negative strand
-        match q_strand {
-            Strand::Positive => {}
-            Strand::Negative => {
-                whole_q_seq = reverse_complement(&whole_q_seq)?;
-            }
-        }
-        // read chain dataline and insert the `-` to sequence
-        parse_chain_to_insert(&chainrec, &mut whole_t_seq, &mut whole_q_seq)?;
-        // get s-lines
-        let t_sline = MAFSLine {
-            mode: 's',
-            name: t_name.to_string(),
-            start: t_start,
-            align_size: t_alilen,
-            strand: t_strand,
-            size: t_size,
-            seq: whole_t_seq,
-        };
-        let q_sline = MAFSLine {
-            mode: 's',
-            name: q_name.to_string(),
-            start: q_start,
-            align_size: q_alilen,
-            strand: q_strand,
-            size: q_size,
-            seq: whole_q_seq,
-        };
-        // get maf record
-        let mafrec = MAFRecord {
-            score,
-            slines: vec![t_sline, q_sline],
-            query_idx: 1,
-        };
-        // write maf record
-        mafwtr.write_record(&mafrec)?;
-    }
-    Ok(())
+    let records = chainreader
+        .records()?
+        .flat_map(|chainrec| {
+            let pafrec = chainrec.and_then(|mut chainrec| chainrec.convert2paf(None));
+            clip_paf_to_regions(pafrec, regions)
+        })
+        .map(|pafrec| {
+            pafrec.and_then(|pafrec| {
+                let mafrec = paf2maf_ungapped(&pafrec, &t_reader, &q_reader, unknown_base)?;
+                Ok((pafrec, mafrec))
+            })
+        });
+    par_write_ordered(records, writer, |_, (pafrec, mut mafrec)| {
+        insert_paf_gaps(&pafrec, &mut mafrec)?;
+        let mut buf = Vec::new();
+        MAFWriter::new(&mut buf).write_record(&mafrec)?;
+        Ok(buf)
+    })
+}
+
+/// Build a MAF record of chain record with ungapped sequences fetched from indexed FASTA
+fn chain2maf_ungapped(
+    chainrec: &ChainRecord,
+    t_reader: &faidx::Reader,
+    q_reader: &faidx::Reader,
+    unknown_base: UnknownBase,
+) -> Result<MAFRecord, WGAError> {
+    // get target info
+    let t_name = chainrec.target_name();
+    let t_start = chainrec.target_start();
+    let t_end = chainrec.coord_sub(chainrec.target_end(), 1)?;
+    let t_alilen = chainrec.coord_sub(chainrec.target_end(), chainrec.target_start())?;
+    // get query info
+    let q_name = chainrec.query_name();
+    let q_strand = chainrec.query_strand();
+    let q_size = chainrec.query_length();
+    let q_alilen = chainrec.coord_sub(chainrec.query_end(), chainrec.query_start())?;
+    // NOTE: if negative strand, we should convert the start position
+    let q_start = match q_strand {
+        Strand::Positive => chainrec.query_start(),
+        Strand::Negative => chainrec.coord_sub(q_size, chainrec.query_end())?,
+    };
+
+    // get seqs from indexed fasta files
+    let (t_seq, q_seq) = profile(Stage::Fetch, || {
+        Ok::<_, WGAError>((
+            t_reader.fetch_seq_string(t_name, t_start as usize, t_end as usize)?,
+            q_reader.fetch_seq_string(
+                q_name,
+                chainrec.query_start() as usize,
+                chainrec.coord_sub(chainrec.query_end(), 1)? as usize,
+            )?,
+        ))
+    })?;
+
+    // handle unknown characters, and reverse complement the query sequence if it is on the negative strand
+    let t_seq = check_bases(t_seq, unknown_base)?;
+    let q_seq = match q_strand {
+        Strand::Positive => check_bases(q_seq, unknown_base)?,
+        Strand::Negative => reverse_complement_with(&q_seq, unknown_base)?,
+    };
+
+    let t_sline = MAFSLine {
+        mode: 's',
+        name: t_name.to_string(),
+        start: t_start,
+        align_size: t_alilen,
+        strand: chainrec.target_strand(),
+        size: chainrec.target_length(),
+        seq: t_seq,
+        qual: None,
+    };
+    let q_sline = MAFSLine {
+        mode: 's',
+        name: q_name.to_string(),
+        start: q_start,
+        align_size: q_alilen,
+        strand: q_strand,
+        size: q_size,
+        seq: q_seq,
+        qual: None,
+    };
+    // 255 as score
+    Ok(MAFRecord {
+        score: 255,
+        slines: vec![t_sline, q_sline],
+        query_idx: 1,
+        tags: Vec::new(),
+    })
 }
 
 /// Parse the Chain Data Lines to insert the `-` to sequence
@@ -364,8 +1246,8 @@ fn parse_chain_to_insert(
 ) -> Result<(), WGAError> {
     let mut current_offset = 0;
     for dataline in &rec.lines {
-        let ins_len = dataline.target_diff;
-        let del_len = dataline.query_diff;
+        let ins_len = dataline.query_gap;
+        let del_len = dataline.target_gap;
         current_offset += dataline.size;
         match ins_len {
             0 => {}
@@ -387,30 +1269,616 @@ fn parse_chain_to_insert(
     Ok(())
 }
 
-/// Convert a Chain Reader to output a PAF file
+/// Convert a Chain Reader to output a PAF file, `=`/`X` CIGAR needs sequences
+/// of target and query from indexed FASTA
 pub fn chain2paf<R: Read + Send>(
     chainreader: &mut ChainReader<R>,
     writer: &mut dyn Write,
+    sort_by: PafSortBy,
+    fa_paths: Option<(&str, &str)>,
 ) -> Result<(), WGAError> {
-    // init csv writer for deserializing
-    let mut wtr = csv::WriterBuilder::new()
-        .delimiter(b'\t')
-        .has_headers(false)
-        .from_writer(writer);
-
-    // multi-threading
-    let pafrecords = chainreader
-        .records()?
-        .par_bridge()
-        .map(|record| -> Result<_, WGAError> {
-            let mut chainrecord = record?;
-            chainrecord.convert2paf(None)
+    let readers = fa_paths
+        .map(|(t_fa_path, q_fa_path)| {
+            Ok::<_, WGAError>((
+                faidx::Reader::from_path(t_fa_path)?,
+                faidx::Reader::from_path(q_fa_path)?,
+            ))
         })
-        .collect::<Result<Vec<_>, WGAError>>()?;
-    // if we should sort pafrecords?
-    for pafrec in pafrecords {
+        .transpose()?;
+    // sequences are fetched in order while reading, CIGAR is split in parallel
+    let records = chainreader.records()?.map(|chainrec| {
+        let chainrec = chainrec?;
+        let mafrec = match &readers {
+            Some((t_reader, q_reader)) => Some(chain2maf_ungapped(
+                &chainrec,
+                t_reader,
+                q_reader,
+                UnknownBase::PassThrough,
+            )?),
+            None => None,
+        };
+        Ok::<_, WGAError>((chainrec, mafrec))
+    });
+    let convert = |mut chainrec: ChainRecord, mafrec: Option<MAFRecord>| {
+        let mut pafrec = chainrec.convert2paf(None)?;
+        if let Some(mafrec) = mafrec {
+            let seqs = (mafrec.target_seq(), mafrec.query_seq());
+            set_cigar_style(&mut pafrec, CigarStyle::Eqx, Some(seqs))?;
+        }
+        Ok::<_, WGAError>(pafrec)
+    };
+    if sort_by != PafSortBy::None {
+        // all records are collected to be sorted, fetched sequences are dropped chunk by chunk
+        let mut pafrecords =
+            par_collect_ordered(records, |_, (chainrec, mafrec)| convert(chainrec, mafrec))?;
+        pafrecords.par_sort_by(|a, b| cmp_paf(a, b, sort_by));
+        let mut wtr = paf_writer(writer);
+        for pafrec in pafrecords {
+            wtr.serialize(pafrec)?;
+        }
+        wtr.flush()?;
+        return Ok(());
+    }
+    // multi-threading, records are written in input order
+    par_write_ordered(records, writer, |_, (chainrec, mafrec)| {
+        let pafrec = convert(chainrec, mafrec)?;
+        let mut buf = Vec::new();
+        // init csv writer for deserializing
+        let mut wtr = paf_writer(&mut buf);
         wtr.serialize(pafrec)?;
+        wtr.flush()?;
+        drop(wtr);
+        Ok(buf)
+    })
+}
+
+/// Convert a Delta Reader to output a PAF file, CIGAR has `M` and `NM:i` of delta errors
+pub fn delta2paf<R: Read + Send>(
+    deltareader: &mut DeltaReader<R>,
+    writer: &mut dyn Write,
+    sort_by: PafSortBy,
+) -> Result<(), WGAError> {
+    let mut wtr = paf_writer(writer);
+    let records = deltareader
+        .records()
+        .map(|deltarec| deltarec?.convert2paf(None));
+    if sort_by != PafSortBy::None {
+        // all records are collected to be sorted
+        let mut pafrecords = records.collect::<Result<Vec<_>, WGAError>>()?;
+        pafrecords.par_sort_by(|a, b| cmp_paf(a, b, sort_by));
+        for pafrec in pafrecords {
+            wtr.serialize(pafrec)?;
+        }
+    } else {
+        for pafrec in records {
+            wtr.serialize(pafrec?)?;
+        }
     }
     wtr.flush()?;
     Ok(())
 }
+
+/// Convert a Delta Reader to output a MAF file, FASTA paths in the delta header
+/// are used if not given
+pub fn delta2maf<R: Read + Send>(
+    deltareader: &mut DeltaReader<R>,
+    writer: &mut dyn Write,
+    t_fa_path: Option<&str>,
+    q_fa_path: Option<&str>,
+    unknown_base: UnknownBase,
+) -> Result<(), WGAError> {
+    let t_fa_path = t_fa_path.unwrap_or(&deltareader.target_path).to_string();
+    let q_fa_path = q_fa_path.unwrap_or(&deltareader.query_path).to_string();
+    // get the target and query fasta reader
+    let t_reader = faidx::Reader::from_path(&t_fa_path)?;
+    let q_reader = faidx::Reader::from_path(&q_fa_path)?;
+
+    // write header
+    let header = format!(
+        "#maf version=1.6 convert_from=delta t_seq_path={} q_seq_path={}",
+        t_fa_path, q_fa_path
+    );
+    MAFWriter::new(&mut *writer).write_header(header)?;
+
+    // sequences are fetched in order while reading, gaps are inserted in parallel
+    let records = deltareader.records().map(|deltarec| {
+        let pafrec = deltarec?.convert2paf(None)?;
+        let mafrec = paf2maf_ungapped(&pafrec, &t_reader, &q_reader, unknown_base)?;
+        Ok::<_, WGAError>((pafrec, mafrec))
+    });
+    par_write_ordered(records, writer, |_, (pafrec, mut mafrec)| {
+        insert_paf_gaps(&pafrec, &mut mafrec)?;
+        let mut buf = Vec::new();
+        MAFWriter::new(&mut buf).write_record(&mafrec)?;
+        Ok(buf)
+    })
+}
+
+/// Convert an AXT Reader to output a MAF file, sizes of sequences are not in AXT
+pub fn axt2maf<R: Read + Send>(
+    axtreader: &mut AxtReader<R>,
+    writer: &mut dyn Write,
+    t_sizes: &HashMap<String, u64>,
+    q_sizes: &HashMap<String, u64>,
+    in_flight: usize,
+) -> Result<(), WGAError> {
+    MAFWriter::new(&mut *writer).write_header("#maf version=1.6 convert_from=axt".to_string())?;
+    // multi-threading, records are written in input order
+    par_write_bounded(axtreader.records(), writer, in_flight, |_, axtrec| {
+        let mafrec = axtrec.to_maf(t_sizes, q_sizes)?;
+        let mut buf = Vec::new();
+        MAFWriter::new(&mut buf).write_record(&mafrec)?;
+        Ok(buf)
+    })
+}
+
+/// Convert an AXT Reader to output a PAF file through MAF blocks
+pub fn axt2paf<R: Read + Send>(
+    axtreader: &mut AxtReader<R>,
+    writer: &mut dyn Write,
+    t_sizes: &HashMap<String, u64>,
+    q_sizes: &HashMap<String, u64>,
+    sort_by: PafSortBy,
+    cigar_style: CigarStyle,
+    in_flight: usize,
+) -> Result<(), WGAError> {
+    let convert = |axtrec: AxtRecord| {
+        let mut mafrec = axtrec.to_maf(t_sizes, q_sizes)?;
+        let mut pafrec = mafrec.convert2paf(None)?;
+        if cigar_style == CigarStyle::M {
+            set_cigar_style(&mut pafrec, cigar_style, None)?;
+        }
+        Ok::<_, WGAError>(pafrec)
+    };
+    if sort_by != PafSortBy::None {
+        // all records are collected to be sorted
+        let mut pafrecords = par_collect_ordered(axtreader.records(), |_, axtrec| convert(axtrec))?;
+        pafrecords.par_sort_by(|a, b| cmp_paf(a, b, sort_by));
+        let mut wtr = paf_writer(writer);
+        for pafrec in pafrecords {
+            wtr.serialize(pafrec)?;
+        }
+        wtr.flush()?;
+        return Ok(());
+    }
+    // multi-threading, records are written in input order
+    par_write_bounded(axtreader.records(), writer, in_flight, |_, axtrec| {
+        let pafrec = convert(axtrec)?;
+        let mut buf = Vec::new();
+        // init csv writer for deserializing
+        let mut wtr = paf_writer(&mut buf);
+        wtr.serialize(pafrec)?;
+        wtr.flush()?;
+        drop(wtr);
+        Ok(buf)
+    })
+}
+
+/// Rewrite CIGAR of a PAF record in the style, `M` is split into `=` and `X` by
+/// ungapped `seqs` of target and query, then matches and `NM:i` tag are updated
+fn set_cigar_style(
+    pafrec: &mut PafRecord,
+    cigar_style: CigarStyle,
+    seqs: Option<(&str, &str)>,
+) -> Result<(), WGAError> {
+    let Some(idx) = pafrec.tags.iter().position(|x| x.starts_with("cg:Z:")) else {
+        return Ok(());
+    };
+    let cigar = &pafrec.tags[idx]["cg:Z:".len()..];
+    let cigar = match (cigar_style, seqs) {
+        (CigarStyle::M, _) => cigar_eqx_to_m(cigar)?,
+        (CigarStyle::Eqx, Some((t_seq, q_seq))) => {
+            cigar_m_to_eqx(cigar, t_seq.as_bytes(), q_seq.as_bytes())?
+        }
+        (CigarStyle::Eqx, None) => return Ok(()),
+    };
+    pafrec.tags[idx] = format!("cg:Z:{}", cigar);
+    if cigar_style == CigarStyle::Eqx {
+        let stat = parse_paf_to_cigar(pafrec, None)?;
+        let edit_dist = stat.mismatch_count
+            + stat.ins_count
+            + stat.del_count
+            + stat.inv_ins_count
+            + stat.inv_del_count;
+        pafrec.matches = stat.match_count as u64;
+        pafrec.tags.retain(|x| !x.starts_with("NM:i:"));
+        pafrec.tags.insert(0, format!("NM:i:{}", edit_dist));
+    }
+    Ok(())
+}
+
+/// Rewrite a Chain file, chains are split at gaps longer than `split` if set.
+/// The first piece keeps the chain id and others get new ids after the largest one
+pub fn chain2chain<R: Read + Send>(
+    chainreader: &mut ChainReader<R>,
+    writer: &mut dyn Write,
+    split: Option<u64>,
+) -> Result<(), WGAError> {
+    let records = chainreader
+        .records()?
+        .collect::<Result<Vec<ChainRecord>, WGAError>>()?;
+    let mut next_id = records
+        .iter()
+        .map(|rec| rec.header.chain_id)
+        .max()
+        .unwrap_or(0)
+        + 1;
+    for rec in records {
+        let pieces = match split {
+            Some(max_gap) => rec.split_at_gaps(max_gap),
+            None => vec![rec],
+        };
+        for (i, mut piece) in pieces.into_iter().enumerate() {
+            if i > 0 {
+                piece.header.chain_id = next_id;
+                next_id += 1;
+            }
+            piece.write_to(writer)?;
+        }
+    }
+    Ok(())
+}
+
+/// autoSql schema of bigChain, refer to https://genome.ucsc.edu/goldenPath/help/bigChain.html
+pub const BIGCHAIN_AS: &str = r#"table bigChain
+"bigChain pairwise alignment"
+    (
+    string chrom;       "Reference sequence chromosome or scaffold"
+    uint   chromStart;  "Start position in chromosome"
+    uint   chromEnd;    "End position in chromosome"
+    string name;        "Name or ID of item, ideally both human readable and unique"
+    uint score;         "Score (0-1000)"
+    char[1] strand;     "+ or - for strand"
+    uint tSize;         "size of target sequence"
+    string qName;       "name of query sequence"
+    uint qSize;         "size of query sequence"
+    uint qStart;        "start of alignment on query sequence"
+    uint qEnd;          "end of alignment on query sequence"
+    float chainScore;   "score from chain"
+    )
+"#;
+
+/// autoSql schema of the link table of bigChain
+pub const BIGLINK_AS: &str = r#"table bigLink
+"bigLink pairwise alignment"
+    (
+    string chrom;       "Reference sequence chromosome or scaffold"
+    uint   chromStart;  "Start in chromosome"
+    uint   chromEnd;    "End in chromosome"
+    string name;        "Name or ID of item, ideally both human readable and unique"
+    uint qStart;        "start of alignment on query sequence"
+    )
+"#;
+
+/// autoSql schema of bigMaf, refer to https://genome.ucsc.edu/goldenPath/help/bigMaf.html
+pub const BIGMAF_AS: &str = r#"table bedMaf
+"Bed3 with MAF block"
+    (
+    string chrom;      "Reference sequence chromosome or scaffold"
+    uint   chromStart; "Start position in chromosome"
+    uint   chromEnd;   "End position in chromosome"
+    lstring mafBlock;   "MAF block"
+    )
+"#;
+
+/// Convert a Chain Reader to bigChain and its link table as input of `bedToBigBed`,
+/// lines are sorted by target name and start
+pub fn chain2bigchain<R: Read + Send>(
+    chainreader: &mut ChainReader<R>,
+    writer: &mut dyn Write,
+    link_writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    let mut chains = Vec::new();
+    let mut links = Vec::new();
+    for chainrec in chainreader.records()? {
+        let chainrec = chainrec?;
+        let id = chainrec.header.chain_id;
+        let t_name = chainrec.target_name();
+        chains.push((
+            t_name.to_string(),
+            chainrec.target_start(),
+            format!(
+                "{}\t{}\t{}\t{}\t1000\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                t_name,
+                chainrec.target_start(),
+                chainrec.target_end(),
+                id,
+                chainrec.query_strand(),
+                chainrec.target_length(),
+                chainrec.query_name(),
+                chainrec.query_length(),
+                chainrec.query_start(),
+                chainrec.query_end(),
+                chainrec.header.score(),
+            ),
+        ));
+        // each ungapped block is a link, query coordinates are on its strand as chain
+        let (mut t_pos, mut q_pos) = (chainrec.target_start(), chainrec.query_start());
+        for dataline in &chainrec.lines {
+            if dataline.size > 0 {
+                links.push((
+                    t_name.to_string(),
+                    t_pos,
+                    format!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        t_name,
+                        t_pos,
+                        t_pos + dataline.size,
+                        id,
+                        q_pos
+                    ),
+                ));
+            }
+            t_pos += dataline.size + dataline.target_gap;
+            q_pos += dataline.size + dataline.query_gap;
+        }
+    }
+    write_sorted_bed(chains, writer)?;
+    write_sorted_bed(links, link_writer)
+}
+
+/// Convert a MAF Reader to bigMaf as input of `bedToBigBed`, lines of each block are joined by `;`,
+/// and `ref_db.` is stripped from target name like `mafToBigMaf`
+pub fn maf2bigmaf<R: Read + Send>(
+    mafreader: &mut MAFReader<R>,
+    writer: &mut dyn Write,
+    ref_db: Option<&str>,
+) -> Result<(), WGAError> {
+    let prefix = ref_db.map(|db| format!("{}.", db));
+    let mut lines = Vec::new();
+    for record in mafreader.records() {
+        let record = record?;
+        let t_name = record.target_name();
+        let chrom = match &prefix {
+            Some(prefix) => t_name.strip_prefix(prefix.as_str()).unwrap_or(t_name),
+            None => t_name,
+        };
+        // fields are split by space, since tab separates BED columns
+        let mut line = format!(
+            "{}\t{}\t{}\ta score={}",
+            chrom,
+            record.target_start(),
+            record.target_end(),
+            record.score
+        );
+        for sline in &record.slines {
+            line.push_str(&format!(
+                ";s {} {} {} {} {} {}",
+                sline.name, sline.start, sline.align_size, sline.strand, sline.size, sline.seq
+            ));
+        }
+        line.push(';');
+        lines.push((chrom.to_string(), record.target_start(), line));
+    }
+    write_sorted_bed(lines, writer)
+}
+
+// sort lines by chrom and start as `sort -k1,1 -k2,2n` for `bedToBigBed`
+fn write_sorted_bed(
+    mut lines: Vec<(String, u64, String)>,
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    lines.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+    for (_, _, line) in lines {
+        writeln!(writer, "{}", line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{read_seq_sizes, reverse_complement, ORDERED_IN_FLIGHT};
+    use std::collections::BTreeMap;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/test/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn maf_to_paf(maf: &[u8]) -> Result<String, WGAError> {
+        let mut out = Vec::new();
+        maf2paf(
+            &mut MAFReader::new(maf)?,
+            &mut out,
+            None,
+            true,
+            false,
+            PafSortBy::None,
+            None,
+            CigarStyle::Eqx,
+            ORDERED_IN_FLIGHT,
+        )?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    // FASTA files in `dir` of random targets and queries agreeing with `=`/`X` of records
+    fn write_paf_fasta(paf: &str, dir: &Path) -> (String, String) {
+        let mut seed = 7u64;
+        let mut base = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            ['A', 'C', 'G', 'T'][(seed >> 62) as usize]
+        };
+        let records = PAFReader::new(paf.as_bytes())
+            .records()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let mut targets = BTreeMap::new();
+        let mut queries = BTreeMap::new();
+        for rec in &records {
+            targets
+                .entry(rec.target_name.clone())
+                .or_insert_with(|| (0..rec.target_length).map(|_| base()).collect::<Vec<_>>());
+            queries
+                .entry(rec.query_name.clone())
+                .or_insert_with(|| (0..rec.query_length).map(|_| base()).collect::<Vec<_>>());
+        }
+        for rec in &records {
+            let target = &targets[&rec.target_name];
+            let mut t_pos = rec.target_start as usize;
+            let mut q_seq = String::new();
+            for (op, len) in parse_cigar_to_units(rec).unwrap() {
+                for _ in 0..len {
+                    match op {
+                        'M' | '=' => q_seq.push(target[t_pos]),
+                        'X' => q_seq.push(match target[t_pos] {
+                            'A' => 'C',
+                            'C' => 'G',
+                            'G' => 'T',
+                            _ => 'A',
+                        }),
+                        'I' => q_seq.push(base()),
+                        _ => {}
+                    }
+                    if op != 'I' {
+                        t_pos += 1;
+                    }
+                }
+            }
+            if rec.strand == Strand::Negative {
+                q_seq = reverse_complement(&q_seq).unwrap();
+            }
+            let range = rec.query_start as usize..rec.query_end as usize;
+            queries.get_mut(&rec.query_name).unwrap()[range]
+                .iter_mut()
+                .zip(q_seq.chars())
+                .for_each(|(q, b)| *q = b);
+        }
+        let write_fasta = |name: &str, seqs: &BTreeMap<String, Vec<char>>| {
+            let path = dir.join(name);
+            let content = seqs
+                .iter()
+                .map(|(name, seq)| format!(">{}\n{}\n", name, seq.iter().collect::<String>()))
+                .collect::<String>();
+            std::fs::write(&path, content).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        (
+            write_fasta("target.fa", &targets),
+            write_fasta("query.fa", &queries),
+        )
+    }
+
+    #[test]
+    fn verify_maf_fixtures() {
+        for name in ["test.maf", "maf/lines.maf", "maf/lines_crlf.maf"] {
+            let maf = std::fs::read(fixture(name)).unwrap();
+            let paf = maf_to_paf(&maf).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert!(!paf.is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn verify_detects_changed_fields() {
+        let maf = std::fs::read(fixture("test.maf")).unwrap();
+        let mut mafrec = MAFReader::new(&maf[..])
+            .unwrap()
+            .records()
+            .next()
+            .unwrap()
+            .unwrap();
+        let mut pafrec = mafrec.convert2paf(None).unwrap();
+        assert!(verify_maf2paf(&mafrec, &pafrec).unwrap());
+        pafrec.query_start += 1;
+        assert!(!verify_maf2paf(&mafrec, &pafrec).unwrap());
+        pafrec.query_start -= 1;
+        mafrec.slines[0].start += 1;
+        assert!(!verify_maf2paf(&mafrec, &pafrec).unwrap());
+    }
+
+    #[test]
+    fn round_trip_paf_fixtures() {
+        let names = [
+            "testdotplot.paf",
+            "paf/wfmash.paf",
+            "paf/minimap2.paf",
+            "paf/anchorwave.paf",
+        ];
+        for name in names {
+            let dir = std::env::temp_dir().join(format!(
+                "wgatools-verify-{}-{}",
+                std::process::id(),
+                name.replace('/', "-")
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            let paf = std::fs::read_to_string(fixture(name)).unwrap();
+            let (t_fa, q_fa) = write_paf_fasta(&paf, &dir);
+            let mut maf = Vec::new();
+            paf2maf(
+                &mut PAFReader::new(paf.as_bytes()),
+                &mut maf,
+                &t_fa,
+                &q_fa,
+                true,
+                UnknownBase::Error,
+                &[],
+                &[],
+            )
+            .unwrap_or_else(|e| panic!("{}: {}", name, e));
+            let back = maf_to_paf(&maf).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            // names, lengths, coordinates and strand are kept
+            let coords = |text: &str| {
+                text.lines()
+                    .map(|line| line.split('\t').take(9).collect::<Vec<_>>().join("\t"))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(coords(&paf), coords(&back), "{}", name);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    fn axt_sizes() -> (HashMap<String, u64>, HashMap<String, u64>) {
+        (
+            read_seq_sizes(&fixture("axt/target.sizes")).unwrap(),
+            read_seq_sizes(&fixture("axt/query.sizes")).unwrap(),
+        )
+    }
+
+    #[test]
+    fn axt2maf_skips_comments_and_blank_lines() {
+        let (t_sizes, q_sizes) = axt_sizes();
+        let mut axtrdr = AxtReader::from_path(fixture("axt/small.axt")).unwrap();
+        let mut out = Vec::new();
+        axt2maf(&mut axtrdr, &mut out, &t_sizes, &q_sizes, 1).unwrap();
+        let mut mafrdr = MAFReader::new(&out[..]).unwrap();
+        assert!(mafrdr.header.starts_with("#maf"));
+        let records = mafrdr.records().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(records.len(), 2);
+        let (target, query) = (&records[0].slines[0], &records[0].slines[1]);
+        assert_eq!((target.start, target.align_size, target.size), (10, 10, 100));
+        assert_eq!((query.start, query.align_size, query.strand), (0, 10, Strand::Positive));
+        assert_eq!(query.seq, "ACGTAGCGTA-");
+        // query of `-` strand is kept on reverse-complemented query
+        let query = &records[1].slines[1];
+        assert_eq!((query.start, query.align_size, query.size), (4, 4, 50));
+        assert_eq!(query.strand, Strand::Negative);
+    }
+
+    #[test]
+    fn axt2paf_in_input_order() {
+        let (t_sizes, q_sizes) = axt_sizes();
+        let mut axtrdr = AxtReader::from_path(fixture("axt/small.axt")).unwrap();
+        let mut out = Vec::new();
+        axt2paf(
+            &mut axtrdr,
+            &mut out,
+            &t_sizes,
+            &q_sizes,
+            PafSortBy::None,
+            CigarStyle::Eqx,
+            1,
+        )
+        .unwrap();
+        let coords = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| line.split('\t').take(9).collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            coords,
+            vec![
+                "q1 50 0 10 + chr1 100 10 20",
+                "q1 50 42 46 - chr1 100 30 36"
+            ]
+        );
+    }
+}
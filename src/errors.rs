@@ -1,6 +1,7 @@
 //! The error kinds when process whole genome alignments(wga)
 
-use crate::tools::mafextra::GenomeRegion;
+use crate::parser::region::GenomeRegion;
+use std::fmt;
 use thiserror::Error;
 
 // define Error types
@@ -30,6 +31,8 @@ pub enum WGAError {
     ThreadPoolBuildError(#[from] rayon::ThreadPoolBuildError),
     #[error("Empty record")]
     EmptyRecord,
+    #[error("No records in `{0}`, it is empty or has only header lines")]
+    EmptyInput(String),
     #[error("regions or region_file must be specified")]
     EmptyRegion,
     #[error("Stdin not allowed here")]
@@ -74,23 +77,194 @@ pub enum WGAError {
     InvalidBase(String),
     #[error("Ah-oh! NOT IMPLEMENTED :(")]
     NotImplemented,
-    #[error("S-line count not match")]
-    SLineCountNotMatch,
+    #[error("S-line count not match, {0} prefixes given but {1} s-lines found: {2}")]
+    SLineCountNotMatch(usize, usize, String),
+    #[error("Sequence length not match, s-line `{0}` has {1} columns but `{2}` has {3}")]
+    SeqLenNotMatch(String, usize, String, usize),
     #[error("Jinja2 Error: {0}, please contact the author")]
     Jinja2Error(#[from] minijinja::Error),
     #[error("Query name:{0} not found in MAF")]
     QueryNameNotFound(String),
+    #[error("Failed to detect input format, expected {0}, please specify it by `--format`")]
+    FormatDetect(String),
+    #[error("Input format is detected as {0}, but expected {1}")]
+    FormatNotExpected(String, String),
+    #[error("{0} records failed round-trip verification")]
+    VerifyFailed(usize),
+    #[error("{0} of {1} records failed validation")]
+    ValidateFailed(usize, usize),
+    #[error("Parse config `{0}` error by: {1}")]
+    ParseConfig(String, String),
+    #[error("Invalid substitution `{0}`, expected `s/pattern/replacement/[g]`")]
+    InvalidSubstitution(String),
+    #[error("Coordinate out of range: `{0}` in record {1}")]
+    CoordOutOfRange(String, String),
+    #[error(
+        "MAF index `{0}` is stale: {1}, please rebuild it by `maf-index` or add `--rebuild-index`"
+    )]
+    StaleIndex(String, String),
+    #[error("Parse PAF error by: {0}")]
+    ParsePaf(String),
+    // invalid value or combination of options
+    #[error("{0}")]
+    InvalidArgs(String),
+    // record of input not in the expected format
+    #[error("{0}")]
+    InvalidRecord(String),
+    // error with the position of record where it occurs
+    #[error("{0} at {1}")]
+    Context(Box<WGAError>, ParseContext),
     // Other error
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
+/// Exit codes by error class, so workflow managers could tell transient errors from fatal ones
+pub const EXIT_OTHER: i32 = 1;
+pub const EXIT_IO: i32 = 2;
+pub const EXIT_PARSE: i32 = 3;
+pub const EXIT_VALIDATION: i32 = 4;
+pub const EXIT_USAGE: i32 = 5;
+
+impl WGAError {
+    /// Exit code of the error class, see `EXIT_*`
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            WGAError::Context(inner, _) => inner.exit_code(),
+            WGAError::Io(_)
+            | WGAError::FileNotExist(_)
+            | WGAError::NotDir(_)
+            | WGAError::HtsLibError(_) => EXIT_IO,
+            WGAError::ParseMaf(_)
+            | WGAError::CsvDeserialize(_)
+            | WGAError::SerdeDeserialize(_)
+            | WGAError::EmptyRecord
+            | WGAError::DuplicateName(_)
+            | WGAError::NomErr(_)
+            | WGAError::ParseChain(_)
+            | WGAError::ParseStrand(_)
+            | WGAError::ParseIntError(_)
+            | WGAError::ParseFloatError(_)
+            | WGAError::CigarTagNotFound
+            | WGAError::CigarOpInvalid(_)
+            | WGAError::NoodlesSamParseError(_)
+            | WGAError::TryIntoNum(_)
+            | WGAError::ReadNameParseError(_)
+            | WGAError::InvalidBase(_)
+            | WGAError::FormatDetect(_)
+            | WGAError::FormatNotExpected(_, _)
+            | WGAError::CoordOutOfRange(_, _)
+            | WGAError::ParsePaf(_)
+            | WGAError::SeqLenNotMatch(_, _, _, _)
+            | WGAError::InvalidRecord(_) => EXIT_PARSE,
+            WGAError::EmptyInput(_)
+            | WGAError::VerifyFailed(_)
+            | WGAError::ValidateFailed(_, _)
+            | WGAError::StaleIndex(_, _) => EXIT_VALIDATION,
+            WGAError::EmptyStdin
+            | WGAError::FileReWrite(_)
+            | WGAError::EmptyRegion
+            | WGAError::StdinNotAllowed
+            | WGAError::StdoutNotAllowed
+            | WGAError::ParseGenomeRegion(_)
+            | WGAError::FailedRegion(_)
+            | WGAError::UnexceptedRegexError(_)
+            | WGAError::RegexBuildError(_)
+            | WGAError::SLineCountNotMatch(_, _, _)
+            | WGAError::QueryNameNotFound(_)
+            | WGAError::ParseConfig(_, _)
+            | WGAError::InvalidSubstitution(_)
+            | WGAError::InvalidArgs(_) => EXIT_USAGE,
+            WGAError::ThreadPoolBuildError(_)
+            | WGAError::NotImplemented
+            | WGAError::Jinja2Error(_)
+            | WGAError::Other(_) => EXIT_OTHER,
+        }
+    }
+
+    /// Attach the position where the error occurs, fields already known are kept
+    pub fn with_context(self, ctx: ParseContext) -> Self {
+        match self {
+            WGAError::Context(inner, known) => WGAError::Context(inner, known.or(ctx)),
+            e => WGAError::Context(Box::new(e), ctx),
+        }
+    }
+
+    /// Fill the file name of an error with position if it is unknown
+    pub fn fill_file(self, file: &str) -> Self {
+        match self {
+            WGAError::Context(inner, known) => WGAError::Context(
+                inner,
+                known.or(ParseContext {
+                    file: Some(file.to_string()),
+                    ..Default::default()
+                }),
+            ),
+            e => e,
+        }
+    }
+}
+
+/// Position of a record in input, unknown fields are left out of error messages
+#[derive(Debug, Default, Clone)]
+pub struct ParseContext {
+    pub file: Option<String>,
+    /// 1-based ordinal of the record
+    pub record: Option<u64>,
+    /// 1-based line number
+    pub line: Option<u64>,
+    /// byte offset of the line
+    pub offset: Option<u64>,
+    /// sequence names or coordinates of the record
+    pub seqs: Option<String>,
+}
+
+impl ParseContext {
+    // fill unknown fields from `other`
+    fn or(self, other: ParseContext) -> ParseContext {
+        ParseContext {
+            file: self.file.or(other.file),
+            record: self.record.or(other.record),
+            line: self.line.or(other.line),
+            offset: self.offset.or(other.offset),
+            seqs: self.seqs.or(other.seqs),
+        }
+    }
+}
+
+impl fmt::Display for ParseContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(file) = &self.file {
+            parts.push(format!("file `{}`", file));
+        }
+        if let Some(record) = self.record {
+            parts.push(format!("record {}", record));
+        }
+        if let Some(line) = self.line {
+            parts.push(format!("line {}", line));
+        }
+        if let Some(offset) = self.offset {
+            parts.push(format!("byte {}", offset));
+        }
+        if let Some(seqs) = &self.seqs {
+            parts.push(seqs.to_string());
+        }
+        match parts.is_empty() {
+            true => write!(f, "unknown position"),
+            false => write!(f, "{}", parts.join(", ")),
+        }
+    }
+}
+
 impl From<nom::Err<nom::error::Error<&str>>> for WGAError {
     fn from(value: nom::Err<nom::error::Error<&str>>) -> Self {
         match value {
-            nom::Err::Error(e) => {
-                WGAError::NomErr(nom::error::Error::new(e.input[..10].to_string(), e.code))
-            }
+            // at most 10 chars of the failing input, which may be shorter
+            nom::Err::Error(e) => WGAError::NomErr(nom::error::Error::new(
+                e.input.chars().take(10).collect(),
+                e.code,
+            )),
             _ => WGAError::Other(anyhow::anyhow!("Other nom Error")),
         }
     }
@@ -103,6 +277,8 @@ pub enum ParseMafErrKind {
     FiledMissing(String),
     #[error("Surplus Filed > 7")]
     SurplusField,
+    #[error("S-line `{0}` start {1} + size {2} exceeds src size {3}")]
+    CoordExceedSize(String, u64, u64, u64),
 }
 
 #[derive(Error, Debug)]
@@ -113,7 +289,7 @@ pub enum ParseChainErrKind {
 
 #[derive(Error, Debug)]
 pub enum ParseGenomeRegionErrKind {
-    #[error("Region `{0}` is match the format of `chr:start-end`")]
+    #[error("Region `{0}` does not match the format of `chr`, `chr:start-end` or `chr:start-`")]
     FormatNotMatch(String),
     #[error("Start `{0}` is larger than end `{1}`")]
     StartGTEnd(u64, u64),
@@ -1,22 +1,168 @@
-use log::LevelFilter;
+use crate::errors::WGAError;
+use chrono::Local;
+use clap::ValueEnum;
+use log::{warn, LevelFilter, Record};
 use log4rs::{
     append::console::{ConsoleAppender, Target},
-    config::{Appender, Config, Root},
-    encode::pattern::PatternEncoder,
+    config::{Appender, Config, Logger, Root},
+    encode::{pattern::PatternEncoder, Encode},
     filter::threshold::ThresholdFilter,
 };
+use serde_json::json;
+use std::cell::Cell;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
 
-pub fn init_logger(verbose: u8) {
+// records read by MAF/PAF/Chain readers and dropped by tools, for the final summary
+static RECORDS_PROCESSED: AtomicU64 = AtomicU64::new(0);
+static RECORDS_SKIPPED: AtomicU64 = AtomicU64::new(0);
+// records read from each source, None for readers without source, i.e. the input of command
+static SOURCE_RECORDS: Mutex<Vec<(Option<String>, u64)>> = Mutex::new(Vec::new());
+static LOG_CONTEXT: OnceLock<LogContext> = OnceLock::new();
+// input without records is an error with --strict
+static STRICT: AtomicBool = AtomicBool::new(false);
+// malformed records are skipped with a warning with --skip-bad-records
+static SKIP_BAD_RECORDS: AtomicBool = AtomicBool::new(false);
+// per-stage timing, only collected with --profile
+static PROFILE: AtomicBool = AtomicBool::new(false);
+static STAGE_NANOS: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+static STAGE_CALLS: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+
+/// Stages timed by `--profile`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Parse,
+    Cigar,
+    Fetch,
+    Call,
+    Write,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [
+        Stage::Parse,
+        Stage::Cigar,
+        Stage::Fetch,
+        Stage::Call,
+        Stage::Write,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Stage::Parse => "parse",
+            Stage::Cigar => "cigar",
+            Stage::Fetch => "fetch",
+            Stage::Call => "call",
+            Stage::Write => "write",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, with a final summary object
+    Json,
+}
+
+/// Categories of warnings, each can be suppressed by `--suppress-warnings`
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum WarnCategory {
+    /// Existing output file or dir is rewritten
+    Overwrite,
+    /// MAF index is missing, stale or inconsistent
+    Index,
+    /// Options ignored or not fit for the input
+    Args,
+    /// Input without records
+    Empty,
+    /// Malformed records skipped by `--skip-bad-records`
+    BadRecord,
+    /// Input handled anyway, e.g. dropped tags, sequences not in FASTA or REF mismatches
+    Input,
+    /// Partial results, e.g. regions or positions not found
+    Result,
+}
+
+impl WarnCategory {
+    /// Log target of warnings in the category, e.g. `warn!(target: WarnCategory::Index.target(), ...)`
+    pub fn target(self) -> &'static str {
+        match self {
+            WarnCategory::Overwrite => "overwrite",
+            WarnCategory::Index => "index",
+            WarnCategory::Args => "args",
+            WarnCategory::Empty => "empty",
+            WarnCategory::BadRecord => "bad-record",
+            WarnCategory::Input => "input",
+            WarnCategory::Result => "result",
+        }
+    }
+}
+
+/// Fields added to each JSON log line
+#[derive(Debug, Clone)]
+struct LogContext {
+    format: LogFormat,
+    subcommand: String,
+    input: Option<String>,
+    start: Instant,
+}
+
+/// Encode a log record as a JSON line
+#[derive(Debug)]
+struct JsonEncoder {
+    context: LogContext,
+}
+
+impl Encode for JsonEncoder {
+    fn encode(&self, w: &mut dyn log4rs::encode::Write, record: &Record) -> anyhow::Result<()> {
+        let line = json!({
+            "timestamp": Local::now().to_rfc3339(),
+            "level": record.level().as_str(),
+            "message": record.args().to_string(),
+            "subcommand": self.context.subcommand,
+            "input": self.context.input,
+        });
+        writeln!(w, "{}", line)?;
+        Ok(())
+    }
+}
+
+/// Init stderr logger, only errors are logged if `quiet`,
+/// warnings of `suppressed` categories are dropped
+pub fn init_logger(
+    verbose: u8,
+    quiet: bool,
+    suppressed: &[WarnCategory],
+    format: LogFormat,
+    subcommand: &str,
+    input: Option<&str>,
+) {
     let log_level = match verbose {
+        _ if quiet => LevelFilter::Error,
         0 => LevelFilter::Warn,
         1 => LevelFilter::Info,
         2 => LevelFilter::Debug,
         _ => LevelFilter::Trace,
     };
+    let context = LOG_CONTEXT.get_or_init(|| LogContext {
+        format,
+        subcommand: subcommand.to_string(),
+        input: input.map(|s| s.to_string()),
+        start: Instant::now(),
+    });
+    let encoder: Box<dyn Encode> = match format {
+        LogFormat::Text => Box::new(PatternEncoder::new("{d} {h({l})} {m}{n}")),
+        LogFormat::Json => Box::new(JsonEncoder {
+            context: context.clone(),
+        }),
+    };
     // Build a stderr logger.
     let log_stderr = ConsoleAppender::builder()
         .target(Target::Stderr)
-        .encoder(Box::new(PatternEncoder::new("{d} {h({l})} {m}{n}")))
+        .encoder(encoder)
         .build();
     let log_config = Config::builder()
         .appender(
@@ -24,8 +170,256 @@ pub fn init_logger(verbose: u8) {
                 .filter(Box::new(ThresholdFilter::new(log_level)))
                 .build("stderr", Box::new(log_stderr)),
         )
+        .loggers(
+            suppressed
+                .iter()
+                .map(|category| Logger::builder().build(category.target(), LevelFilter::Error)),
+        )
         .build(Root::builder().appender("stderr").build(log_level))
         .unwrap();
     // init logger using config
     log4rs::init_config(log_config).unwrap();
 }
+
+// records counted by a reader before adding them to `RECORDS_PROCESSED`
+const RECORD_COUNT_BATCH: u64 = 4096;
+
+/// Records read from input by one reader iterator, added to the summary count and
+/// the count of its source in batches and when dropped, so parallel readers do not contend on it
+pub struct RecordCounter {
+    pending: u64,
+    source: Option<String>,
+}
+
+impl RecordCounter {
+    /// Counter of records read from `source`, None for the input of command.
+    /// The source is registered at once, so a source without records is known
+    pub fn new(source: Option<&str>) -> Self {
+        let source = source.map(str::to_string);
+        let mut sources = SOURCE_RECORDS.lock().unwrap_or_else(|e| e.into_inner());
+        if !sources.iter().any(|(name, _)| *name == source) {
+            sources.push((source.clone(), 0));
+        }
+        RecordCounter { pending: 0, source }
+    }
+
+    /// count a record read from input
+    pub fn add(&mut self) {
+        self.pending += 1;
+        if self.pending >= RECORD_COUNT_BATCH {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.pending == 0 {
+            return;
+        }
+        RECORDS_PROCESSED.fetch_add(self.pending, Ordering::Relaxed);
+        let mut sources = SOURCE_RECORDS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((_, count)) = sources.iter_mut().find(|(name, _)| *name == self.source) {
+            *count += self.pending;
+        }
+        self.pending = 0;
+    }
+}
+
+impl Drop for RecordCounter {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Sources read without any record in order, readers without source count for `input`,
+/// which is also empty if no record is read at all
+pub fn empty_sources(input: &str) -> Vec<String> {
+    let sources = SOURCE_RECORDS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut counts: Vec<(&str, u64)> = Vec::new();
+    for (source, count) in sources.iter() {
+        let name = source.as_deref().unwrap_or(input);
+        match counts.iter_mut().find(|(seen, _)| *seen == name) {
+            Some((_, seen_count)) => *seen_count += count,
+            None => counts.push((name, *count)),
+        }
+    }
+    if counts.is_empty() && records_processed() == 0 {
+        return vec![input.to_string()];
+    }
+    counts
+        .into_iter()
+        .filter(|(_, count)| *count == 0)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// count a record dropped by tools, such as filter
+pub fn record_skipped() {
+    RECORDS_SKIPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// number of records read from input
+pub fn records_processed() -> u64 {
+    RECORDS_PROCESSED.load(Ordering::Relaxed)
+}
+
+/// turn input without records into an error
+pub fn enable_strict() {
+    STRICT.store(true, Ordering::Relaxed);
+}
+
+/// Report an input without records, a warning by default and an error with `--strict`
+pub fn empty_input(source: &str) -> Result<(), WGAError> {
+    let err = WGAError::EmptyInput(source.to_string());
+    if STRICT.load(Ordering::Relaxed) {
+        return Err(err);
+    }
+    warn!(target: WarnCategory::Empty.target(), "{}", err);
+    Ok(())
+}
+
+/// skip malformed records with a warning instead of an error
+pub fn enable_skip_bad_records() {
+    SKIP_BAD_RECORDS.store(true, Ordering::Relaxed);
+}
+
+/// Report a malformed record, an error by default, or a warning and skipped with
+/// `--skip-bad-records`
+pub fn bad_record(err: WGAError) -> Result<(), WGAError> {
+    if !SKIP_BAD_RECORDS.load(Ordering::Relaxed) {
+        return Err(err);
+    }
+    warn!(target: WarnCategory::BadRecord.target(), "skip bad record: {}", err);
+    record_skipped();
+    Ok(())
+}
+
+/// turn on per-stage timing
+pub fn enable_profile() {
+    PROFILE.store(true, Ordering::Relaxed);
+}
+
+thread_local! {
+    static IN_STAGE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// run `f` and add its elapsed time to `stage` if profiling is on,
+/// a stage nested in another one is counted in the outer stage
+pub fn profile<T>(stage: Stage, f: impl FnOnce() -> T) -> T {
+    if !PROFILE.load(Ordering::Relaxed) || IN_STAGE.with(|s| s.get()) {
+        return f();
+    }
+    IN_STAGE.with(|s| s.set(true));
+    let start = Instant::now();
+    let out = f();
+    IN_STAGE.with(|s| s.set(false));
+    let nanos = start.elapsed().as_nanos() as u64;
+    STAGE_NANOS[stage as usize].fetch_add(nanos, Ordering::Relaxed);
+    STAGE_CALLS[stage as usize].fetch_add(1, Ordering::Relaxed);
+    out
+}
+
+fn profile_json() -> serde_json::Value {
+    let stages: serde_json::Map<String, serde_json::Value> = Stage::ALL
+        .iter()
+        .map(|stage| {
+            let idx = *stage as usize;
+            (
+                stage.name().to_string(),
+                json!({
+                    "calls": STAGE_CALLS[idx].load(Ordering::Relaxed),
+                    "secs": STAGE_NANOS[idx].load(Ordering::Relaxed) as f64 / 1e9,
+                }),
+            )
+        })
+        .collect();
+    serde_json::Value::Object(stages)
+}
+
+fn write_profile_table(context: Option<&LogContext>) {
+    let mut stderr = std::io::stderr().lock();
+    let subcommand = context.map(|c| c.subcommand.as_str()).unwrap_or("-");
+    let _ = writeln!(stderr, "profile ({}):", subcommand);
+    let _ = writeln!(stderr, "{:<8}{:>12}{:>14}", "stage", "calls", "secs");
+    for stage in Stage::ALL {
+        let idx = stage as usize;
+        let _ = writeln!(
+            stderr,
+            "{:<8}{:>12}{:>14.6}",
+            stage.name(),
+            STAGE_CALLS[idx].load(Ordering::Relaxed),
+            STAGE_NANOS[idx].load(Ordering::Relaxed) as f64 / 1e9
+        );
+    }
+    if let Some(context) = context {
+        let _ = writeln!(
+            stderr,
+            "{:<8}{:>12}{:>14.6}",
+            "wall",
+            "-",
+            context.start.elapsed().as_secs_f64()
+        );
+    }
+}
+
+/// write the final summary object with exit code to stderr if log format is JSON, regardless of log level
+/// with `--profile`, stage timings are added to it, or printed as a table for text format
+pub fn log_summary(exit_code: i32) {
+    let profiling = PROFILE.load(Ordering::Relaxed);
+    let context = match LOG_CONTEXT.get() {
+        Some(context) if context.format == LogFormat::Json => context,
+        context => {
+            if profiling {
+                write_profile_table(context);
+            }
+            return;
+        }
+    };
+    let mut line = json!({
+        "timestamp": Local::now().to_rfc3339(),
+        "level": "INFO",
+        "message": "summary",
+        "subcommand": context.subcommand,
+        "input": context.input,
+        "summary": {
+            "status": if exit_code == 0 { "ok" } else { "error" },
+            "exit_code": exit_code,
+            "records_processed": records_processed(),
+            "records_skipped": RECORDS_SKIPPED.load(Ordering::Relaxed),
+            "wall_time_secs": context.start.elapsed().as_secs_f64(),
+        },
+    });
+    if profiling {
+        line["summary"]["profile"] = profile_json();
+    }
+    let _ = writeln!(std::io::stderr(), "{}", line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_counter_in_batches() {
+        let mut counter = RecordCounter::new(None);
+        for _ in 0..RECORD_COUNT_BATCH + 10 {
+            counter.add();
+        }
+        assert_eq!(counter.pending, 10);
+        // other tests read records too, so the global count only grows
+        let before = records_processed();
+        drop(counter);
+        assert!(records_processed() >= before + 10);
+    }
+
+    #[test]
+    fn empty_sources_by_name() {
+        // other tests only count records of readers without source
+        let mut counter = RecordCounter::new(Some("counted.maf"));
+        counter.add();
+        drop(counter);
+        let _empty = RecordCounter::new(Some("empty.maf"));
+        let empty = empty_sources("input.maf");
+        assert!(empty.contains(&"empty.maf".to_string()));
+        assert!(!empty.contains(&"counted.maf".to_string()));
+    }
+}
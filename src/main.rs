@@ -1,22 +1,39 @@
 use log::{error, info};
-use wgalib::cli::{make_cli_parse, Commands};
+use wgalib::cli::{
+    make_cli_parse, CallArgs, Cli, Commands, DotplotArgs, FilterArgs, Maf2PafArgs, StatArgs,
+};
 use wgalib::errors::WGAError;
-use wgalib::log::init_logger;
-use wgalib::parser::common::FileFormat;
+use wgalib::log::{
+    empty_input, empty_sources, enable_profile, enable_skip_bad_records, enable_strict,
+    init_logger, log_summary,
+};
+use wgalib::parser::common::{ChunkStrategy, CigarStyle, FileFormat, PafVersion, PanSN};
+use wgalib::parser::maf::MafWriteConfig;
+use wgalib::tools::caller::{CallOpts, MaxSvLen, VcfHeaderExtra};
+use wgalib::tools::stat::{QcThresholds, StatSample};
 use wgalib::tools::tview::tview;
+#[cfg(feature = "polish")]
+use wgalib::utils::wrap_polish;
 use wgalib::utils::{
-    wrap_build_index, wrap_chain2maf, wrap_chain2paf, wrap_chunk, wrap_dotplot, wrap_filter,
-    wrap_gencomp, wrap_maf2chain, wrap_maf2paf, wrap_maf2sam, wrap_maf_call, wrap_maf_extract,
-    wrap_paf2chain, wrap_paf2maf, wrap_paf_call, wrap_paf_cov, wrap_paf_pesudo_maf,
-    wrap_rename_maf, wrap_stat, wrap_validate,
+    commit_outputs, discard_outputs, resolve_call_format, set_atomic_output, set_codec_threads,
+    set_compression_level, set_write_buffer_size, wrap_annotate, wrap_axt2maf, wrap_axt2paf,
+    wrap_bedpe, wrap_blocks, wrap_build_index, wrap_chain2bigchain, wrap_chain2chain,
+    wrap_chain2maf, wrap_chain2paf, wrap_chunk, wrap_consensus, wrap_delta2maf, wrap_delta2paf,
+    wrap_diff, wrap_dotplot, wrap_filter, wrap_gencomp, wrap_maf2aln, wrap_maf2bigmaf,
+    wrap_maf2chain, wrap_maf2paf, wrap_maf2sam, wrap_maf_call, wrap_maf_extract, wrap_maskfa,
+    wrap_paf2chain, wrap_paf2maf, wrap_paf_call, wrap_paf_cov, wrap_paf_pesudo_maf, wrap_pmaf_stat,
+    wrap_pos_map, wrap_rename, wrap_report, wrap_sample, wrap_segments, wrap_stat, wrap_validate,
+    wrap_vcf_lift,
 };
 
 fn main() {
     match main_entry() {
-        Ok(_) => {}
+        Ok(_) => log_summary(0),
         Err(e) => {
             error!("{}", e);
-            std::process::exit(1);
+            let code = e.exit_code();
+            log_summary(code);
+            std::process::exit(code);
         }
     }
 }
@@ -25,123 +42,666 @@ fn main_entry() -> Result<(), WGAError> {
     let cli = make_cli_parse();
     let verbose = cli.verbose;
 
-    init_logger(verbose);
+    init_logger(
+        verbose,
+        cli.quiet,
+        &cli.suppress_warnings,
+        cli.log_format,
+        &cli.subcommand,
+        cli.input.as_deref(),
+    );
+
+    set_write_buffer_size(cli.buffer_size * 1024);
+    set_codec_threads(cli.threads);
+    set_compression_level(cli.compression_level);
+    if cli.profile {
+        enable_profile();
+    }
+    if cli.strict {
+        enable_strict();
+    }
+    if cli.skip_bad_records {
+        enable_skip_bad_records();
+    }
+    set_atomic_output(!cli.no_atomic);
+    MafWriteConfig {
+        pretty: cli.maf_pretty,
+    }
+    .set_global();
 
     rayon::ThreadPoolBuilder::new()
         .num_threads(cli.threads)
         .build_global()?;
 
-    let outfile = cli.outfile;
-    let rewrite = cli.rewrite;
-
     // Info log
     info!("Command: {:?}", &cli.command);
+    if !cli.command.is_single_threaded() {
+        info!("Threads: {}", rayon::current_num_threads());
+    } else if cli.threads > 1 {
+        info!(
+            "`{}` runs in a single thread, `--threads {}` is only used by compression",
+            cli.subcommand, cli.threads
+        );
+    }
+
+    // partial outputs are also removed if a command panics
+    let _discard = DiscardOutputs;
+    // readers built from input stream do not know the file name
+    let input = cli.input.clone();
+    let reads_records = cli.command.reads_records();
+    let result = run_command(cli).map_err(|e| match input.as_deref() {
+        Some(input) if input != "-" => e.fill_file(input),
+        _ => e,
+    });
+    // writers are dropped and finished by now, outputs are complete only on success
+    let result = result.and_then(|()| match reads_records {
+        true => {
+            let source = match input.as_deref() {
+                Some(input) if input != "-" => input,
+                _ => "stdin",
+            };
+            empty_sources(source)
+                .iter()
+                .try_for_each(|s| empty_input(s))
+        }
+        false => Ok(()),
+    });
+    match result {
+        Ok(()) => commit_outputs()?,
+        Err(e) => {
+            discard_outputs();
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+// remove outputs not committed when dropped, which is a no-op after `commit_outputs`
+struct DiscardOutputs;
+
+impl Drop for DiscardOutputs {
+    fn drop(&mut self) {
+        discard_outputs();
+    }
+}
+
+fn run_command(cli: Cli) -> Result<(), WGAError> {
+    let outfile = cli.outfile;
+    let rewrite = cli.rewrite;
+    let strict = cli.strict;
 
     match &cli.command {
-        Commands::Maf2Paf { input, query_name } => {
-            wrap_maf2paf(input, &outfile, query_name.clone(), rewrite)?;
+        Commands::Maf2Paf(args) => {
+            let Maf2PafArgs {
+                input,
+                query_name,
+                verify,
+                sam_tags,
+                odd_char,
+                sort_by,
+                paf_version,
+                pansn_sep,
+                pansn_hap,
+                cigar_style,
+                in_flight,
+            } = args.as_ref();
+            let pansn = match paf_version {
+                PafVersion::Plain => None,
+                PafVersion::Pansn => Some(PanSN {
+                    sep: pansn_sep.clone(),
+                    hap: *pansn_hap,
+                }),
+            };
+            wrap_maf2paf(
+                input,
+                &outfile,
+                query_name.clone(),
+                rewrite,
+                *verify,
+                *sam_tags,
+                *odd_char,
+                *sort_by,
+                pansn,
+                *cigar_style,
+                *in_flight,
+            )?;
         }
         Commands::Paf2Maf {
             input,
             target,
             query,
+            verify,
+            unknown_base,
+            keep_tags,
+            target_region,
         } => {
-            wrap_paf2maf(input, &outfile, target, query, rewrite)?;
+            wrap_paf2maf(
+                input,
+                &outfile,
+                target,
+                query,
+                rewrite,
+                *verify,
+                *unknown_base,
+                keep_tags,
+                target_region,
+            )?;
         }
         Commands::Paf2Chain { input } => {
             wrap_paf2chain(input, &outfile, rewrite)?;
         }
-        Commands::Chain2Paf { input } => {
-            wrap_chain2paf(input, &outfile, rewrite)?;
+        Commands::Chain2Chain { input, split } => {
+            wrap_chain2chain(input, &outfile, rewrite, *split)?;
+        }
+        Commands::Chain2Paf {
+            input,
+            sort_by,
+            cigar_style,
+            target,
+            query,
+        } => {
+            let fa_paths = match (cigar_style, target, query) {
+                (CigarStyle::Eqx, Some(target), Some(query)) => Some((target, query)),
+                _ => None,
+            };
+            wrap_chain2paf(input, &outfile, rewrite, *sort_by, fa_paths)?;
         }
         Commands::Chain2Maf {
             input,
             target,
             query,
+            unknown_base,
+            target_region,
         } => {
-            wrap_chain2maf(input, &outfile, target, query, rewrite)?;
+            wrap_chain2maf(
+                input,
+                &outfile,
+                target,
+                query,
+                rewrite,
+                *unknown_base,
+                target_region,
+            )?;
         }
-        Commands::Maf2Chain { input, query_name } => {
-            wrap_maf2chain(input, &outfile, rewrite, query_name.clone())?;
+        Commands::Axt2Maf {
+            input,
+            target,
+            query,
+            in_flight,
+        } => {
+            wrap_axt2maf(input, &outfile, target, query, rewrite, *in_flight)?;
         }
-        Commands::MafExtract {
+        Commands::Axt2Paf {
             input,
-            regions,
-            file,
+            target,
+            query,
+            sort_by,
+            cigar_style,
+            in_flight,
         } => {
-            wrap_maf_extract(input, regions, file, &outfile, rewrite)?;
+            wrap_axt2paf(
+                input,
+                &outfile,
+                target,
+                query,
+                rewrite,
+                *sort_by,
+                *cigar_style,
+                *in_flight,
+            )?;
         }
-        Commands::Call {
+        Commands::Delta2Maf {
             input,
-            sample,
-            snp,
-            svlen,
-            format,
             target,
             query,
+            unknown_base,
+        } => {
+            wrap_delta2maf(
+                input,
+                &outfile,
+                target.as_deref(),
+                query.as_deref(),
+                rewrite,
+                *unknown_base,
+            )?;
+        }
+        Commands::Delta2Paf { input, sort_by } => {
+            wrap_delta2paf(input, &outfile, rewrite, *sort_by)?;
+        }
+        Commands::Chain2BigChain {
+            input,
+            link,
+            as_dir,
+        } => {
+            wrap_chain2bigchain(input, &outfile, link, as_dir.as_deref(), rewrite)?;
+        }
+        Commands::Maf2BigMaf {
+            input,
+            ref_db,
+            as_dir,
+        } => {
+            wrap_maf2bigmaf(
+                input,
+                &outfile,
+                ref_db.as_deref(),
+                as_dir.as_deref(),
+                rewrite,
+            )?;
+        }
+        Commands::Blocks {
+            input,
+            format,
+            out_format,
             query_name,
-        } => match format {
-            FileFormat::Maf => {
-                wrap_maf_call(
-                    input,
-                    &outfile,
-                    rewrite,
-                    *snp,
-                    *svlen,
-                    false,
-                    sample.as_deref(),
-                    query_name.as_deref(),
-                )?;
-            }
-            FileFormat::Paf => {
-                let (target, query) = match (target, query) {
-                    (Some(t), Some(q)) => (t, q),
-                    _ => {
-                        return Err(WGAError::Other(anyhow::anyhow!(
-                            "target and query are necessary"
-                        )));
+        } => {
+            wrap_blocks(
+                input,
+                *format,
+                *out_format,
+                &outfile,
+                query_name.clone(),
+                rewrite,
+            )?;
+        }
+        Commands::Bedpe {
+            input,
+            format,
+            split,
+            query_name,
+        } => {
+            wrap_bedpe(
+                input,
+                *format,
+                &outfile,
+                *split,
+                query_name.clone(),
+                rewrite,
+            )?;
+        }
+        Commands::Segments {
+            input,
+            format,
+            query_name,
+        } => {
+            wrap_segments(input, *format, &outfile, query_name.clone(), rewrite)?;
+        }
+        Commands::Maf2Chain {
+            input,
+            query_name,
+            query_regex,
+            split,
+            genome_sep,
+            liftover_ready,
+            odd_char,
+        } => {
+            wrap_maf2chain(
+                input,
+                &outfile,
+                rewrite,
+                query_name.clone(),
+                query_regex.as_deref(),
+                *split,
+                *genome_sep,
+                *liftover_ready,
+                *odd_char,
+            )?;
+        }
+        Commands::Maf2Aln {
+            input,
+            out_format,
+            genome_sep,
+            genomes,
+            min_genomes,
+        } => {
+            wrap_maf2aln(
+                input,
+                &outfile,
+                rewrite,
+                *out_format,
+                *genome_sep,
+                genomes,
+                *min_genomes,
+            )?;
+        }
+        Commands::MafExtract {
+            input,
+            regions,
+            file,
+            rebuild_index,
+            fuzzy,
+        } => {
+            wrap_maf_extract(
+                input,
+                regions,
+                file,
+                &outfile,
+                rewrite,
+                *rebuild_index,
+                *fuzzy,
+            )?;
+        }
+        Commands::Call(args) => {
+            let CallArgs {
+                input,
+                sample,
+                diploid,
+                ploidy,
+                snp,
+                svlen,
+                min_indel,
+                max_svlen,
+                filter_long_sv,
+                format,
+                target,
+                query,
+                query_name,
+                mask_near_sv,
+                inv_mode,
+                merge_dist,
+                chunk_strategy,
+                chunk_size,
+                rebuild_index,
+                unknown_base,
+                gvcf,
+                odd_char,
+                block_info,
+                min_qline_qual,
+                max_block_n,
+                max_gap_run,
+                vcf_header_lines,
+                sample_meta,
+                checkpoint,
+                split_by_chrom,
+                between,
+                bnd_distance,
+            } = args.as_ref();
+            let opts = CallOpts {
+                snp: *snp,
+                svlen: *svlen,
+                min_indel: *min_indel,
+                bnd_distance: between.then_some(*bnd_distance),
+                diploid: *diploid,
+                ploidy: *ploidy,
+                query_name: query_name.clone(),
+                mask_near_sv: *mask_near_sv,
+                inv_mode: *inv_mode,
+                merge_dist: *merge_dist,
+                chunk_strategy: *chunk_strategy,
+                chunk_size: chunk_size.unwrap_or(CallOpts::default().chunk_size),
+                rebuild_index: *rebuild_index,
+                unknown_base: *unknown_base,
+                gvcf: *gvcf,
+                odd_char: *odd_char,
+                block_info: *block_info,
+                min_qual: *min_qline_qual,
+                max_svlen: max_svlen.map(|len| MaxSvLen {
+                    len,
+                    filter: *filter_long_sv,
+                }),
+                qc: QcThresholds {
+                    max_n: *max_block_n,
+                    max_gap_run: *max_gap_run,
+                },
+                header_extra: VcfHeaderExtra::new(vcf_header_lines.as_deref(), sample_meta)?,
+                checkpoint: checkpoint.clone(),
+                split_by_chrom: split_by_chrom.clone(),
+            };
+            match resolve_call_format(*format, input)? {
+                FileFormat::Maf => {
+                    wrap_maf_call(
+                        input,
+                        &outfile,
+                        rewrite,
+                        target.as_deref(),
+                        sample.as_deref(),
+                        &opts,
+                    )?;
+                }
+                FileFormat::Paf => {
+                    if checkpoint.is_some() {
+                        return Err(WGAError::InvalidArgs(
+                            "`--checkpoint` only supports MAF input".to_string(),
+                        ));
                     }
-                };
-                wrap_paf_call(
-                    input,
-                    target,
-                    query,
-                    &outfile,
-                    rewrite,
-                    *snp,
-                    *svlen,
-                    true,
-                    sample.as_deref(),
-                )?;
-            }
-            _ => {
-                return Err(WGAError::Other(anyhow::anyhow!("format is not supported")));
+                    if split_by_chrom.is_some() {
+                        return Err(WGAError::InvalidArgs(
+                            "`--split-by-chrom` only supports MAF input".to_string(),
+                        ));
+                    }
+                    if *between {
+                        return Err(WGAError::InvalidArgs(
+                            "`--between` only supports MAF input".to_string(),
+                        ));
+                    }
+                    if *diploid {
+                        return Err(WGAError::InvalidArgs(
+                            "`--diploid` only supports MAF input".to_string(),
+                        ));
+                    }
+                    if *gvcf {
+                        return Err(WGAError::InvalidArgs(
+                            "`--gvcf` only supports MAF input".to_string(),
+                        ));
+                    }
+                    if min_qline_qual.is_some() {
+                        return Err(WGAError::InvalidArgs(
+                            "`--min-qline-qual` only supports MAF input".to_string(),
+                        ));
+                    }
+                    if *merge_dist > 0 {
+                        return Err(WGAError::InvalidArgs(
+                            "`--merge-dist` only supports MAF input".to_string(),
+                        ));
+                    }
+                    if *chunk_strategy != ChunkStrategy::None || chunk_size.is_some() {
+                        return Err(WGAError::InvalidArgs(
+                            "`--chunk-strategy` and `--chunk-size` only support MAF input"
+                                .to_string(),
+                        ));
+                    }
+                    let (target, query) = match (target, query) {
+                        (Some(t), Some(q)) => (t, q),
+                        _ => {
+                            return Err(WGAError::InvalidArgs(
+                                "target and query are necessary".to_string(),
+                            ));
+                        }
+                    };
+                    wrap_paf_call(
+                        input,
+                        target,
+                        query,
+                        &outfile,
+                        rewrite,
+                        sample.as_deref(),
+                        &opts,
+                    )?;
+                }
+                _ => {
+                    return Err(WGAError::InvalidArgs("format is not supported".to_string()));
+                }
             }
-        },
+        }
         Commands::Maf2Sam { input } => {
             wrap_maf2sam(input, &outfile, rewrite)?;
         }
-        Commands::MafIndex { input } => {
-            wrap_build_index(input, &outfile)?;
+        Commands::MafIndex {
+            input,
+            max_mem,
+            check,
+        } => {
+            wrap_build_index(input, &outfile, *max_mem, *check)?;
         }
-        Commands::Tview { input, step } => {
-            tview(input, *step)?;
+        Commands::Annotate {
+            input,
+            maf,
+            query_name,
+            window,
+        } => {
+            wrap_annotate(
+                input,
+                maf,
+                &outfile,
+                rewrite,
+                query_name.as_deref(),
+                *window,
+            )?;
         }
-        Commands::Stat {
+        Commands::VcfLift {
             input,
+            align,
             format,
-            each,
+            fasta,
+            from,
             query_name,
-        } => wrap_stat(*format, input, &outfile, query_name.clone(), rewrite, *each)?,
-        Commands::Dotplot {
+            reject,
+        } => {
+            wrap_vcf_lift(
+                input,
+                align,
+                *format,
+                fasta,
+                *from,
+                query_name.as_deref(),
+                reject.as_deref(),
+                &outfile,
+                rewrite,
+            )?;
+        }
+        Commands::PosMap {
             input,
             format,
-            out_format,
-            no_identity,
-            length,
-            mode,
+            at,
+            query_name,
+        } => {
+            wrap_pos_map(input, *format, at, query_name.as_deref(), &outfile, rewrite)?;
+        }
+        Commands::Consensus {
+            input,
+            target,
+            maf,
+            regions,
+            sample,
+            haplotype,
+        } => {
+            wrap_consensus(
+                input,
+                &outfile,
+                rewrite,
+                target.as_deref(),
+                *maf,
+                regions,
+                sample.as_deref(),
+                *haplotype,
+            )?;
+        }
+        Commands::Sample {
+            input,
+            format,
+            fraction,
+            seed,
+            head,
+            target_region,
+        } => {
+            wrap_sample(
+                input,
+                *format,
+                &outfile,
+                rewrite,
+                *fraction,
+                *seed,
+                *head,
+                target_region,
+            )?;
+        }
+        #[cfg(feature = "polish")]
+        Commands::Polish {
+            input,
+            max_gap,
+            target,
+            query,
+        } => {
+            wrap_polish(
+                input,
+                &outfile,
+                rewrite,
+                *max_gap,
+                target.as_deref(),
+                query.as_deref(),
+            )?;
+        }
+        Commands::Tview {
+            input,
+            step,
+            vcf,
+            rebuild_index,
+        } => {
+            tview(input, *step, vcf.as_deref(), *rebuild_index)?;
+        }
+        Commands::Stat(args) => {
+            let StatArgs {
+                input,
+                format,
+                each,
+                query_name,
+                json_detailed,
+                gap_hist,
+                gap_bins,
+                odd_char,
+                per_sline,
+                qc,
+                strata,
+                group_by,
+                sample,
+                seed,
+            } = args.as_ref();
+            wrap_stat(
+                *format,
+                input,
+                &outfile,
+                query_name.clone(),
+                rewrite,
+                *each,
+                *json_detailed,
+                gap_hist.then_some(gap_bins.as_slice()),
+                *odd_char,
+                *per_sline,
+                *qc,
+                strata.as_deref(),
+                *group_by,
+                sample.map(|fraction| StatSample {
+                    fraction,
+                    seed: *seed,
+                }),
+            )?;
+        }
+        Commands::Diff {
+            input_a,
+            input_b,
+            format,
             query_name,
         } => {
+            wrap_diff(
+                input_a,
+                input_b,
+                *format,
+                &outfile,
+                query_name.as_deref(),
+                rewrite,
+            )?;
+        }
+        Commands::Dotplot(args) => {
+            let DotplotArgs {
+                input,
+                format,
+                out_format,
+                no_identity,
+                length,
+                mode,
+                query_name,
+                target_region,
+                query_region,
+            } = args.as_ref();
             wrap_dotplot(
                 input,
                 *format,
@@ -151,16 +711,28 @@ fn main_entry() -> Result<(), WGAError> {
                 *length,
                 &outfile,
                 query_name.clone(),
+                target_region,
+                query_region,
                 rewrite,
             )?;
         }
-        Commands::Filter {
-            input,
-            format,
-            min_block_size,
-            min_query_size,
-            min_align_size,
-        } => {
+        Commands::Filter(args) => {
+            let FilterArgs {
+                input,
+                format,
+                min_block_size,
+                min_query_size,
+                min_align_size,
+                min_chain_score,
+                min_target_span,
+                dedup,
+                pair_report,
+                split_fraction,
+                dup_redundancy,
+                mask,
+                mask_mode,
+                best_hit,
+            } = args.as_ref();
             wrap_filter(
                 *format,
                 input,
@@ -169,13 +741,49 @@ fn main_entry() -> Result<(), WGAError> {
                 *min_block_size,
                 *min_query_size,
                 *min_align_size,
+                *min_chain_score,
+                *min_target_span,
+                *dedup,
+                *pair_report,
+                *split_fraction,
+                *dup_redundancy,
+                mask.as_deref(),
+                *mask_mode,
+                *best_hit,
             )?;
         }
-        Commands::Rename { input, prefixs } => {
-            wrap_rename_maf(input, &outfile, rewrite, prefixs)?;
+        Commands::Rename {
+            input,
+            format,
+            prefixs,
+            genome_prefix,
+            map,
+            regex,
+            index,
+        } => {
+            wrap_rename(
+                input,
+                *format,
+                &outfile,
+                rewrite,
+                prefixs,
+                genome_prefix,
+                map,
+                regex,
+                index,
+            )?;
         }
-        Commands::PafCov { input } => {
-            wrap_paf_cov(input, &outfile, rewrite)?;
+        Commands::MaskFa {
+            input,
+            format,
+            target,
+            mode,
+            aligned,
+        } => {
+            wrap_maskfa(input, *format, target, *mode, *aligned, &outfile, rewrite)?;
+        }
+        Commands::PafCov { input, group_by } => {
+            wrap_paf_cov(input, &outfile, rewrite, *group_by)?;
         }
         Commands::PafPseudo {
             input,
@@ -183,6 +791,13 @@ fn main_entry() -> Result<(), WGAError> {
             target,
         } => {
             wrap_paf_pesudo_maf(input, &outfile, rewrite, fasta, target)?;
+        }
+        Commands::PmafStat {
+            input,
+            window,
+            min_cov,
+        } => {
+            wrap_pmaf_stat(input, &outfile, rewrite, *window, *min_cov)?;
         } // Commands::TrimOvp { input } => {
         //     wrap_paf_trim_overlap(input, &outfile, rewrite)?;
         // }
@@ -192,8 +807,27 @@ fn main_entry() -> Result<(), WGAError> {
         Commands::GenCompletion { shell } => {
             wrap_gencomp(*shell, &outfile, rewrite)?;
         }
-        Commands::Validate { input, fix } => {
-            wrap_validate(input, fix, &outfile, rewrite)?;
+        Commands::Report {
+            input,
+            format,
+            query_name,
+            bins,
+        } => {
+            wrap_report(
+                input,
+                *format,
+                &outfile,
+                query_name.as_deref(),
+                *bins,
+                rewrite,
+            )?;
+        }
+        Commands::Validate {
+            input,
+            fix,
+            out_format,
+        } => {
+            wrap_validate(input, fix, &outfile, rewrite, *out_format, strict)?;
         }
     }
     Ok(())
@@ -0,0 +1,300 @@
+use crate::errors::{ParseContext, WGAError};
+use crate::log::{profile, RecordCounter, Stage};
+use crate::parser::common::Strand;
+use crate::parser::maf::{MAFRecord, MAFSLine};
+use crate::utils::parse_str2u64;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+
+/// Reader for UCSC AXT format of `lastz`/`blastz` pipelines
+/// refer into https://genome.ucsc.edu/goldenPath/help/axt.html
+pub struct AxtReader<R: Read> {
+    inner: BufReader<R>,
+    // file name in error messages
+    source: Option<String>,
+}
+
+impl<R> AxtReader<R>
+where
+    R: Read + Send,
+{
+    /// Create a new AXT Reader
+    pub fn new(reader: R) -> Self {
+        AxtReader {
+            inner: BufReader::new(reader),
+            source: None,
+        }
+    }
+
+    /// Set file name shown in error messages
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    /// Iterate over the blocks in the AXT file
+    pub fn records(&mut self) -> AxtRecords<'_, R> {
+        AxtRecords {
+            inner: &mut self.inner,
+            source: self.source.clone(),
+            line_no: 0,
+            record_no: 0,
+            line: String::new(),
+            counter: RecordCounter::new(self.source.as_deref()),
+        }
+    }
+}
+
+impl AxtReader<File> {
+    /// Create a new AXT parser from a file path
+    pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<AxtReader<File>> {
+        let source = path.as_ref().to_string_lossy().to_string();
+        File::open(path).map(|file| AxtReader::new(file).with_source(&source))
+    }
+}
+
+/// Define an AXT block, coordinates are kept 1-based and inclusive as in file,
+/// query coordinates are on reverse-complemented query if strand is `-`
+#[derive(Debug, Default)]
+pub struct AxtRecord {
+    pub number: u64,
+    pub target_name: String,
+    pub target_start: u64,
+    pub target_end: u64,
+    pub query_name: String,
+    pub query_start: u64,
+    pub query_end: u64,
+    pub strand: Strand,
+    pub score: i64,
+    pub target_seq: String,
+    pub query_seq: String,
+}
+
+pub struct AxtRecords<'a, R: Read> {
+    inner: &'a mut BufReader<R>,
+    source: Option<String>,
+    line_no: u64,
+    record_no: u64,
+    line: String,
+    counter: RecordCounter,
+}
+
+impl<R: Read> AxtRecords<'_, R> {
+    // position of the current line
+    fn context(&self) -> ParseContext {
+        ParseContext {
+            file: self.source.clone(),
+            record: Some(self.record_no + 1),
+            line: Some(self.line_no),
+            offset: None,
+            seqs: None,
+        }
+    }
+
+    // read the next line into `self.line` without line ending, false at the end
+    fn next_line(&mut self) -> Result<bool, WGAError> {
+        self.line.clear();
+        if self.inner.read_line(&mut self.line)? == 0 {
+            return Ok(false);
+        }
+        self.line_no += 1;
+        let len = self.line.trim_end_matches(['\r', '\n']).len();
+        self.line.truncate(len);
+        Ok(true)
+    }
+
+    fn parse_next(&mut self) -> Result<Option<AxtRecord>, WGAError> {
+        // skip comments and blank lines between blocks
+        loop {
+            if !self.next_line()? {
+                return Ok(None);
+            }
+            if !self.line.trim().is_empty() && !self.line.starts_with('#') {
+                break;
+            }
+        }
+        let mut rec = parse_header(&self.line)?;
+        for seq in [&mut rec.target_seq, &mut rec.query_seq] {
+            if !self.next_line()? || self.line.is_empty() {
+                return Err(WGAError::InvalidRecord(
+                    "AXT block without two sequence lines".to_string(),
+                ));
+            }
+            seq.push_str(&self.line);
+        }
+        if rec.target_seq.len() != rec.query_seq.len() {
+            return Err(WGAError::InvalidRecord(format!(
+                "AXT block {} has sequences of {} and {} columns",
+                rec.number,
+                rec.target_seq.len(),
+                rec.query_seq.len()
+            )));
+        }
+        Ok(Some(rec))
+    }
+}
+
+impl<R: Read> Iterator for AxtRecords<'_, R> {
+    type Item = Result<AxtRecord, WGAError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match profile(Stage::Parse, || self.parse_next()) {
+            Ok(Some(rec)) => {
+                self.record_no += 1;
+                self.counter.add();
+                Some(Ok(rec))
+            }
+            Ok(None) => None,
+            Err(e) => Some(Err(e.with_context(self.context()))),
+        }
+    }
+}
+
+// parse `num tName tStart tEnd qName qStart qEnd strand score`
+fn parse_header(line: &str) -> Result<AxtRecord, WGAError> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+    let [number, t_name, t_start, t_end, q_name, q_start, q_end, strand, score] = fields[..] else {
+        return Err(WGAError::InvalidRecord(format!(
+            "AXT header `{}` should have 9 fields",
+            line
+        )));
+    };
+    let rec = AxtRecord {
+        number: parse_str2u64(number)?,
+        target_name: t_name.to_string(),
+        target_start: parse_str2u64(t_start)?,
+        target_end: parse_str2u64(t_end)?,
+        query_name: q_name.to_string(),
+        query_start: parse_str2u64(q_start)?,
+        query_end: parse_str2u64(q_end)?,
+        strand: strand.parse::<Strand>()?,
+        score: score
+            .parse::<i64>()
+            .map_err(|_| WGAError::ParseIntError(score.to_string()))?,
+        ..Default::default()
+    };
+    if rec.target_start == 0
+        || rec.query_start == 0
+        || rec.target_start > rec.target_end
+        || rec.query_start > rec.query_end
+    {
+        return Err(WGAError::InvalidRecord(format!(
+            "invalid AXT coordinates `{}`",
+            line
+        )));
+    }
+    Ok(rec)
+}
+
+impl AxtRecord {
+    /// Convert to a MAF block of target and query, sequence sizes are looked up by names
+    pub fn to_maf(
+        self,
+        t_sizes: &HashMap<String, u64>,
+        q_sizes: &HashMap<String, u64>,
+    ) -> Result<MAFRecord, WGAError> {
+        let target = sline(
+            self.target_name,
+            self.target_start,
+            self.target_end,
+            Strand::Positive,
+            t_sizes,
+            self.target_seq,
+        )?;
+        let query = sline(
+            self.query_name,
+            self.query_start,
+            self.query_end,
+            self.strand,
+            q_sizes,
+            self.query_seq,
+        )?;
+        Ok(MAFRecord {
+            // MAF score can't be negative
+            score: self.score.max(0) as u64,
+            slines: vec![target, query],
+            query_idx: 1,
+            tags: Vec::new(),
+        })
+    }
+}
+
+// s-line of 1-based inclusive coordinates, bases must fill the span
+fn sline(
+    name: String,
+    start: u64,
+    end: u64,
+    strand: Strand,
+    sizes: &HashMap<String, u64>,
+    seq: String,
+) -> Result<MAFSLine, WGAError> {
+    let size = *sizes
+        .get(&name)
+        .ok_or_else(|| WGAError::InvalidRecord(format!("no size of sequence `{}`", name)))?;
+    let align_size = end - start + 1;
+    let bases = seq.bytes().filter(|&b| b != b'-').count() as u64;
+    if bases != align_size || end > size {
+        return Err(WGAError::InvalidRecord(format!(
+            "`{}:{}-{}` has {} bases in sequence of {} bp",
+            name, start, end, bases, size
+        )));
+    }
+    Ok(MAFSLine {
+        mode: 's',
+        name,
+        start: start - 1,
+        align_size,
+        strand,
+        size,
+        seq,
+        qual: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_header_fields() {
+        let rec = parse_header("2 chr1 11 20 q1 5 14 - -30").unwrap();
+        assert_eq!(rec.number, 2);
+        assert_eq!((rec.target_start, rec.target_end), (11, 20));
+        assert_eq!((rec.query_start, rec.query_end), (5, 14));
+        assert_eq!(rec.strand, Strand::Negative);
+        assert_eq!(rec.score, -30);
+        // 9 fields are required
+        assert!(parse_header("2 chr1 11 20 q1 5 14 -").is_err());
+        assert!(parse_header("2 chr1 11 20 q1 5 14 - 30 1").is_err());
+        // coordinates are 1-based and inclusive
+        assert!(parse_header("2 chr1 0 20 q1 5 14 - 30").is_err());
+        assert!(parse_header("2 chr1 11 20 q1 0 14 - 30").is_err());
+        assert!(parse_header("2 chr1 21 20 q1 5 14 - 30").is_err());
+        assert!(parse_header("2 chr1 11 20 q1 15 14 - 30").is_err());
+    }
+
+    #[test]
+    fn to_maf_negative_strand() {
+        let mut rec = parse_header("0 chr1 31 36 q1 5 8 - 10").unwrap();
+        rec.target_seq = "ACGTAC".to_string();
+        rec.query_seq = "AC--AC".to_string();
+        let t_sizes = HashMap::from([("chr1".to_string(), 100)]);
+        let q_sizes = HashMap::from([("q1".to_string(), 50)]);
+        let maf = rec.to_maf(&t_sizes, &q_sizes).unwrap();
+        let (target, query) = (&maf.slines[0], &maf.slines[1]);
+        assert_eq!((target.start, target.align_size), (30, 6));
+        assert_eq!(target.strand, Strand::Positive);
+        // start on reverse-complemented query is kept as MAF does
+        assert_eq!((query.start, query.align_size, query.size), (4, 4, 50));
+        assert_eq!(query.strand, Strand::Negative);
+        assert_eq!(query.seq, "AC--AC");
+    }
+
+    #[test]
+    fn unequal_columns() {
+        let axt = "0 chr1 1 3 q1 1 2 + 10\nACG\nAC-G\n";
+        let mut axtrdr = AxtReader::new(axt.as_bytes());
+        let err = axtrdr.records().next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("columns"));
+    }
+}
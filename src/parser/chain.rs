@@ -1,4 +1,5 @@
-use crate::errors::{ParseChainErrKind, WGAError};
+use crate::errors::{ParseChainErrKind, ParseContext, WGAError};
+use crate::log::{profile, RecordCounter, Stage};
 use crate::parser::cigar::{parse_chain_to_cigar, parse_cigar_to_trim, parse_maf_seq_to_trim};
 use crate::parser::common::{AlignRecord, SeqInfo, Strand};
 use crate::parser::maf::MAFRecord;
@@ -10,12 +11,14 @@ use nom::multi::fold_many1;
 use nom::sequence::terminated;
 use nom::IResult;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Write};
 use std::{fmt, io};
 
 /// Reader for MAF file format
 pub struct ChainReader<R: Read> {
     inner: BufReader<R>,
+    // file name in error messages
+    source: Option<String>,
 }
 
 impl<R> ChainReader<R>
@@ -26,21 +29,36 @@ where
     pub fn new(reader: R) -> Self {
         ChainReader {
             inner: BufReader::new(reader),
+            source: None,
         }
     }
 
+    /// Set file name shown in error messages
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
     /// Iterate over the records in the Chain file
     pub fn records(&mut self) -> Result<ChainRecords, WGAError> {
         let mut data = String::with_capacity(512);
         self.inner.read_to_string(&mut data)?;
-        Ok(ChainRecords { inner: data })
+        Ok(ChainRecords {
+            inner: data,
+            source: self.source.clone(),
+            offset: 0,
+            line_no: 1,
+            record_no: 0,
+            counter: RecordCounter::new(self.source.as_deref()),
+        })
     }
 }
 
 impl ChainReader<File> {
     /// Create a new PAF parser from a file path
     pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> io::Result<ChainReader<File>> {
-        File::open(path).map(ChainReader::new)
+        let source = path.as_ref().to_string_lossy().to_string();
+        File::open(path).map(|file| ChainReader::new(file).with_source(&source))
     }
 }
 
@@ -54,21 +72,149 @@ pub struct ChainRecord {
 
 pub struct ChainRecords {
     inner: String,
+    source: Option<String>,
+    // byte offset and line number of the next record, number of records read
+    offset: usize,
+    line_no: u64,
+    record_no: u64,
+    counter: RecordCounter,
+}
+
+impl ChainRecords {
+    // position of the next record, with names in its header line if present
+    fn context(&self) -> ParseContext {
+        let header = self.inner[self.offset..].lines().next().unwrap_or_default();
+        let fields = header.split_whitespace().collect::<Vec<_>>();
+        ParseContext {
+            file: self.source.clone(),
+            record: Some(self.record_no + 1),
+            line: Some(self.line_no),
+            offset: Some(self.offset as u64),
+            seqs: match (fields.first(), fields.get(2), fields.get(7)) {
+                (Some(&"chain"), Some(target), Some(query)) => {
+                    Some(format!("target `{}`, query `{}`", target, query))
+                }
+                _ => None,
+            },
+        }
+    }
 }
 
 impl Iterator for ChainRecords {
     type Item = Result<ChainRecord, WGAError>;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.inner.is_empty() {
+        let rest = &self.inner[self.offset..];
+        if rest.is_empty() {
             return None;
         }
-        match chain_parser(&self.inner) {
+        match profile(Stage::Parse, || chain_parser(rest)) {
             Ok((i, r)) => {
-                self.inner = i.to_string();
+                let consumed = rest.len() - i.len();
+                self.line_no += rest[..consumed].matches('\n').count() as u64;
+                self.offset += consumed;
+                self.record_no += 1;
+                self.counter.add();
                 Some(Ok(r))
             }
-            Err(e) => Some(Err(e)),
+            Err(e) => Some(Err(e.with_context(self.context()))),
+        }
+    }
+}
+
+impl ChainRecord {
+    /// Write the chain in standard format followed by a blank line,
+    /// the record is formatted into a buffer and written at once
+    pub fn write_to(&self, writer: &mut dyn Write) -> Result<(), WGAError> {
+        profile(Stage::Write, || {
+            let mut buf = Vec::with_capacity(128 + 24 * self.lines.len());
+            write!(buf, "{}", self.header)?;
+            if let Some((last, lines)) = self.lines.split_last() {
+                for dataline in lines {
+                    write!(buf, "{}", dataline)?;
+                }
+                // last line only has the size
+                write!(buf, "\n{}", last.size)?;
+            }
+            buf.extend_from_slice(b"\n\n");
+            writer.write_all(&buf)?;
+            Ok(())
+        })
+    }
+
+    /// Normalise a chain on target `-` strand by flipping both strands, coordinates
+    /// are converted to the other strand and data lines are reversed
+    fn flip_to_target_positive(&mut self) -> Result<(), WGAError> {
+        if self.header.target.strand == Strand::Positive {
+            return Ok(());
         }
+        let (t_start, t_end) = (self.header.target.start, self.header.target.end);
+        let (q_start, q_end) = (self.header.query.start, self.header.query.end);
+        let new_t_start = self.coord_sub(self.header.target.size, t_end)?;
+        let new_t_end = self.coord_sub(self.header.target.size, t_start)?;
+        let new_q_start = self.coord_sub(self.header.query.size, q_end)?;
+        let new_q_end = self.coord_sub(self.header.query.size, q_start)?;
+        let target = &mut self.header.target;
+        (target.strand, target.start, target.end) = (Strand::Positive, new_t_start, new_t_end);
+        let query = &mut self.header.query;
+        query.strand = match query.strand {
+            Strand::Positive => Strand::Negative,
+            Strand::Negative => Strand::Positive,
+        };
+        (query.start, query.end) = (new_q_start, new_q_end);
+        // gaps after a block come before it once reversed
+        let gaps = self
+            .lines
+            .iter()
+            .map(|line| (line.target_gap, line.query_gap))
+            .collect::<Vec<_>>();
+        self.lines.reverse();
+        for (line, (dt, dq)) in self.lines.iter_mut().zip(gaps.into_iter().rev().skip(1)) {
+            line.target_gap = dt;
+            line.query_gap = dq;
+        }
+        if let Some(last) = self.lines.last_mut() {
+            last.target_gap = 0;
+            last.query_gap = 0;
+        }
+        Ok(())
+    }
+
+    /// Split the chain at gaps longer than `max_gap` in target or query, spans of pieces are
+    /// recomputed and the score is shared by aligned bases, chain ids are kept
+    pub fn split_at_gaps(self, max_gap: u64) -> Vec<ChainRecord> {
+        let total = self.lines.iter().map(|line| line.size).sum::<u64>();
+        let header = self.header;
+        let mut pieces = Vec::new();
+        let mut piece_lines: Vec<ChainDataLine> = Vec::new();
+        // start of the current piece and current position
+        let (mut piece_t, mut piece_q) = (header.target.start, header.query.start);
+        let (mut t, mut q) = (piece_t, piece_q);
+        let mut aligned = 0;
+        let line_count = self.lines.len();
+        for (i, line) in self.lines.into_iter().enumerate() {
+            let (dt, dq) = (line.target_gap, line.query_gap);
+            t += line.size;
+            q += line.size;
+            aligned += line.size;
+            piece_lines.push(line);
+            if i + 1 < line_count && (dt > max_gap || dq > max_gap) {
+                pieces.push(header.piece(piece_t, t, piece_q, q, aligned, total, piece_lines));
+                piece_lines = Vec::new();
+                aligned = 0;
+                piece_t = t + dt;
+                piece_q = q + dq;
+            }
+            t += dt;
+            q += dq;
+        }
+        if pieces.is_empty() {
+            return vec![ChainRecord {
+                header,
+                lines: piece_lines,
+            }];
+        }
+        pieces.push(header.piece(piece_t, t, piece_q, q, aligned, total, piece_lines));
+        pieces
     }
 }
 
@@ -85,8 +231,8 @@ pub struct ChainHeader {
 #[derive(Debug, Default)]
 pub struct ChainDataLine {
     pub size: u64,
-    pub query_diff: u64,
-    pub target_diff: u64,
+    pub target_gap: u64,
+    pub query_gap: u64,
 }
 
 impl fmt::Display for ChainDataLine {
@@ -94,7 +240,7 @@ impl fmt::Display for ChainDataLine {
         write!(
             f,
             "\n{}\t{}\t{}",
-            self.size, self.query_diff, self.target_diff
+            self.size, self.target_gap, self.query_gap
         )
     }
 }
@@ -127,14 +273,17 @@ impl TryFrom<&MAFRecord> for ChainHeader {
             Strand::Positive => {
                 header.query.start += head_ins;
                 header.target.start += head_del;
-                header.query.end -= tail_ins;
-                header.target.end -= tail_del;
+                header.query.end = value.coord_sub(header.query.end, tail_ins)?;
+                header.target.end = value.coord_sub(header.target.end, tail_del)?;
             }
             Strand::Negative => {
                 header.target.start += head_del;
-                header.target.end -= tail_del;
-                header.query.start = header.query.size - (header.query.end - head_ins);
-                header.query.end = header.query.size - (header.query.start + tail_ins);
+                header.target.end = value.coord_sub(header.target.end, tail_del)?;
+                // on the reverse strand, head of alignment is the end of query on forward strand
+                let query_end = value.coord_sub(header.query.end, head_ins)?;
+                let query_start = header.query.start + tail_ins;
+                header.query.start = value.coord_sub(header.query.size, query_end)?;
+                header.query.end = value.coord_sub(header.query.size, query_start)?;
             }
         }
         Ok(header)
@@ -168,20 +317,79 @@ impl TryFrom<&PafRecord> for ChainHeader {
             Strand::Positive => {
                 header.query.start += head_ins;
                 header.target.start += head_del;
-                header.query.end -= tail_ins;
-                header.target.end -= tail_del;
+                header.query.end = value.coord_sub(header.query.end, tail_ins)?;
+                header.target.end = value.coord_sub(header.target.end, tail_del)?;
             }
             Strand::Negative => {
                 header.target.start += head_del;
-                header.target.end -= tail_del;
-                header.query.start = header.query.size - (header.query.end - head_ins);
-                header.query.end = header.query.size - (header.query.start + tail_ins);
+                header.target.end = value.coord_sub(header.target.end, tail_del)?;
+                // on the reverse strand, head of alignment is the end of query on forward strand
+                let query_end = value.coord_sub(header.query.end, head_ins)?;
+                let query_start = header.query.start + tail_ins;
+                header.query.start = value.coord_sub(header.query.size, query_end)?;
+                header.query.end = value.coord_sub(header.query.size, query_start)?;
             }
         }
         Ok(header)
     }
 }
 
+impl ChainHeader {
+    /// Get the chain score
+    pub fn score(&self) -> f64 {
+        self.score
+    }
+
+    /// Set the chain score
+    pub fn set_score(&mut self, score: f64) {
+        self.score = score;
+    }
+
+    /// Get the target info
+    pub fn target(&self) -> &SeqInfo {
+        &self.target
+    }
+
+    /// Get the query info
+    pub fn query(&self) -> &SeqInfo {
+        &self.query
+    }
+
+    // a piece of the chain with its spans and share of score
+    #[allow(clippy::too_many_arguments)]
+    fn piece(
+        &self,
+        target_start: u64,
+        target_end: u64,
+        query_start: u64,
+        query_end: u64,
+        aligned: u64,
+        total: u64,
+        mut lines: Vec<ChainDataLine>,
+    ) -> ChainRecord {
+        // the last data line has only size
+        if let Some(last) = lines.last_mut() {
+            last.target_gap = 0;
+            last.query_gap = 0;
+        }
+        let header = ChainHeader {
+            score: (self.score * aligned as f64 / total.max(1) as f64).round(),
+            target: SeqInfo {
+                start: target_start,
+                end: target_end,
+                ..self.target.clone()
+            },
+            query: SeqInfo {
+                start: query_start,
+                end: query_end,
+                ..self.query.clone()
+            },
+            chain_id: self.chain_id,
+        };
+        ChainRecord { header, lines }
+    }
+}
+
 impl fmt::Display for ChainHeader {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -332,18 +540,18 @@ fn parse_line_to_cdl(line: &str) -> Result<ChainDataLine, WGAError> {
     let size = parse_str2u64(dataline.next().ok_or(WGAError::ParseChain(
         ParseChainErrKind::FiledMissing("size".to_string()),
     ))?)?;
-    let query_diff = match dataline.next() {
-        Some(query_diff) => parse_str2u64(query_diff)?,
+    let target_gap = match dataline.next() {
+        Some(target_gap) => parse_str2u64(target_gap)?,
         None => 0u64,
     };
-    let target_diff = match dataline.next() {
-        Some(target_diff) => parse_str2u64(target_diff)?,
+    let query_gap = match dataline.next() {
+        Some(query_gap) => parse_str2u64(query_gap)?,
         None => 0u64,
     };
     Ok(ChainDataLine {
         size,
-        query_diff,
-        target_diff,
+        target_gap,
+        query_gap,
     })
 }
 
@@ -378,7 +586,9 @@ fn chain_parser(input: &str) -> Result<(&str, ChainRecord), WGAError> {
     let (input, lines) = parse_chain_data_line(input)?;
     let lines = lines?;
     let (input, _) = take_while(|x| x != 'c')(input)?; // should better
-    let chainrecord = ChainRecord { header, lines };
+    let mut chainrecord = ChainRecord { header, lines };
+    // converters assume target on `+` strand
+    chainrecord.flip_to_target_positive()?;
     Ok((input, chainrecord))
 }
 
@@ -429,7 +639,7 @@ impl AlignRecord for ChainRecord {
 
     fn convert2paf(&mut self, _query_name: Option<&str>) -> Result<PafRecord, WGAError> {
         let cigar = parse_chain_to_cigar(self, false);
-        let cigar_string = cigar.cigar_string;
+        let cigar_string = String::from("cg:Z:") + &cigar.cigar_string;
         let block_length =
             (cigar.match_count + cigar.mismatch_count + cigar.del_count + cigar.inv_del_count)
                 as u64;
@@ -451,3 +661,78 @@ impl AlignRecord for ChainRecord {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::maf::MAFReader;
+    use crate::parser::paf::PAFReader;
+
+    // 2I5M3D10M4I2D5I, indels at both ends and several indels at the tail
+    const PAF_NEG: &str = "q\t100\t10\t36\t-\tt\t200\t50\t70\t15\t31\t60\tcg:Z:2I5M3D10M4I2D5I\n";
+    const PAF_POS: &str = "q\t100\t10\t36\t+\tt\t200\t50\t70\t15\t31\t60\tcg:Z:2I5M3D10M4I2D5I\n";
+    const MAF_NEG: &str = "##maf version=1\n\
+        a score=0\n\
+        s t 50 20 + 200 --AAAAAAAAAAAAAAAAAA----AA-----\n\
+        s q 64 26 - 100 AAAAAAA---AAAAAAAAAAAAAA--AAAAA\n\n";
+
+    fn paf_header(paf: &str) -> ChainHeader {
+        let rec = PAFReader::new(paf.as_bytes())
+            .records()
+            .next()
+            .unwrap()
+            .unwrap();
+        ChainHeader::try_from(&rec).unwrap()
+    }
+
+    fn spans(header: &ChainHeader) -> [u64; 4] {
+        [
+            header.target.start,
+            header.target.end,
+            header.query.start,
+            header.query.end,
+        ]
+    }
+
+    #[test]
+    fn trim_all_tail_indels() {
+        // old trim kept only the last tail indel, query end was 31
+        assert_eq!(spans(&paf_header(PAF_POS)), [50, 68, 12, 27]);
+    }
+
+    #[test]
+    fn query_end_on_reverse_strand() {
+        // old query end was computed from the new query start, 29 < start
+        assert_eq!(spans(&paf_header(PAF_NEG)), [50, 68, 66, 81]);
+        let rec = MAFReader::new(MAF_NEG.as_bytes())
+            .unwrap()
+            .records()
+            .next()
+            .unwrap()
+            .unwrap();
+        let header = ChainHeader::try_from(&rec).unwrap();
+        assert_eq!(spans(&header), [50, 68, 66, 81]);
+    }
+
+    #[test]
+    fn trim_without_aligned_column() {
+        // indels would be trimmed as both head and tail, start after end
+        let paf = "q\t100\t10\t15\t+\tt\t200\t50\t53\t0\t8\t60\tcg:Z:5I3D\n";
+        let rec = PAFReader::new(paf.as_bytes())
+            .records()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert!(ChainHeader::try_from(&rec).is_err());
+        let maf = "a score=0\n\
+                   s t 50 3 + 200 AAA-----\n\
+                   s q 10 5 + 100 ---AAAAA\n\n";
+        let rec = MAFReader::new(maf.as_bytes())
+            .unwrap()
+            .records()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert!(ChainHeader::try_from(&rec).is_err());
+    }
+}
@@ -1,14 +1,12 @@
 use crate::errors::WGAError;
+use crate::log::{profile, Stage};
 use crate::parser::chain::{ChainDataLine, ChainRecord};
-use crate::parser::common::AlignRecord;
+use crate::parser::common::{AlignRecord, Block, Strand};
 use crate::tools::dotplot::BasePlotdata;
 use crate::utils::parse_str2u64;
 // use csv::Writer;
 use itertools::Itertools;
-use nom::bytes::complete::{tag, take_till, take_while};
-use nom::error::Error;
-use nom::multi::fold_many1;
-use nom::{AsChar, IResult};
+use nom::bytes::complete::tag;
 use std::io::Write;
 use std::str;
 
@@ -35,124 +33,318 @@ struct CigarUnit {
     len: u64, // length of the operation
 }
 
-struct CigarStrTuple<'a>(&'a str, &'a str);
-/// Convert CigarStrTuple to CigarUnit
-/// NOTE: We have carried out seemingly cumbersome operations here,
-/// in fact, in order to better transmit errors due to
-/// nom::fold_many1, we need to return IResult for FnMut
-fn cst2cu(cst: CigarStrTuple) -> Result<CigarUnit, WGAError> {
-    // just consume the first char of cst.0, will faster than cst.0.as_bytes()[0]
-    let mut chars = cst.0.chars();
-    let op = match chars.next() {
-        Some(c) => c,
-        None => return Err(WGAError::CigarOpInvalid(cst.0.to_string())),
-    };
-    // read cst.0 next again to check if it is a valid op
-    if chars.next().is_some() {
-        return Err(WGAError::CigarOpInvalid(cst.0.to_string()));
+/// Iterate CigarUnits of a cigar string without tag, on bytes rather than chars
+struct CigarUnits<'a> {
+    cigar: &'a [u8],
+    pos: usize,
+}
+
+/// Iterate CigarUnits of a cigar string, error if it is empty.
+/// Clips (`S`/`H` of SAM) at both ends are skipped, as they align nothing
+fn cigar_units(cigar: &str) -> Result<CigarUnits<'_>, WGAError> {
+    let (_, cigar, _) = split_clips(cigar)?;
+    if cigar.is_empty() {
+        return Err(WGAError::CigarOpInvalid(String::new()));
+    }
+    Ok(CigarUnits {
+        cigar: cigar.as_bytes(),
+        pos: 0,
+    })
+}
+
+/// Split clips off both ends of a cigar string without tag,
+/// into the length of leading clips, the rest and the length of trailing clips
+fn split_clips(cigar: &str) -> Result<(u64, &str, u64), WGAError> {
+    let bytes = cigar.as_bytes();
+    let is_clip = |b: Option<&u8>| matches!(b, Some(b'S' | b'H'));
+    let (mut start, mut end) = (0, bytes.len());
+    let mut head = 0;
+    // only a few units are walked when there is no clip
+    loop {
+        let op = start
+            + bytes[start..end]
+                .iter()
+                .position(|b| !b.is_ascii_digit())
+                .unwrap_or(end - start);
+        if op == start || !is_clip(bytes.get(op)) {
+            break;
+        }
+        head += parse_str2u64(&cigar[start..op])?;
+        start = op + 1;
+    }
+    let mut tail = 0;
+    while end > start && is_clip(bytes.get(end - 1)) {
+        let digits = bytes[start..end - 1]
+            .iter()
+            .rposition(|b| !b.is_ascii_digit())
+            .map_or(start, |i| start + i + 1);
+        if digits == end - 1 {
+            break;
+        }
+        tail += parse_str2u64(&cigar[digits..end - 1])?;
+        end = digits;
     }
-    let len = parse_str2u64(cst.1)?; // actually it will never occur error
-    Ok(CigarUnit { op, len })
+    Ok((head, &cigar[start..end], tail))
 }
 
-/// Parse a cigar unit until the input is empty
-fn parse_cigar_str_tuple(input: &str) -> IResult<&str, CigarStrTuple> {
-    // if input is empty, return error to break infinite loop
-    if input.is_empty() {
-        return Err(nom::Err::Error(Error::new(
-            input,
-            nom::error::ErrorKind::Eof,
-        )));
+/// Clips of a cigar string with `cg:Z:` tag, as the length of leading clips,
+/// trailing clips and query consumed by aligned units, None if there is no clip
+pub fn parse_cigar_clips(cigar: &str) -> Result<Option<(u64, u64, u64)>, WGAError> {
+    let cigar = cigar.strip_prefix("cg:Z:").unwrap_or(cigar);
+    let (head, rest, tail) = split_clips(cigar)?;
+    if head == 0 && tail == 0 {
+        return Ok(None);
     }
+    let mut query_len = 0;
+    for cigarunit in cigar_units(rest)? {
+        let cigarunit = cigarunit?;
+        if matches!(cigarunit.op, 'M' | '=' | 'X' | 'I') {
+            query_len += cigarunit.len;
+        }
+    }
+    Ok(Some((head, tail, query_len)))
+}
 
-    // let input = input.as_bytes();
-    // take digits
-    let (input, len) = take_while(AsChar::is_dec_digit)(input)?;
-    // take alphabetic
-    let (input, op) = take_till(AsChar::is_dec_digit)(input)?;
+/// Collapse `=` and `X` of a cigar string without tag into `M`, merging adjacent units
+pub fn cigar_eqx_to_m(cigar: &str) -> Result<String, WGAError> {
+    let mut units: Vec<(u64, char)> = Vec::new();
+    for cigarunit in cigar_units(cigar)? {
+        let cigarunit = cigarunit?;
+        let op = match cigarunit.op {
+            '=' | 'X' => 'M',
+            op => op,
+        };
+        match units.last_mut() {
+            Some((len, last)) if *last == op => *len += cigarunit.len,
+            _ => units.push((cigarunit.len, op)),
+        }
+    }
+    Ok(units
+        .into_iter()
+        .map(|(len, op)| format!("{}{}", len, op))
+        .collect())
+}
 
-    Ok((input, CigarStrTuple(op, len)))
+/// Split `M` of a cigar string without tag into `=` and `X` by comparing bases,
+/// `target` and `query` are the aligned parts without gaps, query on the aligned strand
+pub fn cigar_m_to_eqx(cigar: &str, target: &[u8], query: &[u8]) -> Result<String, WGAError> {
+    let mut cigar_string = String::new();
+    let (mut tpos, mut qpos) = (0, 0);
+    for cigarunit in cigar_units(cigar)? {
+        let cigarunit = cigarunit?;
+        let len = cigarunit.len as usize;
+        match cigarunit.op {
+            'M' | '=' | 'X' => {
+                let (Some(tseq), Some(qseq)) =
+                    (target.get(tpos..tpos + len), query.get(qpos..qpos + len))
+                else {
+                    return Err(WGAError::CigarOpInvalid(format!("{}{}", len, cigarunit.op)));
+                };
+                let groups = tseq
+                    .iter()
+                    .zip(qseq)
+                    .group_by(|(t, q)| if t == q { '=' } else { 'X' });
+                for (op, g) in groups.into_iter() {
+                    cigar_string.push_str(&g.count().to_string());
+                    cigar_string.push(op);
+                }
+                tpos += len;
+                qpos += len;
+            }
+            'I' => {
+                qpos += len;
+                cigar_string.push_str(&format!("{}I", len));
+            }
+            'D' => {
+                tpos += len;
+                cigar_string.push_str(&format!("{}D", len));
+            }
+            op => return Err(WGAError::CigarOpInvalid(op.to_string())),
+        }
+    }
+    Ok(cigar_string)
 }
 
-/// a phantom
-fn null() -> Result<(), WGAError> {
+impl Iterator for CigarUnits<'_> {
+    type Item = Result<CigarUnit, WGAError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.cigar[self.pos..];
+        if rest.is_empty() {
+            return None;
+        }
+        // take digits, accumulate length in the same pass
+        let mut len: u64 = 0;
+        let mut i = 0;
+        while let Some(d) = rest
+            .get(i)
+            .map(|b| b.wrapping_sub(b'0'))
+            .filter(|d| *d < 10)
+        {
+            len = len.wrapping_mul(10).wrapping_add(d as u64);
+            i += 1;
+        }
+        let digits = &rest[..i];
+        // take op until next digit
+        while rest.get(i).is_some_and(|b| !b.is_ascii_digit()) {
+            i += 1;
+        }
+        let op = &rest[digits.len()..i];
+        self.pos += i;
+
+        if op.len() != 1 {
+            return Some(Err(WGAError::CigarOpInvalid(
+                String::from_utf8_lossy(op).into_owned(),
+            )));
+        }
+        // u64 holds any 19 digits, longer ones may overflow
+        if digits.is_empty() || digits.len() > 19 {
+            let digits = String::from_utf8_lossy(digits);
+            len = match parse_str2u64(&digits) {
+                Ok(len) => len,
+                Err(e) => return Some(Err(e)),
+            };
+        }
+        Some(Ok(CigarUnit {
+            op: op[0] as char,
+            len,
+        }))
+    }
+}
+
+/// Walk a cigar unit on a block, emit the block when a gap is met
+fn cigar_unit_block<'a>(
+    op: char,
+    count: u64,
+    block: &mut Block<'a>,
+    blocks: &mut Vec<Block<'a>>,
+) -> Result<(), WGAError> {
+    match op {
+        'M' | '=' | 'X' => {
+            // move query&target, adjacent gapless units are merged
+            block.query_end += count;
+            block.target_end += count;
+        }
+        'I' | 'D' => {
+            if block.target_end > block.target_start {
+                blocks.push(*block);
+            }
+            if op == 'I' {
+                // only move query
+                block.query_end += count;
+            } else {
+                // only move target
+                block.target_end += count;
+            }
+            // sync query&target start with end
+            block.query_start = block.query_end;
+            block.target_start = block.target_end;
+        }
+        _ => return Err(WGAError::CigarOpInvalid(op.to_string())),
+    };
     Ok(())
 }
 
-// fn cigar_unit_block(
-//     op: char,
-//     count: u64,
-//     wtr: &mut Writer<&mut dyn Write>,
-//     block: &mut Block,
-// ) -> Result<(), WGAError> {
-//     match op {
-//         'M' => {
-//             // move query&target
-//             block.query_end += count;
-//             block.target_end += count;
-
-//             // write and serialize
-//             wtr.serialize(&block)?;
-
-//             // sync query&target start with end
-//             block.query_start = block.query_end;
-//             block.target_start = block.target_end;
-//         }
-//         'I' => {
-//             // only move query start&end
-//             block.query_end += count;
-//             block.query_start += count;
-//         }
-//         'D' => {
-//             // only move target start&end
-//             block.target_end += count;
-//             block.target_start += count;
-//         }
-//         _ => return Err(WGAError::CigarOpInvalid(op.to_string())), // TODO: handle `H` for SAM
-//     };
-//     Ok(())
-// }
-
-// Parse cigar string of a AlignRecord[PafRecord, SamRecord] which includes cg:Z: tag and
-// write into a blocks file
-// - For PafRecord: cigar should only contains 'M,I,D'
-// - For SamRecord: cigar's first `[0-9]+H` should represent the query start
-// pub fn parse_cigar_to_blocks<T: AlignRecord>(
-//     rec: &T,
-//     wtr: &mut Writer<&mut dyn Write>,
-// ) -> Result<(), WGAError> {
-//     // get cigar bytes and tags
-//     let cigar = rec.get_cigar_str()?;
-//     let (cigar, _tag) = tag("cg:Z:")(cigar)?;
-
-//     // init a original block
-//     let mut block = Block {
-//         query_name: rec.query_name(),
-//         query_start: rec.query_start(),
-//         query_end: rec.query_start(),
-//         target_name: rec.target_name(),
-//         target_start: rec.target_start(),
-//         target_end: rec.target_start(),
-//         strand: rec.query_strand(),
-//     };
-
-//     // fold cigar bytes into many CigarUnits[#CigarUnit]
-//     let (_, res) = fold_many1(
-//         parse_cigar_str_tuple,
-//         null,
-//         |res: Result<(), WGAError>, cigarunit| {
-//             if res.is_ok() {
-//                 let cigarunit = cst2cu(cigarunit)?;
-//                 cigar_unit_block(cigarunit.op, cigarunit.len, wtr, &mut block)?
-//             }
-//             res
-//         },
-//     )(cigar)?;
-//     res
-// }
+// init a empty block at the start of record
+fn init_block<T: AlignRecord>(rec: &T) -> Block<'_> {
+    Block {
+        query_name: rec.query_name(),
+        query_start: rec.query_start(),
+        query_end: rec.query_start(),
+        target_name: rec.target_name(),
+        target_start: rec.target_start(),
+        target_end: rec.target_start(),
+        strand: rec.query_strand(),
+    }
+}
+
+// the walk goes along the reverse complemented query in negative strand,
+// convert query coordinates back to the forward strand
+fn finish_blocks<T: AlignRecord>(rec: &T, blocks: &mut [Block]) {
+    if rec.query_strand() == Strand::Negative {
+        let (q_start, q_end) = (rec.query_start(), rec.query_end());
+        for block in blocks {
+            let walk_start = block.query_start - q_start;
+            let walk_end = block.query_end - q_start;
+            block.query_start = q_end - walk_end;
+            block.query_end = q_end - walk_start;
+        }
+    }
+}
+
+/// Parse cigar string of a AlignRecord[PafRecord, SamRecord] which includes cg:Z: tag
+/// into gapless blocks
+/// - cigar should only contains 'M,I,D,=,X' between clips 'S,H' at both ends
+pub fn parse_cigar_to_blocks<T: AlignRecord>(rec: &T) -> Result<Vec<Block<'_>>, WGAError> {
+    profile(Stage::Cigar, || cigar_to_blocks(rec)).map_err(|e| rec.context_err(e))
+}
+
+fn cigar_to_blocks<T: AlignRecord>(rec: &T) -> Result<Vec<Block<'_>>, WGAError> {
+    let mut block = init_block(rec);
+    let mut blocks = Vec::new();
+    for (op, len) in parse_cigar_to_units(rec)? {
+        cigar_unit_block(op, len, &mut block, &mut blocks)?;
+    }
+    if block.target_end > block.target_start {
+        blocks.push(block);
+    }
+    finish_blocks(rec, &mut blocks);
+    Ok(blocks)
+}
+
+/// Parse MAF sequences of target and query into gapless blocks
+pub fn parse_maf_seq_to_blocks<T: AlignRecord>(rec: &T) -> Result<Vec<Block<'_>>, WGAError> {
+    profile(Stage::Cigar, || maf_seq_to_blocks(rec))
+}
+
+fn maf_seq_to_blocks<T: AlignRecord>(rec: &T) -> Result<Vec<Block<'_>>, WGAError> {
+    let mut block = init_block(rec);
+    let mut blocks = Vec::new();
+    let group_by_iter = rec
+        .target_seq()
+        .chars()
+        .zip(rec.query_seq().chars())
+        .group_by(|(c1, c2)| cigar_cat_ext_caller(c1, c2));
+    for (op, g) in group_by_iter.into_iter() {
+        // columns with gaps in both are skipped
+        if op == 'W' {
+            continue;
+        }
+        cigar_unit_block(op, g.count() as u64, &mut block, &mut blocks)?;
+    }
+    if block.target_end > block.target_start {
+        blocks.push(block);
+    }
+    finish_blocks(rec, &mut blocks);
+    Ok(blocks)
+}
+
+/// Parse data lines of ChainRecord into gapless blocks
+pub fn parse_chain_to_blocks(rec: &ChainRecord) -> Result<Vec<Block<'_>>, WGAError> {
+    profile(Stage::Cigar, || chain_to_blocks(rec))
+}
+
+fn chain_to_blocks(rec: &ChainRecord) -> Result<Vec<Block<'_>>, WGAError> {
+    let mut block = init_block(rec);
+    let mut blocks = Vec::new();
+    for line in &rec.lines {
+        cigar_unit_block('M', line.size, &mut block, &mut blocks)?;
+        cigar_unit_block('D', line.target_gap, &mut block, &mut blocks)?;
+        cigar_unit_block('I', line.query_gap, &mut block, &mut blocks)?;
+    }
+    if block.target_end > block.target_start {
+        blocks.push(block);
+    }
+    finish_blocks(rec, &mut blocks);
+    Ok(blocks)
+}
 
 ///   Parse maf seq to get indel count in head and tail
 pub fn parse_maf_seq_to_trim<T: AlignRecord>(rec: &T) -> Result<(u64, u64, u64, u64), WGAError> {
+    profile(Stage::Cigar, || maf_seq_to_trim(rec)).map_err(|e| rec.context_err(e))
+}
+
+fn maf_seq_to_trim<T: AlignRecord>(rec: &T) -> Result<(u64, u64, u64, u64), WGAError> {
     let mut head_ins = 0;
     let mut head_del = 0;
     let mut tail_ins = 0;
@@ -160,12 +352,7 @@ pub fn parse_maf_seq_to_trim<T: AlignRecord>(rec: &T) -> Result<(u64, u64, u64,
 
     let mut head_indel = true;
 
-    let seq1_iter = rec.target_seq().chars();
-    let seq2_iter = rec.query_seq().chars();
-
-    let group_by_iter = seq1_iter
-        .zip(seq2_iter)
-        .group_by(|(c1, c2)| cigar_cat_ext(c1, c2));
+    let group_by_iter = maf_columns(rec).group_by(|(c1, c2)| cigar_cat_ext(c1, c2));
     for (k, g) in group_by_iter.into_iter() {
         let count = g.count();
         match k {
@@ -178,17 +365,18 @@ pub fn parse_maf_seq_to_trim<T: AlignRecord>(rec: &T) -> Result<(u64, u64, u64,
                 if head_indel {
                     head_ins += count;
                 }
-                tail_ins = count;
+                tail_ins += count;
             }
             'D' => {
                 if head_indel {
                     head_del += count;
                 }
-                tail_del = count;
+                tail_del += count;
             }
             _ => return Err(WGAError::CigarOpInvalid(k.to_string())),
         }
     }
+    no_aligned_to_trim(head_indel)?;
 
     Ok((
         head_ins as u64,
@@ -200,6 +388,10 @@ pub fn parse_maf_seq_to_trim<T: AlignRecord>(rec: &T) -> Result<(u64, u64, u64,
 
 /// Parse cigar string to get indel count in head and tail
 pub fn parse_cigar_to_trim<T: AlignRecord>(rec: &T) -> Result<(u64, u64, u64, u64), WGAError> {
+    profile(Stage::Cigar, || cigar_to_trim(rec)).map_err(|e| rec.context_err(e))
+}
+
+fn cigar_to_trim<T: AlignRecord>(rec: &T) -> Result<(u64, u64, u64, u64), WGAError> {
     let mut head_ins = 0;
     let mut head_del = 0;
     let mut tail_ins = 0;
@@ -210,47 +402,54 @@ pub fn parse_cigar_to_trim<T: AlignRecord>(rec: &T) -> Result<(u64, u64, u64, u6
     let cigar = rec.get_cigar_string()?;
     let (cigar, _tag) = tag("cg:Z:")(cigar.as_str())?;
 
-    let (_, _) = fold_many1(
-        parse_cigar_str_tuple,
-        null,
-        |res: Result<(), WGAError>, cigarunit| {
-            if res.is_ok() {
-                let cigarunit = cst2cu(cigarunit)?;
-                match cigarunit.op {
-                    'M' | '=' | 'X' => {
-                        tail_ins = 0;
-                        tail_del = 0;
-                        head_indel = false;
-                    }
-                    'I' => {
-                        if head_indel {
-                            head_ins += cigarunit.len;
-                        }
-                        tail_ins = cigarunit.len;
-                    }
-                    'D' => {
-                        if head_indel {
-                            head_del += cigarunit.len;
-                        }
-                        tail_del = cigarunit.len;
-                    }
-                    _ => return Err(WGAError::CigarOpInvalid(cigarunit.op.to_string())), // TODO: handle 'H' for SAM
-                };
+    for cigarunit in cigar_units(cigar)? {
+        let cigarunit = cigarunit?;
+        match cigarunit.op {
+            'M' | '=' | 'X' => {
+                tail_ins = 0;
+                tail_del = 0;
+                head_indel = false;
+            }
+            'I' => {
+                if head_indel {
+                    head_ins += cigarunit.len;
+                }
+                tail_ins += cigarunit.len;
             }
-            res
-        },
-    )(cigar)?;
+            'D' => {
+                if head_indel {
+                    head_del += cigarunit.len;
+                }
+                tail_del += cigarunit.len;
+            }
+            _ => return Err(WGAError::CigarOpInvalid(cigarunit.op.to_string())),
+        };
+    }
+    no_aligned_to_trim(head_indel)?;
 
     Ok((head_ins, head_del, tail_ins, tail_del))
 }
 
+// without any M/=/X, all indels would be trimmed as both head and tail
+fn no_aligned_to_trim(head_indel: bool) -> Result<(), WGAError> {
+    if head_indel {
+        return Err(WGAError::InvalidRecord(
+            "No aligned column (M/=/X) to trim head and tail indels around".to_string(),
+        ));
+    }
+    Ok(())
+}
+
 /// Parse cigar string of a AlignRecord[PafRecord, SamRecord] which includes cg:Z: tag and
 /// write into a chain file.
-/// - For PafRecord: cigar should only contains 'M,I,D'
-/// - For SamRecord: cigar's first `[0-9]+H` should represent the query start
-pub fn parse_cigar_to_chain<T: AlignRecord>(
+/// - cigar should only contains 'M,I,D,=,X' between clips 'S,H' at both ends
+pub fn parse_cigar_to_chain<T: AlignRecord>(rec: &T, wtr: &mut dyn Write) -> Result<(), WGAError> {
+    profile(Stage::Cigar, || cigar_to_chain(rec, wtr)).map_err(|e| rec.context_err(e))
+}
+
+fn cigar_to_chain<T: AlignRecord>(
     rec: &T,
-    wtr: &mut Box<dyn Write>,
+    wtr: &mut dyn Write,
     // ) -> Result<(&'a str, Result<(), WGAError>), WGAError> {
 ) -> Result<(), WGAError> {
     // get cigar bytes and tag
@@ -260,8 +459,8 @@ pub fn parse_cigar_to_chain<T: AlignRecord>(
     // init a ChainDataLine filled 0
     let mut dataline = ChainDataLine {
         size: 0,
-        query_diff: 0,
-        target_diff: 0,
+        target_gap: 0,
+        query_gap: 0,
     };
 
     // try regex for cigar
@@ -271,27 +470,26 @@ pub fn parse_cigar_to_chain<T: AlignRecord>(
     //     let op = cap[2].chars().next()?;
     //     cigar_unit_chain(op, len, wtr, &mut dataline)?;
     // }
-    // Unfortunately, it turns out that this is three times slower than nom
-
-    // fold cigar bytes into many CigarUnits[#CigarUnit]
-    let (_, res) = fold_many1(
-        parse_cigar_str_tuple,
-        null,
-        |res: Result<(), WGAError>, cigarunit| {
-            // Continue execution only when no error occurs
-            if res.is_ok() {
-                let cigarunit = cst2cu(cigarunit)?;
-                cigar_unit_chain(cigarunit.op, cigarunit.len, wtr, &mut dataline)?;
-            }
-            res
-        },
-    )(cigar)?;
+    // Unfortunately, it turns out that this is three times slower than nom,
+    // and nom on chars is slower than walking bytes
 
-    // After all cigar units successfully write done, the last dataline.size should be wrote
-    if res.is_ok() {
-        wtr.write_all(format!("\n{}", dataline.size).as_bytes())?;
+    // walk cigar bytes unit by unit
+    for cigarunit in cigar_units(cigar)? {
+        let cigarunit = cigarunit?;
+        cigar_unit_chain(cigarunit.op, cigarunit.len, wtr, &mut dataline)?;
     }
-    res
+
+    // After all cigar units successfully write done, the last dataline.size should be wrote
+    write!(wtr, "\n{}", dataline.size)?;
+    Ok(())
+}
+
+/// Columns of target and query sequences, columns gapped in both are not aligned and skipped
+pub fn maf_columns<T: AlignRecord>(rec: &T) -> impl Iterator<Item = (char, char)> + '_ {
+    rec.target_seq()
+        .chars()
+        .zip(rec.query_seq().chars())
+        .filter(|(c1, c2)| *c1 != '-' || *c2 != '-')
 }
 
 /// cigar category method -- extension
@@ -310,6 +508,7 @@ pub fn cigar_cat_ext(c1: &char, c2: &char) -> char {
 // cigar category method for caller if follow the MAF format
 // AAA--GGG
 // AAA--GGC
+// soft-masked (lowercase) bases are matched case-insensitively
 
 pub fn cigar_cat_ext_caller(c1: &char, c2: &char) -> char {
     if c1 == &'-' {
@@ -320,7 +519,7 @@ pub fn cigar_cat_ext_caller(c1: &char, c2: &char) -> char {
         }
     } else if c2 == &'-' {
         'D'
-    } else if c1 == c2 {
+    } else if c1.eq_ignore_ascii_case(c2) {
         '='
     } else {
         'X'
@@ -342,9 +541,11 @@ pub fn cigar_cat(c1: &char, c2: &char) -> char {
 
 /// parse MAF two seqs into Cigar
 pub fn parse_maf_seq_to_cigar<T: AlignRecord>(rec: &T, with_h: bool) -> Cigar {
+    profile(Stage::Cigar, || maf_seq_to_cigar(rec, with_h))
+}
+
+fn maf_seq_to_cigar<T: AlignRecord>(rec: &T, with_h: bool) -> Cigar {
     let mut cigar_string = String::new();
-    let seq1_iter = rec.target_seq().chars();
-    let seq2_iter = rec.query_seq().chars();
     let mut match_count = 0;
     let mut mismatch_count = 0;
     let mut ins_event = 0;
@@ -356,9 +557,7 @@ pub fn parse_maf_seq_to_cigar<T: AlignRecord>(rec: &T, with_h: bool) -> Cigar {
     let mut inv_del_event = 0;
     let mut inv_del_count = 0;
     let mut inv_event = 0;
-    let group_by_iter = seq1_iter
-        .zip(seq2_iter)
-        .group_by(|(c1, c2)| cigar_cat_ext(c1, c2));
+    let group_by_iter = maf_columns(rec).group_by(|(c1, c2)| cigar_cat_ext(c1, c2));
 
     let begin = rec.query_start() as usize;
     let end = rec.query_length() - rec.query_end();
@@ -434,57 +633,57 @@ pub fn parse_maf_seq_to_cigar<T: AlignRecord>(rec: &T, with_h: bool) -> Cigar {
 /// parse MAF two seqs adn write into a chain file
 pub fn parse_maf_seq_to_chain<T: AlignRecord>(
     rec: &T,
-    wtr: &mut Box<dyn Write>,
+    wtr: &mut dyn Write,
 ) -> Result<(), WGAError> {
-    let seq1_iter = rec.target_seq().chars();
-    let seq2_iter = rec.query_seq().chars();
-    let group_by_iter = seq1_iter
-        .zip(seq2_iter)
-        .group_by(|(c1, c2)| cigar_cat(c1, c2));
+    profile(Stage::Cigar, || maf_seq_to_chain(rec, wtr))
+}
+
+fn maf_seq_to_chain<T: AlignRecord>(rec: &T, wtr: &mut dyn Write) -> Result<(), WGAError> {
+    let group_by_iter = maf_columns(rec).group_by(|(c1, c2)| cigar_cat(c1, c2));
 
     // init a ChainDataLine filled 0
     let mut dataline = ChainDataLine {
         size: 0,
-        query_diff: 0,
-        target_diff: 0,
+        target_gap: 0,
+        query_gap: 0,
     };
     for (k, g) in group_by_iter.into_iter() {
         let len = g.count() as u64;
         cigar_unit_chain(k, len, wtr, &mut dataline)?;
     }
     // After all cigar units write done, the last dataline.size should be wrote
-    wtr.write_all(format!("\n{}", dataline.size).as_bytes())?;
+    write!(wtr, "\n{}", dataline.size)?;
     Ok(())
 }
 
 fn cigar_unit_chain(
     op: char,
     count: u64,
-    wtr: &mut Box<dyn Write>,
+    wtr: &mut dyn Write,
     dataline: &mut ChainDataLine,
 ) -> Result<(), WGAError> {
     match op {
         'M' | 'X' | '=' => {
-            // will not write unless: [1. size == 0; 2. both no query&target diff]
-            if (dataline.size != 0) && (dataline.target_diff + dataline.query_diff != 0) {
-                wtr.write_all(format!("{}", dataline).as_bytes())?;
+            // will not write unless: [1. size == 0; 2. both no query&target gap]
+            if (dataline.size != 0) && (dataline.query_gap + dataline.target_gap != 0) {
+                write!(wtr, "{}", dataline)?;
                 dataline.size = 0;
             };
             // accumulate size
             dataline.size += count;
-            // init query&target diff
-            dataline.target_diff = 0;
-            dataline.query_diff = 0;
+            // init query&target gap
+            dataline.query_gap = 0;
+            dataline.target_gap = 0;
         }
         'I' => {
-            // accumulate target diff for 'I'
-            dataline.target_diff += count;
+            // accumulate query gap for 'I'
+            dataline.query_gap += count;
         }
         'D' => {
-            // accumulate query diff for 'D'
-            dataline.query_diff += count;
+            // accumulate target gap for 'D'
+            dataline.target_gap += count;
         }
-        _ => return Err(WGAError::CigarOpInvalid(op.to_string())), // TODO: handle 'H' for SAM
+        _ => return Err(WGAError::CigarOpInvalid(op.to_string())),
     };
     Ok(())
 }
@@ -513,45 +712,69 @@ fn cigar_unit_insert_seq(
             q_seq.insert_str(*current_offset as usize, &del_str);
             *current_offset += count;
         }
-        _ => return Err(WGAError::CigarOpInvalid(op.to_string())), // TODO: handle 'H' for SAM
+        _ => return Err(WGAError::CigarOpInvalid(op.to_string())),
     };
     Ok(())
 }
 
+/// Parse cigar of record into (op, len) units
+pub fn parse_cigar_to_units<T: AlignRecord>(rec: &T) -> Result<Vec<(char, u64)>, WGAError> {
+    profile(Stage::Cigar, || cigar_to_units(rec)).map_err(|e| rec.context_err(e))
+}
+
+fn cigar_to_units<T: AlignRecord>(rec: &T) -> Result<Vec<(char, u64)>, WGAError> {
+    // get cigar bytes and tag
+    let cigar = rec.get_cigar_string()?;
+    // MAF record has no `cg:Z:` tag
+    let cigar = cigar.strip_prefix("cg:Z:").unwrap_or(&cigar);
+
+    let mut units = Vec::new();
+    for cigarunit in cigar_units(cigar)? {
+        let cigarunit = cigarunit?;
+        units.push((cigarunit.op, cigarunit.len));
+    }
+    Ok(units)
+}
+
 /// Parse cigar to insert `-` in MAF sequences
 pub fn parse_cigar_to_insert<T: AlignRecord>(
     rec: &T,
     t_seq: &mut String,
     q_seq: &mut String,
+) -> Result<(), WGAError> {
+    profile(Stage::Cigar, || cigar_to_insert(rec, t_seq, q_seq)).map_err(|e| rec.context_err(e))
+}
+
+fn cigar_to_insert<T: AlignRecord>(
+    rec: &T,
+    t_seq: &mut String,
+    q_seq: &mut String,
 ) -> Result<(), WGAError> {
     // get cigar bytes and tag
     let cigar = rec.get_cigar_string()?;
     let (cigar, _tag) = tag("cg:Z:")(cigar.as_str())?;
 
-    // fold cigar bytes into many CigarUnits[#CigarUnit]
+    // walk cigar bytes unit by unit
     let mut current_offset = 0;
-    let (_, res) = fold_many1(
-        parse_cigar_str_tuple,
-        null,
-        |res: Result<(), WGAError>, cigarunit| {
-            if res.is_ok() {
-                let cigarunit = cst2cu(cigarunit)?;
-                cigar_unit_insert_seq(
-                    cigarunit.op,
-                    cigarunit.len,
-                    &mut current_offset,
-                    t_seq,
-                    q_seq,
-                )?
-            }
-            res
-        },
-    )(cigar)?;
-    res
+    for cigarunit in cigar_units(cigar)? {
+        let cigarunit = cigarunit?;
+        cigar_unit_insert_seq(
+            cigarunit.op,
+            cigarunit.len,
+            &mut current_offset,
+            t_seq,
+            q_seq,
+        )?
+    }
+    Ok(())
 }
 
 /// parse ChainRecord into Cigar
 pub fn parse_chain_to_cigar(rec: &ChainRecord, _with_h: bool) -> Cigar {
+    profile(Stage::Cigar, || chain_to_cigar(rec))
+}
+
+fn chain_to_cigar(rec: &ChainRecord) -> Cigar {
     let mut cigar_string = String::new();
     let mut match_count = 0;
     let mismatch_count = 0;
@@ -575,8 +798,8 @@ pub fn parse_chain_to_cigar(rec: &ChainRecord, _with_h: bool) -> Cigar {
 
     for dataline in &rec.lines {
         let match_len = dataline.size;
-        let ins_len = dataline.target_diff;
-        let del_len = dataline.query_diff;
+        let ins_len = dataline.query_gap;
+        let del_len = dataline.target_gap;
         cigar_string.push_str(&match_len.to_string());
         cigar_string.push('M');
         match_count += match_len as usize;
@@ -625,10 +848,16 @@ pub fn parse_chain_to_cigar(rec: &ChainRecord, _with_h: bool) -> Cigar {
     }
 }
 
-/// Parse CIGAR to Cigar struct
-pub fn parse_paf_to_cigar<T: AlignRecord>(rec: &T) -> Result<Cigar, WGAError> {
+/// Parse CIGAR to Cigar struct, mismatches in `M` are taken from edit distance `nm`
+/// (`NM:i` tag) less indels if given, so `M` and `=`/`X` CIGARs count the same
+pub fn parse_paf_to_cigar<T: AlignRecord>(rec: &T, nm: Option<usize>) -> Result<Cigar, WGAError> {
+    profile(Stage::Cigar, || paf_to_cigar(rec, nm)).map_err(|e| rec.context_err(e))
+}
+
+fn paf_to_cigar<T: AlignRecord>(rec: &T, nm: Option<usize>) -> Result<Cigar, WGAError> {
     let cigar_string = String::new();
     let mut match_count = 0;
+    let mut m_count = 0;
     let mut mismatch_count = 0;
     let mut ins_event = 0;
     let mut ins_count = 0;
@@ -650,46 +879,48 @@ pub fn parse_paf_to_cigar<T: AlignRecord>(rec: &T) -> Result<Cigar, WGAError> {
 
     let cigar = rec.get_cigar_string()?;
     let (cigar, _tag) = tag("cg:Z:")(cigar.as_str())?;
-    let mut current_offset = 0;
-    let (_, res) = fold_many1(
-        parse_cigar_str_tuple,
-        null,
-        |res: Result<(), WGAError>, cigarunit| {
-            if res.is_ok() {
-                let cigarunit = cst2cu(cigarunit)?;
-                match cigarunit.op {
-                    'M' | '=' => {
-                        match_count += cigarunit.len as usize;
-                    }
-                    'X' => {
-                        mismatch_count += cigarunit.len as usize;
-                    }
-                    'I' => {
-                        if inv {
-                            inv_ins_event += 1;
-                            inv_ins_count += cigarunit.len as usize;
-                        } else {
-                            ins_event += 1;
-                            ins_count += cigarunit.len as usize;
-                        }
-                    }
-                    'D' => {
-                        if inv {
-                            inv_del_event += 1;
-                            inv_del_count += cigarunit.len as usize;
-                        } else {
-                            del_event += 1;
-                            del_count += cigarunit.len as usize;
-                        }
-                    }
-                    _ => return Err(WGAError::CigarOpInvalid(cigarunit.op.to_string())), // TODO: handle 'H' for SAM
-                };
-                current_offset += cigarunit.len;
+    for cigarunit in cigar_units(cigar)? {
+        let cigarunit = cigarunit?;
+        match cigarunit.op {
+            'M' => {
+                m_count += cigarunit.len as usize;
+            }
+            '=' => {
+                match_count += cigarunit.len as usize;
+            }
+            'X' => {
+                mismatch_count += cigarunit.len as usize;
             }
-            res
-        },
-    )(cigar)?;
-    res?;
+            'I' => {
+                if inv {
+                    inv_ins_event += 1;
+                    inv_ins_count += cigarunit.len as usize;
+                } else {
+                    ins_event += 1;
+                    ins_count += cigarunit.len as usize;
+                }
+            }
+            'D' => {
+                if inv {
+                    inv_del_event += 1;
+                    inv_del_count += cigarunit.len as usize;
+                } else {
+                    del_event += 1;
+                    del_count += cigarunit.len as usize;
+                }
+            }
+            _ => return Err(WGAError::CigarOpInvalid(cigarunit.op.to_string())),
+        };
+    }
+    if m_count > 0 {
+        // NM counts mismatches in `X` and `M` with bases of indels
+        let indels = ins_count + del_count + inv_ins_count + inv_del_count;
+        let m_mismatch = nm.map_or(0, |nm| {
+            nm.saturating_sub(indels + mismatch_count).min(m_count)
+        });
+        match_count += m_count - m_mismatch;
+        mismatch_count += m_mismatch;
+    }
     Ok(Cigar {
         cigar_string,
         match_count,
@@ -710,33 +941,25 @@ pub fn parse_paf_to_cigar<T: AlignRecord>(rec: &T) -> Result<Cigar, WGAError> {
 pub fn update_cov_vec(cov_vec: &mut [usize], cigar: &str, start: usize) -> Result<(), WGAError> {
     let (cigar, _tag) = tag("cg:Z:")(cigar)?;
     let mut pos = start;
-    let (_, res) = fold_many1(
-        parse_cigar_str_tuple,
-        null,
-        |res: Result<(), WGAError>, cigarunit| {
-            if res.is_ok() {
-                let cigarunit = cst2cu(cigarunit)?;
-                let length = cigarunit.len as usize;
-                match cigarunit.op {
-                    'M' | '=' => {
-                        for i in pos..(pos + length) {
-                            if i < cov_vec.len() {
-                                cov_vec[i] += 1;
-                            }
-                        }
-                        pos += length;
+    for cigarunit in cigar_units(cigar)? {
+        let cigarunit = cigarunit?;
+        let length = cigarunit.len as usize;
+        match cigarunit.op {
+            'M' | '=' => {
+                for i in pos..(pos + length) {
+                    if i < cov_vec.len() {
+                        cov_vec[i] += 1;
                     }
-                    'I' | 'S' => {}
+                }
+                pos += length;
+            }
+            'I' | 'S' => {}
 
-                    _ => {
-                        pos += length;
-                    }
-                };
+            _ => {
+                pos += length;
             }
-            res
-        },
-    )(cigar)?;
-    res?;
+        };
+    }
     Ok(())
 }
 
@@ -749,57 +972,45 @@ pub fn gen_pesudo_maf_by_cigar(
     let (cigar, _tag) = tag("cg:Z:")(cigar)?;
     let mut current_offset = 0;
     // let mut seq_line = String::new();
-    let (_, res) = fold_many1(
-        parse_cigar_str_tuple,
-        null,
-        |res: Result<(), WGAError>, cigarunit| {
-            if res.is_ok() {
-                let cigarunit = cst2cu(cigarunit)?;
-                let length = cigarunit.len as usize;
-                match cigarunit.op {
-                    'M' | '=' => {
-                        if base {
-                            current_offset += length;
-                        } else {
-                            for _ in 0..length {
-                                raw_q_seq.push('1');
-                            }
-                        }
-                    }
-                    'I' | 'S' => {
-                        if base {
-                            // del from whole_q_seq
-                            raw_q_seq.drain(current_offset..(current_offset + length));
-                        } else {
-                            // do nothing
-                        }
+    for cigarunit in cigar_units(cigar)? {
+        let cigarunit = cigarunit?;
+        let length = cigarunit.len as usize;
+        match cigarunit.op {
+            'M' | '=' => {
+                if base {
+                    current_offset += length;
+                } else {
+                    for _ in 0..length {
+                        raw_q_seq.push('1');
                     }
-                    'D' => {
-                        if base {
-                            raw_q_seq.insert_str(current_offset, &"-".repeat(length));
-                            current_offset += length;
-                        } else {
-                            for _ in 0..length {
-                                raw_q_seq.push('-');
-                            }
-                        }
+                }
+            }
+            'I' | 'S' if base => {
+                // del from whole_q_seq
+                raw_q_seq.drain(current_offset..(current_offset + length));
+            }
+            'D' => {
+                if base {
+                    raw_q_seq.insert_str(current_offset, &"-".repeat(length));
+                    current_offset += length;
+                } else {
+                    for _ in 0..length {
+                        raw_q_seq.push('-');
                     }
-                    'X' => {
-                        if base {
-                            current_offset += length;
-                        } else {
-                            for _ in 0..length {
-                                raw_q_seq.push('0');
-                            }
-                        }
+                }
+            }
+            'X' => {
+                if base {
+                    current_offset += length;
+                } else {
+                    for _ in 0..length {
+                        raw_q_seq.push('0');
                     }
-                    _ => {}
-                };
+                }
             }
-            res
-        },
-    )(cigar)?;
-    res?;
+            _ => {}
+        };
+    }
     Ok(())
 }
 
@@ -917,6 +1128,13 @@ fn emit_baseplotdatas<T: AlignRecord>(
 pub fn parse_cigar_to_base_plotdata<T: AlignRecord>(
     rec: &T,
     skip_cutoff: usize,
+) -> Result<Vec<BasePlotdata>, WGAError> {
+    cigar_to_base_plotdata(rec, skip_cutoff).map_err(|e| rec.context_err(e))
+}
+
+fn cigar_to_base_plotdata<T: AlignRecord>(
+    rec: &T,
+    skip_cutoff: usize,
 ) -> Result<Vec<BasePlotdata>, WGAError> {
     let cigar = rec.get_cigar_string()?;
     let (cigar, _tag) = tag("cg:Z:")(cigar.as_str())?;
@@ -927,27 +1145,19 @@ pub fn parse_cigar_to_base_plotdata<T: AlignRecord>(
     let mut base_plotdata_vec = Vec::new();
     let mut last_m = false;
 
-    let (_, res) = fold_many1(
-        parse_cigar_str_tuple,
-        null,
-        |res: Result<(), WGAError>, cigarunit| {
-            if res.is_ok() {
-                let cigarunit = cst2cu(cigarunit)?;
-                emit_baseplotdatas(
-                    &mut ref_current_offset,
-                    &mut query_current_offset,
-                    rec,
-                    cigarunit.op,
-                    cigarunit.len as usize,
-                    skip_cutoff,
-                    &mut base_plotdata_vec,
-                    &mut last_m,
-                );
-            }
-            res
-        },
-    )(cigar)?;
-    res?;
+    for cigarunit in cigar_units(cigar)? {
+        let cigarunit = cigarunit?;
+        emit_baseplotdatas(
+            &mut ref_current_offset,
+            &mut query_current_offset,
+            rec,
+            cigarunit.op,
+            cigarunit.len as usize,
+            skip_cutoff,
+            &mut base_plotdata_vec,
+            &mut last_m,
+        );
+    }
     Ok(base_plotdata_vec)
 }
 
@@ -956,8 +1166,6 @@ pub fn parse_maf_to_base_plotdata<T: AlignRecord>(
     rec: &T,
     skip_cutoff: usize,
 ) -> Result<Vec<BasePlotdata>, WGAError> {
-    let seq1_iter = rec.target_seq().chars();
-    let seq2_iter = rec.query_seq().chars();
     let ref_start = rec.target_start();
     let query_start = rec.query_start();
     let mut ref_current_offset = ref_start;
@@ -965,9 +1173,7 @@ pub fn parse_maf_to_base_plotdata<T: AlignRecord>(
     let mut base_plotdata_vec = Vec::new();
     let mut last_m = false;
 
-    let group_by_iter = seq1_iter
-        .zip(seq2_iter)
-        .group_by(|(c1, c2)| cigar_cat_ext(c1, c2));
+    let group_by_iter = maf_columns(rec).group_by(|(c1, c2)| cigar_cat_ext(c1, c2));
     for (k, g) in group_by_iter.into_iter() {
         let length = g.count();
         emit_baseplotdatas(
@@ -983,3 +1189,74 @@ pub fn parse_maf_to_base_plotdata<T: AlignRecord>(
     }
     Ok(base_plotdata_vec)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn units(cigar: &str) -> Result<Vec<(char, u64)>, WGAError> {
+        cigar_units(cigar)?
+            .map(|unit| unit.map(|unit| (unit.op, unit.len)))
+            .collect()
+    }
+
+    #[test]
+    fn cigar_units_of_valid_cigar() {
+        assert_eq!(
+            units("3S10=2X1I4D7M2H").unwrap(),
+            [('=', 10), ('X', 2), ('I', 1), ('D', 4), ('M', 7)]
+        );
+        assert_eq!(units("0M").unwrap(), [('M', 0)]);
+    }
+
+    #[test]
+    fn cigar_units_of_empty_cigar() {
+        assert!(units("").is_err());
+        // only clips, nothing aligned
+        assert!(units("5S").is_err());
+    }
+
+    #[test]
+    fn cigar_units_without_length() {
+        assert!(units("M").is_err());
+        assert!(units("10MI").is_err());
+        assert!(units("10M5").is_err());
+    }
+
+    #[test]
+    fn cigar_units_of_multi_byte_op() {
+        assert!(units("10é").is_err());
+        assert!(units("10M3é2I").is_err());
+        assert!(units("10Mé").is_err());
+    }
+
+    #[test]
+    fn cigar_units_of_long_length() {
+        assert_eq!(
+            units("9999999999999999999M").unwrap(),
+            [('M', 9_999_999_999_999_999_999)]
+        );
+        // more than 19 digits are parsed with overflow check
+        assert_eq!(units("00000000000000000000012I").unwrap(), [('I', 12)]);
+        assert_eq!(units("18446744073709551615D").unwrap(), [('D', u64::MAX)]);
+        assert!(units("18446744073709551616D").is_err());
+        assert!(units("99999999999999999999999M").is_err());
+    }
+
+    #[test]
+    fn maf_cigar_skips_columns_gapped_in_both() {
+        let maf = "a score=0\n\
+                   s t 0 8 + 100 AC--GTAC-A\n\
+                   s q 0 8 + 100 AC--GAACAA\n\
+                   s r 0 9 + 100 ACTTGTAC-A\n";
+        let rec = crate::parser::maf::MAFReader::new(maf.as_bytes())
+            .unwrap()
+            .records()
+            .next()
+            .unwrap()
+            .unwrap();
+        let cigar = parse_maf_seq_to_cigar(&rec, false);
+        assert_eq!(cigar.cigar_string, "3=1X2=1I1=");
+        assert_eq!(cigar.match_count, 6);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::errors::WGAError;
+use crate::errors::{ParseContext, WGAError};
 use crate::parser::cigar::Cigar;
 use crate::parser::maf::MAFRecord;
 use crate::parser::paf::PafRecord;
@@ -6,6 +6,7 @@ use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
+use std::io::{self, BufRead, Write};
 use std::str::FromStr;
 
 /// Enum the file types
@@ -17,6 +18,8 @@ pub enum FileFormat {
     // Bam,
     Paf,
     Chain,
+    /// Detect format from the first record
+    Auto,
     #[value(skip)]
     Bedpe,
     #[value(skip)]
@@ -25,11 +28,57 @@ pub enum FileFormat {
     Unknown,
 }
 
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl FileFormat {
+    /// Detect format by peeking the first record in buffer, input is not consumed.
+    /// Return `FileFormat::Unknown` if failed.
+    pub fn detect<R: BufRead + ?Sized>(reader: &mut R) -> Result<FileFormat, WGAError> {
+        let buf = reader.fill_buf()?;
+        let first_line = String::from_utf8_lossy(buf)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .find(|line| !line.is_empty());
+        let first_line = match first_line {
+            Some(line) => line,
+            None => return Ok(FileFormat::Unknown),
+        };
+
+        if first_line.starts_with('#') || first_line.split_whitespace().next() == Some("a") {
+            return Ok(FileFormat::Maf);
+        }
+        if first_line.starts_with("chain") {
+            return Ok(FileFormat::Chain);
+        }
+        if first_line.starts_with('@') {
+            return Ok(FileFormat::Sam);
+        }
+        let fields = first_line.split('\t').collect::<Vec<&str>>();
+        let is_num = |idx: usize| fields[idx].parse::<u64>().is_ok();
+        // PAF: 12 mandatory columns, strand at the 5th
+        if fields.len() >= 12
+            && [1, 2, 3, 6, 7, 8, 9, 10, 11].iter().all(|&i| is_num(i))
+            && (fields[4] == "+" || fields[4] == "-")
+        {
+            return Ok(FileFormat::Paf);
+        }
+        // SAM: 11 mandatory columns, FLAG/POS/MAPQ are numbers
+        if fields.len() >= 11 && [1, 3, 4].iter().all(|&i| is_num(i)) {
+            return Ok(FileFormat::Sam);
+        }
+        Ok(FileFormat::Unknown)
+    }
+}
+
 /// Represented in:
 /// - PAF 1-9 columns
 /// - CHAIN header lines
 /// - MAF header lines
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct SeqInfo {
     pub name: String,
     pub size: u64,
@@ -80,6 +129,36 @@ pub struct Block<'a> {
     pub(crate) strand: Strand,
 }
 
+impl Block<'_> {
+    /// write block as a TSV line or a BED6 line on target
+    pub fn write_line(&self, writer: &mut dyn Write, format: BlocksOutFormat) -> io::Result<()> {
+        match format {
+            BlocksOutFormat::Tsv => writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                self.query_name,
+                self.query_start,
+                self.query_end,
+                self.target_name,
+                self.target_start,
+                self.target_end,
+                self.strand
+            ),
+            BlocksOutFormat::Bed => writeln!(
+                writer,
+                "{}\t{}\t{}\t{}:{}-{}\t0\t{}",
+                self.target_name,
+                self.target_start,
+                self.target_end,
+                self.query_name,
+                self.query_start,
+                self.query_end,
+                self.strand
+            ),
+        }
+    }
+}
+
 /// impl Default for Block
 impl Default for Block<'_> {
     fn default() -> Self {
@@ -139,6 +218,17 @@ impl From<Cigar> for RecStat {
     }
 }
 
+/// Metadata of a sequence in alignment record, without the sequence itself.
+/// Coordinates are as stored in the record, e.g. `start` of MAF s-line is on its strand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeqMeta<'a> {
+    pub name: &'a str,
+    pub start: u64,
+    pub align_size: u64,
+    pub size: u64,
+    pub strand: Strand,
+}
+
 pub trait AlignRecord {
     fn query_name(&self) -> &str;
     fn query_length(&self) -> u64;
@@ -173,6 +263,57 @@ pub trait AlignRecord {
     fn get_stat(&self) -> Result<RecStat, WGAError> {
         Ok(RecStat::default())
     }
+    /// Position of record for error context
+    fn coord_context(&self) -> String {
+        format!(
+            "target {}:{}-{} query {}:{}-{}({})",
+            self.target_name(),
+            self.target_start(),
+            self.target_end(),
+            self.query_name(),
+            self.query_start(),
+            self.query_end(),
+            self.query_strand()
+        )
+    }
+    /// Attach position of record to an error
+    fn context_err(&self, err: WGAError) -> WGAError {
+        err.with_context(ParseContext {
+            seqs: Some(self.coord_context()),
+            ..Default::default()
+        })
+    }
+    /// Checked `a - b` for coordinates, error with record position if underflow
+    fn coord_sub(&self, a: u64, b: u64) -> Result<u64, WGAError> {
+        a.checked_sub(b).ok_or_else(|| {
+            WGAError::CoordOutOfRange(format!("{} - {}", a, b), self.coord_context())
+        })
+    }
+    /// Checked `a + b` for coordinates, error with record position if overflow
+    fn coord_add(&self, a: u64, b: u64) -> Result<u64, WGAError> {
+        a.checked_add(b).ok_or_else(|| {
+            WGAError::CoordOutOfRange(format!("{} + {}", a, b), self.coord_context())
+        })
+    }
+    /// Metadata of all sequences in record, target first
+    fn seq_metas(&self) -> Vec<SeqMeta<'_>> {
+        vec![
+            SeqMeta {
+                name: self.target_name(),
+                start: self.target_start(),
+                align_size: self.target_end() - self.target_start(),
+                size: self.target_length(),
+                strand: self.target_strand(),
+            },
+            SeqMeta {
+                name: self.query_name(),
+                start: self.query_start(),
+                align_size: self.query_end() - self.query_start(),
+                size: self.query_length(),
+                strand: self.query_strand(),
+            },
+        ]
+    }
 }
 
 /// Re-count align-size from seqs
@@ -189,6 +330,184 @@ pub fn recount_align_size(seq: &str) -> (u64, u64) {
     (align_size, gap_size)
 }
 
+/// How to report variants nested within an inversion
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq)]
+pub enum InvMode {
+    /// Keep nested variants with `INV_NEST=TRUE`
+    Nested,
+    /// Report nested variants as normal ones, with query positions in the inverted orientation
+    Flatten,
+    /// Only report the INV record
+    Suppress,
+}
+
+/// How to split long blocks into chunks to call variants in parallel
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq)]
+pub enum ChunkStrategy {
+    /// Call each block as a whole
+    None,
+    /// Split every `chunk-size` columns, variants across boundaries may be split or missed
+    Fixed,
+    /// Split at the first gap-free window after every `chunk-size` columns, same calls as `none`
+    SvAware,
+}
+
+/// How to apply masked regions of BED to alignment records on target
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq)]
+pub enum MaskMode {
+    /// Drop records overlapping masked regions
+    Drop,
+    /// Cut masked regions out of records, a record may be split into several ones
+    Trim,
+    /// Lowercase bases of all sequences aligned to masked regions, only for MAF
+    Softmask,
+}
+
+impl fmt::Display for MaskMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+/// How to mask bases of FASTA
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq)]
+pub enum FastaMaskMode {
+    /// Replace bases with `N`
+    Hard,
+    /// Lowercase bases
+    Soft,
+}
+
+/// Which side of alignments the best hits are selected on
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq)]
+pub enum BestHit {
+    /// Keep the best target alignment of each query interval
+    Query,
+    /// Keep the best query alignment of each target interval
+    Target,
+    /// Keep alignments best on both query and target, i.e. one-to-one
+    Both,
+}
+
+impl fmt::Display for BestHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+/// How to handle characters out of IUPAC nucleotide codes in FASTA sequences
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq)]
+pub enum UnknownBase {
+    /// Abort with an error
+    Error,
+    /// Replace them with `N`
+    ReplaceWithN,
+    /// Keep them as they are, also when reverse complementing
+    PassThrough,
+}
+
+/// How to handle characters other than IUPAC nucleotide codes and `-` in MAF sequences,
+/// such as `.` for missing bases or `*` for padding
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Default)]
+pub enum OddChar {
+    /// Treat them as gaps, the align size of s-line should not count them
+    TreatAsGap,
+    /// Treat them as `N`, the align size of s-line should count them
+    #[default]
+    TreatAsN,
+    /// Abort with an error
+    Error,
+}
+
+/// Order of PAF records in output
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Default)]
+pub enum PafSortBy {
+    /// Keep the order of input
+    #[default]
+    None,
+    /// Sort by query name, start and end, e.g. for seqwish or impg
+    Query,
+    /// Sort by target name, start and end
+    Target,
+}
+
+/// Operations of aligned bases in CIGAR of PAF output
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq)]
+pub enum CigarStyle {
+    /// `=` for matches and `X` for mismatches
+    Eqx,
+    /// `M` for both
+    M,
+}
+
+/// Naming of sequences in PAF output
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Default)]
+pub enum PafVersion {
+    /// Keep names of input
+    #[default]
+    Plain,
+    /// Rename `Genome.Chr` to PanSN `Genome#1#Chr`, e.g. for pggb-style tools
+    Pansn,
+}
+
+/// PanSN naming with separator and haplotype
+#[derive(Debug, Clone)]
+pub struct PanSN {
+    pub sep: String,
+    pub hap: u32,
+}
+
+impl PanSN {
+    /// Rename `Genome.Chr` to `Genome{sep}{hap}{sep}Chr`, names without `.`
+    /// or with the separator already are kept
+    pub fn rename(&self, name: &str) -> String {
+        if name.contains(self.sep.as_str()) {
+            return name.to_string();
+        }
+        match name.split_once('.') {
+            Some((genome, chr)) => format!("{genome}{sep}{}{sep}{chr}", self.hap, sep = self.sep),
+            None => name.to_string(),
+        }
+    }
+}
+
+/// Level of PanSN names (`sample#hap#chr`) to aggregate statistics
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Default)]
+pub enum GroupBy {
+    /// Each sequence
+    #[default]
+    Sequence,
+    /// `sample#hap` of names
+    Haplotype,
+    /// `sample` of names
+    Sample,
+}
+
+impl GroupBy {
+    /// Group of a name, names with fewer PanSN fields than the level are kept
+    pub fn group<'a>(&self, name: &'a str) -> &'a str {
+        let fields = match self {
+            GroupBy::Sequence => return name,
+            GroupBy::Haplotype => 2,
+            GroupBy::Sample => 1,
+        };
+        match name.match_indices('#').nth(fields - 1) {
+            Some((idx, _)) => &name[..idx],
+            None => name,
+        }
+    }
+}
+
+/// Side of alignment the input coordinates are on
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Default)]
+pub enum LiftFrom {
+    /// Lift from query to target
+    #[default]
+    Query,
+    /// Lift from target to query
+    Target,
+}
+
 #[derive(Debug, ValueEnum, Clone, Copy)]
 pub enum DotplotMode {
     BaseLevel,
@@ -201,3 +520,26 @@ pub enum DotplotoutFormat {
     Json,
     Csv,
 }
+
+/// Output format of validate, text for human and JSON/TSV for automated QC
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq)]
+pub enum ValidateOutFormat {
+    Text,
+    Json,
+    Tsv,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy)]
+pub enum BlocksOutFormat {
+    Tsv,
+    Bed,
+}
+
+/// Output format of per-genome alignment matrix
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq)]
+pub enum AlnOutFormat {
+    /// Aligned FASTA
+    Fasta,
+    /// Relaxed sequential PHYLIP
+    Phylip,
+}
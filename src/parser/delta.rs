@@ -0,0 +1,447 @@
+use crate::errors::{ParseContext, WGAError};
+use crate::log::{profile, RecordCounter, Stage};
+use crate::parser::cigar::parse_paf_to_cigar;
+use crate::parser::common::{AlignRecord, RecStat, Strand};
+use crate::parser::paf::PafRecord;
+use crate::utils::parse_str2u64;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+
+/// Reader for MUMmer delta file format of `nucmer`
+/// refer into https://mummer4.github.io/tutorial/tutorial.html
+pub struct DeltaReader<R: Read> {
+    inner: BufReader<R>,
+    // file name in error messages
+    source: Option<String>,
+    /// FASTA paths of reference and query in the first line
+    pub target_path: String,
+    pub query_path: String,
+    // lines read by header
+    line_no: u64,
+}
+
+impl<R> DeltaReader<R>
+where
+    R: Read + Send,
+{
+    /// Create a new Delta Reader, the two header lines are read at once
+    pub fn new(reader: R) -> Result<Self, WGAError> {
+        let mut inner = BufReader::new(reader);
+        let mut line = String::new();
+        inner.read_line(&mut line)?;
+        let mut paths = line.split_whitespace();
+        let (target_path, query_path) = match (paths.next(), paths.next()) {
+            (Some(t), Some(q)) => (t.to_string(), q.to_string()),
+            _ => {
+                return Err(WGAError::InvalidRecord(format!(
+                    "delta header `{}` without reference and query paths",
+                    line.trim_end()
+                )))
+            }
+        };
+        line.clear();
+        inner.read_line(&mut line)?;
+        // PROMER coordinates are amino acids, which can't be aligned as bases
+        if line.trim() != "NUCMER" {
+            return Err(WGAError::InvalidRecord(format!(
+                "delta of `{}` is not supported, only NUCMER",
+                line.trim()
+            )));
+        }
+        Ok(DeltaReader {
+            inner,
+            source: None,
+            target_path,
+            query_path,
+            line_no: 2,
+        })
+    }
+
+    /// Set file name shown in error messages
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    /// Iterate over the alignments in the Delta file
+    pub fn records(&mut self) -> DeltaRecords<'_, R> {
+        DeltaRecords {
+            inner: &mut self.inner,
+            source: self.source.clone(),
+            line_no: self.line_no,
+            record_no: 0,
+            seqs: None,
+            line: String::new(),
+            counter: RecordCounter::new(self.source.as_deref()),
+        }
+    }
+}
+
+impl DeltaReader<File> {
+    /// Create a new Delta parser from a file path
+    pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Result<DeltaReader<File>, WGAError> {
+        let source = path.as_ref().to_string_lossy().to_string();
+        let file = File::open(path)?;
+        Ok(DeltaReader::new(file)?.with_source(&source))
+    }
+}
+
+/// Define a delta alignment, coordinates are converted to 0-based half-open
+/// on forward strand like PAF
+#[derive(Debug, Default, Clone)]
+pub struct DeltaRecord {
+    pub target_name: String,
+    pub target_length: u64,
+    pub target_start: u64,
+    pub target_end: u64,
+    pub query_name: String,
+    pub query_length: u64,
+    pub query_start: u64,
+    pub query_end: u64,
+    pub strand: Strand,
+    /// Mismatches and indels, used as edit distance
+    pub errors: u64,
+    /// Positions of indels, positive for deletion in query, negative for insertion
+    pub indels: Vec<i64>,
+}
+
+// names and lengths in the current `>` header
+type DeltaSeqs = (String, u64, String, u64);
+
+pub struct DeltaRecords<'a, R: Read> {
+    inner: &'a mut BufReader<R>,
+    source: Option<String>,
+    line_no: u64,
+    record_no: u64,
+    seqs: Option<DeltaSeqs>,
+    line: String,
+    counter: RecordCounter,
+}
+
+impl<R: Read> DeltaRecords<'_, R> {
+    // position of the current line, with names of the current header if present
+    fn context(&self) -> ParseContext {
+        ParseContext {
+            file: self.source.clone(),
+            record: Some(self.record_no + 1),
+            line: Some(self.line_no),
+            offset: None,
+            seqs: self
+                .seqs
+                .as_ref()
+                .map(|(t, _, q, _)| format!("target `{}`, query `{}`", t, q)),
+        }
+    }
+
+    // read the next non-empty line into `self.line`, false at the end
+    fn next_line(&mut self) -> Result<bool, WGAError> {
+        loop {
+            self.line.clear();
+            if self.inner.read_line(&mut self.line)? == 0 {
+                return Ok(false);
+            }
+            self.line_no += 1;
+            if !self.line.trim().is_empty() {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn parse_next(&mut self) -> Result<Option<DeltaRecord>, WGAError> {
+        loop {
+            if !self.next_line()? {
+                return Ok(None);
+            }
+            if let Some(header) = self.line.strip_prefix('>') {
+                self.seqs = Some(parse_seqs(header)?);
+                continue;
+            }
+            let (t_name, t_len, q_name, q_len) = self.seqs.clone().ok_or_else(|| {
+                WGAError::InvalidRecord("alignment before any `>` header".to_string())
+            })?;
+            let fields = self
+                .line
+                .split_whitespace()
+                .map(parse_str2u64)
+                .collect::<Result<Vec<_>, _>>()?;
+            let [rs, re, qs, qe, errors, _, _] = fields[..] else {
+                return Err(WGAError::InvalidRecord(format!(
+                    "alignment line `{}` should have 7 fields",
+                    self.line.trim_end()
+                )));
+            };
+            if rs == 0 || qs == 0 || qe == 0 || rs > re {
+                return Err(WGAError::InvalidRecord(format!(
+                    "invalid alignment coordinates `{}`",
+                    self.line.trim_end()
+                )));
+            }
+            // distances to the next indel, ended by 0
+            let mut indels = Vec::new();
+            loop {
+                if !self.next_line()? {
+                    return Err(WGAError::InvalidRecord(
+                        "indels of alignment not ended by 0".to_string(),
+                    ));
+                }
+                let indel = self.line.trim().parse::<i64>().map_err(|_| {
+                    WGAError::InvalidRecord(format!("invalid indel `{}`", self.line.trim_end()))
+                })?;
+                if indel == 0 {
+                    break;
+                }
+                indels.push(indel);
+            }
+            let strand = if qs <= qe {
+                Strand::Positive
+            } else {
+                Strand::Negative
+            };
+            let rec = DeltaRecord {
+                target_name: t_name,
+                target_length: t_len,
+                target_start: rs - 1,
+                target_end: re,
+                query_name: q_name,
+                query_length: q_len,
+                query_start: qs.min(qe) - 1,
+                query_end: qs.max(qe),
+                strand,
+                errors,
+                indels,
+            };
+            // indels must fit in the aligned spans
+            rec.cigar_units()?;
+            return Ok(Some(rec));
+        }
+    }
+}
+
+impl<R: Read> Iterator for DeltaRecords<'_, R> {
+    type Item = Result<DeltaRecord, WGAError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match profile(Stage::Parse, || self.parse_next()) {
+            Ok(Some(rec)) => {
+                self.record_no += 1;
+                self.counter.add();
+                Some(Ok(rec))
+            }
+            Ok(None) => None,
+            Err(e) => Some(Err(e.with_context(self.context()))),
+        }
+    }
+}
+
+// parse `ref qry reflen qrylen` after `>`
+fn parse_seqs(header: &str) -> Result<DeltaSeqs, WGAError> {
+    let fields = header.split_whitespace().collect::<Vec<_>>();
+    match fields[..] {
+        [t_name, q_name, t_len, q_len] => Ok((
+            t_name.to_string(),
+            parse_str2u64(t_len)?,
+            q_name.to_string(),
+            parse_str2u64(q_len)?,
+        )),
+        _ => Err(WGAError::InvalidRecord(format!(
+            "delta header `>{}` should have 4 fields",
+            header.trim_end()
+        ))),
+    }
+}
+
+impl DeltaRecord {
+    /// CIGAR operations and lengths from indels, adjacent same operations are merged
+    pub fn cigar_units(&self) -> Result<Vec<(u64, char)>, WGAError> {
+        let t_alilen = self.target_end - self.target_start;
+        let q_alilen = self.query_end - self.query_start;
+        let mut units: Vec<(u64, char)> = Vec::new();
+        let mut push = |len: u64, op: char| {
+            if len == 0 {
+                return;
+            }
+            match units.last_mut() {
+                Some((last_len, last_op)) if *last_op == op => *last_len += len,
+                _ => units.push((len, op)),
+            }
+        };
+        let (mut t_used, mut q_used) = (0, 0);
+        for &indel in &self.indels {
+            // bases before the indel are aligned
+            let run = indel.unsigned_abs() - 1;
+            push(run, 'M');
+            t_used += run;
+            q_used += run;
+            if indel > 0 {
+                push(1, 'D');
+                t_used += 1;
+            } else {
+                push(1, 'I');
+                q_used += 1;
+            }
+        }
+        if t_used > t_alilen || q_used > q_alilen || t_alilen - t_used != q_alilen - q_used {
+            return Err(WGAError::InvalidRecord(format!(
+                "indels not fit in alignment of {} target and {} query bases",
+                t_alilen, q_alilen
+            )));
+        }
+        push(t_alilen - t_used, 'M');
+        Ok(units)
+    }
+}
+
+impl AlignRecord for DeltaRecord {
+    fn query_name(&self) -> &str {
+        &self.query_name
+    }
+
+    fn query_length(&self) -> u64 {
+        self.query_length
+    }
+
+    fn query_start(&self) -> u64 {
+        self.query_start
+    }
+
+    fn query_end(&self) -> u64 {
+        self.query_end
+    }
+
+    fn query_strand(&self) -> Strand {
+        self.strand
+    }
+
+    fn target_name(&self) -> &str {
+        &self.target_name
+    }
+
+    fn target_length(&self) -> u64 {
+        self.target_length
+    }
+
+    fn target_start(&self) -> u64 {
+        self.target_start
+    }
+
+    fn target_end(&self) -> u64 {
+        self.target_end
+    }
+
+    fn target_strand(&self) -> Strand {
+        Strand::Positive
+    }
+
+    fn target_align_size(&self) -> u64 {
+        self.target_end - self.target_start
+    }
+
+    fn get_cigar_string(&self) -> Result<String, WGAError> {
+        Ok(self
+            .cigar_units()?
+            .iter()
+            .map(|(len, op)| format!("{}{}", len, op))
+            .collect())
+    }
+
+    fn convert2paf(&mut self, _query_name: Option<&str>) -> Result<PafRecord, WGAError> {
+        let units = self.cigar_units()?;
+        let aligned = units
+            .iter()
+            .filter(|(_, op)| *op == 'M')
+            .map(|(len, _)| len)
+            .sum::<u64>();
+        let block_length = units.iter().map(|(len, _)| len).sum::<u64>();
+        // errors count indels and mismatches
+        let indels = self.indels.len() as u64;
+        let matches = aligned.saturating_sub(self.errors.saturating_sub(indels));
+        Ok(PafRecord {
+            query_name: self.query_name.clone(),
+            query_length: self.query_length,
+            query_start: self.query_start,
+            query_end: self.query_end,
+            strand: self.strand,
+            target_name: self.target_name.clone(),
+            target_length: self.target_length,
+            target_start: self.target_start,
+            target_end: self.target_end,
+            matches,
+            block_length,
+            mapq: 255,
+            tags: vec![
+                format!("NM:i:{}", self.errors),
+                format!("cg:Z:{}", self.get_cigar_string()?),
+            ],
+        })
+    }
+
+    fn get_stat(&self) -> Result<RecStat, WGAError> {
+        let pafrec = self.clone().convert2paf(None)?;
+        let cigar = parse_paf_to_cigar(&pafrec, Some(self.errors as usize))?;
+        Ok(RecStat::from(cigar))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::delta2paf;
+    use crate::parser::common::PafSortBy;
+
+    const NUCMER: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test/delta/nucmer.delta");
+
+    #[test]
+    fn read_nucmer_fixture() {
+        let mut reader = DeltaReader::from_path(NUCMER).unwrap();
+        assert_eq!(reader.target_path, "/data/ref.fa");
+        assert_eq!(reader.query_path, "/data/qry.fa");
+        let recs = reader.records().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(recs.len(), 2);
+
+        // 1-based inclusive coordinates are 0-based half-open
+        let fwd = &recs[0];
+        assert_eq!((fwd.target_start, fwd.target_end), (100, 120));
+        assert_eq!((fwd.query_start, fwd.query_end), (0, 19));
+        assert_eq!(fwd.strand, Strand::Positive);
+        // positive distance is a deletion in query, negative an insertion
+        assert_eq!(
+            fwd.cigar_units().unwrap(),
+            [
+                (4, 'M'),
+                (1, 'D'),
+                (2, 'M'),
+                (1, 'I'),
+                (1, 'M'),
+                (1, 'D'),
+                (11, 'M')
+            ]
+        );
+
+        // `qs > qe` is on reverse strand, coordinates are on the forward strand
+        let rev = &recs[1];
+        assert_eq!((rev.target_start, rev.target_end), (500, 510));
+        assert_eq!((rev.query_start, rev.query_end), (19, 30));
+        assert_eq!(rev.strand, Strand::Negative);
+        assert_eq!(rev.get_cigar_string().unwrap(), "3M1I7M");
+    }
+
+    #[test]
+    fn delta_to_paf() {
+        let mut reader = DeltaReader::from_path(NUCMER).unwrap();
+        let mut out = Vec::new();
+        delta2paf(&mut reader, &mut out, PafSortBy::None).unwrap();
+        // matches are aligned bases minus mismatches, errors other than indels
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Q\t448\t0\t19\t+\tT\t1000\t100\t120\t18\t21\t255\tNM:i:3\tcg:Z:4M1D2M1I1M1D11M\n\
+             Q\t448\t19\t30\t-\tT\t1000\t500\t510\t9\t11\t255\tNM:i:2\tcg:Z:3M1I7M\n"
+        );
+    }
+
+    #[test]
+    fn indels_out_of_alignment() {
+        let delta = "a.fa b.fa\nNUCMER\n>T Q 1000 448\n1 5 1 5 1 1 0\n7\n0\n";
+        let mut reader = DeltaReader::new(delta.as_bytes()).unwrap();
+        let err = reader.records().next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("indels not fit"), "{}", err);
+    }
+}
@@ -1,19 +1,32 @@
-use crate::errors::{ParseMafErrKind, WGAError};
+use crate::errors::{ParseContext, ParseMafErrKind, WGAError};
+use crate::log::{bad_record, profile, RecordCounter, Stage, WarnCategory};
 use crate::parser::cigar::parse_maf_seq_to_cigar;
-use crate::parser::common::{AlignRecord, RecStat, Strand};
+use crate::parser::common::{recount_align_size, AlignRecord, OddChar, RecStat, SeqMeta, Strand};
 use crate::parser::paf::PafRecord;
-use crate::utils::parse_str2u64;
-use anyhow::anyhow;
+use crate::utils::{is_iupac_base, parse_str2u64};
 use log::warn;
 use std::cmp::Ordering;
 use std::fs::File;
 use std::io::Write;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+
+// layout of s-lines for all MAF outputs, set by `--maf-pretty` before any writer is created
+static PRETTY: AtomicBool = AtomicBool::new(false);
 
 /// Parser for MAF file format
 pub struct MAFReader<R: Read> {
     pub inner: BufReader<R>,
     pub header: String,
+    // file name in error messages
+    source: Option<String>,
+    // byte offset, number of lines and records read, numbers are unknown after seeking
+    offset: u64,
+    line_no: Option<u64>,
+    record_no: Option<u64>,
+    // offset, score and tags of a-line which ends the previous block without an empty line
+    next_aline: Option<(u64, u64, Vec<String>)>,
+    odd_char: OddChar,
 }
 
 impl<R> MAFReader<R>
@@ -24,29 +37,65 @@ where
     pub fn new(reader: R) -> Result<Self, WGAError> {
         let mut buf_reader = BufReader::new(reader);
         let mut header = String::new();
-        buf_reader.read_line(&mut header)?;
-        if !header.starts_with('#') {
-            warn!("MAF Header is not start with `#`")
+        let offset = buf_reader.read_line(&mut header)? as u64;
+        let header = header.trim_end().to_string();
+        // empty input is reported after running
+        if !header.is_empty() && !header.starts_with('#') {
+            warn!(target: WarnCategory::Input.target(), "MAF Header is not start with `#`")
         }
         Ok(MAFReader {
             inner: buf_reader,
             header,
+            source: None,
+            offset,
+            line_no: Some(1),
+            record_no: Some(0),
+            next_aline: None,
+            odd_char: OddChar::default(),
         })
     }
 
+    /// Set file name shown in error messages
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    /// Set how to handle odd characters in sequences, `N` by default
+    pub fn with_odd_char(mut self, odd_char: OddChar) -> Self {
+        self.odd_char = odd_char;
+        self
+    }
+
     /// Iterate over the records in the MAF file
-    pub fn records(&mut self) -> MAFRecords<R> {
+    pub fn records(&mut self) -> MAFRecords<'_, R> {
+        let counter = RecordCounter::new(self.source.as_deref());
         MAFRecords {
-            inner: self.inner.by_ref(),
+            reader: self,
+            line: String::new(),
+            line_offset: 0,
+            counter,
         }
     }
 }
 
+impl<R: Read + Seek + Send> MAFReader<R> {
+    /// Seek to `offset` such as from index, line and record numbers are unknown after it
+    pub fn seek(&mut self, offset: u64) -> Result<(), WGAError> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.offset = offset;
+        self.line_no = None;
+        self.record_no = None;
+        self.next_aline = None;
+        Ok(())
+    }
+}
+
 impl MAFReader<File> {
     /// Create a new PAF parser from a file path
     pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> Result<MAFReader<File>, WGAError> {
         match File::open(path.as_ref()) {
-            Ok(file) => Ok(MAFReader::new(file)?),
+            Ok(file) => Ok(MAFReader::new(file)?.with_source(&path.as_ref().to_string_lossy())),
             Err(_) => Err(WGAError::FileNotExist(path.as_ref().to_path_buf())),
         }
     }
@@ -69,12 +118,26 @@ pub struct MAFSLine {
     pub strand: Strand,
     pub size: u64,
     pub seq: String,
+    /// Base qualities of the following q-line, `0-9` or `F` for each column and `-` for gaps
+    pub qual: Option<String>,
 }
 
 // impl mut for MAFSLine
 impl MAFSLine {
+    /// Get metadata without sequence
+    pub fn meta(&self) -> SeqMeta<'_> {
+        SeqMeta {
+            name: &self.name,
+            start: self.start,
+            align_size: self.align_size,
+            size: self.size,
+            strand: self.strand,
+        }
+    }
+
     fn get_col_coord(&self, pos: u64) -> u64 {
-        let mut col_coord = 0;
+        // the end of sequence if pos is out of bases
+        let mut col_coord = self.seq.len() as u64;
         let mut flag = 0;
         // skip '-'
         for (i, c) in self.seq.chars().enumerate() {
@@ -109,14 +172,64 @@ impl MAFSLine {
     }
 }
 
+// parse score and PAF tags of a-line, other variables are ignored,
+// score is 255 if absent or not an integer
+fn parse_aline(line: &str) -> (u64, Vec<String>) {
+    let mut score = 255;
+    let mut tags = Vec::new();
+    for (key, value) in line
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|var| var.split_once('='))
+    {
+        if key == "score" {
+            score = value.parse().unwrap_or(255);
+        } else if let Some(tag) = key.strip_prefix("paf_") {
+            tags.push(format!("{}:{}", tag, value));
+        }
+    }
+    (score, tags)
+}
+
 // main parse function for s-line
-fn parse_sline(line: String) -> Result<MAFSLine, WGAError> {
+fn parse_sline(line: &str, odd_char: OddChar) -> Result<MAFSLine, WGAError> {
+    fix_odd_chars(parse_sline_fields(line)?, odd_char)
+}
+
+// handle characters other than IUPAC codes and gaps in sequence by `odd_char`,
+// align size should still match the sequence after that
+fn fix_odd_chars(mut sline: MAFSLine, odd_char: OddChar) -> Result<MAFSLine, WGAError> {
+    let odd = |c: char| c != '-' && !is_iupac_base(c);
+    let Some(c) = sline.seq.chars().find(|&c| odd(c)) else {
+        return Ok(sline);
+    };
+    let (replace, treat_as) = match odd_char {
+        OddChar::TreatAsGap => ('-', "gaps"),
+        OddChar::TreatAsN => ('N', "`N`"),
+        OddChar::Error => return Err(WGAError::InvalidBase(c.to_string())),
+    };
+    sline.seq = sline
+        .seq
+        .chars()
+        .map(|c| if odd(c) { replace } else { c })
+        .collect();
+    let (align_size, _) = recount_align_size(&sline.seq);
+    if align_size != sline.align_size {
+        return Err(WGAError::InvalidRecord(format!(
+            "align size {} of `{}` does not match {} bases in sequence with `{}` treated as {}",
+            sline.align_size, sline.name, align_size, c, treat_as
+        )));
+    }
+    Ok(sline)
+}
+
+fn parse_sline_fields(line: &str) -> Result<MAFSLine, WGAError> {
     let mut iter = line.split_whitespace();
     let mode = match iter.next() {
         Some(mode) => mode
             .chars()
             .next()
-            .ok_or(WGAError::Other(anyhow!("mode is empty"))),
+            .ok_or(WGAError::InvalidRecord("mode is empty".to_string())),
         None => {
             return Err(WGAError::ParseMaf(ParseMafErrKind::FiledMissing(
                 "mode".to_string(),
@@ -174,6 +287,12 @@ fn parse_sline(line: String) -> Result<MAFSLine, WGAError> {
     if iter.next().is_some() {
         return Err(WGAError::ParseMaf(ParseMafErrKind::SurplusField));
     };
+    // coordinates on reverse strand are calculated by `size - start - align_size`
+    if start.checked_add(align_size).is_none_or(|end| end > size) {
+        return Err(WGAError::ParseMaf(ParseMafErrKind::CoordExceedSize(
+            name, start, align_size, size,
+        )));
+    }
     Ok(MAFSLine {
         mode,
         name,
@@ -182,9 +301,37 @@ fn parse_sline(line: String) -> Result<MAFSLine, WGAError> {
         strand,
         size,
         seq,
+        qual: None,
     })
 }
 
+// parse q-line of the last s-line, it should have the same name and columns
+fn parse_qline(line: &str, sline: &mut MAFSLine) -> Result<(), WGAError> {
+    let mut iter = line.split_whitespace().skip(1);
+    let (Some(name), Some(qual), None) = (iter.next(), iter.next(), iter.next()) else {
+        return Err(WGAError::ParseMaf(ParseMafErrKind::FiledMissing(
+            "q-line name or quality".to_string(),
+        )));
+    };
+    if name != sline.name || qual.len() != sline.seq.len() {
+        return Err(WGAError::InvalidRecord(format!(
+            "q-line of `{}` with {} columns does not match the s-line of `{}` with {} columns",
+            name,
+            qual.len(),
+            sline.name,
+            sline.seq.len()
+        )));
+    }
+    if let Some(c) = qual.chars().find(|c| !matches!(c, '0'..='9' | 'F' | '-')) {
+        return Err(WGAError::InvalidRecord(format!(
+            "invalid quality `{}` in q-line of `{}`",
+            c, name
+        )));
+    }
+    sline.qual = Some(qual.to_string());
+    Ok(())
+}
+
 /// A MAF alignment record refer to https://genome.ucsc.edu/FAQ/FAQformat.html#format5
 /// a pair of a-lines should be a align record
 #[derive(Debug, PartialEq, Eq)]
@@ -192,40 +339,78 @@ pub struct MAFRecord {
     pub score: u64,
     pub slines: Vec<MAFSLine>,
     pub query_idx: usize,
+    /// PAF tags like `tp:A:P`, kept in a-line as `paf_tp=A:P`
+    pub tags: Vec<String>,
 }
 
 impl MAFRecord {
-    pub fn slice_block(&mut self, cut_start: u64, cut_end: u64, ord: usize) {
-        let sline = &mut self.slines[ord];
+    /// Check all s-lines have the same number of columns
+    pub fn check_seq_len(&self) -> Result<(), WGAError> {
+        if let Some((first, rest)) = self.slines.split_first() {
+            if let Some(sline) = rest.iter().find(|sline| sline.seq.len() != first.seq.len()) {
+                return Err(WGAError::SeqLenNotMatch(
+                    sline.name.clone(),
+                    sline.seq.len(),
+                    first.name.clone(),
+                    first.seq.len(),
+                ));
+            }
+        }
+        Ok(())
+    }
 
-        let cut_start_index = cut_start - sline.start;
-        let cut_end_index = cut_end - sline.start;
+    pub fn slice_block(
+        &mut self,
+        cut_start: u64,
+        cut_end: u64,
+        ord: usize,
+    ) -> Result<(), WGAError> {
+        let cut_start_index = self.coord_sub(cut_start, self.slines[ord].start)?;
+        let cut_end_index = self.coord_sub(cut_end, self.slines[ord].start)?;
 
-        sline.set_start(cut_start);
-        sline.set_align_size(cut_end - cut_start);
+        let start_coord = self.slines[ord].get_col_coord(cut_start_index);
+        let end_coord = self.slines[ord].get_col_coord(cut_end_index);
+        if start_coord > end_coord {
+            return Err(WGAError::CoordOutOfRange(
+                format!("columns {}..{}", start_coord, end_coord),
+                self.coord_context(),
+            ));
+        }
+
+        // other s-lines are recomputed by their own bases before the cut
+        *self = self.slice_columns(start_coord as usize, end_coord as usize)?;
+        Ok(())
+    }
 
-        let start_coord = sline.get_col_coord(cut_start_index);
-        let end_coord = sline.get_col_coord(cut_end_index);
-        sline.seq = sline.seq[start_coord as usize..end_coord as usize].to_string();
+    /// Slice columns `[col_start, col_end)` of the block,
+    /// start and align_size of every s-line are recomputed from its own bases
+    pub fn slice_columns(&self, col_start: usize, col_end: usize) -> Result<MAFRecord, WGAError> {
+        self.column_slicer().slice(col_start, col_end)
+    }
 
-        let mut sline_idx_vec = (0..self.slines.len()).collect::<Vec<usize>>();
-        sline_idx_vec.remove(ord);
-        for sline in sline_idx_vec.iter() {
-            let sline = &mut self.slines[*sline];
-            let new_s_start = sline.start + cut_start_index;
-            sline.set_start(new_s_start);
-            let new_seq = sline.seq[start_coord as usize..end_coord as usize].to_string();
-            let pre_align_size = end_coord - start_coord;
-            let gap_size = new_seq.matches('-').count() as u64;
-            sline.set_align_size(pre_align_size - gap_size);
-            sline.seq = new_seq;
+    /// Slicer of columns in ascending order, see [`ColumnSlicer`]
+    pub fn column_slicer(&self) -> ColumnSlicer<'_> {
+        ColumnSlicer {
+            rec: self,
+            col: 0,
+            heads: vec![0; self.slines.len()],
         }
     }
 
     pub fn rename(&mut self, prefixs: &[&str]) -> Result<(), WGAError> {
         // check prefixs length and slines length
         if prefixs.len() != self.slines.len() {
-            return Err(WGAError::SLineCountNotMatch);
+            let metas = self.seq_metas();
+            let names = metas
+                .iter()
+                .map(|meta| meta.name)
+                .collect::<Vec<_>>()
+                .join(",");
+            return Err(WGAError::SLineCountNotMatch(
+                prefixs.len(),
+                self.slines.len(),
+                names,
+            ));
         }
         for (order, sline) in self.slines.iter_mut().enumerate() {
             let prefix = prefixs[order];
@@ -286,6 +471,68 @@ impl MAFRecord {
     // }
 }
 
+/// Slicer of block columns, bases of every s-line before a slice are counted
+/// from the end of the last one, so slicing a block in ascending order is linear
+pub struct ColumnSlicer<'a> {
+    rec: &'a MAFRecord,
+    // first column not counted yet
+    col: usize,
+    // bases of every s-line before `col`
+    heads: Vec<u64>,
+}
+
+impl ColumnSlicer<'_> {
+    /// Slice columns `[col_start, col_end)`, slices before the last one are recounted from column 0
+    pub fn slice(&mut self, col_start: usize, col_end: usize) -> Result<MAFRecord, WGAError> {
+        let rec = self.rec;
+        if col_start < self.col {
+            self.col = 0;
+            self.heads.iter_mut().for_each(|head| *head = 0);
+        }
+        let mut slines = Vec::with_capacity(rec.slines.len());
+        for (sline, head) in rec.slines.iter().zip(self.heads.iter_mut()) {
+            let (new_seq, head_seq) = match (
+                sline.seq.get(col_start..col_end),
+                sline.seq.get(self.col..col_start),
+            ) {
+                (Some(new_seq), Some(head_seq)) => (new_seq, head_seq),
+                _ => {
+                    return Err(WGAError::CoordOutOfRange(
+                        format!("columns {}..{} of {}", col_start, col_end, sline.name),
+                        rec.coord_context(),
+                    ))
+                }
+            };
+            *head += recount_align_size(head_seq).0;
+            let (align_size, _) = recount_align_size(new_seq);
+            slines.push(MAFSLine {
+                mode: sline.mode,
+                name: sline.name.clone(),
+                start: rec.coord_add(sline.start, *head)?,
+                align_size,
+                strand: sline.strand,
+                size: sline.size,
+                seq: new_seq.to_string(),
+                qual: sline
+                    .qual
+                    .as_ref()
+                    .and_then(|qual| qual.get(col_start..col_end))
+                    .map(str::to_string),
+            });
+            // bases of the slice are counted as head of the next one
+            *head += align_size;
+        }
+        self.col = col_end;
+        // tags describe the whole alignment, not valid for a slice
+        Ok(MAFRecord {
+            score: rec.score,
+            slines,
+            query_idx: rec.query_idx,
+            tags: Vec::new(),
+        })
+    }
+}
+
 // impl PartialEq for MAFRecord
 impl PartialOrd<Self> for MAFRecord {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
@@ -316,6 +563,7 @@ impl Default for MAFRecord {
             score: 255,
             slines: Vec::new(),
             query_idx: 1,
+            tags: Vec::new(),
         }
     }
 }
@@ -323,59 +571,165 @@ impl Default for MAFRecord {
 /// A MAF record iterator
 /// two s-lines should be a record
 pub struct MAFRecords<'a, R: Read + Send> {
-    inner: &'a mut BufReader<R>,
+    reader: &'a mut MAFReader<R>,
+    // line buffer reused for all lines
+    line: String,
+    // byte offset of the line in buffer
+    line_offset: u64,
+    counter: RecordCounter,
 }
 
-/// impl Iterator trait for MAFRecords
-impl<R: Read + Send> Iterator for MAFRecords<'_, R> {
-    type Item = Result<MAFRecord, WGAError>;
+impl<R: Read + Send> MAFRecords<'_, R> {
+    // read the next line into buffer, false if EOF
+    fn read_next_line(&mut self) -> Result<bool, WGAError> {
+        self.line.clear();
+        let len = self.reader.inner.read_line(&mut self.line)?;
+        self.line_offset = self.reader.offset;
+        self.reader.offset += len as u64;
+        if len > 0 {
+            self.reader.line_no = self.reader.line_no.map(|n| n + 1);
+        }
+        Ok(len > 0)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let score = 255;
-        match self.inner.lines().next() {
-            Some(Ok(line)) => {
-                if !line.starts_with('s') {
-                    self.next() // skip empty line
-                } else {
-                    // start read multi s-lines
-                    // init a maf-record
-                    let mut mafrecord = MAFRecord {
-                        score,
-                        slines: Vec::new(),
-                        query_idx: 1,
-                    };
-                    let sline = match parse_sline(line) {
+    // position of the line in buffer, with names of s-lines read in the block
+    fn context(&self, slines: &[MAFSLine]) -> ParseContext {
+        ParseContext {
+            file: self.reader.source.clone(),
+            record: self.reader.record_no.map(|n| n + 1),
+            line: self.reader.line_no,
+            offset: Some(self.line_offset),
+            seqs: match slines.is_empty() {
+                true => None,
+                false => Some(format!(
+                    "s-lines {}",
+                    slines
+                        .iter()
+                        .map(|sline| sline.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                )),
+            },
+        }
+    }
+
+    /// Next record with the byte offset of its first line, which is the a-line if present,
+    /// otherwise the first s-line. The offset is absolute if the reader is not seeked
+    /// other than by [`MAFReader::seek`].
+    ///
+    /// Lines are handled as a state machine:
+    /// - out of block, an a-line or s-line starts a block, other lines are skipped;
+    /// - in block, s-lines are added, q-lines are kept in the s-line before them,
+    ///   an empty line or a-line ends it, and other lines such as i/e-lines and comments are skipped.
+    pub fn next_with_offset(&mut self) -> Option<Result<(u64, MAFRecord), WGAError>> {
+        profile(Stage::Parse, || loop {
+            match self.read_block()? {
+                Ok(Some(rec)) => return Some(Ok(rec)),
+                // bad block skipped
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        })
+    }
+
+    // next block, None in result if it's malformed and skipped
+    fn read_block(&mut self) -> Option<Result<Option<(u64, MAFRecord)>, WGAError>> {
+        let mut aline = self.reader.next_aline.take();
+        // lines are trimmed for CRLF and arbitrary whitespace,
+        // skip lines until the first s-line of a block
+        loop {
+            match self.read_next_line() {
+                Ok(true) => {}
+                Ok(false) => return None, // iterator over
+                Err(e) => return Some(Err(e.with_context(self.context(&[])))),
+            }
+            match self.line.trim_start().chars().next() {
+                Some('s') => break,
+                Some('a') => {
+                    let (score, tags) = parse_aline(&self.line);
+                    aline = Some((self.line_offset, score, tags));
+                }
+                _ => continue,
+            }
+        }
+        // a block without a-line starts from its first s-line
+        let (offset, score, tags) = aline.unwrap_or((self.line_offset, 255, Vec::new()));
+        let first_line = self.reader.line_no;
+        // init a maf-record
+        let mut mafrecord = MAFRecord {
+            score,
+            slines: Vec::new(),
+            query_idx: 1,
+            tags,
+        };
+        let sline = match parse_sline(&self.line, self.reader.odd_char) {
+            Ok(sline) => sline,
+            // if catch error, return error
+            Err(e) => return Some(Err(e.with_context(self.context(&mafrecord.slines)))),
+        };
+        mafrecord.slines.push(sline); // push first s-line
+
+        // start read next sequential s-lines
+        loop {
+            match self.read_next_line() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => return Some(Err(e.with_context(self.context(&mafrecord.slines)))),
+            }
+            let line = self.line.trim_start();
+            match line.chars().next() {
+                Some('s') => {
+                    let sline = match parse_sline(&self.line, self.reader.odd_char) {
                         Ok(sline) => sline,
-                        // if catch error, return error
-                        Err(e) => return Some(Err(e)),
+                        Err(e) => {
+                            return Some(Err(e.with_context(self.context(&mafrecord.slines))))
+                        }
                     };
-                    mafrecord.slines.push(sline); // push first s-line
-                                                  // start read next sequential s-lines
-                    for line in self.inner.lines() {
-                        match line {
-                            Ok(line) => {
-                                if line.starts_with('s') {
-                                    let sline = match parse_sline(line) {
-                                        Ok(sline) => sline,
-                                        Err(e) => return Some(Err(e)),
-                                    };
-                                    mafrecord.slines.push(sline);
-                                } else {
-                                    // if s-line is over, break
-                                    break;
-                                }
-                            }
-                            _ => {
-                                // if line is empty, break
-                                break;
-                            }
+                    mafrecord.slines.push(sline);
+                }
+                // next a-line belongs to the next block
+                Some('a') => {
+                    let (score, tags) = parse_aline(&self.line);
+                    self.reader.next_aline = Some((self.line_offset, score, tags));
+                    break;
+                }
+                // block is over by empty line
+                None => break,
+                Some('q') => {
+                    if let Some(sline) = mafrecord.slines.last_mut() {
+                        if let Err(e) = parse_qline(&self.line, sline) {
+                            return Some(Err(e.with_context(self.context(&mafrecord.slines))));
                         }
                     }
-                    Some(Ok(mafrecord))
                 }
+                // i/e-lines and comments within the block
+                Some(_) => continue,
             }
-            _ => None, // if line is empty, iterator over
         }
+        // columns are walked in pairs of s-lines, which must have the same length
+        let checked = mafrecord.check_seq_len().map_err(|e| {
+            e.with_context(ParseContext {
+                line: first_line,
+                offset: Some(offset),
+                ..self.context(&mafrecord.slines)
+            })
+        });
+        self.reader.record_no = self.reader.record_no.map(|n| n + 1);
+        self.counter.add();
+        if let Err(e) = checked {
+            return Some(bad_record(e).map(|_| None));
+        }
+        Some(Ok(Some((offset, mafrecord))))
+    }
+}
+
+/// impl Iterator trait for MAFRecords
+impl<R: Read + Send> Iterator for MAFRecords<'_, R> {
+    type Item = Result<MAFRecord, WGAError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_offset()
+            .map(|rec| rec.map(|(_, mafrecord)| mafrecord))
     }
 }
 
@@ -491,6 +845,10 @@ impl AlignRecord for MAFRecord {
         let cigar = parse_maf_seq_to_cigar(self, false);
         Ok(RecStat::from(cigar))
     }
+
+    fn seq_metas(&self) -> Vec<SeqMeta<'_>> {
+        self.slines.iter().map(MAFSLine::meta).collect()
+    }
 }
 
 /// A MAF Writer
@@ -499,15 +857,43 @@ where
     W: Write,
 {
     inner: W,
+    config: MafWriteConfig,
+}
+
+/// Layout of s-lines written by [`MAFWriter`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MafWriteConfig {
+    /// Pad name, start, size and source size to the widest of the block and separate
+    /// fields by spaces, like the UCSC pretty format, otherwise fields are separated by tabs
+    pub pretty: bool,
+}
+
+impl MafWriteConfig {
+    /// Config of MAF outputs shared by all commands
+    pub fn global() -> Self {
+        MafWriteConfig {
+            pretty: PRETTY.load(AtomicOrdering::Relaxed),
+        }
+    }
+
+    /// Set config of MAF outputs for all commands
+    pub fn set_global(self) {
+        PRETTY.store(self.pretty, AtomicOrdering::Relaxed);
+    }
 }
 
 impl<W> MAFWriter<W>
 where
     W: Write,
 {
-    /// Create a new MAF writer
+    /// Create a new MAF writer with the global config
     pub fn new(inner: W) -> Self {
-        Self { inner }
+        Self::with_config(inner, MafWriteConfig::global())
+    }
+
+    /// Create a new MAF writer with `config`
+    pub fn with_config(inner: W, config: MafWriteConfig) -> Self {
+        Self { inner, config }
     }
 
     /// write header
@@ -518,19 +904,154 @@ where
 
     /// write records
     pub fn write_record(&mut self, record: &MAFRecord) -> Result<(), WGAError> {
-        // write a-line
-        let a_line = format!("a score={}\n", record.score);
-        write!(self.inner, "{}", a_line)?;
-        for sline in record.slines.iter() {
-            // write s-line
-            let s_line = format!(
-                "s\t{}\t{}\t{}\t{}\t{}\t{}",
-                sline.name, sline.start, sline.align_size, sline.strand, sline.size, sline.seq
-            );
-            writeln!(self.inner, "{}", s_line)?;
+        profile(Stage::Write, || self.write_block(record))
+    }
+
+    fn write_block(&mut self, record: &MAFRecord) -> Result<(), WGAError> {
+        // write a-line, tags as `paf_TAG=TYPE:VALUE`
+        write!(self.inner, "a score={}", record.score)?;
+        for tag in &record.tags {
+            if let Some((name, value)) = tag.split_once(':') {
+                write!(self.inner, " paf_{}={}", name, value)?;
+            }
+        }
+        writeln!(self.inner)?;
+        match self.config.pretty {
+            true => self.write_pretty_slines(&record.slines)?,
+            false => {
+                for sline in record.slines.iter() {
+                    // write s-line, sequence is written as is without formatting
+                    write!(
+                        self.inner,
+                        "s\t{}\t{}\t{}\t{}\t{}\t",
+                        sline.name, sline.start, sline.align_size, sline.strand, sline.size
+                    )?;
+                    self.inner.write_all(sline.seq.as_bytes())?;
+                    self.inner.write_all(b"\n")?;
+                }
+            }
         }
         // write a empty line
         writeln!(self.inner)?;
         Ok(())
     }
+
+    // names are left-aligned and numbers are right-aligned to the widest of the block
+    fn write_pretty_slines(&mut self, slines: &[MAFSLine]) -> Result<(), WGAError> {
+        let width = |f: fn(&MAFSLine) -> usize| slines.iter().map(f).max().unwrap_or(0);
+        let name_w = width(|sline| sline.name.len());
+        let start_w = width(|sline| sline.start.to_string().len());
+        let size_w = width(|sline| sline.align_size.to_string().len());
+        let src_w = width(|sline| sline.size.to_string().len());
+        for sline in slines {
+            write!(
+                self.inner,
+                "s {:<name_w$} {:>start_w$} {:>size_w$} {} {:>src_w$} ",
+                sline.name, sline.start, sline.align_size, sline.strand, sline.size
+            )?;
+            self.inner.write_all(sline.seq.as_bytes())?;
+            self.inner.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THREE_WAY: &str = "##maf version=1\n\
+        a score=10\n\
+        s ref 100 14 + 1000 ACG--TACGTAC-GTAC\n\
+        s q1  0   14 + 20   A-GTTTAC--ACGGTAC\n\
+        s q2  5   13 - 30   ---TTTACGTACGG-AC\n\n";
+
+    fn read_block(maf: &str) -> MAFRecord {
+        MAFReader::new(maf.as_bytes())
+            .unwrap()
+            .records()
+            .next()
+            .unwrap()
+            .unwrap()
+    }
+
+    // start of every s-line counted from column 0
+    fn naive_starts(rec: &MAFRecord, col_start: usize) -> Vec<u64> {
+        rec.slines
+            .iter()
+            .map(|sline| sline.start + recount_align_size(&sline.seq[..col_start]).0)
+            .collect()
+    }
+
+    #[test]
+    fn slicer_matches_slices_from_column_0() {
+        let rec = read_block(THREE_WAY);
+        let n_cols = rec.slines[0].seq.len();
+        for len in 1..=n_cols {
+            let mut slicer = rec.column_slicer();
+            let mut start = 0;
+            while start < n_cols {
+                let end = (start + len).min(n_cols);
+                let slice = slicer.slice(start, end).unwrap();
+                assert_eq!(slice, rec.slice_columns(start, end).unwrap());
+                let starts = slice.slines.iter().map(|s| s.start).collect::<Vec<_>>();
+                assert_eq!(starts, naive_starts(&rec, start));
+                start = end;
+            }
+        }
+    }
+
+    #[test]
+    fn slicer_keeps_sizes_of_every_sline() {
+        let rec = read_block(THREE_WAY);
+        let mut slicer = rec.column_slicer();
+        let slices = [(0, 5), (5, 9), (9, 17)]
+            .iter()
+            .map(|&(start, end)| slicer.slice(start, end).unwrap())
+            .collect::<Vec<_>>();
+        for (idx, sline) in rec.slines.iter().enumerate() {
+            let total: u64 = slices.iter().map(|s| s.slines[idx].align_size).sum();
+            assert_eq!(total, sline.align_size);
+            let last = &slices[2].slines[idx];
+            assert_eq!(last.start + last.align_size, sline.start + sline.align_size);
+        }
+        assert_eq!(slices[1].slines[0].start, 103);
+        assert_eq!(slices[1].slines[1].start, 4);
+        assert_eq!(slices[1].slines[2].start, 7);
+    }
+
+    #[test]
+    fn slicer_skips_and_rewinds_columns() {
+        let rec = read_block(THREE_WAY);
+        let mut slicer = rec.column_slicer();
+        // columns between slices are counted, earlier slices are recounted
+        for (start, end) in [(2, 4), (8, 12), (13, 17), (0, 3), (6, 6)] {
+            assert_eq!(
+                slicer.slice(start, end).unwrap(),
+                rec.slice_columns(start, end).unwrap()
+            );
+        }
+        assert!(slicer.slice(10, 18).is_err());
+    }
+
+    fn read_fixture(name: &str) -> Vec<MAFRecord> {
+        let path = format!("{}/test/maf/{}", env!("CARGO_MANIFEST_DIR"), name);
+        MAFReader::from_path(path)
+            .unwrap()
+            .records()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn read_crlf_indented_and_info_lines() {
+        let recs = read_fixture("lines.maf");
+        assert_eq!(recs.len(), 2);
+        assert!(recs.iter().all(|rec| rec.slines.len() == 3));
+        assert!(recs[0].slines[1].qual.is_some());
+        assert_eq!(recs[1].score, 5062);
+        assert_eq!(recs[1].tags, ["tp:A:P"]);
+        // CRLF, leading and trailing whitespace and i/e lines make no difference
+        assert_eq!(read_fixture("lines_crlf.maf"), recs);
+    }
 }
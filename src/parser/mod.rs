@@ -1,5 +1,8 @@
+pub mod axt;
 pub mod chain;
 pub mod cigar;
 pub mod common;
+pub mod delta;
 pub mod maf;
 pub mod paf;
+pub mod region;
@@ -1,7 +1,8 @@
-use crate::errors::WGAError;
-use crate::parser::cigar::parse_paf_to_cigar;
+use crate::errors::{ParseContext, WGAError};
+use crate::log::{profile, RecordCounter, Stage};
+use crate::parser::cigar::{parse_cigar_clips, parse_paf_to_cigar};
 use crate::parser::common::{AlignRecord, RecStat, Strand};
-use csv::{DeserializeRecordsIter, ReaderBuilder};
+use csv::{DeserializeRecordsIter, QuoteStyle, ReaderBuilder, WriterBuilder};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::fs::File;
@@ -11,6 +12,8 @@ use std::str;
 /// Parser for PAF format files
 pub struct PAFReader<R: io::Read> {
     inner: csv::Reader<R>,
+    // file name in error messages
+    source: Option<String>,
 }
 
 impl<R> PAFReader<R>
@@ -25,14 +28,25 @@ where
                 .delimiter(b'\t')
                 .has_headers(false)
                 .comment(Some(b'#'))
+                // tags are raw strings, a `"` in them is not a quote
+                .quoting(false)
                 .from_reader(reader),
+            source: None,
         }
     }
 
+    /// Set file name shown in error messages
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
     /// Iterate over the records in the PAF file
     pub fn records(&mut self) -> Records<'_, R> {
         Records {
             inner: self.inner.deserialize(),
+            source: self.source.as_deref(),
+            counter: RecordCounter::new(self.source.as_deref()),
         }
     }
 }
@@ -40,10 +54,21 @@ where
 impl PAFReader<File> {
     /// Create a new PAF parser from a file path
     pub fn from_path<P: AsRef<std::path::Path>>(path: P) -> io::Result<PAFReader<File>> {
-        File::open(path).map(PAFReader::new)
+        let source = path.as_ref().to_string_lossy().to_string();
+        File::open(path).map(|file| PAFReader::new(file).with_source(&source))
     }
 }
 
+/// Writer of PAF records, tags vary in number and are written as they are without quoting
+pub fn paf_writer<W: io::Write>(writer: W) -> csv::Writer<W> {
+    WriterBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .flexible(true)
+        .quote_style(QuoteStyle::Never)
+        .from_writer(writer)
+}
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 /// A PAF record refer to https://github.com/lh3/miniasm/blob/master/PAF.md
 pub struct PafRecord {
@@ -63,16 +88,82 @@ pub struct PafRecord {
     pub tags: Vec<String>,
 }
 
+impl PafRecord {
+    /// Edit distance in `NM:i` tag, tags are only checked when used
+    fn edit_distance(&self) -> Result<Option<usize>, WGAError> {
+        let Some(tag) = self.tags.iter().find(|x| x.starts_with("NM:")) else {
+            return Ok(None);
+        };
+        match tag.strip_prefix("NM:i:").map(str::parse) {
+            Some(Ok(nm)) => Ok(Some(nm)),
+            _ => Err(WGAError::InvalidRecord(format!("invalid tag `{}`", tag))),
+        }
+    }
+
+    /// PAF converted from SAM may have clips in `cg` tag, which are outside of
+    /// query start and end in general. If query start and end include clipped bases,
+    /// move them onto the aligned part, leading clips are at query end on `-` strand
+    fn exclude_clips(&mut self) -> Result<(), WGAError> {
+        let Some(cigar) = self.tags.iter().find(|x| x.starts_with("cg:Z:")) else {
+            return Ok(());
+        };
+        let Some((head, tail, query_len)) = parse_cigar_clips(cigar)? else {
+            return Ok(());
+        };
+        if self.query_end.checked_sub(self.query_start) == Some(head + tail + query_len) {
+            let (start_clip, end_clip) = match self.strand {
+                Strand::Positive => (head, tail),
+                Strand::Negative => (tail, head),
+            };
+            self.query_start += start_clip;
+            self.query_end -= end_clip;
+        }
+        Ok(())
+    }
+}
+
 /// An iterator struct for PAF records
 pub struct Records<'a, R: io::Read> {
     inner: DeserializeRecordsIter<'a, R, PafRecord>,
+    source: Option<&'a str>,
+    counter: RecordCounter,
+}
+
+impl<R: io::Read> Records<'_, R> {
+    // attach the position of failing record from csv
+    fn with_context(&self, err: csv::Error) -> WGAError {
+        let mut ctx = ParseContext {
+            file: self.source.map(str::to_string),
+            ..Default::default()
+        };
+        let pos = err.position().cloned();
+        if let Some(pos) = pos {
+            ctx.record = Some(pos.record() + 1);
+            ctx.line = Some(pos.line());
+            ctx.offset = Some(pos.byte());
+        }
+        let err = match err.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => WGAError::ParsePaf(err.to_string()),
+            _ => WGAError::CsvDeserialize(err),
+        };
+        err.with_context(ctx)
+    }
 }
 
 /// impl Iterator for Records
 impl<R: io::Read> Iterator for Records<'_, R> {
-    type Item = csv::Result<PafRecord>;
-    fn next(&mut self) -> Option<csv::Result<PafRecord>> {
-        self.inner.next()
+    type Item = Result<PafRecord, WGAError>;
+    fn next(&mut self) -> Option<Result<PafRecord, WGAError>> {
+        match profile(Stage::Parse, || self.inner.next())? {
+            Ok(mut rec) => {
+                self.counter.add();
+                if let Err(e) = rec.exclude_clips() {
+                    return Some(Err(rec.context_err(e)));
+                }
+                Some(Ok(rec))
+            }
+            Err(err) => Some(Err(self.with_context(err))),
+        }
     }
 }
 
@@ -145,7 +236,8 @@ impl AlignRecord for PafRecord {
 
     fn get_stat(&self) -> Result<RecStat, WGAError> {
         // just convert cigar to stat
-        let cigar = parse_paf_to_cigar(self)?;
+        let cigar =
+            parse_paf_to_cigar(self, self.edit_distance().map_err(|e| self.context_err(e))?)?;
         Ok(RecStat::from(cigar))
     }
 }
@@ -215,3 +307,104 @@ fn cs_to_cigar(cs_tag: &str) -> String {
 
     cigar
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::cigar::parse_cigar_to_units;
+
+    fn fixture(name: &str) -> String {
+        let path = format!("{}/test/paf/{}", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    fn read(paf: &str) -> Vec<PafRecord> {
+        PAFReader::new(paf.as_bytes())
+            .records()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    fn write(recs: &[PafRecord]) -> String {
+        let mut buf = Vec::new();
+        let mut wtr = paf_writer(&mut buf);
+        for rec in recs {
+            wtr.serialize(rec).unwrap();
+        }
+        wtr.flush().unwrap();
+        drop(wtr);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn read_paf_of_aligners() {
+        for name in ["wfmash.paf", "minimap2.paf", "anchorwave.paf"] {
+            let paf = fixture(name);
+            let recs = read(&paf);
+            assert_eq!(recs.len(), paf.lines().count(), "{}", name);
+            for rec in &recs {
+                // spans of cigar match the coordinates
+                let units = parse_cigar_to_units(rec).unwrap();
+                let span = |ops: &str| -> u64 {
+                    units
+                        .iter()
+                        .filter(|(op, _)| ops.contains(*op))
+                        .map(|(_, len)| len)
+                        .sum()
+                };
+                assert_eq!(span("M=XI"), rec.query_end - rec.query_start, "{}", name);
+                assert_eq!(span("M=XD"), rec.target_end - rec.target_start, "{}", name);
+                rec.get_stat().unwrap();
+            }
+            // tags are written back as they are
+            assert_eq!(write(&recs), paf, "{}", name);
+        }
+    }
+
+    #[test]
+    fn edit_distance_of_minimap2() {
+        let recs = read(&fixture("minimap2.paf"));
+        let nms = recs
+            .iter()
+            .map(|rec| rec.edit_distance().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(nms, [Some(51), Some(39), Some(5)]);
+        assert_eq!(
+            read(&fixture("wfmash.paf"))[0].edit_distance().unwrap(),
+            None
+        );
+    }
+
+    const FIELDS: &str = "q\t100\t0\t10\t+\tt\t100\t0\t10\t10\t10\t60";
+
+    #[test]
+    fn keep_tag_without_type() {
+        let paf = format!("{}\tzz:5\tab\tcg:Z:10M\n", FIELDS);
+        let recs = read(&paf);
+        assert_eq!(recs[0].tags, ["zz:5", "ab", "cg:Z:10M"]);
+        // not used, not checked
+        recs[0].get_stat().unwrap();
+        assert_eq!(write(&recs), paf);
+    }
+
+    #[test]
+    fn keep_tag_with_quote() {
+        let paf = format!("{}\tco:Z:say \"hi\"\t\"x:Z:y\tcg:Z:10M\n", FIELDS);
+        let recs = read(&paf);
+        assert_eq!(recs[0].tags, ["co:Z:say \"hi\"", "\"x:Z:y", "cg:Z:10M"]);
+        assert_eq!(write(&recs), paf);
+    }
+
+    #[test]
+    fn reject_invalid_nm_when_used() {
+        for nm in ["NM:i:abc", "NM:5", "NM:f:1.5", "NM:i:-1"] {
+            let paf = format!("{}\t{}\tcg:Z:10M\n", FIELDS, nm);
+            // reading, writing and cigar do not use NM
+            let recs = read(&paf);
+            assert_eq!(write(&recs), paf);
+            assert!(parse_cigar_to_units(&recs[0]).is_ok());
+            assert!(recs[0].edit_distance().is_err(), "{}", nm);
+            assert!(recs[0].get_stat().is_err(), "{}", nm);
+        }
+    }
+}
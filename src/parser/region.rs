@@ -0,0 +1,170 @@
+use crate::errors::{ParseGenomeRegionErrKind, WGAError};
+use crate::utils::{get_input_reader, parse_str2u64};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::io::BufRead;
+use std::str::FromStr;
+
+/// End of regions without end, `chr` or `chr:start-`, clipped to sequence length when used
+pub const OPEN_END: u64 = u64::MAX;
+
+/// A region `[start, end)` (0-based, half-open) on a sequence
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenomeRegion {
+    pub name: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl GenomeRegion {
+    /// Region of the whole sequence
+    pub fn whole(name: &str) -> Self {
+        GenomeRegion {
+            name: name.to_string(),
+            start: 0,
+            end: OPEN_END,
+        }
+    }
+
+    /// If the region covers the whole sequence
+    pub fn is_whole(&self) -> bool {
+        self.start == 0 && self.end == OPEN_END
+    }
+
+    /// If `[start, end)` on sequence `name` overlaps the region
+    pub fn overlaps(&self, name: &str, start: u64, end: u64) -> bool {
+        self.name == name && start < self.end && end > self.start
+    }
+}
+
+/// Parse `chr`, `chr:start-end` or `chr:start-`, names with `:` are taken as whole sequences
+/// unless they end with coordinates
+impl FromStr for GenomeRegion {
+    type Err = WGAError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let format_err =
+            || WGAError::ParseGenomeRegion(ParseGenomeRegionErrKind::FormatNotMatch(spec.into()));
+        let (name, range) = match spec.rsplit_once(':') {
+            // coordinates start with a digit, e.g. `chr1:100-200`
+            Some((name, range)) if range.starts_with(|c: char| c.is_ascii_digit()) => {
+                (name, Some(range))
+            }
+            _ => (spec, None),
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format_err());
+        }
+        let Some(range) = range else {
+            return Ok(GenomeRegion::whole(name));
+        };
+        let (start, end) = range.split_once('-').ok_or_else(format_err)?;
+        let start = parse_str2u64(start).map_err(|_| format_err())?;
+        let end = match end {
+            "" => OPEN_END,
+            end => parse_str2u64(end).map_err(|_| format_err())?,
+        };
+        if start > end {
+            return Err(WGAError::ParseGenomeRegion(
+                ParseGenomeRegionErrKind::StartGTEnd(start, end),
+            ));
+        }
+        Ok(GenomeRegion {
+            name: name.to_string(),
+            start,
+            end,
+        })
+    }
+}
+
+impl Display for GenomeRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.is_whole(), self.end == OPEN_END) {
+            (true, _) => write!(f, "{}", self.name),
+            (false, true) => write!(f, "{}:{}-", self.name, self.start),
+            (false, false) => write!(f, "{}:{}-{}", self.name, self.start, self.end),
+        }
+    }
+}
+
+/// Parse region specs of command line in order, each one is a region of [`GenomeRegion`],
+/// a comma separated list of them, or a BED file ending with `.bed` (compressed or not)
+pub fn parse_region_specs<S: AsRef<str>>(specs: &[S]) -> Result<Vec<GenomeRegion>, WGAError> {
+    let mut regions = Vec::new();
+    for spec in specs.iter().flat_map(|spec| spec.as_ref().split(',')) {
+        match is_bed_path(spec) {
+            true => regions.extend(read_bed_regions(get_input_reader(&Some(spec.into()))?)?),
+            false => regions.push(spec.parse()?),
+        }
+    }
+    Ok(regions)
+}
+
+/// Parse region specs of one axis or sequence, error if they are not exactly one region
+pub fn parse_single_region(spec: &str) -> Result<GenomeRegion, WGAError> {
+    let mut regions = parse_region_specs(&[spec])?;
+    match regions.len() {
+        1 => Ok(regions.remove(0)),
+        n => Err(WGAError::InvalidArgs(format!(
+            "only one region is allowed, but `{}` has {}",
+            spec, n
+        ))),
+    }
+}
+
+/// Read regions of the first 3 columns of BED, `track`/`browser`/`#` lines are skipped
+pub fn read_bed_regions<R: BufRead>(reader: R) -> Result<Vec<GenomeRegion>, WGAError> {
+    Ok(read_named_bed_regions(reader)?
+        .into_iter()
+        .map(|(region, _)| region)
+        .collect())
+}
+
+/// Read regions of BED with the 4th column (name) if any, `track`/`browser`/`#` lines are skipped
+pub fn read_named_bed_regions<R: BufRead>(
+    reader: R,
+) -> Result<Vec<(GenomeRegion, Option<String>)>, WGAError> {
+    let mut regions = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let fields = line.split('\t').take(4).collect::<Vec<&str>>();
+        let (name, start, end) = match fields.as_slice() {
+            [name, start, end, ..] => (name, parse_str2u64(start)?, parse_str2u64(end)?),
+            _ => {
+                return Err(WGAError::InvalidRecord(format!(
+                    "invalid BED record: {}",
+                    line
+                )))
+            }
+        };
+        if start > end {
+            return Err(WGAError::ParseGenomeRegion(
+                ParseGenomeRegionErrKind::StartGTEnd(start, end),
+            ));
+        }
+        let region = GenomeRegion {
+            name: name.to_string(),
+            start,
+            end,
+        };
+        regions.push((region, fields.get(3).map(|field| field.to_string())));
+    }
+    Ok(regions)
+}
+
+// BED files are told from sequence names by extension, before that of compression
+fn is_bed_path(spec: &str) -> bool {
+    let spec = spec.to_ascii_lowercase();
+    let spec = [".gz", ".bgz", ".bz2", ".xz", ".zst"]
+        .iter()
+        .find_map(|ext| spec.strip_suffix(ext))
+        .unwrap_or(&spec);
+    spec.ends_with(".bed")
+}
@@ -0,0 +1,212 @@
+use crate::errors::WGAError;
+use crate::log::RecordCounter;
+use crate::parser::common::{AlignRecord, Strand};
+use crate::parser::maf::{MAFReader, MAFRecord};
+use crate::utils::parse_str2u64;
+use log::info;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::{BufRead, Read, Write};
+
+// INFO fields added to variants covered by a block
+const INFO_HEADERS: [&str; 4] = [
+    "##INFO=<ID=BID,Number=1,Type=Float,Description=\"Identity of aligned columns in the alignment block\">",
+    "##INFO=<ID=BEDGE,Number=1,Type=Integer,Description=\"Distance to the nearest edge of the alignment block on target\">",
+    "##INFO=<ID=BINV,Number=0,Type=Flag,Description=\"Alignment block is on the negative strand of query\">",
+    "##INFO=<ID=GAPD,Number=1,Type=Float,Description=\"Fraction of gap columns of the alignment block within the window around POS\">",
+];
+
+/// Alignment context of a variant in its covering block
+#[derive(Debug)]
+struct BlockContext {
+    align_size: u64,
+    identity: f64,
+    edge_dist: u64,
+    inverted: bool,
+    gap_density: f64,
+}
+
+impl BlockContext {
+    fn to_info(&self) -> String {
+        let mut info = format!(
+            "BID={:.4};BEDGE={};GAPD={:.4}",
+            self.identity, self.edge_dist, self.gap_density
+        );
+        if self.inverted {
+            info.push_str(";BINV");
+        }
+        info
+    }
+}
+
+/// Annotate variants in VCF with the alignment context of the MAF block covering POS on target,
+/// the longest one if several blocks cover it, and variants without covering block are kept unchanged
+pub fn annotate_vcf<B: BufRead, R: Read + Send>(
+    vcf_reader: B,
+    mafreader: &mut MAFReader<R>,
+    writer: &mut dyn Write,
+    query_name: Option<&str>,
+    window: u64,
+) -> Result<(), WGAError> {
+    let mut headers = Vec::new();
+    let mut records = Vec::new();
+    // VCF is the input of command
+    let mut counter = RecordCounter::new(None);
+    for line in vcf_reader.lines() {
+        let line = line?;
+        match line.starts_with('#') {
+            true => headers.push(line),
+            false if !line.is_empty() => {
+                counter.add();
+                records.push(line);
+            }
+            false => {}
+        }
+    }
+
+    // 0-based positions of variants grouped by CHROM and sorted
+    let mut positions: HashMap<&str, Vec<(u64, usize)>> = HashMap::new();
+    for (idx, line) in records.iter().enumerate() {
+        let mut fields = line.split('\t');
+        let (chrom, pos) = match (fields.next(), fields.next()) {
+            (Some(chrom), Some(pos)) => (chrom, parse_str2u64(pos)?),
+            _ => {
+                return Err(WGAError::InvalidRecord(format!(
+                    "invalid VCF record: {}",
+                    line
+                )))
+            }
+        };
+        positions
+            .entry(chrom)
+            .or_default()
+            .push((pos.saturating_sub(1), idx));
+    }
+    for poses in positions.values_mut() {
+        poses.sort_unstable();
+    }
+
+    let contexts = mafreader
+        .records()
+        .par_bridge()
+        .map(|rec| {
+            let mut rec = rec?;
+            if let Some(qname) = query_name {
+                match rec.get_query_idx_byname(qname) {
+                    Some(idx) => rec.set_query_idx(idx),
+                    None => return Ok(Vec::new()),
+                }
+            }
+            Ok(block_contexts(&rec, &positions, window))
+        })
+        .collect::<Result<Vec<_>, WGAError>>()?;
+
+    // keep the context of the longest covering block
+    let mut best: HashMap<usize, BlockContext> = HashMap::new();
+    for (idx, context) in contexts.into_iter().flatten() {
+        match best.get(&idx) {
+            Some(prev) if prev.align_size >= context.align_size => {}
+            _ => {
+                best.insert(idx, context);
+            }
+        }
+    }
+    info!(
+        "annotated {} of {} variants with alignment context",
+        best.len(),
+        records.len()
+    );
+
+    for header in &headers {
+        if header.starts_with("#CHROM") {
+            for info_header in INFO_HEADERS {
+                writeln!(writer, "{}", info_header)?;
+            }
+        }
+        writeln!(writer, "{}", header)?;
+    }
+    for (idx, line) in records.iter().enumerate() {
+        let context = match best.get(&idx) {
+            Some(context) => context,
+            None => {
+                writeln!(writer, "{}", line)?;
+                continue;
+            }
+        };
+        let mut fields = line.split('\t').collect::<Vec<_>>();
+        let info = match fields.get(7) {
+            Some(&".") | Some(&"") | None => context.to_info(),
+            Some(info) => format!("{};{}", info, context.to_info()),
+        };
+        // INFO is the 8th column, sites-only VCF may end before it
+        fields.resize(fields.len().max(8), ".");
+        fields[7] = &info;
+        writeln!(writer, "{}", fields.join("\t"))?;
+    }
+    Ok(())
+}
+
+// contexts of variants within the target range of a block
+fn block_contexts(
+    rec: &MAFRecord,
+    positions: &HashMap<&str, Vec<(u64, usize)>>,
+    window: u64,
+) -> Vec<(usize, BlockContext)> {
+    let (t_start, t_end) = (rec.target_start(), rec.target_end());
+    let covered = match positions.get(rec.target_name()) {
+        Some(poses) => {
+            let from = poses.partition_point(|(pos, _)| *pos < t_start);
+            let to = poses.partition_point(|(pos, _)| *pos < t_end);
+            &poses[from..to]
+        }
+        None => return Vec::new(),
+    };
+    if covered.is_empty() {
+        return Vec::new();
+    }
+
+    let t_seq = rec.target_seq().as_bytes();
+    let q_seq = rec.query_seq().as_bytes();
+    // column of each target base, and count of gap columns before each column
+    let mut t_cols = Vec::with_capacity((t_end - t_start) as usize);
+    let mut gap_prefix = Vec::with_capacity(t_seq.len() + 1);
+    let (mut gaps, mut matches, mut aligned) = (0, 0, 0);
+    gap_prefix.push(0);
+    for (col, (t, q)) in t_seq.iter().zip(q_seq).enumerate() {
+        if *t != b'-' {
+            t_cols.push(col);
+        }
+        if *t == b'-' || *q == b'-' {
+            gaps += 1;
+        } else {
+            aligned += 1;
+            if t.eq_ignore_ascii_case(q) {
+                matches += 1;
+            }
+        }
+        gap_prefix.push(gaps);
+    }
+    let identity = match aligned {
+        0 => 0.0,
+        _ => matches as f64 / aligned as f64,
+    };
+
+    covered
+        .iter()
+        .map(|&(pos, idx)| {
+            // window on target clipped to the block
+            let lo = pos.saturating_sub(window).max(t_start) - t_start;
+            let hi = (pos + window).min(t_end - 1) - t_start;
+            let (col_lo, col_hi) = (t_cols[lo as usize], t_cols[hi as usize]);
+            let window_gaps = gap_prefix[col_hi + 1] - gap_prefix[col_lo];
+            let context = BlockContext {
+                align_size: t_end - t_start,
+                identity,
+                edge_dist: (pos - t_start).min(t_end - 1 - pos),
+                inverted: rec.query_strand() == Strand::Negative,
+                gap_density: window_gaps as f64 / (col_hi - col_lo + 1) as f64,
+            };
+            (idx, context)
+        })
+        .collect()
+}
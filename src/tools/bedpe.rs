@@ -0,0 +1,109 @@
+use crate::{
+    errors::WGAError,
+    parser::{
+        chain::ChainReader,
+        cigar::{parse_chain_to_blocks, parse_cigar_to_blocks, parse_maf_seq_to_blocks},
+        common::{AlignRecord, Block},
+        maf::MAFReader,
+        paf::PAFReader,
+    },
+    utils::par_write_ordered,
+};
+use std::io::{Read, Write};
+
+const BEDPE_HEADER: &[u8] =
+    b"#chrom1\tstart1\tend1\tchrom2\tstart2\tend2\tname\tscore\tstrand1\tstrand2\tidentity\n";
+
+// BEDPE lines of a record with its index as name, target is the first end and query the
+// second on forward strand, segments are written instead of the record span if any
+fn write_bedpe_lines<T: AlignRecord>(
+    idx: usize,
+    rec: &T,
+    segments: Option<Vec<Block>>,
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    let stat = rec.get_stat()?;
+    // chains have no bases to count identity
+    let identity = match stat.aligned_size {
+        0 => ".".to_string(),
+        size => format!("{:.4}", stat.matched as f64 / size as f64),
+    };
+    let spans = match segments {
+        Some(blocks) => blocks
+            .iter()
+            .map(|b| (b.target_start, b.target_end, b.query_start, b.query_end))
+            .collect(),
+        None => vec![(
+            rec.target_start(),
+            rec.target_end(),
+            rec.query_start(),
+            rec.query_end(),
+        )],
+    };
+    for (t_start, t_end, q_start, q_end) in spans {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t.\t{}\t{}\t{}",
+            rec.target_name(),
+            t_start,
+            t_end,
+            rec.query_name(),
+            q_start,
+            q_end,
+            idx,
+            rec.target_strand(),
+            rec.query_strand(),
+            identity
+        )?;
+    }
+    Ok(())
+}
+
+/// Write MAF records as BEDPE in input order, one line per gap-free segment if `split`
+pub fn write_maf_bedpe<R: Read + Send>(
+    reader: &mut MAFReader<R>,
+    writer: &mut dyn Write,
+    query_name: Option<&str>,
+    split: bool,
+) -> Result<(), WGAError> {
+    writer.write_all(BEDPE_HEADER)?;
+    par_write_ordered(reader.records(), writer, |idx, mut rec| {
+        if let Some(qname) = query_name {
+            rec.set_query_idx_byname(qname)?;
+        }
+        let segments = split.then(|| parse_maf_seq_to_blocks(&rec)).transpose()?;
+        let mut buf = Vec::new();
+        write_bedpe_lines(idx, &rec, segments, &mut buf)?;
+        Ok(buf)
+    })
+}
+
+/// Write PAF records as BEDPE in input order, one line per gap-free segment if `split`
+pub fn write_paf_bedpe<R: Read + Send>(
+    reader: &mut PAFReader<R>,
+    writer: &mut dyn Write,
+    split: bool,
+) -> Result<(), WGAError> {
+    writer.write_all(BEDPE_HEADER)?;
+    par_write_ordered(reader.records(), writer, |idx, rec| {
+        let segments = split.then(|| parse_cigar_to_blocks(&rec)).transpose()?;
+        let mut buf = Vec::new();
+        write_bedpe_lines(idx, &rec, segments, &mut buf)?;
+        Ok(buf)
+    })
+}
+
+/// Write chain records as BEDPE in input order, one line per gap-free segment if `split`
+pub fn write_chain_bedpe<R: Read + Send>(
+    reader: &mut ChainReader<R>,
+    writer: &mut dyn Write,
+    split: bool,
+) -> Result<(), WGAError> {
+    writer.write_all(BEDPE_HEADER)?;
+    par_write_ordered(reader.records()?, writer, |idx, rec| {
+        let segments = split.then(|| parse_chain_to_blocks(&rec)).transpose()?;
+        let mut buf = Vec::new();
+        write_bedpe_lines(idx, &rec, segments, &mut buf)?;
+        Ok(buf)
+    })
+}
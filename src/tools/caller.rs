@@ -1,29 +1,131 @@
+use crate::converter::{insert_paf_gaps, paf2maf_ungapped};
 use crate::errors::WGAError;
-use crate::parser::cigar::{cigar_cat_ext_caller, parse_cigar_to_insert};
-use crate::parser::common::{AlignRecord, Strand};
-use crate::parser::maf::{MAFReader, MAFRecord, MAFSLine};
+use crate::log::{profile, record_skipped, Stage, WarnCategory};
+use crate::parser::cigar::cigar_cat_ext_caller;
+use crate::parser::common::{AlignRecord, ChunkStrategy, InvMode, OddChar, Strand, UnknownBase};
+use crate::parser::maf::{MAFReader, MAFRecord};
 use crate::parser::paf::PAFReader;
 use crate::tools::index::MafIndex;
-use crate::utils::reverse_complement;
+use crate::tools::stat::{BlockQc, QcThresholds};
+use crate::utils::{
+    create_output_writer, get_input_reader, merge_intervals, par_write_ordered, read_seq_size_list,
+};
 use itertools::Itertools;
+use log::{info, warn};
 use noodles::vcf;
 use noodles::vcf::{
     header::{
         record::value::{
-            map::{format::Type as fmttype, info::Type as infotype, Contig, Format, Info},
+            map::{
+                format::Type as fmttype, info::Type as infotype, AlternativeAllele, Contig, Filter,
+                Format, Info,
+            },
             Map,
         },
         Number,
     },
     record::{
-        genotypes::keys::key as gtkey, info::field::key as infokey, Info as recinfo, Position,
+        alternate_bases::allele::Symbol, genotypes::keys::key as gtkey,
+        info::field::key as infokey, Filters, Info as recinfo, Position,
     },
     Header, Record,
 };
 use rayon::iter::ParallelIterator;
 use rayon::prelude::*;
 use rust_htslib::faidx;
-use std::io::{Read, Write};
+use rust_lapper::{Interval, Lapper};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// FILTER of variants with query qualities below `--min-qline-qual`
+const LOW_QUAL: &str = "LowQual";
+
+// FILTER of SVs longer than `--max-svlen` if they are kept
+const LONG_SV: &str = "LongSV";
+
+/// SVs longer than `len` are dropped, or kept with FILTER `LongSV` if `filter`
+#[derive(Debug, Clone, Copy)]
+pub struct MaxSvLen {
+    pub len: u64,
+    pub filter: bool,
+}
+
+/// Options of `call`, built once from the command line and shared by all call functions,
+/// defaults are those of the command line
+#[derive(Debug, Clone)]
+pub struct CallOpts {
+    /// call SNPs
+    pub snp: bool,
+    /// indels longer than it are SVs
+    pub svlen: u64,
+    /// indels of at least N bp and up to `svlen` are plain VCF indels without SVTYPE
+    pub min_indel: Option<u64>,
+    /// min distance of BND on the same target, None to not call BND
+    pub bnd_distance: Option<u64>,
+    /// two MAFs are haplotypes of one sample
+    pub diploid: bool,
+    pub ploidy: u8,
+    /// query of multiple-alignment blocks, None for the first one
+    pub query_name: Option<String>,
+    /// SNPs and small indels within N bp of an SV breakpoint are dropped
+    pub mask_near_sv: u64,
+    pub inv_mode: InvMode,
+    /// SVs of the same type with breakpoints and lengths within N bp are merged
+    pub merge_dist: u64,
+    pub chunk_strategy: ChunkStrategy,
+    pub chunk_size: usize,
+    /// rebuild a stale MAF index instead of ignoring it
+    pub rebuild_index: bool,
+    pub unknown_base: UnknownBase,
+    pub gvcf: bool,
+    pub odd_char: OddChar,
+    /// annotate variants with INFO of their source block
+    pub block_info: bool,
+    /// FILTER of variants with q-line qualities below it
+    pub min_qual: Option<u8>,
+    pub max_svlen: Option<MaxSvLen>,
+    pub qc: QcThresholds,
+    pub header_extra: VcfHeaderExtra,
+    /// checkpoint file to resume from
+    pub checkpoint: Option<String>,
+    /// dir of one VCF per target instead of the output
+    pub split_by_chrom: Option<String>,
+}
+
+impl Default for CallOpts {
+    fn default() -> Self {
+        Self {
+            snp: false,
+            svlen: 50,
+            min_indel: None,
+            bnd_distance: None,
+            diploid: false,
+            ploidy: 2,
+            query_name: None,
+            mask_near_sv: 0,
+            inv_mode: InvMode::Nested,
+            merge_dist: 0,
+            chunk_strategy: ChunkStrategy::None,
+            chunk_size: 1_000_000,
+            rebuild_index: false,
+            unknown_base: UnknownBase::Error,
+            gvcf: false,
+            odd_char: OddChar::default(),
+            block_info: false,
+            min_qual: None,
+            max_svlen: None,
+            qc: QcThresholds::default(),
+            header_extra: VcfHeaderExtra::default(),
+            checkpoint: None,
+            split_by_chrom: None,
+        }
+    }
+}
 
 // A example:
 //
@@ -35,167 +137,1197 @@ use std::io::{Read, Write};
 // main function, it return a Result<(), WGAErr>
 // NOTE: but other functions took anyhow, bucause noodles::vcf's error' organization is too complex
 // and it will not be error in 99.9% cases
-#[allow(clippy::too_many_arguments)]
 pub fn call_var_maf<R: Read + Send>(
     mafreader: &mut MAFReader<R>,
-    mafindex: Option<MafIndex>,
+    contigs: Option<Vec<(String, u64)>>,
     writer: &mut dyn Write,
-    if_snp: bool,
-    svlen_cutoff: u64,
-    _between: bool,
     sample: Option<&str>,
-    query_name: Option<&str>,
+    opts: &CallOpts,
+    checkpoint: Option<&mut CallCheckpoint>,
 ) -> Result<(), WGAError> {
+    let query_name = opts.query_name.as_deref();
+    let (gvcf, ploidy) = (opts.gvcf, opts.ploidy);
     let mut vcf_wtr = vcf::Writer::new(writer);
     let sample = sample.unwrap_or("sample");
-    let mut header = build_header(sample)?;
+    let mut header = build_header(&[sample])?;
+    if opts.block_info {
+        add_header_block_info(&mut header)?;
+    }
+    if let Some(min_qual) = opts.min_qual {
+        header.filters_mut().insert(
+            LOW_QUAL.to_string(),
+            Map::<Filter>::new(format!("Query base quality in q-line below {}", min_qual)),
+        );
+    }
+    add_header_max_svlen(&mut header, opts.max_svlen);
+
+    let mut mafrecords = read_maf_blocks(mafreader, query_name, &opts.qc)?;
+    let bnd_recs = match opts.bnd_distance {
+        Some(distance) => {
+            header.infos_mut().insert(
+                infokey::MATE_BREAKEND_IDS,
+                Map::<Info>::from(&infokey::MATE_BREAKEND_IDS),
+            );
+            profile(Stage::Call, || {
+                call_breakends(&mut mafrecords, query_name, distance)
+            })?
+        }
+        None => Vec::new(),
+    };
+
+    // add contig to header
+    let seen = mafrecords
+        .iter()
+        .map(|rec| (rec.slines[0].name.clone(), rec.slines[0].size))
+        .collect();
+    insert_contigs(header_contigs(contigs, seen)?, &mut header)?;
+    if gvcf {
+        header.alternative_alleles_mut().insert(
+            Symbol::Unspecified,
+            Map::<AlternativeAllele>::new("Any allele other than REF"),
+        );
+    }
+    opts.header_extra.apply(&mut header)?;
+
+    let call = |mafrecords| call_blocks(mafrecords, opts);
+    if let Some(dir) = &opts.split_by_chrom {
+        return write_split_by_chrom(mafrecords, &header, Path::new(dir), gvcf, ploidy, call);
+    }
+    let Some(checkpoint) = checkpoint else {
+        let (mut var_recs, mafrecords) = call(mafrecords)?;
+        if !bnd_recs.is_empty() {
+            info!("{} breakends between blocks", bnd_recs.len());
+            insert_breakends(&mut var_recs, bnd_recs, &mafrecords);
+        }
+        return profile(Stage::Write, || {
+            vcf_wtr.write_header(&header)?;
+            write_calls(&mut vcf_wtr, &header, var_recs, &mafrecords, gvcf, ploidy)
+        });
+    };
 
+    // call and write target by target, each one done is recorded in checkpoint
+    if !checkpoint.header_written() {
+        vcf_wtr.write_header(&header)?;
+        checkpoint.commit(None, vcf_wtr.get_mut())?;
+    }
+    let targets = mafrecords
+        .into_iter()
+        .group_by(|rec| rec.target_name().to_string())
+        .into_iter()
+        .map(|(name, recs)| (name, recs.collect::<Vec<_>>()))
+        .collect::<Vec<_>>();
+    let total = targets.len();
+    for (idx, (name, mafrecords)) in targets.into_iter().enumerate() {
+        if checkpoint.is_done(&name) {
+            info!("skip {} ({}/{}), done in checkpoint", name, idx + 1, total);
+            continue;
+        }
+        let (var_recs, mafrecords) = call(mafrecords)?;
+        let count = var_recs.len();
+        profile(Stage::Write, || {
+            write_calls(&mut vcf_wtr, &header, var_recs, &mafrecords, gvcf, ploidy)
+        })?;
+        checkpoint.commit(Some(&name), vcf_wtr.get_mut())?;
+        info!(
+            "called {} variants of {} ({}/{})",
+            count,
+            name,
+            idx + 1,
+            total
+        );
+    }
+    checkpoint.finish()
+}
+
+// call and write sorted blocks target by target into `{dir}/{target}.vcf`,
+// each with the full header but only the contig line of its target
+fn write_split_by_chrom(
+    mafrecords: Vec<MAFRecord>,
+    header: &Header,
+    dir: &Path,
+    gvcf: bool,
+    ploidy: u8,
+    call: impl Fn(Vec<MAFRecord>) -> Result<(Vec<VarRec>, Vec<MAFRecord>), WGAError>,
+) -> Result<(), WGAError> {
+    let targets = mafrecords
+        .into_iter()
+        .group_by(|rec| rec.target_name().to_string())
+        .into_iter()
+        .map(|(name, recs)| (name, recs.collect::<Vec<_>>()))
+        .collect::<Vec<_>>();
+    let total = targets.len();
+    for (idx, (name, mafrecords)) in targets.into_iter().enumerate() {
+        let mut chrom_header = header.clone();
+        chrom_header.contigs_mut().clear();
+        insert_contigs(
+            vec![(name.clone(), mafrecords[0].target_length())],
+            &mut chrom_header,
+        )?;
+        let (var_recs, mafrecords) = call(mafrecords)?;
+        let count = var_recs.len();
+        // a target name may not be a valid file name
+        let out_path = dir.join(format!("{}.vcf", name.replace('/', "_")));
+        let mut vcf_wtr = vcf::Writer::new(create_output_writer(&out_path)?);
+        profile(Stage::Write, || {
+            vcf_wtr.write_header(&chrom_header)?;
+            write_calls(
+                &mut vcf_wtr,
+                &chrom_header,
+                var_recs,
+                &mafrecords,
+                gvcf,
+                ploidy,
+            )
+        })?;
+        vcf_wtr.get_mut().flush()?;
+        info!(
+            "called {} variants of {} ({}/{}) into `{}`",
+            count,
+            name,
+            idx + 1,
+            total,
+            out_path.display()
+        );
+    }
+    Ok(())
+}
+
+// write variants of sorted blocks, with reference and no-call blocks of them if `gvcf`
+fn write_calls<W: Write>(
+    vcf_wtr: &mut vcf::Writer<W>,
+    header: &Header,
+    var_recs: Vec<VarRec>,
+    mafrecords: &[MAFRecord],
+    gvcf: bool,
+    ploidy: u8,
+) -> Result<(), WGAError> {
+    if !gvcf {
+        for rec in var_recs {
+            vcf_wtr.write_record(header, &rec.to_record(ploidy)?)?;
+        }
+        return Ok(());
+    }
+
+    let mut recs = var_recs
+        .into_iter()
+        .map(|rec| {
+            let format = format!("GT:QI\t{}:{}", genotype('1', ploidy), rec.qi.join(","));
+            (rec, format)
+        })
+        .collect::<Vec<_>>();
+    for (rec, aligned) in gvcf_blocks(mafrecords) {
+        let allele = match aligned {
+            true => '0',
+            false => '.',
+        };
+        recs.push((rec, format!("GT:QI\t{}:.", genotype(allele, ploidy))));
+    }
+    recs.sort_by(|(a, _), (b, _)| natord::compare(&a.chro, &b.chro).then(a.pos.cmp(&b.pos)));
+    for (rec, format) in recs {
+        vcf_wtr.write_record(header, &rec.to_record_with(&format)?)?;
+    }
+    Ok(())
+}
+
+/// Paired BND records of junctions between blocks adjacent on query, if their ends are
+/// on different targets or at least `distance` apart on the same target.
+/// The junction goes from the end of the first block to the start of the next one in query,
+/// ends on target are the last and first aligned target bases, swapped on negative strand.
+fn call_breakends(
+    mafrecords: &mut [MAFRecord],
+    query_name: Option<&str>,
+    distance: u64,
+) -> Result<Vec<VarRec>, WGAError> {
+    for rec in mafrecords.iter_mut() {
+        match query_name {
+            Some(qname) => rec.set_query_idx_byname(qname)?,
+            None => rec.set_query_idx(1),
+        }
+    }
+    let mut order = (0..mafrecords.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&mafrecords[a], &mafrecords[b]);
+        (a.query_name(), a.query_start(), a.query_end()).cmp(&(
+            b.query_name(),
+            b.query_start(),
+            b.query_end(),
+        ))
+    });
+
+    let mut bnd_recs = Vec::new();
+    for (a, b) in order.into_iter().tuple_windows() {
+        let (a, b) = (&mafrecords[a], &mafrecords[b]);
+        if a.query_name() != b.query_name() {
+            continue;
+        }
+        let a_fwd = a.query_strand() == Strand::Positive;
+        let b_fwd = b.query_strand() == Strand::Positive;
+        // 0-based target ends of the junction and their bases
+        let (a_pos, a_base) = junction_end(a, !a_fwd)?;
+        let (b_pos, b_base) = junction_end(b, b_fwd)?;
+        if a.target_name() == b.target_name() && a_pos.abs_diff(b_pos) < distance {
+            continue;
+        }
+        // query gap between the two blocks
+        let (q_start, q_end) = (a.query_end().min(b.query_start()), b.query_start());
+        let id = bnd_recs.len() / 2 + 1;
+        let (a_id, b_id) = (format!("bnd_{}_1", id), format!("bnd_{}_2", id));
+        let a_mate = format!("{}:{}", b.target_name(), b_pos + 1);
+        let b_mate = format!("{}:{}", a.target_name(), a_pos + 1);
+        // VCF breakend notation by the side joined and orientation of the mate piece
+        let a_alt = match (a_fwd, b_fwd) {
+            (true, true) => format!("{}[{}[", a_base, a_mate),
+            (true, false) => format!("{}]{}]", a_base, a_mate),
+            (false, true) => format!("[{}[{}", a_mate, a_base),
+            (false, false) => format!("]{}]{}", a_mate, a_base),
+        };
+        let b_alt = match (a_fwd, b_fwd) {
+            (true, true) => format!("]{}]{}", b_mate, b_base),
+            (false, true) => format!("[{}[{}", b_mate, b_base),
+            (true, false) => format!("{}]{}]", b_base, b_mate),
+            (false, false) => format!("{}[{}[", b_base, b_mate),
+        };
+        for (rec, pos, base, alt, id, mate_id, fwd) in [
+            (a, a_pos, a_base, a_alt, &a_id, &b_id, a_fwd),
+            (b, b_pos, b_base, b_alt, &b_id, &a_id, b_fwd),
+        ] {
+            let surfix = if fwd { 'P' } else { 'N' };
+            bnd_recs.push(VarRec {
+                id: Some(id.clone()),
+                chro: rec.target_name().to_string(),
+                pos: pos + 1,
+                end: pos + 1,
+                svtype: Some("BND"),
+                ref_base: base.clone(),
+                alt_base: alt,
+                info: Some(format!("SVTYPE=BND;MATEID={}", mate_id)),
+                qi: vec![format!(
+                    "{}@{}@{}@{}",
+                    rec.query_name(),
+                    q_start,
+                    q_end,
+                    surfix
+                )],
+                low_qual: None,
+                svlen: 0,
+                long_sv: None,
+            });
+        }
+    }
+    Ok(bnd_recs)
+}
+
+// 0-based position and base of the first aligned target base of a block, or the last one,
+// the base is normalized as REF to be repeated in ALT
+fn junction_end(rec: &MAFRecord, first: bool) -> Result<(u64, String), WGAError> {
+    let mut bases = rec.target_seq().chars().filter(|&c| c != '-');
+    let (pos, base) = match first {
+        true => (rec.target_start(), bases.next()),
+        false => (rec.target_end().saturating_sub(1), bases.next_back()),
+    };
+    let base = base.ok_or_else(|| {
+        WGAError::InvalidRecord(format!(
+            "block at {}:{} has no target base",
+            rec.target_name(),
+            rec.target_start()
+        ))
+    })?;
+    Ok((pos, normalize_ref_base(&base.to_string())))
+}
+
+// insert BND records into variants in the order of targets of blocks, then position
+fn insert_breakends(var_recs: &mut Vec<VarRec>, bnd_recs: Vec<VarRec>, mafrecords: &[MAFRecord]) {
+    let mut ranks: HashMap<&str, usize> = HashMap::new();
+    for rec in mafrecords {
+        let rank = ranks.len();
+        ranks.entry(rec.target_name()).or_insert(rank);
+    }
+    var_recs.extend(bnd_recs);
+    var_recs.sort_by_key(|rec| (ranks.get(rec.chro.as_str()).copied(), rec.pos));
+}
+
+/// Reference blocks of aligned invariant stretches (true) and no-call blocks of unaligned
+/// regions (false) on target, as gVCF records with `END`.
+/// A target base is invariant if it is the same as the query base, and is not the anchor
+/// of an indel; blocks on negative strand are covered by `INV` and have no reference block.
+/// If blocks overlap, a base variant in any of them is not in reference blocks.
+fn gvcf_blocks(mafrecords: &[MAFRecord]) -> Vec<(VarRec, bool)> {
+    // per target: sequence size, aligned and variant intervals,
+    // invariant runs with the record and column of their first base
+    #[derive(Default)]
+    struct TargetRuns {
+        size: u64,
+        aligned: Vec<(u64, u64)>,
+        variant: Vec<(u64, u64)>,
+        invariant: Vec<(u64, u64, usize, usize)>,
+    }
+    let mut targets: HashMap<&str, TargetRuns> = HashMap::new();
+    for (rec_idx, rec) in mafrecords.iter().enumerate() {
+        let runs = targets.entry(rec.target_name()).or_default();
+        runs.size = rec.slines[0].size;
+        let (t_start, t_end) = (rec.target_start(), rec.target_end());
+        runs.aligned.push((t_start, t_end));
+        if rec.query_strand() == Strand::Negative {
+            runs.variant.push((t_start, t_end));
+            continue;
+        }
+
+        // invariant flag and column of each target base
+        let mut flags: Vec<(bool, usize)> = Vec::with_capacity((t_end - t_start) as usize);
+        let t_seq = rec.target_seq().as_bytes();
+        let q_seq = rec.query_seq().as_bytes();
+        for (col, (t, q)) in t_seq.iter().zip(q_seq).enumerate() {
+            let gap = *t == b'-' || *q == b'-';
+            // the base before an indel is its anchor
+            if gap {
+                if let Some(last) = flags.last_mut() {
+                    last.0 = false;
+                }
+            }
+            if *t != b'-' {
+                let same = !gap && t.eq_ignore_ascii_case(q) && !t.eq_ignore_ascii_case(&b'N');
+                flags.push((same, col));
+            }
+        }
+        for (invariant, group) in &flags.iter().enumerate().group_by(|(_, (flag, _))| *flag) {
+            let group = group.collect::<Vec<_>>();
+            let (first, &(_, col)) = group[0];
+            let (start, end) = (
+                t_start + first as u64,
+                t_start + (first + group.len()) as u64,
+            );
+            match invariant {
+                true => runs.invariant.push((start, end, rec_idx, col)),
+                false => runs.variant.push((start, end)),
+            }
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for (name, mut runs) in targets {
+        let block = |start: u64, end: u64, ref_base: String| VarRec {
+            id: None,
+            chro: name.to_string(),
+            pos: start + 1,
+            end,
+            svtype: None,
+            ref_base,
+            alt_base: "<*>".to_string(),
+            info: Some(format!("END={}", end)),
+            qi: Vec::new(),
+            low_qual: None,
+            svlen: 0,
+            long_sv: None,
+        };
+        // unaligned regions
+        let aligned = merge_intervals(std::mem::take(&mut runs.aligned));
+        let mut cursor = 0;
+        for &(start, end) in aligned.iter().chain([&(runs.size, runs.size)]) {
+            if start > cursor {
+                blocks.push((block(cursor, start, "N".to_string()), false));
+            }
+            cursor = cursor.max(end);
+        }
+
+        // invariant runs without overlaps and variant bases
+        let variant = merge_intervals(std::mem::take(&mut runs.variant));
+        runs.invariant.sort_unstable();
+        let mut pieces: Vec<(u64, u64, usize, usize)> = Vec::new();
+        let mut done = 0;
+        let mut var_idx = 0;
+        for (run_start, end, rec_idx, col) in runs.invariant {
+            let mut start = run_start.max(done);
+            while start < end {
+                while var_idx < variant.len() && variant[var_idx].1 <= start {
+                    var_idx += 1;
+                }
+                let piece_end = match variant.get(var_idx) {
+                    Some(&(v_start, v_end)) if v_start <= start => {
+                        start = v_end.min(end);
+                        continue;
+                    }
+                    Some(&(v_start, _)) => v_start.min(end),
+                    None => end,
+                };
+                pieces.push((
+                    start,
+                    piece_end,
+                    rec_idx,
+                    col + (start - run_start) as usize,
+                ));
+                start = piece_end;
+            }
+            done = done.max(end);
+        }
+
+        // join contiguous pieces, REF is the target base at start
+        let mut last: Option<(u64, u64, String)> = None;
+        for (start, end, rec_idx, col) in pieces {
+            match last.as_mut() {
+                Some(last) if last.1 == start => last.1 = end,
+                _ => {
+                    if let Some((start, end, ref_base)) = last.take() {
+                        blocks.push((block(start, end, ref_base), true));
+                    }
+                    let ref_base = mafrecords[rec_idx].target_seq()[col..col + 1].to_string();
+                    last = Some((start, end, ref_base));
+                }
+            }
+        }
+        if let Some((start, end, ref_base)) = last {
+            blocks.push((block(start, end, ref_base), true));
+        }
+    }
+    blocks
+}
+
+/// Call variants from MAFs of the same target, one per sample, into a multi-sample VCF.
+/// Variants with the same alleles are merged, a sample without the variant is `0|0`
+/// if its blocks cover POS, otherwise `./.`, or `0` and `.` if `ploidy` is 1.
+/// If `diploid`, the two MAFs are haplotypes of the only sample and jointly genotyped,
+/// e.g. `1|0` for a variant only in the first one, and `.` for a haplotype not covering POS.
+/// Contigs of header are `contigs` if given, or targets of all MAFs
+pub fn call_var_mafs<R: Read + Send>(
+    mafreaders: Vec<MAFReader<R>>,
+    contigs: Option<Vec<(String, u64)>>,
+    samples: &[String],
+    writer: &mut dyn Write,
+    opts: &CallOpts,
+) -> Result<(), WGAError> {
+    let (diploid, ploidy) = (opts.diploid, opts.ploidy);
+    let mut vcf_wtr = vcf::Writer::new(writer);
+    let mut header = build_header(samples)?;
+    add_header_max_svlen(&mut header, opts.max_svlen);
+
+    let mut seen: HashMap<String, u64> = HashMap::new();
+    let input_count = mafreaders.len();
+    let mut coverages = Vec::with_capacity(input_count);
+    // variants keyed by position and alleles, with QI of each input
+    let mut merged: HashMap<VarKey, (VarRec, SampleQi)> = HashMap::new();
+    for (idx, mut mafreader) in mafreaders.into_iter().enumerate() {
+        let (var_recs, mafrecords) = call_maf_vars(&mut mafreader, opts)?;
+        match diploid {
+            true => info!(
+                "called {} variants of haplotype {}",
+                var_recs.len(),
+                idx + 1
+            ),
+            false => info!(
+                "called {} variants of sample {}",
+                var_recs.len(),
+                samples[idx]
+            ),
+        }
+
+        let mut intervals: HashMap<String, Vec<Interval<u64, ()>>> = HashMap::new();
+        for rec in &mafrecords {
+            let target = &rec.slines[0];
+            seen.insert(target.name.clone(), target.size);
+            intervals
+                .entry(target.name.clone())
+                .or_default()
+                .push(Interval {
+                    start: rec.target_start(),
+                    stop: rec.target_end(),
+                    val: (),
+                });
+        }
+        coverages.push(
+            intervals
+                .into_iter()
+                .map(|(name, ivs)| (name, Lapper::new(ivs)))
+                .collect::<HashMap<_, _>>(),
+        );
+
+        for mut rec in var_recs {
+            let key = VarKey::from(&rec);
+            let qi = std::mem::take(&mut rec.qi);
+            let (_, sample_qis) = merged
+                .entry(key)
+                .or_insert_with(|| (rec, vec![None; input_count]));
+            sample_qis[idx].get_or_insert_with(Vec::new).extend(qi);
+        }
+    }
+
+    insert_contigs(header_contigs(contigs, seen)?, &mut header)?;
+
+    let mut merged = merged.into_iter().collect::<Vec<_>>();
+    merged.sort_by(|(a, _), (b, _)| {
+        natord::compare(&a.0, &b.0)
+            .then_with(|| (a.1, a.2, &a.3, &a.4).cmp(&(b.1, b.2, &b.3, &b.4)))
+    });
+
+    opts.header_extra.apply(&mut header)?;
+    profile(Stage::Write, || {
+        vcf_wtr.write_header(&header)?;
+        for (_, (rec, sample_qis)) in merged {
+            // allele of each input, `.` if its blocks do not cover POS
+            let calls = sample_qis
+                .iter()
+                .zip(&coverages)
+                .map(|(sample_qi, coverage)| {
+                    let covered = coverage
+                        .get(&rec.chro)
+                        .is_some_and(|lapper| lapper.find(rec.pos - 1, rec.pos).next().is_some());
+                    match (sample_qi, covered) {
+                        (Some(qi), _) => ('1', qi.join(",")),
+                        (None, true) => ('0', String::new()),
+                        (None, false) => ('.', String::new()),
+                    }
+                })
+                .collect::<Vec<_>>();
+            let mut format = String::from("GT:QI");
+            if diploid {
+                let gt = calls.iter().map(|(allele, _)| *allele).join("|");
+                // QI of haplotypes are separated like GT
+                let qi = calls
+                    .iter()
+                    .map(|(_, qi)| if qi.is_empty() { "." } else { qi.as_str() })
+                    .join("|");
+                format.push_str(&format!("\t{}:{}", gt, qi));
+            } else {
+                for (allele, qi) in calls {
+                    let value = match allele {
+                        '1' => format!("{}:{}", genotype(allele, ploidy), qi),
+                        _ => format!("{}:.", genotype(allele, ploidy)),
+                    };
+                    format.push('\t');
+                    format.push_str(&value);
+                }
+            }
+            vcf_wtr.write_record(&header, &rec.to_record_with(&format)?)?;
+        }
+        Ok(())
+    })
+}
+
+// call variants of all blocks, also returns the blocks called,
+// INFO of the source block is added to variants if `block_info`,
+// FILTER is set by q-line qualities of query if `min_qual`,
+// SVs longer than `max_svlen` are dropped or filtered, blocks failing `qc` are skipped
+fn call_maf_vars<R: Read + Send>(
+    mafreader: &mut MAFReader<R>,
+    opts: &CallOpts,
+) -> Result<(Vec<VarRec>, Vec<MAFRecord>), WGAError> {
+    call_blocks(
+        read_maf_blocks(mafreader, opts.query_name.as_deref(), &opts.qc)?,
+        opts,
+    )
+}
+
+// read all blocks sorted by target, blocks failing `qc` are skipped
+fn read_maf_blocks<R: Read + Send>(
+    mafreader: &mut MAFReader<R>,
+    query_name: Option<&str>,
+    qc: &QcThresholds,
+) -> Result<Vec<MAFRecord>, WGAError> {
     let mut mafrecords = mafreader
         .records()
         .par_bridge()
         .collect::<Result<Vec<_>, WGAError>>()?;
+    if !qc.is_empty() {
+        let passed = mafrecords
+            .par_iter_mut()
+            .map(|rec| pass_qc(rec, query_name, qc))
+            .collect::<Result<Vec<_>, WGAError>>()?;
+        let count = mafrecords.len();
+        let mut passed = passed.into_iter();
+        mafrecords.retain(|_| passed.next().unwrap_or(true));
+        for _ in mafrecords.len()..count {
+            record_skipped();
+        }
+        info!("{} blocks skipped by QC", count - mafrecords.len());
+    }
     // if sort
     mafrecords.sort();
-    let within_var_recs = mafrecords
+    Ok(mafrecords)
+}
+
+// call variants of sorted blocks, also returns the blocks called
+fn call_blocks(
+    mafrecords: Vec<MAFRecord>,
+    opts: &CallOpts,
+) -> Result<(Vec<VarRec>, Vec<MAFRecord>), WGAError> {
+    // SNPs within `mask_near_sv` of an SV should be in the same chunk
+    let safe_flank = opts.mask_near_sv as usize + 1;
+    // chunks share the info of the block before splitting
+    let mut blocks = stitch_contiguous(mafrecords)
+        .into_iter()
+        .map(|mut rec| {
+            let info = match opts.block_info {
+                true => Some(BlockInfo::new(&mut rec, opts.query_name.as_deref())?),
+                false => None,
+            };
+            let chunks = split_block(rec, opts.chunk_strategy, opts.chunk_size, safe_flank)?;
+            Ok::<_, WGAError>(chunks.into_iter().map(move |chunk| (chunk, info.clone())))
+        })
+        .flatten_ok()
+        .collect::<Result<Vec<_>, WGAError>>()?;
+    let within_var_recs = blocks
         .par_iter_mut()
-        .try_fold(Vec::new, |mut acc, rec| {
-            let var_recs = call_within_var(rec, if_snp, svlen_cutoff, query_name)?;
+        .try_fold(Vec::new, |mut acc, (rec, info)| {
+            let mut var_recs = call_within_var(rec, opts)?;
+            if let Some(info) = info {
+                info.annotate(&mut var_recs);
+            }
             acc.extend(var_recs);
-            Ok::<Vec<Record>, WGAError>(acc)
+            Ok::<Vec<VarRec>, WGAError>(acc)
         })
         .try_reduce(Vec::new, |mut acc, mut vec| {
             acc.append(&mut vec);
             Ok(acc)
         })?;
 
-    // add contig to header
-    add_header_contig(mafindex, &mut header)?;
+    let mafrecords = blocks.into_iter().map(|(rec, _)| rec).collect();
+    // SVs are only duplicated by merging nearby ones or splitting blocks into chunks
+    let mut var_recs = match opts.merge_dist > 0 || opts.chunk_strategy != ChunkStrategy::None {
+        true => merge_dup_svs(within_var_recs, opts.merge_dist),
+        false => within_var_recs,
+    };
+    let inversions = var_recs
+        .iter()
+        .filter(|rec| rec.svtype == Some("INV"))
+        .count();
+    warn_inv_ends_unmasked(opts, inversions);
+    let long = limit_svlen(&mut var_recs, opts.max_svlen);
+    if long > 0 {
+        info!("{} SVs longer than `--max-svlen`", long);
+    }
+    Ok((var_recs, mafrecords))
+}
 
-    vcf_wtr.write_header(&header)?;
-    for rec in within_var_recs {
-        vcf_wtr.write_record(&header, &rec)?;
+// ends of reverse-strand blocks are not SV breakpoints, so variants next to them are kept
+fn warn_inv_ends_unmasked(opts: &CallOpts, inversions: usize) {
+    if opts.mask_near_sv > 0 && inversions > 0 {
+        warn!(
+            target: WarnCategory::Result.target(),
+            "ends of {} reverse-strand blocks are not used as SV breakpoints, \
+             SNPs and small indels near them are not masked by `--mask-near-sv`",
+            inversions
+        );
     }
-    Ok(())
 }
 
-#[allow(clippy::too_many_arguments)]
 pub fn call_var_paf<R: Read + Send>(
     pafreader: &mut PAFReader<R>,
     t_fa_path: &str,
     q_fa_path: &str,
     writer: &mut dyn Write,
-    if_snp: bool,
-    svlen_cutoff: u64,
-    _between: bool,
     sample: Option<&str>,
+    opts: &CallOpts,
 ) -> Result<(), WGAError> {
-    let mut vcf_wtr = vcf::Writer::new(writer);
+    // blocks of PAF have the only query and no q-line
+    let opts = &CallOpts {
+        query_name: None,
+        min_qual: None,
+        ..opts.clone()
+    };
     let sample = sample.unwrap_or("sample");
-    let mut header = build_header(sample)?;
+    let mut header = build_header(&[sample])?;
+    if opts.block_info {
+        add_header_block_info(&mut header)?;
+    }
+    add_header_max_svlen(&mut header, opts.max_svlen);
 
-    // collect all PAF records
-    let pafrecords = pafreader
-        .records()
-        .par_bridge()
-        .collect::<Result<Vec<_>, _>>()?;
+    // header does not depend on records, write it first
+    insert_contigs(read_seq_size_list(t_fa_path)?, &mut header)?;
+    opts.header_extra.apply(&mut header)?;
+    vcf::Writer::new(&mut *writer).write_header(&header)?;
 
     // get FASTA readers
     let t_reader = faidx::Reader::from_path(t_fa_path)?;
     let q_reader = faidx::Reader::from_path(q_fa_path)?;
 
-    // map PAF records to MAF records
-    let mut maf_records = pafrecords
+    // reverse-strand records, reported after all are called
+    let inversions = AtomicUsize::new(0);
+    // map PAF records to MAF records while reading, sequences are fetched in order
+    let maf_records = pafreader.records().map(|pafrec| {
+        let pafrec = pafrec?;
+        let mafrec = paf2maf_ungapped(&pafrec, &t_reader, &q_reader, opts.unknown_base)?;
+        Ok::<_, WGAError>((pafrec, mafrec))
+    });
+
+    // insert gaps, call and write variants chunk by chunk
+    par_write_ordered(maf_records, writer, |_, (pafrec, mut rec)| {
+        insert_paf_gaps(&pafrec, &mut rec)?;
+        if !pass_qc(&mut rec, None, &opts.qc)? {
+            record_skipped();
+            return Ok(Vec::new());
+        }
+        if rec.query_strand() == Strand::Negative {
+            inversions.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut var_recs = call_within_var(&mut rec, opts)?;
+        if opts.block_info {
+            BlockInfo::new(&mut rec, None)?.annotate(&mut var_recs);
+        }
+        limit_svlen(&mut var_recs, opts.max_svlen);
+        let mut vcf_wtr = vcf::Writer::new(Vec::new());
+        for var_rec in var_recs {
+            vcf_wtr.write_record(&header, &var_rec.to_record(opts.ploidy)?)?;
+        }
+        Ok(vcf_wtr.get_ref().to_vec())
+    })?;
+    warn_inv_ends_unmasked(opts, inversions.into_inner());
+    Ok(())
+}
+
+// if N bases and gap runs of the block are within `qc`
+fn pass_qc(
+    rec: &mut MAFRecord,
+    query_name: Option<&str>,
+    qc: &QcThresholds,
+) -> Result<bool, WGAError> {
+    if qc.is_empty() {
+        return Ok(true);
+    }
+    match query_name {
+        Some(qname) => rec.set_query_idx_byname(qname)?,
+        None => rec.set_query_idx(1),
+    }
+    Ok(qc.pass(&BlockQc::new(rec)))
+}
+
+/// Variants of a block with explicit alleles as (POS, REF, ALT), including SNPs and indels
+/// of any length, symbolic ones are left out
+pub fn call_block_alleles(mafrec: &mut MAFRecord) -> Result<Vec<(u64, String, String)>, WGAError> {
+    let opts = CallOpts {
+        snp: true,
+        svlen: 0,
+        ..Default::default()
+    };
+    let var_recs = call_within_var(mafrec, &opts)?;
+    Ok(var_recs
+        .into_iter()
+        .filter(|var_rec| !var_rec.alt_base.starts_with('<'))
+        .map(|var_rec| (var_rec.pos, var_rec.ref_base, var_rec.alt_base))
+        .collect())
+}
+
+/// A called variant, converted to VCF record when writing
+#[derive(Debug)]
+struct VarRec {
+    // ID of BND records referred by MATEID, None for others
+    id: Option<String>,
+    chro: String,
+    pos: u64,
+    // END of SV, same as `pos` for SNP
+    end: u64,
+    svtype: Option<&'static str>,
+    ref_base: String,
+    alt_base: String,
+    info: Option<String>,
+    // query supports, more than one if duplicates are merged
+    qi: Vec<String>,
+    // if query bases are below `--min-qline-qual`, None if not checked
+    low_qual: Option<bool>,
+    // length of SV, 0 for others
+    svlen: u64,
+    // if SV is longer than `--max-svlen` with FILTER, None if not checked
+    long_sv: Option<bool>,
+}
+
+impl VarRec {
+    fn to_record(&self, ploidy: u8) -> anyhow::Result<Record> {
+        self.to_record_with(&format!(
+            "GT:QI\t{}:{}",
+            genotype('1', ploidy),
+            self.qi.join(",")
+        ))
+    }
+
+    fn to_record_with(&self, format: &str) -> anyhow::Result<Record> {
+        get_variant_rec(
+            &self.chro,
+            self.id.as_deref(),
+            self.pos as usize,
+            &self.ref_base,
+            &self.alt_base,
+            self.info.as_deref(),
+            &[(LOW_QUAL, self.low_qual), (LONG_SV, self.long_sv)],
+            Some(format),
+        )
+    }
+}
+
+/// Provenance of the block a variant is called from, as INFO tags
+#[derive(Debug, Clone)]
+struct BlockInfo {
+    // target span as `name:start-end`
+    id: String,
+    identity: f32,
+    len: u64,
+}
+
+impl BlockInfo {
+    fn new(rec: &mut MAFRecord, query_name: Option<&str>) -> Result<Self, WGAError> {
+        match query_name {
+            Some(qname) => rec.set_query_idx_byname(qname)?,
+            None => rec.set_query_idx(1),
+        }
+        let rec_stat = rec.get_stat()?;
+        let len = rec.target_end() - rec.target_start();
+        Ok(BlockInfo {
+            id: format!(
+                "{}:{}-{}",
+                rec.target_name(),
+                rec.target_start(),
+                rec.target_end()
+            ),
+            identity: rec_stat.matched as f32 / rec_stat.aligned_size.max(1) as f32,
+            len,
+        })
+    }
+
+    fn annotate(&self, var_recs: &mut [VarRec]) {
+        let tags = format!(
+            "BLOCK_ID={};BLOCK_IDENTITY={:.4};BLOCK_LEN={}",
+            self.id, self.identity, self.len
+        );
+        for var_rec in var_recs {
+            var_rec.info = Some(match var_rec.info.take() {
+                Some(info) => format!("{};{}", info, tags),
+                None => tags.clone(),
+            });
+        }
+    }
+}
+
+/// CHROM, POS, END, REF, ALT and INFO of a variant, the same in every sample carrying it
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct VarKey(String, u64, u64, String, String, Option<String>);
+
+// QI of a merged variant in each sample, None if the sample does not carry it
+type SampleQi = Vec<Option<Vec<String>>>;
+
+impl From<&VarRec> for VarKey {
+    fn from(rec: &VarRec) -> Self {
+        VarKey(
+            rec.chro.clone(),
+            rec.pos,
+            rec.end,
+            rec.ref_base.clone(),
+            rec.alt_base.clone(),
+            rec.info.clone(),
+        )
+    }
+}
+
+// join sorted blocks which are contiguous on every s-line, such as output of `chunk`,
+// so variants across block boundaries are called as in the unchunked block
+fn stitch_contiguous(mafrecords: Vec<MAFRecord>) -> Vec<MAFRecord> {
+    let mut stitched: Vec<MAFRecord> = Vec::with_capacity(mafrecords.len());
+    for rec in mafrecords {
+        if let Some(last) = stitched.last_mut() {
+            let contiguous = last.slines.len() == rec.slines.len()
+                && last.slines.iter().zip(&rec.slines).all(|(a, b)| {
+                    a.name == b.name && a.strand == b.strand && a.start + a.align_size == b.start
+                });
+            if contiguous {
+                for (a, b) in last.slines.iter_mut().zip(rec.slines) {
+                    a.align_size += b.align_size;
+                    a.seq.push_str(&b.seq);
+                    // qualities are kept only if both blocks have them
+                    a.qual = match (a.qual.take(), b.qual) {
+                        (Some(mut qual), Some(b_qual)) => {
+                            qual.push_str(&b_qual);
+                            Some(qual)
+                        }
+                        _ => None,
+                    };
+                }
+                continue;
+            }
+        }
+        stitched.push(rec);
+    }
+    stitched
+}
+
+// split a block into chunks of about `chunk_size` columns by `strategy`,
+// blocks on negative strand are kept whole for their INV record
+fn split_block(
+    rec: MAFRecord,
+    strategy: ChunkStrategy,
+    chunk_size: usize,
+    safe_flank: usize,
+) -> Result<Vec<MAFRecord>, WGAError> {
+    let n_cols = rec.slines[0].seq.len();
+    if strategy == ChunkStrategy::None
+        || n_cols <= chunk_size
+        || rec
+            .slines
+            .iter()
+            .any(|sline| sline.strand == Strand::Negative)
+    {
+        return Ok(vec![rec]);
+    }
+    let mut chunks = Vec::new();
+    let mut slicer = rec.column_slicer();
+    let mut start = 0;
+    while start < n_cols {
+        let end = match strategy {
+            ChunkStrategy::SvAware => find_safe_boundary(&rec, start + chunk_size, safe_flank),
+            _ => start + chunk_size,
+        }
+        .min(n_cols);
+        chunks.push(slicer.slice(start, end)?);
+        start = end;
+    }
+    Ok(chunks)
+}
+
+// first column from `from` with `flank` columns on both sides aligned on every s-line,
+// so no indel is split and SNPs near an SV stay with it, end of block if not found
+fn find_safe_boundary(rec: &MAFRecord, from: usize, flank: usize) -> usize {
+    let seqs = rec
+        .slines
         .iter()
-        .map(|pafrec| {
-            // get target information
-            let t_name = &pafrec.target_name;
-            let t_start = pafrec.target_start;
-            let t_end = pafrec.target_end - 1;
-            let t_strand = pafrec.target_strand();
-            let t_alilen = pafrec.target_end - pafrec.target_start;
-            let t_size = pafrec.target_length;
-
-            // get query information
-            let q_name = &pafrec.query_name;
-            let q_strand = pafrec.query_strand();
-            let q_size = pafrec.query_length;
-            let q_alilen = pafrec.query_end - pafrec.query_start;
-            let q_start = match q_strand {
-                Strand::Positive => pafrec.query_start,
-                Strand::Negative => q_size - pafrec.query_end,
-            };
+        .map(|sline| sline.seq.as_bytes())
+        .collect::<Vec<_>>();
+    let n_cols = seqs[0].len();
+    // aligned columns ending at `col`
+    let mut run = 0;
+    for col in from.saturating_sub(flank)..n_cols {
+        let aligned = seqs
+            .iter()
+            .all(|seq| seq.get(col).is_some_and(|&base| base != b'-'));
+        run = if aligned { run + 1 } else { 0 };
+        // window of `col + 1 - flank` is full
+        if run >= 2 * flank && col + 1 >= from + flank {
+            return col + 1 - flank;
+        }
+    }
+    n_cols
+}
 
-            // get whole target and query sequence
-            let mut whole_t_seq =
-                t_reader.fetch_seq_string(t_name, t_start as usize, t_end as usize)?;
-            let mut whole_q_seq = q_reader.fetch_seq_string(
-                q_name,
-                pafrec.query_start as usize,
-                (pafrec.query_end - 1) as usize,
-            )?;
-
-            // reverse complement query sequence if it is negative strand
-            if q_strand == Strand::Negative {
-                whole_q_seq = reverse_complement(&whole_q_seq)?;
+// merge SVs of the same type on the same target whose breakpoints and lengths are all within
+// `distance`, SVs at the same breakpoints must also have the same alleles, e.g. INS of
+// different sequences are kept, query supports of duplicates are appended to QI of the first one
+fn merge_dup_svs(var_recs: Vec<VarRec>, distance: u64) -> Vec<VarRec> {
+    let mut sv_idxs = (0..var_recs.len())
+        .filter(|&i| var_recs[i].svtype.is_some())
+        .collect::<Vec<_>>();
+    sv_idxs.sort_by(|&a, &b| {
+        let (a, b) = (&var_recs[a], &var_recs[b]);
+        (&a.chro, a.svtype, a.pos, a.end).cmp(&(&b.chro, b.svtype, b.pos, b.end))
+    });
+    let is_dup = |first: &VarRec, rec: &VarRec| {
+        first.chro == rec.chro
+            && first.svtype == rec.svtype
+            && rec.pos.abs_diff(first.pos) <= distance
+            && rec.end.abs_diff(first.end) <= distance
+            && rec.svlen.abs_diff(first.svlen) <= distance
+            && ((first.pos, first.end) != (rec.pos, rec.end)
+                || (&first.ref_base, &first.alt_base) == (&rec.ref_base, &rec.alt_base))
+    };
+    // (duplicate, first) pairs, every first SV within `distance` of the current one is a
+    // candidate, so one far END does not split a pair of duplicates around it
+    let mut dups = Vec::new();
+    let mut open: VecDeque<usize> = VecDeque::new();
+    for idx in sv_idxs {
+        let rec = &var_recs[idx];
+        while let Some(&f) = open.front() {
+            let f_rec = &var_recs[f];
+            if (&f_rec.chro, f_rec.svtype) == (&rec.chro, rec.svtype)
+                && f_rec.pos + distance >= rec.pos
+            {
+                break;
             }
+            open.pop_front();
+        }
+        match open.iter().find(|&&f| is_dup(&var_recs[f], rec)) {
+            Some(&f) => dups.push((idx, f)),
+            None => open.push_back(idx),
+        }
+    }
 
-            // parse CIGAR to insertions
-            parse_cigar_to_insert(pafrec, &mut whole_t_seq, &mut whole_q_seq)?;
-
-            // build MAF SLine
-            let t_sline = MAFSLine {
-                mode: 's',
-                name: t_name.to_string(),
-                start: t_start,
-                align_size: t_alilen,
-                strand: t_strand,
-                size: t_size,
-                seq: whole_t_seq,
-            };
+    let mut var_recs = var_recs.into_iter().map(Some).collect::<Vec<_>>();
+    for (idx, f) in dups {
+        if let Some(dup) = var_recs[idx].take() {
+            if let Some(f_rec) = var_recs[f].as_mut() {
+                for qi in dup.qi {
+                    if !f_rec.qi.contains(&qi) {
+                        f_rec.qi.push(qi);
+                    }
+                }
+            }
+        }
+    }
+    var_recs.into_iter().flatten().collect()
+}
 
-            let q_sline = MAFSLine {
-                mode: 's',
-                name: q_name.to_string(),
-                start: q_start,
-                align_size: q_alilen,
-                strand: q_strand,
-                size: q_size,
-                seq: whole_q_seq,
-            };
+/// Progress of `call` saved in a checkpoint file, so a crashed run resumes after
+/// the last target whose variants are all written
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CallCheckpoint {
+    /// Input MAF and output VCF, a resumed run must use the same ones
+    pub input: String,
+    pub output: String,
+    /// Targets done, in order of output
+    pub done: Vec<String>,
+    /// Size of output after the header and variants of `done`, 0 before the header
+    pub output_bytes: u64,
+    #[serde(skip)]
+    path: PathBuf,
+}
 
-            // build MAF record
-            Ok(MAFRecord {
-                score: pafrec.mapq,
-                slines: vec![t_sline, q_sline],
-                query_idx: 1,
-            })
-        })
-        .collect::<Result<Vec<_>, WGAError>>()?;
+impl CallCheckpoint {
+    /// Load the checkpoint of a previous run if `path` exists, or start a new one
+    pub fn open(path: &str, input: &str, output: &str) -> Result<Self, WGAError> {
+        if !Path::new(path).exists() {
+            return Ok(CallCheckpoint {
+                input: input.to_string(),
+                output: output.to_string(),
+                done: Vec::new(),
+                output_bytes: 0,
+                path: PathBuf::from(path),
+            });
+        }
+        let mut checkpoint: CallCheckpoint =
+            serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if checkpoint.input != input || checkpoint.output != output {
+            return Err(WGAError::InvalidArgs(format!(
+                "checkpoint `{}` is of input `{}` and output `{}`, remove it to start over",
+                path, checkpoint.input, checkpoint.output
+            )));
+        }
+        checkpoint.path = PathBuf::from(path);
+        Ok(checkpoint)
+    }
 
-    // lazy use, TODO refine it
-    let within_var_recs = maf_records
-        .par_iter_mut()
-        .try_fold(Vec::new, |mut acc, rec| {
-            let var_recs = call_within_var(rec, if_snp, svlen_cutoff, None)?;
-            acc.extend(var_recs);
-            Ok::<Vec<Record>, WGAError>(acc)
-        })
-        .try_reduce(Vec::new, |mut acc, mut vec| {
-            acc.append(&mut vec);
-            Ok(acc)
-        })?;
+    /// Whether the header is written by a previous run, so output is appended
+    pub fn header_written(&self) -> bool {
+        self.output_bytes > 0
+    }
 
-    // write VCF
-    add_header_contig(None, &mut header)?;
-    vcf_wtr.write_header(&header)?;
-    for rec in within_var_recs {
-        vcf_wtr.write_record(&header, &rec)?;
+    fn is_done(&self, target: &str) -> bool {
+        self.done.iter().any(|name| name == target)
     }
 
-    Ok(())
+    // flush output and record its size with `target` done, the file is replaced at once
+    fn commit(&mut self, target: Option<&str>, writer: &mut dyn Write) -> Result<(), WGAError> {
+        writer.flush()?;
+        self.output_bytes = std::fs::metadata(&self.output)?.len();
+        if let Some(target) = target {
+            self.done.push(target.to_string());
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        serde_json::to_writer(File::create(&tmp)?, self)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    // all targets are done, the checkpoint is removed
+    fn finish(&self) -> Result<(), WGAError> {
+        info!(
+            "all {} targets done, remove checkpoint `{}`",
+            self.done.len(),
+            self.path.display()
+        );
+        std::fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Meta-information lines added to the VCF header, e.g. `##reference=` for submission
+#[derive(Debug, Clone, Default)]
+pub struct VcfHeaderExtra {
+    /// `##` lines, INFO/FORMAT/FILTER/ALT/contig lines replace generated ones of the same ID
+    pub lines: Vec<String>,
+    /// `key=value` pairs of the `##SAMPLE` line of each sample
+    pub sample_meta: Vec<(String, String)>,
+}
+
+impl VcfHeaderExtra {
+    /// Read `##` lines from a file and split `key=value` of sample metadata
+    pub fn new(lines_path: Option<&str>, sample_meta: &[String]) -> Result<Self, WGAError> {
+        let mut lines = Vec::new();
+        if let Some(path) = lines_path {
+            let reader = get_input_reader(&Some(path.to_string()))?;
+            for line in reader.lines() {
+                let line = line?;
+                let line = line.trim_end();
+                if line.is_empty() {
+                    continue;
+                }
+                if !line.starts_with("##") || line.starts_with("##fileformat=") {
+                    return Err(WGAError::InvalidArgs(format!(
+                        "invalid VCF header line in `{}`, expected `##key=value` other than fileformat: {}",
+                        path, line
+                    )));
+                }
+                lines.push(line.to_string());
+            }
+        }
+        let sample_meta = sample_meta
+            .iter()
+            .map(|kv| match kv.split_once('=') {
+                Some((key, value)) if !key.is_empty() && key != "ID" => {
+                    Ok((key.to_string(), value.to_string()))
+                }
+                _ => Err(WGAError::InvalidArgs(format!(
+                    "invalid sample metadata `{}`, expected `key=value` with key other than ID",
+                    kv
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(VcfHeaderExtra { lines, sample_meta })
+    }
+
+    // parse the lines as a header and merge its records into `header`
+    fn apply(&self, header: &mut Header) -> Result<(), WGAError> {
+        if self.lines.is_empty() && self.sample_meta.is_empty() {
+            return Ok(());
+        }
+        let mut text = format!("##fileformat={}\n", header.file_format());
+        for line in &self.lines {
+            text.push_str(line);
+            text.push('\n');
+        }
+        if !self.sample_meta.is_empty() {
+            let meta = self
+                .sample_meta
+                .iter()
+                .map(|(key, value)| format!(",{}={}", key, value))
+                .collect::<String>();
+            for sample in header.sample_names() {
+                text.push_str(&format!("##SAMPLE=<ID={}{}>\n", sample, meta));
+            }
+        }
+        text.push_str("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n");
+        let extra = text
+            .parse::<Header>()
+            .map_err(|e| WGAError::InvalidArgs(format!("invalid VCF header lines: {}", e)))?;
+
+        for (id, info) in extra.infos() {
+            header.infos_mut().insert(id.clone(), info.clone());
+        }
+        for (id, format) in extra.formats() {
+            header.formats_mut().insert(id.clone(), format.clone());
+        }
+        for (id, filter) in extra.filters() {
+            header.filters_mut().insert(id.clone(), filter.clone());
+        }
+        for (id, alt) in extra.alternative_alleles() {
+            header
+                .alternative_alleles_mut()
+                .insert(id.clone(), alt.clone());
+        }
+        for (id, contig) in extra.contigs() {
+            header.contigs_mut().insert(id.clone(), contig.clone());
+        }
+        for (key, records) in extra.other_records() {
+            header
+                .other_records_mut()
+                .insert(key.clone(), records.clone());
+        }
+        Ok(())
+    }
 }
 
-fn build_header(sample_name: &str) -> anyhow::Result<Header> {
+fn build_header<S: AsRef<str>>(sample_names: &[S]) -> anyhow::Result<Header> {
     let svlen_id = infokey::SV_LENGTHS;
     let svlen_info = Map::<Info>::from(&svlen_id);
 
@@ -213,49 +1345,133 @@ fn build_header(sample_name: &str) -> anyhow::Result<Header> {
     );
 
     let queryinfo_id = "QI".parse::<gtkey::Key>()?;
-    let queryinfo_info =
-        Map::<Format>::new(Number::Count(1), fmttype::String, "Query informations");
+    let queryinfo_info = Map::<Format>::new(Number::Unknown, fmttype::String, "Query informations");
 
     let gt_id = gtkey::GENOTYPE;
     let gt_format = Map::<Format>::from(&gt_id);
 
-    Ok(Header::builder()
+    let svclass_id = "SVCLASS".parse::<infokey::Key>()?;
+    let svclass_info = Map::<Info>::new(
+        Number::Count(1),
+        infotype::String,
+        "Size class of SV: <50, 50-999, 1k-10k or >10k",
+    );
+
+    let mut builder = Header::builder()
         .add_info(svlen_id, svlen_info)
         .add_info(svtype_id, svtype_info)
         .add_info(end_id, end_info)
         .add_info(inv_nest_id, inv_nest_info)
+        .add_info(svclass_id, svclass_info)
         .add_format(queryinfo_id, queryinfo_info)
-        .add_format(gt_id, gt_format)
-        .add_sample_name(sample_name)
-        .build())
-}
-
-fn add_header_contig(mafindex: Option<MafIndex>, header: &mut Header) -> anyhow::Result<()> {
-    if let Some(mafindex) = mafindex {
-        let mut contig_vec: Vec<(String, u64)> = Vec::new();
-        for (name, item) in mafindex {
-            if item.ord == 0 {
-                let size = item.size;
-                contig_vec.push((name, size));
+        .add_format(gt_id, gt_format);
+    for sample_name in sample_names {
+        builder = builder.add_sample_name(sample_name.as_ref());
+    }
+    Ok(builder.build())
+}
+
+fn add_header_block_info(header: &mut Header) -> anyhow::Result<()> {
+    let infos = [
+        (
+            "BLOCK_ID",
+            infotype::String,
+            "Target span of the source alignment block",
+        ),
+        (
+            "BLOCK_IDENTITY",
+            infotype::Float,
+            "Identity of the source alignment block",
+        ),
+        (
+            "BLOCK_LEN",
+            infotype::Integer,
+            "Target length of the source alignment block",
+        ),
+    ];
+    for (id, ty, description) in infos {
+        header.infos_mut().insert(
+            id.parse()?,
+            Map::<Info>::new(Number::Count(1), ty, description),
+        );
+    }
+    Ok(())
+}
+
+/// Contigs of VCF header from target sequences of MAF index, in natural order
+pub fn index_contigs(mafindex: MafIndex) -> Vec<(String, u64)> {
+    let mut contigs = mafindex
+        .into_iter()
+        .filter(|(_, item)| item.ord == 0)
+        .map(|(name, item)| (name, item.size))
+        .collect::<Vec<_>>();
+    contigs.sort_by(|a, b| natord::compare(&a.0, &b.0));
+    contigs
+}
+
+// contigs given by FASTA or MAF index if targets seen in blocks are all in them with the
+// same sizes, or the targets seen in natural order
+fn header_contigs(
+    contigs: Option<Vec<(String, u64)>>,
+    seen: HashMap<String, u64>,
+) -> Result<Vec<(String, u64)>, WGAError> {
+    let mut seen = seen.into_iter().collect::<Vec<_>>();
+    seen.sort_by(|a, b| natord::compare(&a.0, &b.0));
+    let Some(contigs) = contigs else {
+        return Ok(seen);
+    };
+    let sizes = contigs
+        .iter()
+        .map(|(name, size)| (name.as_str(), *size))
+        .collect::<HashMap<_, _>>();
+    for (name, size) in seen {
+        match sizes.get(name.as_str()) {
+            Some(contig_size) if *contig_size == size => {}
+            Some(contig_size) => {
+                return Err(WGAError::InvalidArgs(format!(
+                    "target `{}` is {} bp in blocks but {} bp in target FASTA or MAF index",
+                    name, size, contig_size
+                )))
+            }
+            None => {
+                return Err(WGAError::InvalidArgs(format!(
+                    "target `{}` of blocks is not in target FASTA or MAF index",
+                    name
+                )))
             }
-            // natual sort by name use natord::compare
-            contig_vec.sort_by(|a, b| natord::compare(&a.0, &b.0));
-        }
-        for (name, size) in contig_vec {
-            let mut contigmap = Map::<Contig>::new();
-            *contigmap.length_mut() = Some(size as usize);
-            header.contigs_mut().insert(name.parse()?, contigmap);
         }
     }
+    Ok(contigs)
+}
+
+// GT of an allele (`1`, `0` or `.` for no-call) repeated by ploidy, e.g. `1|1` or `1`,
+// no-call of diploid is unphased `./.`
+fn genotype(allele: char, ploidy: u8) -> String {
+    match (allele, ploidy) {
+        (allele, 1) => allele.to_string(),
+        ('.', _) => "./.".to_string(),
+        (allele, _) => format!("{}|{}", allele, allele),
+    }
+}
+
+fn insert_contigs(contig_vec: Vec<(String, u64)>, header: &mut Header) -> anyhow::Result<()> {
+    for (name, size) in contig_vec {
+        let mut contigmap = Map::<Contig>::new();
+        *contigmap.length_mut() = Some(size as usize);
+        header.contigs_mut().insert(name.parse()?, contigmap);
+    }
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn get_variant_rec(
     chro: &str,
+    id: Option<&str>,
     pos: usize,
     ref_base: &str,
     alt_base: &str,
     info: Option<&str>,
+    filters: &[(&str, Option<bool>)],
     format: Option<&str>,
 ) -> anyhow::Result<Record> {
     // let genotypes = Genotypes::new(keys, vec![vec![Some(Value::from("1|1"))]]);
@@ -269,24 +1485,121 @@ fn get_variant_rec(
         Some(info) => info.parse().unwrap_or_default(),
         None => recinfo::default(),
     };
-    Ok(Record::builder()
+
+    let ref_base = normalize_ref_base(ref_base);
+    let alt_base = match normalize_alt_base(alt_base) {
+        Some(alt_base) => alt_base,
+        None => {
+            // downgrade SV to symbolic ALT by SVTYPE, other bases are replaced by `N`
+            // as `<*>` is the unspecified allele of gVCF, not a called one
+            let replaced = match info
+                .and_then(|info| info.split(';').find_map(|kv| kv.strip_prefix("SVTYPE=")))
+            {
+                Some(svtype) => format!("<{}>", svtype),
+                None => mask_alt_base(alt_base),
+            };
+            warn!(
+                target: WarnCategory::Input.target(),
+                "ALT '{}' at {}:{} is not representable, use {}",
+                alt_base, chro, pos, replaced
+            );
+            replaced
+        }
+    };
+    let mut builder = Record::builder()
         .set_chromosome(chro.parse()?)
         .set_position(Position::from(pos))
         .set_reference_bases(ref_base.parse()?)
         .set_alternate_bases(alt_base.parse()?)
         .set_info(infos)
-        .set_genotypes(genotypes)
-        .build()?)
+        .set_genotypes(genotypes);
+    if let Some(id) = id {
+        builder = builder.set_ids(id.parse()?);
+    }
+    // FILTER is PASS if all checked filters are passed, missing if none is checked
+    let failed = filters
+        .iter()
+        .filter(|(_, fail)| *fail == Some(true))
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        builder = builder.set_filters(Filters::try_from_iter(failed)?);
+    } else if filters.iter().any(|(_, fail)| fail.is_some()) {
+        builder = builder.set_filters(Filters::Pass);
+    }
+    Ok(builder.build()?)
 }
 
-fn call_within_var(
-    mafrec: &mut MAFRecord,
-    if_snp: bool,
-    svlen_cutoff: u64,
-    query_name: Option<&str>,
-) -> Result<Vec<Record>, WGAError> {
+// slice of ungapped sequence, error with record position if out of range
+fn seq_slice<'a, T: AlignRecord>(
+    rec: &T,
+    seq: &'a str,
+    start: usize,
+    end: usize,
+) -> Result<&'a str, WGAError> {
+    seq.get(start..end).ok_or_else(|| {
+        WGAError::CoordOutOfRange(
+            format!("slice {}..{} of sequence length {}", start, end, seq.len()),
+            rec.coord_context(),
+        )
+    })
+}
+
+// uppercase REF bases, IUPAC ambiguity codes are resolved to the first
+// base in alphabetical order as VCF spec suggests, others to `N`
+fn normalize_ref_base(ref_base: &str) -> String {
+    ref_base
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ('A' | 'C' | 'G' | 'T' | 'N') => c,
+            'R' | 'M' | 'W' | 'D' | 'H' | 'V' => 'A',
+            'S' | 'Y' | 'B' => 'C',
+            'K' => 'G',
+            _ => 'N',
+        })
+        .collect()
+}
+
+// uppercase ALT bases, None if any base is out of `ACGTN`
+fn normalize_alt_base(alt_base: &str) -> Option<String> {
+    if alt_base.starts_with('<') || alt_base.contains(['[', ']']) {
+        return Some(alt_base.to_string());
+    }
+    let alt_base = alt_base.to_ascii_uppercase();
+    alt_base
+        .chars()
+        .all(|c| matches!(c, 'A' | 'C' | 'G' | 'T' | 'N'))
+        .then_some(alt_base)
+}
+
+// uppercase ALT bases, those out of `ACGTN` are replaced by `N`
+fn mask_alt_base(alt_base: &str) -> String {
+    alt_base
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ('A' | 'C' | 'G' | 'T') => c,
+            _ => 'N',
+        })
+        .collect()
+}
+
+fn call_within_var(mafrec: &mut MAFRecord, opts: &CallOpts) -> Result<Vec<VarRec>, WGAError> {
+    profile(Stage::Call, || within_var(mafrec, opts))
+}
+
+fn within_var(mafrec: &mut MAFRecord, opts: &CallOpts) -> Result<Vec<VarRec>, WGAError> {
     // target:ACG-TTTGATGCTAGCT---ACG
     // query :ACCATTT--TGCTAACTGGGACG
+    let CallOpts {
+        snp: if_snp,
+        svlen: svlen_cutoff,
+        min_indel,
+        mask_near_sv,
+        inv_mode,
+        min_qual,
+        ..
+    } = *opts;
+    let query_name = opts.query_name.as_deref();
 
     match query_name {
         Some(qname) => mafrec.set_query_idx_byname(qname)?,
@@ -294,6 +1607,9 @@ fn call_within_var(
     }
 
     let mut var_recs = Vec::new();
+    // SV breakpoints and maskable (SNP/small indel) records with their positions
+    let mut sv_breakpoints = Vec::new();
+    let mut maskable_recs = Vec::new();
 
     let mut target_current_offset = mafrec.target_start();
     let mut query_current_offset = mafrec.query_start();
@@ -305,8 +1621,6 @@ fn call_within_var(
     let q_start = mafrec.query_start();
     let q_end = mafrec.query_end();
 
-    let init_format: &str = "GT:QI\t1|1:";
-
     let mut t_seq_ref = mafrec.target_seq().to_string();
     t_seq_ref.retain(|c| c != '-');
 
@@ -320,22 +1634,29 @@ fn call_within_var(
         Strand::Positive => 'P',
     };
     if strand == Strand::Negative {
-        let ref_base = &t_seq_ref[0..1];
-        let info = format!("SVTYPE=INV;END={}", t_end);
-        let queryinfo = format!(
-            "{}{}@{}@{}@{}",
-            init_format, q_chro, q_start, q_end, format_surfix
+        let ref_base = seq_slice(mafrec, &t_seq_ref, 0, 1)?;
+        let info = format!(
+            "SVTYPE=INV;END={};SVCLASS={}",
+            t_end,
+            sv_class(t_end - t_start)
         );
-        let record = get_variant_rec(
-            chro,
-            target_current_offset as usize + 1,
-            ref_base,
-            "<INV>",
-            // &id,
-            Some(&info),
-            Some(&queryinfo),
-        );
-        var_recs.push(record?);
+        let queryinfo = format!("{}@{}@{}@{}", q_chro, q_start, q_end, format_surfix);
+        var_recs.push(VarRec {
+            id: None,
+            chro: chro.to_string(),
+            pos: target_current_offset + 1,
+            end: t_end,
+            svtype: Some("INV"),
+            ref_base: ref_base.to_string(),
+            alt_base: "<INV>".to_string(),
+            info: Some(info),
+            qi: vec![queryinfo],
+            low_qual: None,
+            svlen: t_end - t_start,
+            long_sv: None,
+        });
+        // ends of a reverse block are not junctions inside it, e.g. of a contig
+        // assembled in reverse, so they are not SV breakpoints for masking
     }
 
     let t_seq_iter = mafrec.target_seq().chars();
@@ -346,11 +1667,54 @@ fn call_within_var(
 
     let mut init_info = String::new();
     if strand == Strand::Negative {
-        init_info.push_str("INV_NEST=TRUE;");
+        match inv_mode {
+            InvMode::Nested => init_info.push_str("INV_NEST=TRUE;"),
+            InvMode::Flatten => {}
+            InvMode::Suppress => return Ok(var_recs),
+        }
     }
+    // INFO of plain indels, which have no SV tags
+    let indel_info = init_info
+        .strip_suffix(';')
+        .filter(|info| !info.is_empty())
+        .map(|info| info.to_string());
+    // indels not longer than the SV cutoff are plain indels with `--min-indel`
+    let is_indel = |len: u64| len <= svlen_cutoff && min_indel.is_some_and(|min| len >= min);
+    // the walk goes along the reverse complemented query, offset `k` in the walk is
+    // `q_end - k` in the inverted orientation, which is reported when flatten.
+    // return (start, end) of query span with length `len` at walk offset
+    let flatten = strand == Strand::Negative && inv_mode == InvMode::Flatten;
+    let query_span = |offset: u64, len: u64| -> Result<(u64, u64), WGAError> {
+        if flatten {
+            let k = mafrec.coord_sub(offset, q_start)?;
+            let end = mafrec.coord_sub(q_end, k)?;
+            Ok((mafrec.coord_sub(end, len)?, end))
+        } else {
+            Ok((offset, mafrec.coord_add(offset, len)?))
+        }
+    };
+    // qualities of query bases in columns below `min_qual`, None if not checked
+    let qual = match min_qual {
+        Some(_) => mafrec.slines[mafrec.query_idx].qual.as_deref(),
+        None => None,
+    };
+    let low_qual = |cols: Range<usize>| -> Option<bool> {
+        let min_qual = min_qual?;
+        let lowest = qual?
+            .get(cols)?
+            .bytes()
+            .filter(|&b| b != b'-')
+            .map(qline_value)
+            .min()?;
+        Some(lowest < min_qual)
+    };
     let mut after_m = false;
+    // column of the current group
+    let mut col = 0;
     for (k, g) in group_by_iter.into_iter() {
         let len = g.count() as u64;
+        let group_col = col;
+        col += len as usize;
         match k {
             '=' => {
                 target_current_offset += len;
@@ -361,7 +1725,8 @@ fn call_within_var(
                 // do nothing
             }
             'I' => {
-                if len > svlen_cutoff {
+                let indel = is_indel(len);
+                if len > svlen_cutoff || indel {
                     // This case for:
                     // t: ----A
                     // q: AAAAA
@@ -376,44 +1741,57 @@ fn call_within_var(
                         after_m = false;
                         continue;
                     }
-                    let t_slice_start = (target_current_offset - t_start - 1) as usize;
+                    let t_slice_start =
+                        mafrec.coord_sub(target_current_offset, t_start + 1)? as usize;
                     let t_slice_end = t_slice_start + 1;
 
-                    let q_slice_start = (query_current_offset - q_start - 1) as usize;
+                    let q_slice_start =
+                        mafrec.coord_sub(query_current_offset, q_start + 1)? as usize;
                     let q_slice_end = q_slice_start + len as usize + 1;
 
-                    let info = format!(
-                        "{}SVTYPE=INS;SVLEN={};END={}",
-                        init_info, len, target_current_offset
-                    );
-
-                    let queryinfo = format!(
-                        "{}{}@{}@{}@{}",
-                        init_format,
-                        q_chro,
-                        query_current_offset,
-                        query_current_offset + len,
-                        format_surfix
-                    );
-
-                    let ref_base = &t_seq_ref[t_slice_start..t_slice_end];
-                    let alt_base = &q_seq_ref[q_slice_start..q_slice_end];
-                    let record = get_variant_rec(
-                        chro,
-                        target_current_offset as usize,
-                        ref_base,
-                        alt_base,
-                        // &id,
-                        Some(&info),
-                        Some(&queryinfo),
-                    );
-                    var_recs.push(record?);
+                    let info = match indel {
+                        true => indel_info.clone(),
+                        false => Some(format!(
+                            "{}SVTYPE=INS;SVLEN={};END={};SVCLASS={}",
+                            init_info,
+                            len,
+                            target_current_offset,
+                            sv_class(len)
+                        )),
+                    };
+
+                    let (qi_start, qi_end) = query_span(query_current_offset, len)?;
+                    let queryinfo = format!("{}@{}@{}@{}", q_chro, qi_start, qi_end, format_surfix);
+
+                    let ref_base = seq_slice(mafrec, &t_seq_ref, t_slice_start, t_slice_end)?;
+                    let alt_base = seq_slice(mafrec, &q_seq_ref, q_slice_start, q_slice_end)?;
+                    let record = VarRec {
+                        id: None,
+                        chro: chro.to_string(),
+                        pos: target_current_offset,
+                        end: target_current_offset,
+                        svtype: (!indel).then_some("INS"),
+                        ref_base: ref_base.to_string(),
+                        alt_base: alt_base.to_string(),
+                        info,
+                        qi: vec![queryinfo],
+                        low_qual: low_qual(group_col..col),
+                        svlen: if indel { 0 } else { len },
+                        long_sv: None,
+                    };
+                    if !indel {
+                        sv_breakpoints.push(target_current_offset);
+                    } else {
+                        maskable_recs.push((var_recs.len(), target_current_offset));
+                    }
+                    var_recs.push(record);
                 }
                 query_current_offset += len;
                 after_m = false;
             }
             'D' => {
-                if len > svlen_cutoff {
+                let indel = is_indel(len);
+                if len > svlen_cutoff || indel {
                     // for this case:
                     // t: AAAAA
                     // q: ----A
@@ -433,64 +1811,93 @@ fn call_within_var(
                         continue;
                     }
 
-                    let t_slice_start = (target_current_offset - t_start - 1) as usize;
+                    let t_slice_start =
+                        mafrec.coord_sub(target_current_offset, t_start + 1)? as usize;
                     let t_slice_end = t_slice_start + len as usize + 1;
 
-                    let q_slice_start = (query_current_offset - q_start - 1) as usize;
+                    let q_slice_start =
+                        mafrec.coord_sub(query_current_offset, q_start + 1)? as usize;
                     let q_slice_end = q_slice_start + 1;
 
                     let end = target_current_offset + len;
-                    let info = format!("{}SVTYPE=DEL;SVLEN={};END={}", init_info, len, end);
-                    let queryinfo = format!(
-                        "{}{}@{}@{}@{}",
-                        init_format,
-                        q_chro,
-                        query_current_offset,
-                        query_current_offset,
-                        format_surfix
-                    );
+                    let info = match indel {
+                        true => indel_info.clone(),
+                        false => Some(format!(
+                            "{}SVTYPE=DEL;SVLEN={};END={};SVCLASS={}",
+                            init_info,
+                            len,
+                            end,
+                            sv_class(len)
+                        )),
+                    };
+                    let (qi_pos, _) = query_span(query_current_offset, 0)?;
+                    let queryinfo = format!("{}@{}@{}@{}", q_chro, qi_pos, qi_pos, format_surfix);
                     // let id = format!("DEL{}", del_count);
-                    let ref_base = &t_seq_ref[t_slice_start..t_slice_end];
-                    let alt_base = &q_seq_ref[q_slice_start..q_slice_end];
-                    let record = get_variant_rec(
-                        chro,
-                        target_current_offset as usize,
-                        ref_base,
-                        alt_base,
-                        // &id,
-                        Some(&info),
-                        Some(&queryinfo),
-                    );
-                    var_recs.push(record?);
+                    let ref_base = seq_slice(mafrec, &t_seq_ref, t_slice_start, t_slice_end)?;
+                    let alt_base = seq_slice(mafrec, &q_seq_ref, q_slice_start, q_slice_end)?;
+                    let record = VarRec {
+                        id: None,
+                        chro: chro.to_string(),
+                        pos: target_current_offset,
+                        end,
+                        svtype: (!indel).then_some("DEL"),
+                        ref_base: ref_base.to_string(),
+                        alt_base: alt_base.to_string(),
+                        info,
+                        qi: vec![queryinfo],
+                        // query has no base in deleted columns, use the anchor one
+                        low_qual: low_qual(group_col - 1..group_col),
+                        svlen: if indel { 0 } else { len },
+                        long_sv: None,
+                    };
+                    if !indel {
+                        sv_breakpoints.push(target_current_offset);
+                        sv_breakpoints.push(end);
+                    } else {
+                        maskable_recs.push((var_recs.len(), target_current_offset));
+                    }
+                    var_recs.push(record);
                 }
                 target_current_offset += len;
                 after_m = false;
             }
             'X' => {
                 if if_snp {
-                    for _ in 0..len {
-                        let t_slice_start = (target_current_offset - t_start) as usize;
+                    for i in 0..len as usize {
+                        let t_slice_start =
+                            mafrec.coord_sub(target_current_offset, t_start)? as usize;
                         let t_slice_end = t_slice_start + 1;
 
-                        let q_slice_start = (query_current_offset - q_start) as usize;
+                        let q_slice_start =
+                            mafrec.coord_sub(query_current_offset, q_start)? as usize;
                         let q_slice_end = q_slice_start + 1;
 
-                        let ref_base = &t_seq_ref[t_slice_start..t_slice_end];
-                        let alt_base = &q_seq_ref[q_slice_start..q_slice_end];
-
-                        let queryinfo = format!(
-                            "{}{}@{}@{}",
-                            init_format, q_chro, query_current_offset, format_surfix
-                        );
-                        let record = get_variant_rec(
-                            chro,
-                            target_current_offset as usize + 1,
-                            ref_base,
-                            alt_base,
-                            None,
-                            Some(&queryinfo),
-                        );
-                        var_recs.push(record?);
+                        let ref_base = seq_slice(mafrec, &t_seq_ref, t_slice_start, t_slice_end)?;
+                        let alt_base = seq_slice(mafrec, &q_seq_ref, q_slice_start, q_slice_end)?;
+                        // ambiguous REF may be resolved to the same base as ALT
+                        if normalize_ref_base(ref_base).eq_ignore_ascii_case(alt_base) {
+                            target_current_offset += 1;
+                            query_current_offset += 1;
+                            continue;
+                        }
+
+                        let (qi_pos, _) = query_span(query_current_offset, 1)?;
+                        let queryinfo = format!("{}@{}@{}", q_chro, qi_pos, format_surfix);
+                        var_recs.push(VarRec {
+                            id: None,
+                            chro: chro.to_string(),
+                            pos: target_current_offset + 1,
+                            end: target_current_offset + 1,
+                            svtype: None,
+                            ref_base: ref_base.to_string(),
+                            alt_base: alt_base.to_string(),
+                            info: None,
+                            qi: vec![queryinfo],
+                            low_qual: low_qual(group_col + i..group_col + i + 1),
+                            svlen: 0,
+                            long_sv: None,
+                        });
+                        maskable_recs.push((var_recs.len() - 1, target_current_offset + 1));
                         target_current_offset += 1;
                         query_current_offset += 1;
                     }
@@ -503,5 +1910,367 @@ fn call_within_var(
             _ => {}
         }
     }
+
+    if mask_near_sv > 0 && !sv_breakpoints.is_empty() {
+        mask_vars_near_sv(
+            &mut var_recs,
+            &mut sv_breakpoints,
+            &maskable_recs,
+            mask_near_sv,
+        );
+    }
     Ok(var_recs)
 }
+
+// value of a q-line quality, `F` for finished sequence is above all
+fn qline_value(qual: u8) -> u8 {
+    match qual {
+        b'F' => 10,
+        qual => qual - b'0',
+    }
+}
+
+/// drop SNPs/small indels within `distance` bp of any SV breakpoint in the same block
+fn mask_vars_near_sv(
+    var_recs: &mut Vec<VarRec>,
+    sv_breakpoints: &mut [u64],
+    maskable_recs: &[(usize, u64)],
+    distance: u64,
+) {
+    sv_breakpoints.sort_unstable();
+    let mut masked = vec![false; var_recs.len()];
+    for &(idx, pos) in maskable_recs {
+        // the nearest breakpoints are on both sides of the insertion point
+        let i = sv_breakpoints.partition_point(|&bp| bp < pos);
+        let near_right = sv_breakpoints.get(i).is_some_and(|bp| bp - pos <= distance);
+        let near_left = i > 0 && pos - sv_breakpoints[i - 1] <= distance;
+        masked[idx] = near_left || near_right;
+    }
+    let mut masked_iter = masked.into_iter();
+    var_recs.retain(|_| !masked_iter.next().unwrap_or(false));
+}
+
+// size class of SV as INFO `SVCLASS`
+fn sv_class(len: u64) -> &'static str {
+    match len {
+        0..=49 => "<50",
+        50..=999 => "50-999",
+        1000..=10000 => "1k-10k",
+        _ => ">10k",
+    }
+}
+
+// drop SVs longer than `max_svlen`, or set their FILTER if it is kept,
+// return the number of long SVs
+fn limit_svlen(var_recs: &mut Vec<VarRec>, max_svlen: Option<MaxSvLen>) -> usize {
+    let Some(max_svlen) = max_svlen else {
+        return 0;
+    };
+    let count = var_recs.len();
+    if !max_svlen.filter {
+        var_recs.retain(|rec| rec.svlen <= max_svlen.len);
+        return count - var_recs.len();
+    }
+    let mut long = 0;
+    for rec in var_recs {
+        let is_long = rec.svlen > max_svlen.len;
+        long += is_long as usize;
+        rec.long_sv = Some(is_long);
+    }
+    long
+}
+
+// add FILTER of long SVs to header if they are kept
+fn add_header_max_svlen(header: &mut Header, max_svlen: Option<MaxSvLen>) {
+    if let Some(max_svlen) = max_svlen.filter(|max_svlen| max_svlen.filter) {
+        header.filters_mut().insert(
+            LONG_SV.to_string(),
+            Map::<Filter>::new(format!("SV longer than {} bp", max_svlen.len)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_block(maf: &str) -> MAFRecord {
+        MAFReader::new(maf.as_bytes())
+            .unwrap()
+            .records()
+            .next()
+            .unwrap()
+            .unwrap()
+    }
+
+    fn pair_block(target: &str, query: &str, strand: char) -> MAFRecord {
+        let size = |seq: &str| seq.chars().filter(|c| *c != '-').count();
+        read_block(&format!(
+            "##maf version=1\na score=0\ns t 0 {} + 1000 {}\ns q 0 {} {} 1000 {}\n\n",
+            size(target),
+            target,
+            size(query),
+            strand,
+            query
+        ))
+    }
+
+    fn cols(chunks: &[MAFRecord]) -> Vec<usize> {
+        chunks.iter().map(|rec| rec.slines[0].seq.len()).collect()
+    }
+
+    // chunks put together give the block back
+    fn assert_joined(rec: &MAFRecord, chunks: &[MAFRecord]) {
+        for (idx, sline) in rec.slines.iter().enumerate() {
+            let seq = chunks
+                .iter()
+                .map(|chunk| chunk.slines[idx].seq.as_str())
+                .collect::<String>();
+            assert_eq!(seq, sline.seq);
+            let mut start = sline.start;
+            for chunk in chunks {
+                assert_eq!(chunk.slines[idx].start, start);
+                start += chunk.slines[idx].align_size;
+            }
+            assert_eq!(start, sline.start + sline.align_size);
+        }
+    }
+
+    const TARGET: &str = "ACGTACGTAC--GTACGTACGTACGTAC";
+    const QUERY: &str = "ACGTACGTACGGGTAC---ACGTACGTAC";
+
+    #[test]
+    fn chunk_none_keeps_block() {
+        let rec = pair_block(TARGET, &QUERY[..28], '+');
+        let chunks = split_block(
+            pair_block(TARGET, &QUERY[..28], '+'),
+            ChunkStrategy::None,
+            4,
+            2,
+        );
+        assert_eq!(chunks.unwrap(), [rec]);
+    }
+
+    #[test]
+    fn chunk_short_block_is_kept() {
+        for strategy in [
+            ChunkStrategy::None,
+            ChunkStrategy::Fixed,
+            ChunkStrategy::SvAware,
+        ] {
+            let rec = pair_block("ACGT-A", "AC-TTA", '+');
+            let chunks = split_block(pair_block("ACGT-A", "AC-TTA", '+'), strategy, 6, 2);
+            assert_eq!(chunks.unwrap(), [rec]);
+        }
+    }
+
+    #[test]
+    fn chunk_fixed_splits_every_chunk_size() {
+        let rec = pair_block(TARGET, &QUERY[..28], '+');
+        let chunks = split_block(
+            pair_block(TARGET, &QUERY[..28], '+'),
+            ChunkStrategy::Fixed,
+            8,
+            2,
+        );
+        let chunks = chunks.unwrap();
+        assert_eq!(cols(&chunks), [8, 8, 8, 4]);
+        assert_joined(&rec, &chunks);
+    }
+
+    #[test]
+    fn chunk_sv_aware_avoids_indels() {
+        let rec = pair_block(TARGET, &QUERY[..28], '+');
+        let chunks = split_block(
+            pair_block(TARGET, &QUERY[..28], '+'),
+            ChunkStrategy::SvAware,
+            8,
+            2,
+        );
+        let chunks = chunks.unwrap();
+        // indels at columns 10-11 and 16-18 are kept away from boundaries by 2 columns
+        assert_eq!(cols(&chunks), [8, 13, 7]);
+        assert_joined(&rec, &chunks);
+        for chunk in &chunks {
+            for sline in &chunk.slines {
+                assert!(!sline.seq.starts_with('-') && !sline.seq.ends_with('-'));
+            }
+        }
+    }
+
+    #[test]
+    fn chunk_sv_aware_without_safe_window() {
+        // gaps every 3 columns leave no window of 2 * 2 aligned columns
+        let target = "AC-".repeat(10);
+        let query = "A-C".repeat(10);
+        let chunks = split_block(
+            pair_block(&target, &query, '+'),
+            ChunkStrategy::SvAware,
+            4,
+            2,
+        );
+        assert_eq!(cols(&chunks.unwrap()), [30]);
+    }
+
+    #[test]
+    fn chunk_negative_strand_is_kept() {
+        for strategy in [ChunkStrategy::Fixed, ChunkStrategy::SvAware] {
+            let chunks = split_block(pair_block(TARGET, &QUERY[..28], '-'), strategy, 4, 1);
+            assert_eq!(cols(&chunks.unwrap()), [28]);
+        }
+    }
+
+    #[test]
+    fn chunk_at_odd_bytes() {
+        // odd characters are read as `N`, one column each
+        let rec = pair_block("ACGTAéGTAC", "ACGTAC\u{1}TAC", '+');
+        assert_eq!(rec.slines[0].seq, "ACGTANGTAC");
+        assert_eq!(rec.slines[1].seq, "ACGTACNTAC");
+        for strategy in [ChunkStrategy::Fixed, ChunkStrategy::SvAware] {
+            let chunks = split_block(
+                pair_block("ACGTAéGTAC", "ACGTAC\u{1}TAC", '+'),
+                strategy,
+                5,
+                1,
+            );
+            assert_joined(&rec, &chunks.unwrap());
+        }
+        // multi-byte characters not read by MAFReader are errors, not a panic at byte index
+        for strategy in [ChunkStrategy::Fixed, ChunkStrategy::SvAware] {
+            let mut rec = pair_block("ACGTACGTAC", "ACGTACGTAC", '+');
+            rec.slines[0].seq = "ACGTéCGTA".to_string();
+            assert!(split_block(rec, strategy, 5, 1).is_err());
+        }
+    }
+
+    fn called_vcf(blocks: Vec<MAFRecord>, chunk_strategy: ChunkStrategy) -> Vec<String> {
+        let opts = CallOpts {
+            snp: true,
+            svlen: 5,
+            chunk_strategy,
+            chunk_size: 8,
+            ..Default::default()
+        };
+        let (var_recs, _) = call_blocks(blocks, &opts).unwrap();
+        var_recs
+            .iter()
+            .map(|var| var.to_record(opts.ploidy).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn chunked_calls_as_unchunked() {
+        // SNP, DEL of 6bp and INS of 7bp
+        let target = "ACGTACGTACGTACGTACGTAC-------GTACGTACGT";
+        let query = "ACGAACG------CGTACGTACGGGGGGGGTACGTACGT";
+        let unchunked = called_vcf(vec![pair_block(target, query, '+')], ChunkStrategy::None);
+        assert_eq!(unchunked.len(), 3);
+        // blocks split through the SVs, as output of `chunk`
+        let chunks = split_block(pair_block(target, query, '+'), ChunkStrategy::Fixed, 5, 1);
+        assert_eq!(called_vcf(chunks.unwrap(), ChunkStrategy::None), unchunked);
+        // blocks split by `--chunk-strategy sv-aware` away from the SVs
+        let blocks = vec![pair_block(target, query, '+')];
+        assert_eq!(called_vcf(blocks, ChunkStrategy::SvAware), unchunked);
+    }
+
+    fn sv(pos: u64, end: u64, alt_base: &str, qi: &str) -> VarRec {
+        VarRec {
+            id: None,
+            chro: "chr1".to_string(),
+            pos,
+            end,
+            svtype: Some("INS"),
+            ref_base: "A".to_string(),
+            alt_base: alt_base.to_string(),
+            info: None,
+            qi: vec![qi.to_string()],
+            low_qual: None,
+            svlen: alt_base.len() as u64 - 1,
+            long_sv: None,
+        }
+    }
+
+    fn merged_qi(var_recs: Vec<VarRec>, distance: u64) -> Vec<String> {
+        merge_dup_svs(var_recs, distance)
+            .iter()
+            .map(|var| var.qi.join(","))
+            .collect()
+    }
+
+    #[test]
+    fn merge_dup_svs_by_alleles() {
+        let ins = "A".to_string() + &"C".repeat(60);
+        let other = "A".to_string() + &"G".repeat(60);
+        // same INS from two queries is merged, INS of another sequence is kept
+        let var_recs = vec![
+            sv(10, 10, &ins, "q1"),
+            sv(10, 10, &ins, "q2"),
+            sv(10, 10, &other, "q3"),
+        ];
+        assert_eq!(merged_qi(var_recs, 0), ["q1,q2", "q3"]);
+        // nearby INS of similar length are merged only within the distance
+        let longer = ins.clone() + "CC";
+        let var_recs = vec![sv(10, 10, &ins, "q1"), sv(12, 12, &longer, "q2")];
+        assert_eq!(merged_qi(var_recs, 1), ["q1", "q2"]);
+        let var_recs = vec![sv(10, 10, &ins, "q1"), sv(12, 12, &longer, "q2")];
+        assert_eq!(merged_qi(var_recs, 2), ["q1,q2"]);
+    }
+
+    #[test]
+    fn merge_dup_svs_not_split_by_far_end() {
+        let ins = "A".to_string() + &"C".repeat(60);
+        let var_recs = vec![
+            sv(10, 100, &ins, "q1"),
+            sv(11, 5000, &ins, "q2"),
+            sv(12, 101, &ins, "q3"),
+        ];
+        assert_eq!(merged_qi(var_recs, 5), ["q1,q3", "q2"]);
+    }
+
+    fn called_pos(rec: &mut MAFRecord, svlen: u64, mask_near_sv: u64) -> Vec<u64> {
+        let opts = CallOpts {
+            snp: true,
+            svlen,
+            mask_near_sv,
+            ..Default::default()
+        };
+        within_var(rec, &opts)
+            .unwrap()
+            .iter()
+            .map(|var| var.pos)
+            .collect()
+    }
+
+    #[test]
+    fn mask_near_sv_by_svlen() {
+        // SNP at 3, DEL of 20bp after 5, SNP at 30
+        let target = "ACTTACGTACGTACGTACGTACGTACGTAC";
+        let query = "ACGTA--------------------CGTAG";
+        let mut rec = pair_block(target, query, '+');
+        // the DEL is not longer than `--svlen`, nothing is masked
+        assert_eq!(called_pos(&mut rec, 50, 4), [3, 30]);
+        // the DEL is an SV with `--svlen 10`, the SNP within 4bp of it is masked
+        assert_eq!(called_pos(&mut rec, 10, 4), [5, 30]);
+        assert_eq!(called_pos(&mut rec, 10, 0), [3, 5, 30]);
+    }
+
+    #[test]
+    fn mask_near_sv_not_at_reverse_block_ends() {
+        let mut rec = pair_block("ACGTACGTAC", "ACTTACGTAG", '-');
+        // INV and SNPs next to both ends of the block are kept
+        assert_eq!(called_pos(&mut rec, 50, 5), [1, 3, 10]);
+    }
+
+    #[test]
+    fn unrepresentable_alt_base() {
+        let alt = |alt_base: &str, info: Option<&str>| {
+            let rec = get_variant_rec("chr1", None, 10, "A", alt_base, info, &[], None).unwrap();
+            rec.to_string().split('\t').nth(4).unwrap().to_string()
+        };
+        assert_eq!(alt("g", None), "G");
+        // SNP and small indel keep their length
+        assert_eq!(alt("R", None), "N");
+        assert_eq!(alt("AYk", None), "ANN");
+        assert_eq!(alt("ACGTRACGT", Some("SVTYPE=INS;SVLEN=8")), "<INS>");
+    }
+}
@@ -1,9 +1,6 @@
 use crate::{
     errors::WGAError,
-    parser::{
-        common::recount_align_size,
-        maf::{MAFReader, MAFRecord, MAFSLine, MAFWriter},
-    },
+    parser::maf::{MAFReader, MAFWriter},
 };
 use std::io::{Read, Write};
 
@@ -29,62 +26,93 @@ pub fn chunk_maf<R: Read + Send>(
     let header = format!("#maf version=1.6 split_length={}", chunk_length);
     mafwtr.write_header(header)?;
 
-    // chunk each block
+    // chunk each block, every s-line is recomputed so multi-way blocks are kept consistent
+    let chunk_length = chunk_length as usize;
     for rec in reader.records() {
         let rec = rec?;
-        let block_length = rec.slines[0].seq.len() as u64;
+        let block_length = rec.slines[0].seq.len();
+        let mut slicer = rec.column_slicer();
 
-        // init sline_end_vec
-        let mut sline_end_vec = vec![];
-        for s in &rec.slines {
-            sline_end_vec.push(s.start);
-        }
-        // chunk each s-line in a block until the end
         let mut chunk_start = 0;
-        let mut chunk_end = chunk_length;
-
-        // chunk each s-line in a block until the end
-        while chunk_end < block_length {
-            let new_rec = emit_new_maf_rec(&rec, chunk_start, chunk_end, &mut sline_end_vec)?;
-            mafwtr.write_record(&new_rec)?;
+        while chunk_start + chunk_length < block_length {
+            let chunk_end = chunk_start + chunk_length;
+            mafwtr.write_record(&slicer.slice(chunk_start, chunk_end)?)?;
             chunk_start = chunk_end;
-            chunk_end += chunk_length;
         }
 
         // last chunk
-        let new_rec = emit_new_maf_rec(&rec, chunk_start, block_length, &mut sline_end_vec)?;
-        mafwtr.write_record(&new_rec)?;
+        mafwtr.write_record(&slicer.slice(chunk_start, block_length)?)?;
     }
 
     Ok(())
 }
 
-// emit new maf rec
-fn emit_new_maf_rec(
-    rec: &MAFRecord,
-    chunk_start: u64,
-    chunk_end: u64,
-    end_vec: &mut [u64],
-) -> Result<MAFRecord, WGAError> {
-    let mut new_rec = MAFRecord {
-        score: rec.score,
-        slines: vec![],
-        query_idx: 1,
-    };
-    for (i, sline) in rec.slines.iter().enumerate() {
-        let new_seq = &sline.seq[chunk_start as usize..chunk_end as usize];
-        let (align_size, _) = recount_align_size(new_seq);
-        let new_sline = MAFSLine {
-            mode: 's',
-            name: sline.name.clone(),
-            start: end_vec[i],
-            align_size,
-            strand: sline.strand,
-            size: sline.size,
-            seq: new_seq.to_string(),
-        };
-        new_rec.slines.push(new_sline);
-        end_vec[i] += align_size;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::maf::MAFRecord;
+
+    fn chunk(maf: &str, chunk_length: u64) -> Vec<MAFRecord> {
+        let mut out = Vec::new();
+        chunk_maf(
+            MAFReader::new(maf.as_bytes()).unwrap(),
+            chunk_length,
+            &mut out,
+        )
+        .unwrap();
+        MAFReader::new(out.as_slice())
+            .unwrap()
+            .records()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn chunk_three_way_blocks() {
+        let maf = "##maf version=1\n\
+            a score=1\n\
+            s A 0  5  + 5  -----ATCGT\n\
+            s B 0  10 + 10 GGGGGATCGT\n\
+            s C 20 7  - 40 GG---AT-GT\n\n\
+            a score=2\n\
+            s A 5  3  + 8  ACG\n\
+            s B 10 3  + 13 AGC\n\
+            s C 27 2  - 40 AC-\n\n";
+        let recs = chunk(maf, 5);
+        assert_eq!(recs.len(), 3);
+        let starts = |rec: &MAFRecord| rec.slines.iter().map(|s| s.start).collect::<Vec<_>>();
+        let sizes = |rec: &MAFRecord| rec.slines.iter().map(|s| s.align_size).collect::<Vec<_>>();
+        assert_eq!(starts(&recs[0]), [0, 0, 20]);
+        assert_eq!(sizes(&recs[0]), [0, 5, 2]);
+        assert_eq!(starts(&recs[1]), [0, 5, 22]);
+        assert_eq!(sizes(&recs[1]), [5, 5, 4]);
+        // blocks shorter than chunk length are kept whole
+        assert_eq!(starts(&recs[2]), [5, 10, 27]);
+        assert_eq!(sizes(&recs[2]), [3, 3, 2]);
+    }
+
+    #[test]
+    fn chunk_long_block_in_order() {
+        let n = 2000;
+        let maf = format!(
+            "##maf version=1\na score=0\ns A 0 {n} + {n} {a}\ns B 0 {n} + {n} {a}\ns C 0 {half} + {n} {c}\n\n",
+            n = n,
+            half = n / 2,
+            a = "A".repeat(n),
+            c = "A-".repeat(n / 2),
+        );
+        let recs = chunk(&maf, 7);
+        assert_eq!(recs.len(), n.div_ceil(7));
+        for (idx, rec) in recs.iter().enumerate() {
+            let col = (idx * 7) as u64;
+            assert_eq!(rec.slines[0].start, col);
+            assert_eq!(rec.slines[1].start, col);
+            assert_eq!(rec.slines[2].start, col.div_ceil(2));
+        }
+        let last = recs.last().unwrap();
+        assert_eq!(
+            last.slines[2].start + last.slines[2].align_size,
+            n as u64 / 2
+        );
     }
-    Ok(new_rec)
 }
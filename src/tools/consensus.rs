@@ -0,0 +1,439 @@
+use crate::errors::WGAError;
+use crate::log::WarnCategory;
+use crate::parser::common::{AlignRecord, Strand};
+use crate::parser::maf::MAFReader;
+use crate::parser::region::GenomeRegion;
+use crate::tools::caller::call_block_alleles;
+use crate::utils::{parse_str2u64, reverse_complement, IndexedFasta};
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::io::{BufRead, Read, Write};
+
+// bases per line of FASTA output
+const FASTA_LINE_WIDTH: usize = 60;
+
+/// A variant with explicit alleles, POS is 1-based as VCF
+#[derive(Debug)]
+struct ConsVar {
+    pos: u64,
+    ref_allele: String,
+    alt_allele: String,
+}
+
+/// Counts of variants when applying
+#[derive(Debug, Default)]
+struct ConsStat {
+    applied: usize,
+    symbolic: usize,
+    missing: usize,
+    not_carried: usize,
+    overlapped: usize,
+    ref_mismatched: usize,
+}
+
+impl ConsStat {
+    fn report(&self) {
+        info!(
+            "applied {} variants, skipped {} symbolic, {} missing (`.` or `*`), {} not carried by GT and {} overlapped",
+            self.applied, self.symbolic, self.missing, self.not_carried, self.overlapped
+        );
+        if self.ref_mismatched > 0 {
+            warn!(
+                target: WarnCategory::Input.target(),
+                "REF of {} variants mismatch the reference, applied anyway",
+                self.ref_mismatched
+            );
+        }
+    }
+}
+
+/// Reconstruct query sequences by applying variants in VCF to reference,
+/// one FASTA record per region, or per reference sequence if no region is given,
+/// ALTs are picked by GT of `haplotype` (1-based) in `sample`, the first sample if None
+pub fn consensus_vcf<R: BufRead>(
+    vcf_reader: R,
+    ref_path: &str,
+    regions: Vec<GenomeRegion>,
+    sample: Option<&str>,
+    haplotype: usize,
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    let mut stat = ConsStat::default();
+    let mut variants = read_cons_vars(vcf_reader, sample, haplotype, &mut stat)?;
+    for vars in variants.values_mut() {
+        sort_cons_vars(vars);
+    }
+
+    let fasta = IndexedFasta::from_path(ref_path)?;
+    let regions = match regions.is_empty() {
+        true => whole_regions(&fasta),
+        false => regions,
+    };
+
+    for region in regions {
+        // empty regions have nothing to fetch
+        let ref_seq = match region.start < region.end {
+            true => fasta.fetch(
+                &region.name,
+                region.start as usize,
+                region.end.min(usize::MAX as u64) as usize - 1,
+            )?,
+            false => fasta.contains(&region.name).then(String::new),
+        };
+        let Some(ref_seq) = ref_seq else {
+            warn!(target: WarnCategory::Result.target(), "{}", WGAError::FailedRegion(region));
+            continue;
+        };
+        if region.start >= region.end {
+            continue;
+        }
+        // region end may be out of sequence
+        let end = region.start + ref_seq.len() as u64;
+        let vars = variants
+            .get(&region.name)
+            .map(|vars| {
+                vars.iter()
+                    .filter(|var| {
+                        var.pos > region.start && var.pos - 1 + var.ref_allele.len() as u64 <= end
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let seq = apply_variants(&ref_seq, region.start, &vars, &mut stat);
+        // whole sequences are named without range
+        let name = match region.is_whole() {
+            true => region.name,
+            false => format!("{}:{}-{}", region.name, region.start, end),
+        };
+        write_fasta(writer, &name, &seq)?;
+    }
+    stat.report();
+    Ok(())
+}
+
+/// Reconstruct query sequence of each MAF block by calling variants and applying them to
+/// target sequence, the result is checked against the query sequence in block to
+/// report variants dropped by `call`
+pub fn consensus_maf<R: Read + Send>(
+    mafreader: &mut MAFReader<R>,
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    let mut stat = ConsStat::default();
+    let mut diff_count = 0;
+    for rec in mafreader.records() {
+        let mut rec = rec?;
+        let mut vars = call_block_alleles(&mut rec)?
+            .into_iter()
+            .map(|(pos, ref_allele, alt_allele)| ConsVar {
+                pos,
+                ref_allele,
+                alt_allele,
+            })
+            .collect::<Vec<_>>();
+        sort_cons_vars(&mut vars);
+
+        let mut t_seq = rec.target_seq().to_string();
+        t_seq.retain(|c| c != '-');
+        let mut q_seq = rec.query_seq().to_string();
+        q_seq.retain(|c| c != '-');
+
+        let seq = apply_variants(
+            &t_seq,
+            rec.target_start(),
+            &vars.iter().collect::<Vec<_>>(),
+            &mut stat,
+        );
+        // soft-masked bases are not called as SNPs
+        if !seq.eq_ignore_ascii_case(&q_seq) {
+            debug!(
+                "consensus differs from query sequence in record {}",
+                rec.coord_context()
+            );
+            diff_count += 1;
+        }
+        // consensus is along target, convert to the forward strand of query
+        let seq = match rec.query_strand() {
+            Strand::Positive => seq,
+            Strand::Negative => reverse_complement(&seq)?,
+        };
+        let name = format!(
+            "{}:{}-{}",
+            rec.query_name(),
+            rec.query_start(),
+            rec.query_end()
+        );
+        write_fasta(writer, &name, &seq)?;
+    }
+    stat.report();
+    if diff_count > 0 {
+        warn!(
+            target: WarnCategory::Result.target(),
+            "consensus of {} blocks differ from query, e.g. indels at block start or right after another indel are not called",
+            diff_count
+        );
+    }
+    Ok(())
+}
+
+// sort variants by POS, the longer one goes first at the same POS, since an indel of `call`
+// is anchored on the base before it and its ALT includes the SNP on that base
+fn sort_cons_vars(vars: &mut [ConsVar]) {
+    vars.sort_by_key(|var| {
+        let len = var.ref_allele.len().max(var.alt_allele.len());
+        (var.pos, std::cmp::Reverse(len))
+    });
+}
+
+// apply sorted variants to reference sequence starting at 0-based `offset`,
+// a variant overlapping the previous applied one is skipped
+fn apply_variants(ref_seq: &str, offset: u64, vars: &[&ConsVar], stat: &mut ConsStat) -> String {
+    let mut seq = String::with_capacity(ref_seq.len());
+    // 0-based position in `ref_seq` not consumed yet
+    let mut cursor = 0;
+    for var in vars {
+        if var.alt_allele.starts_with('<') {
+            stat.symbolic += 1;
+            continue;
+        }
+        if var.alt_allele == "." || var.alt_allele == "*" {
+            stat.missing += 1;
+            continue;
+        }
+        let start = (var.pos - 1 - offset) as usize;
+        if start < cursor {
+            stat.overlapped += 1;
+            continue;
+        }
+        let end = start + var.ref_allele.len();
+        if !ref_seq[start..end].eq_ignore_ascii_case(&var.ref_allele) {
+            stat.ref_mismatched += 1;
+        }
+        seq.push_str(&ref_seq[cursor..start]);
+        seq.push_str(&var.alt_allele);
+        cursor = end;
+        stat.applied += 1;
+    }
+    seq.push_str(&ref_seq[cursor..]);
+    seq
+}
+
+// regions of whole sequences in FASTA, in the order of index
+fn whole_regions(fasta: &IndexedFasta) -> Vec<GenomeRegion> {
+    fasta
+        .names()
+        .iter()
+        .map(|name| GenomeRegion::whole(name))
+        .collect()
+}
+
+// read variants from a plain or compressed VCF, grouped by CHROM, the ALT of each record is
+// picked by GT of `haplotype` (1-based) in the sample column, or the first ALT if VCF has no GT
+fn read_cons_vars<R: BufRead>(
+    reader: R,
+    sample: Option<&str>,
+    haplotype: usize,
+    stat: &mut ConsStat,
+) -> Result<HashMap<String, Vec<ConsVar>>, WGAError> {
+    let mut variants: HashMap<String, Vec<ConsVar>> = HashMap::new();
+    // 0-based column of the sample, None for sites-only VCF
+    let mut column = None;
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with("#CHROM") {
+            column = sample_column(&line, sample)?;
+            continue;
+        }
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if sample.is_some() && column.is_none() {
+            return Err(WGAError::InvalidArgs(
+                "`--sample` is set but VCF has no `#CHROM` header line".to_string(),
+            ));
+        }
+        let invalid = || WGAError::InvalidRecord(format!("invalid VCF record: {}", line));
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        let (chrom, pos, ref_allele, alt) = match fields.as_slice() {
+            [chrom, pos, _id, ref_allele, alt, ..] => (chrom, pos, ref_allele, alt),
+            _ => return Err(invalid()),
+        };
+        let pos = parse_str2u64(pos)?;
+        if pos == 0 {
+            return Err(WGAError::InvalidRecord(format!(
+                "invalid VCF POS `0`: {}",
+                line
+            )));
+        }
+        let allele = match column {
+            Some(column) => {
+                let (format, value) = match (fields.get(8), fields.get(column)) {
+                    (Some(format), Some(value)) => (format, value),
+                    _ => return Err(invalid()),
+                };
+                gt_allele(format, value, haplotype).ok_or_else(invalid)?
+            }
+            None => Some(1),
+        };
+        // GT `0` or `.` keeps the reference
+        let Some(allele) = allele.filter(|allele| *allele > 0) else {
+            stat.not_carried += 1;
+            continue;
+        };
+        let alt_allele = alt.split(',').nth(allele - 1).ok_or_else(invalid)?;
+        variants
+            .entry(chrom.to_string())
+            .or_default()
+            .push(ConsVar {
+                pos,
+                ref_allele: ref_allele.to_string(),
+                alt_allele: alt_allele.to_string(),
+            });
+    }
+    Ok(variants)
+}
+
+// 0-based column of `sample` in `#CHROM` line, the first sample if not given,
+// None if VCF has no sample
+fn sample_column(header: &str, sample: Option<&str>) -> Result<Option<usize>, WGAError> {
+    let names = header.split('\t').skip(9).collect::<Vec<_>>();
+    match sample {
+        Some(sample) => match names.iter().position(|name| *name == sample) {
+            Some(i) => Ok(Some(9 + i)),
+            None => Err(WGAError::InvalidArgs(format!(
+                "sample `{}` not found in VCF, samples are: {}",
+                sample,
+                names.join(",")
+            ))),
+        },
+        None => Ok((!names.is_empty()).then_some(9)),
+    }
+}
+
+// allele index of `haplotype` (1-based) in GT of a sample, Some(None) for `.`, the only allele
+// of a haploid GT is used for any haplotype, None for an invalid GT,
+// the first ALT if FORMAT has no GT
+fn gt_allele(format: &str, value: &str, haplotype: usize) -> Option<Option<usize>> {
+    let Some(gt_idx) = format.split(':').position(|key| key == "GT") else {
+        return Some(Some(1));
+    };
+    let gt = value.split(':').nth(gt_idx).unwrap_or(".");
+    let alleles = gt.split(['/', '|']).collect::<Vec<_>>();
+    let allele = match alleles.as_slice() {
+        [allele] => allele,
+        _ => alleles.get(haplotype - 1).unwrap_or(&"."),
+    };
+    match *allele {
+        "." => Some(None),
+        allele => allele.parse().ok().map(Some),
+    }
+}
+
+fn write_fasta(writer: &mut dyn Write, name: &str, seq: &str) -> Result<(), WGAError> {
+    writeln!(writer, ">{}", name)?;
+    for line in seq.as_bytes().chunks(FASTA_LINE_WIDTH) {
+        writer.write_all(line)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REF_FA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test/fasta/ref.fa");
+
+    fn var(pos: u64, ref_allele: &str, alt_allele: &str) -> ConsVar {
+        ConsVar {
+            pos,
+            ref_allele: ref_allele.to_string(),
+            alt_allele: alt_allele.to_string(),
+        }
+    }
+
+    #[test]
+    fn apply_sorted_variants() {
+        // 1-based POS with offset 10 of `ACGTACGT`
+        let mut vars = vec![
+            var(12, "C", "G"),
+            var(14, "TA", "T"),
+            var(16, "C", "CTT"),
+            // overlaps the deletion
+            var(15, "A", "G"),
+            var(17, "G", "<INV>"),
+            var(18, "T", "*"),
+            var(18, "T", "."),
+        ];
+        sort_cons_vars(&mut vars);
+        let mut stat = ConsStat::default();
+        let seq = apply_variants("ACGTACGT", 10, &vars.iter().collect::<Vec<_>>(), &mut stat);
+        assert_eq!(seq, "AGGTCTTGT");
+        assert_eq!(stat.applied, 3);
+        assert_eq!(stat.overlapped, 1);
+        assert_eq!(stat.symbolic, 1);
+        assert_eq!(stat.missing, 2);
+        assert_eq!(stat.ref_mismatched, 0);
+    }
+
+    #[test]
+    fn select_alt_by_gt() {
+        assert_eq!(gt_allele("GT:QI", "0|2:.", 1), Some(Some(0)));
+        assert_eq!(gt_allele("GT:QI", "0|2:.", 2), Some(Some(2)));
+        // haploid GT for any haplotype
+        assert_eq!(gt_allele("GT", "1", 2), Some(Some(1)));
+        assert_eq!(gt_allele("GT", "./.", 1), Some(None));
+        assert_eq!(gt_allele("QI:GT", ".:1/0", 1), Some(Some(1)));
+        assert_eq!(gt_allele("QI", ".", 2), Some(Some(1)));
+        assert_eq!(gt_allele("GT", "a|1", 1), None);
+    }
+
+    const VCF: &str = "##fileformat=VCFv4.2
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1\ts2
+chr1\t2\t.\tA\tG,T\t.\t.\t.\tGT\t1|2\t0|0
+chr1\t5\t.\tG\tGAA\t.\t.\t.\tGT\t0|1\t1|.
+chr1\t10\t.\tC\t*\t.\t.\t.\tGT\t1|1\t1|1
+chr2\t2\t.\tATT\tA\t.\t.\t.\tGT\t1\t0
+";
+
+    fn consensus(vcf: &str, sample: Option<&str>, haplotype: usize) -> Result<String, WGAError> {
+        let regions = vec!["chr1".parse()?, "chr2:0-7".parse()?];
+        let mut out = Vec::new();
+        consensus_vcf(vcf.as_bytes(), REF_FA, regions, sample, haplotype, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn consensus_vcf_by_sample_and_haplotype() -> Result<(), WGAError> {
+        // chr1 AACCGGTTACGTTGCAAGCT, chr2 GATTACA
+        assert_eq!(
+            consensus(VCF, None, 1)?,
+            ">chr1\nAGCCGGTTACGTTGCAAGCT\n>chr2:0-7\nGAACA\n"
+        );
+        assert_eq!(
+            consensus(VCF, Some("s1"), 2)?,
+            ">chr1\nATCCGAAGTTACGTTGCAAGCT\n>chr2:0-7\nGAACA\n"
+        );
+        assert_eq!(
+            consensus(VCF, Some("s2"), 1)?,
+            ">chr1\nAACCGAAGTTACGTTGCAAGCT\n>chr2:0-7\nGATTACA\n"
+        );
+        // sites-only VCF applies the first ALT
+        let sites = VCF
+            .lines()
+            .map(|line| line.split('\t').take(8).collect::<Vec<_>>().join("\t") + "\n")
+            .collect::<String>();
+        assert_eq!(
+            consensus(&sites, None, 1)?,
+            ">chr1\nAGCCGAAGTTACGTTGCAAGCT\n>chr2:0-7\nGAACA\n"
+        );
+        assert!(matches!(
+            consensus(VCF, Some("s3"), 1),
+            Err(WGAError::InvalidArgs(_))
+        ));
+        assert!(matches!(
+            consensus(&sites, Some("s1"), 1),
+            Err(WGAError::InvalidArgs(_))
+        ));
+        Ok(())
+    }
+}
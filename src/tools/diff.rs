@@ -0,0 +1,345 @@
+use crate::{
+    errors::WGAError,
+    parser::{
+        cigar::parse_cigar_to_units,
+        common::{AlignRecord, Strand},
+    },
+    utils::merge_intervals,
+};
+use rayon::prelude::*;
+use rust_lapper::{Interval, Lapper};
+use std::{collections::HashMap, io::Write};
+
+type Iv = Interval<u64, usize>;
+
+/// Target interval, strand and aligned columns of an alignment record
+#[derive(Debug)]
+pub struct DiffBlock {
+    target_name: String,
+    query_name: String,
+    start: u64,
+    end: u64,
+    query_start: u64,
+    query_end: u64,
+    strand: Strand,
+    /// match rate of `M` columns, whose match state is unknown per column
+    m_identity: f64,
+    segs: Vec<DiffSeg>,
+}
+
+/// Target columns of a cigar unit, `walk` is the query offset along the aligned strand
+#[derive(Debug)]
+struct DiffSeg {
+    start: u64,
+    end: u64,
+    walk: u64,
+    op: char,
+}
+
+/// Identity and query interval of the columns of a block in a target interval
+struct Overlap {
+    identity: f64,
+    query_start: u64,
+    query_end: u64,
+}
+
+/// A line of diff result
+struct DiffLine {
+    start: u64,
+    end: u64,
+    kind: &'static str,
+    value_a: String,
+    value_b: String,
+}
+
+// collect diff blocks from records
+pub fn diff_blocks<T, I>(records: I) -> Result<Vec<DiffBlock>, WGAError>
+where
+    T: AlignRecord + Send,
+    I: Iterator<Item = Result<T, WGAError>> + Send,
+{
+    records
+        .par_bridge()
+        .map(|rec| {
+            let rec = rec?;
+            let stat = rec.get_stat()?;
+            let m_identity = match stat.matched + stat.mismatched {
+                0 => 0.0,
+                n => stat.matched as f64 / n as f64,
+            };
+            // ins columns are not on target and only move query
+            let mut segs = Vec::new();
+            let (mut pos, mut walk) = (rec.target_start(), 0);
+            for (op, len) in parse_cigar_to_units(&rec)? {
+                match op {
+                    'M' | '=' | 'X' | 'D' => {
+                        segs.push(DiffSeg {
+                            start: pos,
+                            end: pos + len,
+                            walk,
+                            op,
+                        });
+                        pos += len;
+                        if op != 'D' {
+                            walk += len;
+                        }
+                    }
+                    'I' => walk += len,
+                    _ => return Err(rec.context_err(WGAError::CigarOpInvalid(op.to_string()))),
+                }
+            }
+            Ok(DiffBlock {
+                target_name: rec.target_name().to_string(),
+                query_name: rec.query_name().to_string(),
+                start: rec.target_start(),
+                end: rec.target_end(),
+                query_start: rec.query_start(),
+                query_end: rec.query_end(),
+                strand: rec.query_strand(),
+                m_identity,
+                segs,
+            })
+        })
+        .collect()
+}
+
+impl DiffBlock {
+    // identity and query interval of columns in target `start..end`
+    fn overlap(&self, start: u64, end: u64) -> Overlap {
+        let (mut matched, mut aligned) = (0.0, 0);
+        let (mut walk_start, mut walk_end) = (u64::MAX, 0);
+        let first = self.segs.partition_point(|seg| seg.end <= start);
+        for seg in self.segs[first..].iter().take_while(|seg| seg.start < end) {
+            let seg_start = seg.start.max(start);
+            let len = seg.end.min(end) - seg_start;
+            aligned += len;
+            let walk = seg.walk + seg_start - seg.start;
+            match seg.op {
+                '=' => matched += len as f64,
+                'M' => matched += len as f64 * self.m_identity,
+                _ => {}
+            }
+            // a deletion is the query point between its flanks
+            let seg_walk_end = if seg.op == 'D' { walk } else { walk + len };
+            walk_start = walk_start.min(walk);
+            walk_end = walk_end.max(seg_walk_end);
+        }
+        let identity = match aligned {
+            0 => 0.0,
+            n => matched / n as f64,
+        };
+        let walk_start = walk_start.min(walk_end);
+        // walk goes along the reverse complemented query in negative strand
+        let (query_start, query_end) = match self.strand {
+            Strand::Positive => (self.query_start + walk_start, self.query_start + walk_end),
+            Strand::Negative => (self.query_end - walk_end, self.query_end - walk_start),
+        };
+        Overlap {
+            identity,
+            query_start,
+            query_end,
+        }
+    }
+}
+
+// query intervals overlap, a deletion-only interval is a point on query
+fn query_overlaps(a: &Overlap, b: &Overlap) -> bool {
+    a.query_start < b.query_end.max(b.query_start + 1)
+        && b.query_start < a.query_end.max(a.query_start + 1)
+}
+
+// main function of diff, compare two alignments on target intervals of each target-query pair:
+// `only_a`/`only_b`: intervals aligned in one but not to the same query locus in the other
+// `identity`: shared intervals with identity of A and B over their columns in the interval
+// `strand`: shared intervals with discordant strand
+pub fn diff_alignments(
+    blocks_a: Vec<DiffBlock>,
+    blocks_b: Vec<DiffBlock>,
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    let mut pairs: HashMap<(String, String), (Vec<DiffBlock>, Vec<DiffBlock>)> = HashMap::new();
+    for block in blocks_a {
+        let key = (block.target_name.clone(), block.query_name.clone());
+        pairs.entry(key).or_default().0.push(block);
+    }
+    for block in blocks_b {
+        let key = (block.target_name.clone(), block.query_name.clone());
+        pairs.entry(key).or_default().1.push(block);
+    }
+    let mut pairs = pairs.into_iter().collect::<Vec<_>>();
+    pairs.sort_by(|a, b| {
+        natord::compare(&a.0 .0, &b.0 .0).then_with(|| natord::compare(&a.0 .1, &b.0 .1))
+    });
+
+    writer.write_all(
+        b"target_name\ttarget_start\ttarget_end\tquery_name\ttype\tvalue_a\tvalue_b\n",
+    )?;
+    for ((target_name, query_name), (pair_a, pair_b)) in pairs {
+        for line in diff_pair(&pair_a, &pair_b) {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                target_name,
+                line.start,
+                line.end,
+                query_name,
+                line.kind,
+                line.value_a,
+                line.value_b
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// diff blocks of a target-query pair, sorted by position
+fn diff_pair(pair_a: &[DiffBlock], pair_b: &[DiffBlock]) -> Vec<DiffLine> {
+    let lapper_a = build_lapper(pair_a);
+    let lapper_b = build_lapper(pair_b);
+
+    let mut lines = Vec::new();
+    let mut shared = Vec::new();
+    for iv_a in lapper_a.iter() {
+        for iv_b in lapper_b.find(iv_a.start, iv_a.stop) {
+            let (block_a, block_b) = (&pair_a[iv_a.val], &pair_b[iv_b.val]);
+            let start = iv_a.start.max(iv_b.start);
+            let end = iv_a.stop.min(iv_b.stop);
+            // same target aligned to different query loci is not shared
+            let (overlap_a, overlap_b) = (block_a.overlap(start, end), block_b.overlap(start, end));
+            if !query_overlaps(&overlap_a, &overlap_b) {
+                continue;
+            }
+            shared.push((start, end));
+            lines.push(DiffLine {
+                start,
+                end,
+                kind: "identity",
+                value_a: format!("{:.6}", overlap_a.identity),
+                value_b: format!("{:.6}", overlap_b.identity),
+            });
+            if block_a.strand != block_b.strand {
+                lines.push(DiffLine {
+                    start,
+                    end,
+                    kind: "strand",
+                    value_a: block_a.strand.to_string(),
+                    value_b: block_b.strand.to_string(),
+                });
+            }
+        }
+    }
+
+    let shared = merge_intervals(shared);
+    for (kind, this) in [("only_a", &lapper_a), ("only_b", &lapper_b)] {
+        for (start, end) in subtract_ivs(&merged_ivs(this), &shared) {
+            lines.push(DiffLine {
+                start,
+                end,
+                kind,
+                value_a: ".".to_string(),
+                value_b: ".".to_string(),
+            });
+        }
+    }
+    lines.sort_by(|a, b| {
+        (a.start, a.end, a.kind, &a.value_a, &a.value_b)
+            .cmp(&(b.start, b.end, b.kind, &b.value_a, &b.value_b))
+    });
+    lines
+}
+
+fn build_lapper(blocks: &[DiffBlock]) -> Lapper<u64, usize> {
+    Lapper::new(
+        blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| block.start < block.end)
+            .map(|(idx, block)| Iv {
+                start: block.start,
+                stop: block.end,
+                val: idx,
+            })
+            .collect(),
+    )
+}
+
+// sorted and merged intervals
+fn merged_ivs(lapper: &Lapper<u64, usize>) -> Vec<(u64, u64)> {
+    merge_intervals(lapper.iter().map(|iv| (iv.start, iv.stop)).collect())
+}
+
+// intervals in `a` but not in `b`, both are sorted and merged
+fn subtract_ivs(a: &[(u64, u64)], b: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    let mut result = Vec::new();
+    let mut j = 0;
+    for &(start, end) in a {
+        // skip intervals of `b` before this one
+        while j < b.len() && b[j].1 <= start {
+            j += 1;
+        }
+        let mut cur = start;
+        let mut k = j;
+        while k < b.len() && b[k].0 < end {
+            if b[k].0 > cur {
+                result.push((cur, b[k].0));
+            }
+            cur = cur.max(b[k].1);
+            k += 1;
+        }
+        if cur < end {
+            result.push((cur, end));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::paf::PAFReader;
+
+    fn diff(paf_a: &str, paf_b: &str) -> Vec<String> {
+        let blocks_a = diff_blocks(PAFReader::new(paf_a.as_bytes()).records()).unwrap();
+        let blocks_b = diff_blocks(PAFReader::new(paf_b.as_bytes()).records()).unwrap();
+        let mut out = Vec::new();
+        diff_alignments(blocks_a, blocks_b, &mut out).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn same_target_different_query_locus() {
+        let paf_a = "q\t1000\t0\t100\t+\tt\t1000\t0\t100\t100\t100\t60\tcg:Z:100=\n";
+        let paf_b = "q\t1000\t500\t600\t+\tt\t1000\t0\t100\t100\t100\t60\tcg:Z:100=\n";
+        assert_eq!(
+            diff(paf_a, paf_b),
+            ["t\t0\t100\tq\tonly_a\t.\t.", "t\t0\t100\tq\tonly_b\t.\t."]
+        );
+    }
+
+    #[test]
+    fn identity_of_overlapping_columns() {
+        let paf_a = "q\t1000\t0\t100\t+\tt\t1000\t0\t100\t50\t100\t60\tcg:Z:50=50X\n";
+        let paf_b = "q\t1000\t50\t100\t+\tt\t1000\t50\t100\t50\t50\t60\tcg:Z:50=\n";
+        assert_eq!(
+            diff(paf_a, paf_b),
+            [
+                "t\t0\t50\tq\tonly_a\t.\t.",
+                "t\t50\t100\tq\tidentity\t0.000000\t1.000000"
+            ]
+        );
+        // inverted alignment of the same locus is shared with discordant strand
+        let paf_b = "q\t1000\t0\t100\t-\tt\t1000\t0\t100\t100\t100\t60\tcg:Z:100=\n";
+        assert_eq!(
+            diff(paf_a, paf_b),
+            [
+                "t\t0\t100\tq\tidentity\t0.500000\t1.000000",
+                "t\t0\t100\tq\tstrand\t+\t-"
+            ]
+        );
+    }
+}
@@ -3,17 +3,22 @@ use crate::{
     parser::{
         cigar::{parse_cigar_to_base_plotdata, parse_maf_to_base_plotdata},
         common::{AlignRecord, DotplotMode, DotplotoutFormat, FileFormat, Strand},
-        maf::MAFReader,
+        maf::{MAFReader, MAFRecord},
         paf::PAFReader,
+        region::GenomeRegion,
+    },
+    tools::{
+        index::MafIndex,
+        mafextra::{build_lappers, extract_region_records},
     },
 };
 use minijinja::{context, Environment};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::io::{BufRead, Read, Write};
 
-const DOTPLOT_SPEC: &str = r#"
+pub(crate) const DOTPLOT_SPEC: &str = r#"
 {
     "$schema": "https://vega.github.io/schema/vega-lite/v5.json",
     "height": 800,
@@ -117,18 +122,50 @@ const VEGA_TEMP: &str = r#"<head>
 
 <body>
     <div id="view" style="display: flex; justify-content: space-evenly;"></div>
+    <div style="font-family: monospace; margin: 1em;">
+        <div>Shift + drag to select a region, double click to clear</div>
+        <pre id="region-cmd"></pre>
+        <button id="region-copy" disabled>Copy</button>
+    </div>
     <script>
         const spec = {{ vl_json | safe }};
+        const source = {{ source_json | safe }};
+        const cmdBox = document.getElementById('region-cmd');
+        const copyBtn = document.getElementById('region-copy');
+        // facet values are kept in unit names of selection tuples
+        const facetOf = (unit, channel) => {
+            const m = unit.match(new RegExp('__facet_' + channel + '_(.*?)(__facet_|$)'));
+            return m ? m[1] : null;
+        };
+        const span = (range) => Math.max(0, Math.floor(Math.min(...range))) + '-' + Math.ceil(Math.max(...range));
+        const regionCmd = (tuple) => {
+            const ranges = {};
+            tuple.fields.forEach((f, i) => { ranges[f.field] = tuple.values[i]; });
+            const target = facetOf(tuple.unit, 'column') + ':' + span(ranges.ref_start);
+            const query = facetOf(tuple.unit, 'row') + ':' + span(ranges.query_start);
+            const cmd = source.format === 'maf'
+                ? 'wgatools maf-ext ' + source.input + ' -r ' + target
+                : 'wgatools paf2maf ' + source.input + ' -g target.fa -q query.fa --target-region ' + target;
+            return '# target ' + target + ', query ' + query + '\n' + cmd;
+        };
         vegaEmbed(
             '#view',
             spec
-        );
+        ).then((result) => {
+            result.view.addDataListener('region_store', (name, tuples) => {
+                cmdBox.textContent = tuples.map(regionCmd).join('\n');
+                copyBtn.disabled = tuples.length === 0;
+            });
+        });
+        copyBtn.addEventListener('click', () => {
+            navigator.clipboard.writeText(cmdBox.textContent.split('\n').filter((l) => !l.startsWith('#')).join('\n'));
+        });
     </script>
 </body>
 "#;
 
 #[derive(Debug, Deserialize, Serialize)]
-struct AllPlotdata {
+pub(crate) struct AllPlotdata {
     ref_start: u64,
     ref_end: u64,
     query_start: u64,
@@ -149,6 +186,125 @@ pub struct BasePlotdata {
     pub query_chro: String,
 }
 
+/// Target/query regions to clip segments to, None for whole genome
+#[derive(Debug, Default)]
+pub struct PlotWindow {
+    pub target: Option<GenomeRegion>,
+    pub query: Option<GenomeRegion>,
+}
+
+impl PlotWindow {
+    pub fn is_empty(&self) -> bool {
+        self.target.is_none() && self.query.is_none()
+    }
+
+    // if a record overlaps the window
+    fn hits<T: AlignRecord>(&self, rec: &T) -> bool {
+        let hit = |region: &Option<GenomeRegion>, name: &str, start: u64, end: u64| match region {
+            Some(region) => region.name == name && start <= region.end && end >= region.start,
+            None => true,
+        };
+        hit(
+            &self.target,
+            rec.target_name(),
+            rec.target_start(),
+            rec.target_end(),
+        ) && hit(
+            &self.query,
+            rec.query_name(),
+            rec.query_start(),
+            rec.query_end(),
+        )
+    }
+
+    // clip a segment to the window, false if it's out of the window
+    fn clip<S: PlotSegment>(&self, seg: &mut S) -> bool {
+        let (ref_chro, query_chro, [ref_start, ref_end, query_start, query_end]) =
+            seg.segment_mut();
+        let mut coords = [
+            *ref_start as f64,
+            *ref_end as f64,
+            *query_start as f64,
+            *query_end as f64,
+        ];
+        if let Some(region) = &self.target {
+            if region.name != ref_chro || !clip_axis(&mut coords, 0, region) {
+                return false;
+            }
+        }
+        if let Some(region) = &self.query {
+            if region.name != query_chro || !clip_axis(&mut coords, 2, region) {
+                return false;
+            }
+        }
+        *ref_start = coords[0].round() as u64;
+        *ref_end = coords[1].round() as u64;
+        *query_start = coords[2].round() as u64;
+        *query_end = coords[3].round() as u64;
+        true
+    }
+}
+
+// clip the axis starting at `axis` (0 for ref, 2 for query) of a segment
+// [ref_start, ref_end, query_start, query_end] to the region, the other
+// axis is interpolated linearly
+fn clip_axis(coords: &mut [f64; 4], axis: usize, region: &GenomeRegion) -> bool {
+    let other = 2 - axis;
+    let (a0, a1) = (coords[axis], coords[axis + 1]);
+    let (b0, b1) = (coords[other], coords[other + 1]);
+    let (lo, hi) = (region.start as f64, region.end as f64);
+    if a0.max(a1) < lo || a0.min(a1) > hi {
+        return false;
+    }
+    // gap segment has no length on this axis
+    if a0 == a1 {
+        return true;
+    }
+    let interpolate = |a: f64| b0 + (b1 - b0) * (a - a0) / (a1 - a0);
+    let (new_a0, new_a1) = (a0.clamp(lo, hi), a1.clamp(lo, hi));
+    coords[axis] = new_a0;
+    coords[axis + 1] = new_a1;
+    coords[other] = interpolate(new_a0);
+    coords[other + 1] = interpolate(new_a1);
+    true
+}
+
+/// A segment in dotplot with its coordinates
+trait PlotSegment {
+    // (ref_chro, query_chro, [ref_start, ref_end, query_start, query_end])
+    fn segment_mut(&mut self) -> (&str, &str, [&mut u64; 4]);
+}
+
+impl PlotSegment for AllPlotdata {
+    fn segment_mut(&mut self) -> (&str, &str, [&mut u64; 4]) {
+        (
+            &self.ref_chro,
+            &self.query_chro,
+            [
+                &mut self.ref_start,
+                &mut self.ref_end,
+                &mut self.query_start,
+                &mut self.query_end,
+            ],
+        )
+    }
+}
+
+impl PlotSegment for BasePlotdata {
+    fn segment_mut(&mut self) -> (&str, &str, [&mut u64; 4]) {
+        (
+            &self.ref_chro,
+            &self.query_chro,
+            [
+                &mut self.ref_start,
+                &mut self.ref_end,
+                &mut self.query_start,
+                &mut self.query_end,
+            ],
+        )
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn dotplot(
     reader: Box<dyn BufRead + Send>,
@@ -159,42 +315,83 @@ pub fn dotplot(
     no_identity: bool,
     skip_cutoff: usize,
     query_name: Option<&str>,
+    window: &PlotWindow,
+    maf_index: Option<(&str, &MafIndex)>,
+    input: Option<&str>,
 ) -> Result<(), WGAError> {
     // init vega spec
     let mut vega_spec: Value = serde_json::from_str(DOTPLOT_SPEC)?;
+    // input of region commands in HTML, placeholder for STDIN
+    let source = match format {
+        FileFormat::Maf => json!({"format": "maf", "input": input.unwrap_or("input.maf")}),
+        _ => json!({"format": "paf", "input": input.unwrap_or("input.paf")}),
+    };
+
+    // only seek blocks in target region if MAF index is available
+    let region_records = match (format, maf_index, &window.target) {
+        (FileFormat::Maf, Some((maf_path, mafindex)), Some(region)) => Some(
+            extract_region_records(maf_path, &build_lappers(mafindex), region)?,
+        ),
+        _ => None,
+    };
 
     // match mode to generate data
     match mode {
         DotplotMode::Overview => {
-            let pair_stat_vec = match format {
-                FileFormat::Maf => {
-                    generate_maf_data(MAFReader::new(reader)?, no_identity, query_name)?
+            let mut pair_stat_vec = match (format, region_records) {
+                (FileFormat::Maf, Some(records)) => {
+                    generate_maf_data(records.into_iter().map(Ok), no_identity, query_name, window)?
+                }
+                (FileFormat::Maf, None) => generate_maf_data(
+                    MAFReader::new(reader)?.records(),
+                    no_identity,
+                    query_name,
+                    window,
+                )?,
+                (FileFormat::Paf, _) => {
+                    generate_paf_data(PAFReader::new(reader), no_identity, window)?
                 }
-                FileFormat::Paf => generate_paf_data(PAFReader::new(reader), no_identity)?,
                 _ => {
-                    return Err(WGAError::Other(anyhow::anyhow!(
-                        "Only support MAF and PAF format"
-                    )));
+                    return Err(WGAError::InvalidArgs(
+                        "Only support MAF and PAF format".to_string(),
+                    ));
                 }
             };
-            render_output(pair_stat_vec, writer, out_format, vega_spec)?;
+            if !window.is_empty() {
+                pair_stat_vec.retain_mut(|data| window.clip(data));
+            }
+            render_output(pair_stat_vec, writer, out_format, vega_spec, &source)?;
         }
         DotplotMode::BaseLevel => {
-            let pair_base_plot_vec = match format {
-                FileFormat::Maf => {
-                    generate_maf_basedata(MAFReader::new(reader)?, skip_cutoff, query_name)?
+            let pair_base_plot_vec = match (format, region_records) {
+                (FileFormat::Maf, Some(records)) => generate_maf_basedata(
+                    records.into_iter().map(Ok),
+                    skip_cutoff,
+                    query_name,
+                    window,
+                )?,
+                (FileFormat::Maf, None) => generate_maf_basedata(
+                    MAFReader::new(reader)?.records(),
+                    skip_cutoff,
+                    query_name,
+                    window,
+                )?,
+                (FileFormat::Paf, _) => {
+                    generate_paf_basedata(PAFReader::new(reader), skip_cutoff, window)?
                 }
-                FileFormat::Paf => generate_paf_basedata(PAFReader::new(reader), skip_cutoff)?,
                 _ => {
-                    return Err(WGAError::Other(anyhow::anyhow!(
-                        "Only support MAF and PAF format"
-                    )));
+                    return Err(WGAError::InvalidArgs(
+                        "Only support MAF and PAF format".to_string(),
+                    ));
                 }
             };
-            let final_base_plotdata = pair_base_plot_vec
+            let mut final_base_plotdata = pair_base_plot_vec
                 .into_par_iter()
                 .flatten()
                 .collect::<Vec<_>>();
+            if !window.is_empty() {
+                final_base_plotdata.retain_mut(|data| window.clip(data));
+            }
 
             // change the vega spec
             vega_spec["encoding"]["x"]["scale"]["zero"] = false.into();
@@ -204,18 +401,40 @@ pub fn dotplot(
             vega_spec["encoding"]["color"]["type"] = "nominal".into();
             vega_spec["encoding"]["tooltip"][2]["field"] = "cigar".into();
 
-            render_output(final_base_plotdata, writer, out_format, vega_spec)?;
+            render_output(final_base_plotdata, writer, out_format, vega_spec, &source)?;
         }
     }
     Ok(())
 }
 
+// shift + drag selects a region for commands, so zoom only pans without shift
+fn add_region_selection(vega_spec: &mut Value) {
+    vega_spec["params"][0]["select"] = json!({
+        "type": "interval",
+        "translate": "[pointerdown[!event.shiftKey], window:pointerup] > window:pointermove!"
+    });
+    if let Some(params) = vega_spec["params"].as_array_mut() {
+        params.push(json!({
+            "name": "region",
+            "select": {
+                "type": "interval",
+                "encodings": ["x", "y"],
+                "on": "[pointerdown[event.shiftKey], window:pointerup] > window:pointermove!",
+                "translate": false,
+                "zoom": false,
+                "clear": "dblclick"
+            }
+        }));
+    }
+}
+
 /// render data output
 fn render_output<S: Serialize>(
     data: Vec<S>,
     writer: &mut dyn Write,
     format: DotplotoutFormat,
     mut vega_spec: Value,
+    source: &Value,
 ) -> Result<(), WGAError> {
     match format {
         DotplotoutFormat::Json => {
@@ -227,8 +446,11 @@ fn render_output<S: Serialize>(
             env.add_template("vega", VEGA_TEMP)?;
             let template = env.get_template("vega")?;
             vega_spec["data"]["values"] = serde_json::to_value(&data)?;
+            add_region_selection(&mut vega_spec);
             let vl_json = serde_json::to_string(&vega_spec)?;
-            let rendered = template.render(context! { vl_json => vl_json })?;
+            let source_json = serde_json::to_string(source)?;
+            let rendered =
+                template.render(context! { vl_json => vl_json, source_json => source_json })?;
             writeln!(writer, "{}", rendered)?;
         }
         DotplotoutFormat::Csv => {
@@ -243,20 +465,22 @@ fn render_output<S: Serialize>(
 }
 
 /// Generate Plotdatas from MAF records
-fn generate_maf_data<R: Read + Send>(
-    mut reader: MAFReader<R>,
+fn generate_maf_data<I: Iterator<Item = Result<MAFRecord, WGAError>> + Send>(
+    records: I,
     no_identity: bool,
     query_name: Option<&str>,
+    window: &PlotWindow,
 ) -> Result<Vec<AllPlotdata>, WGAError> {
-    let pair_stat_vec = reader
-        .records()
+    let pair_stat_vec = records
         .par_bridge()
         .try_fold(Vec::new, |mut acc, rec| {
             let mut rec = rec?;
             if let Some(qname) = query_name {
                 rec.set_query_idx_byname(qname)?;
             }
-            acc.push(rec_dot_data(&rec, no_identity)?);
+            if window.hits(&rec) {
+                acc.push(rec_dot_data(&rec, no_identity)?);
+            }
             Ok::<Vec<AllPlotdata>, WGAError>(acc)
         })
         .try_reduce(Vec::new, |mut acc, mut vec| {
@@ -270,12 +494,16 @@ fn generate_maf_data<R: Read + Send>(
 fn generate_paf_data<R: Read + Send>(
     mut reader: PAFReader<R>,
     no_identity: bool,
+    window: &PlotWindow,
 ) -> Result<Vec<AllPlotdata>, WGAError> {
     let pair_stat_vec = reader
         .records()
         .par_bridge()
         .try_fold(Vec::new, |mut acc, rec| {
-            acc.push(rec_dot_data(&rec?, no_identity)?);
+            let rec = rec?;
+            if window.hits(&rec) {
+                acc.push(rec_dot_data(&rec, no_identity)?);
+            }
             Ok::<Vec<AllPlotdata>, WGAError>(acc)
         })
         .try_reduce(Vec::new, |mut acc, mut vec| {
@@ -289,12 +517,16 @@ fn generate_paf_data<R: Read + Send>(
 fn generate_paf_basedata<R: Read + Send>(
     mut reader: PAFReader<R>,
     cutoff: usize,
+    window: &PlotWindow,
 ) -> Result<Vec<Vec<BasePlotdata>>, WGAError> {
     let pair_stat_vec = reader
         .records()
         .par_bridge()
         .try_fold(Vec::new, |mut acc, rec| {
-            acc.push(parse_cigar_to_base_plotdata(&rec?, cutoff)?);
+            let rec = rec?;
+            if window.hits(&rec) {
+                acc.push(parse_cigar_to_base_plotdata(&rec, cutoff)?);
+            }
             Ok::<Vec<Vec<BasePlotdata>>, WGAError>(acc)
         })
         .try_reduce(Vec::new, |mut acc, mut vec| {
@@ -306,20 +538,22 @@ fn generate_paf_basedata<R: Read + Send>(
 }
 
 /// Generate BasePlotdatas from MAF records
-fn generate_maf_basedata<R: Read + Send>(
-    mut reader: MAFReader<R>,
+fn generate_maf_basedata<I: Iterator<Item = Result<MAFRecord, WGAError>> + Send>(
+    records: I,
     cutoff: usize,
     query_name: Option<&str>,
+    window: &PlotWindow,
 ) -> Result<Vec<Vec<BasePlotdata>>, WGAError> {
-    let pair_stat_vec = reader
-        .records()
+    let pair_stat_vec = records
         .par_bridge()
         .try_fold(Vec::new, |mut acc, rec| {
             let mut rec = rec?;
             if let Some(qname) = query_name {
                 rec.set_query_idx_byname(qname)?;
             }
-            acc.push(parse_maf_to_base_plotdata(&rec, cutoff)?);
+            if window.hits(&rec) {
+                acc.push(parse_maf_to_base_plotdata(&rec, cutoff)?);
+            }
             Ok::<Vec<Vec<BasePlotdata>>, WGAError>(acc)
         })
         .try_reduce(Vec::new, |mut acc, mut vec| {
@@ -331,7 +565,10 @@ fn generate_maf_basedata<R: Read + Send>(
 }
 
 // stat a record to generate a Plotdata
-fn rec_dot_data<T: AlignRecord>(rec: &T, no_identity: bool) -> Result<AllPlotdata, WGAError> {
+pub(crate) fn rec_dot_data<T: AlignRecord>(
+    rec: &T,
+    no_identity: bool,
+) -> Result<AllPlotdata, WGAError> {
     // get pair
     let ref_start = rec.target_start();
     let mut query_start = rec.query_start();
@@ -1,16 +1,23 @@
 use crate::{
     errors::WGAError,
+    log::record_skipped,
     parser::{
         chain::ChainReader,
-        common::AlignRecord,
-        maf::{MAFReader, MAFWriter},
-        paf::PAFReader,
+        cigar::parse_cigar_to_units,
+        common::{AlignRecord, BestHit, MaskMode, Strand},
+        maf::{MAFReader, MAFRecord, MAFWriter},
+        paf::{paf_writer, PAFReader, PafRecord},
+        region::read_bed_regions,
     },
+    utils::union_length,
 };
+use log::info;
 use rayon::prelude::*;
+use rust_lapper::{Interval, Lapper};
+use std::cmp::Ordering;
 use std::{
     collections::HashMap,
-    io::{Read, Write},
+    io::{BufRead, Read, Write},
 };
 
 // filter chain
@@ -19,20 +26,27 @@ pub fn filter_chain<R: Read + Send>(
     writer: &mut dyn Write,
     min_block_size: u64,
     min_query_size: u64,
+    min_chain_score: f64,
+    min_target_span: u64,
+    mask: Option<&Mask>,
 ) -> Result<(), WGAError> {
     for rec in reader.records()? {
         let rec = rec?;
+        // chain can only be dropped by mask
+        if mask.is_some_and(|mask| mask.overlaps(&rec)) {
+            record_skipped();
+            continue;
+        }
+        // filter by native chain quality: score and target span
+        let target_span = rec.target_end() - rec.target_start();
+        if rec.header.score() < min_chain_score || target_span < min_target_span {
+            record_skipped();
+            continue;
+        }
         let rec = filter_alignrec(&rec, min_block_size, min_query_size)?;
         // just write the record
         if let Some(rec) = rec {
-            let chainheader = &rec.header;
-            let chainblocks = &rec.lines;
-            writer.write_all(format!("{}", chainheader).as_bytes())?;
-            for dataline in chainblocks {
-                writer.write_all(format!("{}", dataline).as_bytes())?;
-            }
-            // additional newline for standard chain format
-            writer.write_all(b"\n\n")?;
+            rec.write_to(writer)?;
         }
     }
     Ok(())
@@ -44,49 +58,254 @@ pub fn filter_paf<R: Read + Send>(
     writer: &mut dyn Write,
     min_block_size: u64,
     min_query_size: u64,
+    mask: Option<&Mask>,
+    best_hit: Option<BestHit>,
 ) -> Result<(), WGAError> {
-    let mut pafwtr = csv::WriterBuilder::new()
-        .flexible(true)
-        .delimiter(b'\t')
-        .has_headers(false)
-        .from_writer(writer);
+    let mut pafwtr = paf_writer(writer);
+    // best hits need all records, keep the filtered ones in input order
+    let mut recs = Vec::new();
     for rec in reader.records() {
         let rec = rec?;
-        let rec = filter_alignrec(&rec, min_block_size, min_query_size)?;
-        // just write the record
-        if let Some(rec) = rec {
-            pafwtr.serialize(rec)?;
+        let masked = match mask {
+            Some(mask) => mask.apply_paf(rec)?,
+            None => vec![rec],
+        };
+        for rec in masked {
+            if filter_alignrec(&rec, min_block_size, min_query_size)?.is_none() {
+                continue;
+            }
+            // just write the record
+            match best_hit {
+                Some(_) => recs.push(rec),
+                None => pafwtr.serialize(rec)?,
+            }
+        }
+    }
+    if let Some(best_hit) = best_hit {
+        let keep = best_paf_hits(&recs, best_hit);
+        info!(
+            "kept {} of {} records as best hits on {}",
+            keep.iter().filter(|&&k| k).count(),
+            recs.len(),
+            best_hit
+        );
+        for (rec, keep) in recs.iter().zip(keep) {
+            if keep {
+                pafwtr.serialize(rec)?;
+            } else {
+                record_skipped();
+            }
         }
     }
     Ok(())
 }
 
+// a record is not a best hit if more than this fraction of its interval is covered by better ones
+const BEST_HIT_MAX_OVERLAP: f64 = 0.5;
+
+// interval of a PAF record on query or target
+type PafSide = fn(&PafRecord) -> (&str, u64, u64);
+
+fn paf_query_iv(rec: &PafRecord) -> (&str, u64, u64) {
+    (&rec.query_name, rec.query_start, rec.query_end)
+}
+
+fn paf_target_iv(rec: &PafRecord) -> (&str, u64, u64) {
+    (&rec.target_name, rec.target_start, rec.target_end)
+}
+
+/// mark best hits: records are ranked by identity × alignment block length, then input order,
+/// and a record is dropped if its interval on query (or target, or either of them for `both`)
+/// is mostly covered by better records kept on the same sequence
+fn best_paf_hits(recs: &[PafRecord], best_hit: BestHit) -> Vec<bool> {
+    let sides: Vec<PafSide> = match best_hit {
+        BestHit::Query => vec![paf_query_iv],
+        BestHit::Target => vec![paf_target_iv],
+        BestHit::Both => vec![paf_query_iv, paf_target_iv],
+    };
+
+    // interval trees of records on each sequence of each side
+    let lappers = sides
+        .iter()
+        .map(|side| {
+            let mut ivs: HashMap<&str, Vec<Interval<u64, usize>>> = HashMap::new();
+            for (idx, rec) in recs.iter().enumerate() {
+                let (name, start, stop) = side(rec);
+                ivs.entry(name).or_default().push(Interval {
+                    start,
+                    stop,
+                    val: idx,
+                });
+            }
+            ivs.into_iter()
+                .map(|(name, ivs)| (name, Lapper::new(ivs)))
+                .collect::<HashMap<_, _>>()
+        })
+        .collect::<Vec<_>>();
+
+    // identity × alignment block length is the number of matches,
+    // the one with higher identity goes first if equal
+    let identity = |rec: &PafRecord| match rec.block_length {
+        0 => 0.0,
+        len => rec.matches as f64 / len as f64,
+    };
+    let mut ranked = (0..recs.len()).collect::<Vec<_>>();
+    ranked.sort_by(|&a, &b| {
+        recs[b]
+            .matches
+            .cmp(&recs[a].matches)
+            .then(
+                identity(&recs[b])
+                    .partial_cmp(&identity(&recs[a]))
+                    .unwrap_or(Ordering::Equal),
+            )
+            .then(a.cmp(&b))
+    });
+
+    let mut keep = vec![false; recs.len()];
+    for idx in ranked {
+        let covered = sides.iter().zip(&lappers).any(|(side, lapper)| {
+            let (name, start, end) = side(&recs[idx]);
+            let overlaps = lapper[name]
+                .find(start, end)
+                .filter(|iv| keep[iv.val])
+                .map(|iv| (iv.start.max(start), iv.stop.min(end)))
+                .collect::<Vec<_>>();
+            union_length(overlaps) as f64 > BEST_HIT_MAX_OVERLAP * (end - start) as f64
+        });
+        keep[idx] = !covered;
+    }
+    keep
+}
+
 // filter maf
 pub fn filter_maf<R: Read + Send>(
     mut reader: MAFReader<R>,
     writer: &mut dyn Write,
     min_block_size: u64,
     min_query_size: u64,
+    dedup: bool,
+    mask: Option<&Mask>,
 ) -> Result<(), WGAError> {
     // init a MAFWriter
     let mut mafwtr = MAFWriter::new(writer);
     // write header
     let header = format!(
-        "#maf version=1.6 filter=blocksize>={} querysize>={}",
-        min_block_size, min_query_size
+        "#maf version=1.6 filter=blocksize>={} querysize>={}{}{}",
+        min_block_size,
+        min_query_size,
+        if dedup { " dedup" } else { "" },
+        match mask {
+            Some(mask) => format!(" mask={}", mask.mode),
+            None => String::new(),
+        }
     );
     mafwtr.write_header(header)?;
-    for rec in reader.records() {
-        let rec = rec?;
-        let rec = filter_alignrec(&rec, min_block_size, min_query_size)?;
-        // just write the record
-        if let Some(rec) = rec {
-            mafwtr.write_record(rec)?;
+    // records after masking
+    let records = reader.records().map(|rec| match mask {
+        Some(mask) => mask.apply_maf(rec?),
+        None => Ok(vec![rec?]),
+    });
+    if dedup {
+        // dedup needs all blocks, keep the filtered ones in input order
+        let mut recs = Vec::new();
+        for masked in records {
+            for rec in masked? {
+                if filter_alignrec(&rec, min_block_size, min_query_size)?.is_some() {
+                    recs.push(rec);
+                }
+            }
+        }
+        let keep = dedup_maf_blocks(&recs)?;
+        for (rec, keep) in recs.iter().zip(keep) {
+            if keep {
+                mafwtr.write_record(rec)?;
+            } else {
+                record_skipped();
+            }
+        }
+        return Ok(());
+    }
+    for masked in records {
+        for rec in masked? {
+            let rec = filter_alignrec(&rec, min_block_size, min_query_size)?;
+            // just write the record
+            if let Some(rec) = rec {
+                mafwtr.write_record(rec)?;
+            }
         }
     }
     Ok(())
 }
 
+/// mark duplicated blocks: for the same target-query pair, a block whose target
+/// interval is identical to or contained in (or contains) a better block is dropped.
+/// blocks are ranked by identity, then aligned length, then input order
+fn dedup_maf_blocks(recs: &[MAFRecord]) -> Result<Vec<bool>, WGAError> {
+    // group block index by target-query pair
+    let mut groups: HashMap<(&str, &str), Vec<usize>> = HashMap::new();
+    for (idx, rec) in recs.iter().enumerate() {
+        groups
+            .entry((rec.target_name(), rec.query_name()))
+            .or_default()
+            .push(idx);
+    }
+
+    // identity and aligned length of each block
+    let scores = recs
+        .par_iter()
+        .map(|rec| {
+            let stat = rec.get_stat()?;
+            let identity = if stat.aligned_size == 0 {
+                0.0
+            } else {
+                stat.matched as f64 / stat.aligned_size as f64
+            };
+            Ok((identity, rec.target_align_size()))
+        })
+        .collect::<Result<Vec<(f64, u64)>, WGAError>>()?;
+
+    let mut keep = vec![true; recs.len()];
+    for idxs in groups.values() {
+        if idxs.len() < 2 {
+            continue;
+        }
+        let lapper = Lapper::new(
+            idxs.iter()
+                .map(|&idx| Interval {
+                    start: recs[idx].target_start(),
+                    stop: recs[idx].target_end(),
+                    val: idx,
+                })
+                .collect::<Vec<Interval<u64, usize>>>(),
+        );
+        let mut ranked = idxs.clone();
+        ranked.sort_by(|&a, &b| {
+            scores[b]
+                .0
+                .partial_cmp(&scores[a].0)
+                .unwrap_or(Ordering::Equal)
+                .then(scores[b].1.cmp(&scores[a].1))
+                .then(a.cmp(&b))
+        });
+        let mut kept = vec![false; recs.len()];
+        for idx in ranked {
+            let (start, end) = (recs[idx].target_start(), recs[idx].target_end());
+            let dup = lapper.find(start, end).any(|iv| {
+                kept[iv.val]
+                    && ((iv.start <= start && iv.stop >= end)
+                        || (start <= iv.start && end >= iv.stop))
+            });
+            if dup {
+                keep[idx] = false;
+            } else {
+                kept[idx] = true;
+            }
+        }
+    }
+    Ok(keep)
+}
+
 // filter record, return Option
 fn filter_alignrec<T: AlignRecord>(
     rec: &T,
@@ -98,6 +317,7 @@ fn filter_alignrec<T: AlignRecord>(
 
     // if in condition, return None
     if (block_length < min_block_size) | (query_length < min_query_size) {
+        record_skipped();
         return Ok(None);
     }
 
@@ -141,11 +361,7 @@ pub fn filter_paf_align_pair<R: Read + Send>(
             },
         )?;
 
-    let mut pafwtr = csv::WriterBuilder::new()
-        .flexible(true)
-        .delimiter(b'\t')
-        .has_headers(false)
-        .from_writer(writer);
+    let mut pafwtr = paf_writer(writer);
     // filter by align_size_sum
     for rec in all_recs {
         let q_name = rec.query_name().to_string();
@@ -154,7 +370,402 @@ pub fn filter_paf_align_pair<R: Read + Send>(
         let align_size_sum = align_size_sum_map.get(&key).unwrap();
         if *align_size_sum >= filt_align_size {
             pafwtr.serialize(rec)?;
+        } else {
+            record_skipped();
         }
     }
     Ok(())
 }
+
+/// Default of `--split-fraction`: a query is split if the best target takes less
+/// than this fraction of its aligned size
+pub const SPLIT_BEST_FRACTION: f64 = 0.9;
+/// Default of `--dup-redundancy`: a query is duplicated if its aligned bases are
+/// covered this many times on average
+pub const DUP_REDUNDANCY: f64 = 1.5;
+
+/// Alignments of a query-target pair
+#[derive(Debug, Default)]
+struct PairAlign {
+    query_length: u64,
+    align_size: u64,
+    query_ivs: Vec<(u64, u64)>,
+}
+
+// report query coverage and redundancy for all-to-all alignment paf,
+// pairs with align size less than `filt_align_size` are ignored
+pub fn report_paf_align_pair<R: Read + Send>(
+    mut reader: PAFReader<R>,
+    writer: &mut dyn Write,
+    filt_align_size: u64,
+    split_fraction: f64,
+    dup_redundancy: f64,
+) -> Result<(), WGAError> {
+    // parallel read and groupby
+    let pair_aligns = reader
+        .records()
+        .par_bridge()
+        .try_fold(
+            HashMap::new,
+            |mut acc: HashMap<(String, String), PairAlign>, rec| {
+                let rec = rec?;
+                let pair = acc
+                    .entry((rec.query_name.clone(), rec.target_name.clone()))
+                    .or_default();
+                pair.query_length = rec.query_length;
+                pair.align_size += rec.query_end.saturating_sub(rec.query_start);
+                pair.query_ivs.push((rec.query_start, rec.query_end));
+                Ok::<_, WGAError>(acc)
+            },
+        )
+        .try_reduce(HashMap::new, |mut acc, map| {
+            for (key, mut pair) in map {
+                let entry = acc.entry(key).or_default();
+                entry.query_length = pair.query_length;
+                entry.align_size += pair.align_size;
+                entry.query_ivs.append(&mut pair.query_ivs);
+            }
+            Ok(acc)
+        })?;
+
+    // groupby query
+    let mut queries: HashMap<String, Vec<(String, PairAlign)>> = HashMap::new();
+    for ((q_name, t_name), pair) in pair_aligns {
+        if pair.align_size >= filt_align_size {
+            queries.entry(q_name).or_default().push((t_name, pair));
+        }
+    }
+    let mut queries = queries.into_iter().collect::<Vec<_>>();
+    queries.sort_by(|a, b| natord::compare(&a.0, &b.0));
+
+    writer.write_all(
+        b"query_name\tquery_length\taligned_length\tn_targets\tbest_target\tbest_fraction\tredundancy\tflag\n",
+    )?;
+    for (q_name, mut pairs) in queries {
+        // best target by align size, then name
+        pairs.sort_by(|a, b| {
+            b.1.align_size
+                .cmp(&a.1.align_size)
+                .then_with(|| natord::compare(&a.0, &b.0))
+        });
+        let total_size = pairs.iter().map(|(_, pair)| pair.align_size).sum::<u64>();
+        let query_ivs = pairs
+            .iter()
+            .flat_map(|(_, pair)| pair.query_ivs.iter().copied())
+            .collect::<Vec<_>>();
+        let aligned_length = union_length(query_ivs);
+        let (best_target, best_pair) = &pairs[0];
+        let best_fraction = match total_size {
+            0 => 0.0,
+            n => best_pair.align_size as f64 / n as f64,
+        };
+        let redundancy = match aligned_length {
+            0 => 0.0,
+            n => total_size as f64 / n as f64,
+        };
+
+        let mut flags = Vec::new();
+        if pairs.len() > 1 && best_fraction < split_fraction {
+            flags.push("split");
+        }
+        if redundancy >= dup_redundancy {
+            flags.push("duplicated");
+        }
+        let flag = match flags.is_empty() {
+            true => ".".to_string(),
+            false => flags.join(","),
+        };
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{:.6}\t{:.6}\t{}",
+            q_name,
+            best_pair.query_length,
+            aligned_length,
+            pairs.len(),
+            best_target,
+            best_fraction,
+            redundancy,
+            flag
+        )?;
+    }
+    Ok(())
+}
+
+/// Masked regions on target from BED, overlapping ones are merged
+pub struct Mask {
+    regions: HashMap<String, Lapper<u64, ()>>,
+    mode: MaskMode,
+}
+
+impl Mask {
+    /// Read masked regions from BED, `track`/`browser`/`#` lines are skipped
+    pub fn from_bed<R: BufRead>(reader: R, mode: MaskMode) -> Result<Self, WGAError> {
+        let mut intervals: HashMap<String, Vec<Interval<u64, ()>>> = HashMap::new();
+        for region in read_bed_regions(reader)? {
+            if region.start < region.end {
+                intervals.entry(region.name).or_default().push(Interval {
+                    start: region.start,
+                    stop: region.end,
+                    val: (),
+                });
+            }
+        }
+        let regions = intervals
+            .into_iter()
+            .map(|(name, ivs)| {
+                let mut lapper = Lapper::new(ivs);
+                lapper.merge_overlaps();
+                (name, lapper)
+            })
+            .collect();
+        Ok(Mask { regions, mode })
+    }
+
+    // masked regions overlapping target range of record, clipped to the range
+    fn masked<T: AlignRecord>(&self, rec: &T) -> Vec<(u64, u64)> {
+        let (start, end) = (rec.target_start(), rec.target_end());
+        match self.regions.get(rec.target_name()) {
+            Some(lapper) => lapper
+                .find(start, end)
+                .map(|iv| (iv.start.max(start), iv.stop.min(end)))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn overlaps<T: AlignRecord>(&self, rec: &T) -> bool {
+        !self.masked(rec).is_empty()
+    }
+
+    // unmasked target ranges of record between masked regions
+    fn unmasked<T: AlignRecord>(&self, rec: &T, masked: &[(u64, u64)]) -> Vec<(u64, u64)> {
+        let mut ranges = Vec::new();
+        let mut pos = rec.target_start();
+        for &(start, end) in masked {
+            if start > pos {
+                ranges.push((pos, start));
+            }
+            pos = pos.max(end);
+        }
+        if pos < rec.target_end() {
+            ranges.push((pos, rec.target_end()));
+        }
+        ranges
+    }
+
+    /// Apply mask to a MAF record, a dropped or fully masked record gives nothing
+    fn apply_maf(&self, mut rec: MAFRecord) -> Result<Vec<MAFRecord>, WGAError> {
+        let masked = self.masked(&rec);
+        if masked.is_empty() {
+            return Ok(vec![rec]);
+        }
+        // column of each target base
+        let t_start = rec.target_start();
+        let t_cols = rec.slines[0]
+            .seq
+            .bytes()
+            .enumerate()
+            .filter(|(_, base)| *base != b'-')
+            .map(|(col, _)| col)
+            .collect::<Vec<_>>();
+        // columns from the first to the last target base of range
+        let cols = |(start, end): (u64, u64)| {
+            (
+                t_cols[(start - t_start) as usize],
+                t_cols[(end - 1 - t_start) as usize] + 1,
+            )
+        };
+        let recs = match self.mode {
+            MaskMode::Drop => Vec::new(),
+            MaskMode::Trim => {
+                let t_seq = rec.target_seq().as_bytes();
+                let q_seq = rec.query_seq().as_bytes();
+                let aligned = |col: &usize| t_seq[*col] != b'-' && q_seq[*col] != b'-';
+                let mut recs = Vec::new();
+                let mut slicer = rec.column_slicer();
+                for range in self.unmasked(&rec, &masked) {
+                    // gaps at both ends are removed as PAF
+                    let (col_start, col_end) = cols(range);
+                    let col_start = (col_start..col_end).find(aligned);
+                    let col_end = (col_start.unwrap_or(col_end)..col_end).rev().find(aligned);
+                    if let (Some(col_start), Some(col_end)) = (col_start, col_end) {
+                        recs.push(slicer.slice(col_start, col_end + 1)?);
+                    }
+                }
+                recs
+            }
+            MaskMode::Softmask => {
+                for range in masked {
+                    let (col_start, col_end) = cols(range);
+                    for sline in rec.slines.iter_mut() {
+                        if let Some(seq) = sline.seq.get_mut(col_start..col_end) {
+                            seq.make_ascii_lowercase();
+                        }
+                    }
+                }
+                vec![rec]
+            }
+        };
+        if recs.is_empty() {
+            record_skipped();
+        }
+        Ok(recs)
+    }
+
+    /// Apply mask to a PAF record, trimmed records get a new CIGAR and other tags are dropped
+    fn apply_paf(&self, rec: PafRecord) -> Result<Vec<PafRecord>, WGAError> {
+        let masked = self.masked(&rec);
+        if masked.is_empty() {
+            return Ok(vec![rec]);
+        }
+        let recs = match self.mode {
+            MaskMode::Trim => {
+                let units = parse_cigar_to_units(&rec)?;
+                let mut recs = Vec::new();
+                for range in self.unmasked(&rec, &masked) {
+                    if let Some(piece) = trim_paf(&rec, &units, range)? {
+                        recs.push(piece);
+                    }
+                }
+                recs
+            }
+            // softmask is rejected for PAF before reading
+            MaskMode::Drop | MaskMode::Softmask => Vec::new(),
+        };
+        if recs.is_empty() {
+            record_skipped();
+        }
+        Ok(recs)
+    }
+}
+
+// cut PAF record to target range `[seg_start, seg_end)` by CIGAR units, gaps at both ends are
+// removed, None if no aligned base is in range
+fn trim_paf(
+    rec: &PafRecord,
+    units: &[(char, u64)],
+    (seg_start, seg_end): (u64, u64),
+) -> Result<Option<PafRecord>, WGAError> {
+    let mut ops: Vec<(char, u64)> = Vec::new();
+    // offsets of query along the alignment and target range of aligned bases in piece
+    let mut q_range: Option<(u64, u64)> = None;
+    let mut t_range = (0, 0);
+    let (mut t_pos, mut q_off) = (rec.target_start, 0);
+    for &(op, len) in units {
+        let (ov_start, ov_end) = (t_pos.max(seg_start), (t_pos + len).min(seg_end));
+        match op {
+            'M' | '=' | 'X' => {
+                if ov_start < ov_end {
+                    let q_end = q_off + ov_end - t_pos;
+                    q_range = match q_range {
+                        Some((q_start, _)) => Some((q_start, q_end)),
+                        None => {
+                            t_range.0 = ov_start;
+                            Some((q_off + ov_start - t_pos, q_end))
+                        }
+                    };
+                    t_range.1 = ov_end;
+                    ops.push((op, ov_end - ov_start));
+                }
+                t_pos += len;
+                q_off += len;
+            }
+            'D' => {
+                if q_range.is_some() && ov_start < ov_end {
+                    ops.push((op, ov_end - ov_start));
+                }
+                t_pos += len;
+            }
+            'I' => {
+                // an insertion is within range if target bases of range are on both sides
+                if q_range.is_some() && t_pos < seg_end {
+                    ops.push((op, len));
+                }
+                q_off += len;
+            }
+            _ => return Err(WGAError::CigarOpInvalid(op.to_string())),
+        }
+    }
+    while ops.last().is_some_and(|(op, _)| *op == 'D' || *op == 'I') {
+        ops.pop();
+    }
+    let (q_start, q_end) = match q_range {
+        Some(q_range) => q_range,
+        None => return Ok(None),
+    };
+
+    // matches are counted by `=`, or estimated by the fraction of `M` if CIGAR has no `=`/`X`
+    let has_eqx = units.iter().any(|(op, _)| *op == '=' || *op == 'X');
+    let matches = match has_eqx {
+        true => ops
+            .iter()
+            .filter(|(op, _)| *op == '=')
+            .map(|(_, len)| len)
+            .sum(),
+        false => {
+            let m_len = |ops: &[(char, u64)]| {
+                ops.iter()
+                    .filter(|(op, _)| *op == 'M')
+                    .map(|(_, len)| len)
+                    .sum::<u64>()
+            };
+            match m_len(units) {
+                0 => 0,
+                total => rec.matches * m_len(&ops) / total,
+            }
+        }
+    };
+    let (query_start, query_end) = match rec.strand {
+        Strand::Positive => (rec.query_start + q_start, rec.query_start + q_end),
+        // query of negative strand is aligned from its end
+        Strand::Negative => (rec.query_end - q_end, rec.query_end - q_start),
+    };
+    let cigar = ops
+        .iter()
+        .map(|(op, len)| format!("{}{}", len, op))
+        .collect::<String>();
+    Ok(Some(PafRecord {
+        query_name: rec.query_name.clone(),
+        query_length: rec.query_length,
+        query_start,
+        query_end,
+        strand: rec.strand,
+        target_name: rec.target_name.clone(),
+        target_length: rec.target_length,
+        target_start: t_range.0,
+        target_end: t_range.1,
+        matches,
+        block_length: ops.iter().map(|(_, len)| len).sum(),
+        mapq: rec.mapq,
+        tags: vec![format!("cg:Z:{}", cigar)],
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pair_report_flags_by_cutoffs() {
+        let paf = "q1\t1000\t0\t600\t+\tt1\t5000\t0\t600\t600\t600\t60\tcg:Z:600M\n\
+                   q1\t1000\t500\t950\t+\tt2\t5000\t0\t450\t450\t450\t60\tcg:Z:450M\n";
+        let flag = |split_fraction: f64, dup_redundancy: f64| {
+            let mut out = Vec::new();
+            let reader = PAFReader::new(paf.as_bytes());
+            report_paf_align_pair(reader, &mut out, 0, split_fraction, dup_redundancy).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            out.lines()
+                .nth(1)
+                .unwrap()
+                .rsplit('\t')
+                .next()
+                .unwrap()
+                .to_string()
+        };
+        // best fraction is 0.57 and redundancy is 1.11
+        assert_eq!(flag(SPLIT_BEST_FRACTION, DUP_REDUNDANCY), "split");
+        assert_eq!(flag(0.5, 1.1), "duplicated");
+        assert_eq!(flag(0.6, 1.1), "split,duplicated");
+        assert_eq!(flag(0.5, 1.5), ".");
+    }
+}
@@ -1,30 +1,133 @@
 use crate::{
     errors::WGAError,
+    log::WarnCategory,
     parser::{common::Strand, maf::MAFReader},
 };
 use anyhow::anyhow;
 use itertools::enumerate;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     fs::File,
-    io::{Seek, Write},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
 };
 
+// bytes at the head of MAF file to checksum
+const CHECKSUM_BYTES: u64 = 16 << 20;
+
+/// Format version of index files, bumped when the layout changes
+pub const INDEX_VERSION: u32 = 1;
+
+/// Default memory cap of intervals in MiB when building index
+pub const INDEX_MAX_MEM_MB: usize = 1024;
+// mismatched intervals reported by `check_index`
+const CHECK_REPORT_MAX: usize = 10;
+// candidates reported for an ambiguous sequence name
+const CANDIDATE_REPORT_MAX: usize = 10;
+// blocks between progress logs
+const PROGRESS_BLOCKS: u64 = 1_000_000;
+// bytes of an interval in the spill file: name id, start, end, strand and offset
+const SPILL_ENTRY_BYTES: usize = 29;
+
+// size, order and interval count of an indexed sequence
+struct IndexedSeq {
+    name: String,
+    size: u64,
+    ord: usize,
+    count: usize,
+}
+
+// temporary file of intervals over the memory cap, removed when dropped
+struct SpillFile {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+}
+
+impl SpillFile {
+    fn write(&mut self, entries: &[(u32, IvP)]) -> Result<(), WGAError> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => self
+                .writer
+                .insert(BufWriter::new(File::create(&self.path)?)),
+        };
+        for (id, ivl) in entries {
+            writer.write_all(&id.to_le_bytes())?;
+            writer.write_all(&ivl.start.to_le_bytes())?;
+            writer.write_all(&ivl.end.to_le_bytes())?;
+            writer.write_all(&[(ivl.strand == Strand::Negative) as u8])?;
+            writer.write_all(&ivl.offset.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    // intervals of names in `ids`, grouped by name in input order
+    fn read_ids(&self, ids: Range<usize>) -> Result<Vec<Vec<IvP>>, WGAError> {
+        let mut groups = ids.clone().map(|_| Vec::new()).collect::<Vec<_>>();
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut buf = [0u8; SPILL_ENTRY_BYTES];
+        loop {
+            match reader.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let id = u32::from_le_bytes(buf[0..4].try_into().unwrap_or_default()) as usize;
+            if !ids.contains(&id) {
+                continue;
+            }
+            let u64_at =
+                |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap_or_default());
+            groups[id - ids.start].push(IvP {
+                start: u64_at(4),
+                end: u64_at(12),
+                strand: match buf[20] {
+                    1 => Strand::Negative,
+                    _ => Strand::Positive,
+                },
+                offset: u64_at(21),
+            });
+        }
+        Ok(groups)
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Build index of MAF in two passes: intervals are read into memory up to `max_mem` bytes
+/// and spilled to `spill_path` over it, then written by batches of sequences
 pub fn build_index(
     mafreader: &mut MAFReader<File>,
-    idx_wtr: Box<dyn Write>,
+    mut idx_wtr: Box<dyn Write>,
+    source: SourceMeta,
+    spill_path: &Path,
+    max_mem: usize,
 ) -> Result<(), WGAError> {
-    // init a MAfIndex2 struct
-    let mut idx: MafIndex = HashMap::new();
-
-    loop {
-        let offset = mafreader.inner.stream_position()?;
-        let record = mafreader.records().next();
-        let record = match record {
-            Some(r) => r?,
-            None => break,
-        };
+    let cap = (max_mem / std::mem::size_of::<(u32, IvP)>()).max(1);
+    let mut ids: HashMap<String, u32> = HashMap::new();
+    let mut seqs: Vec<IndexedSeq> = Vec::new();
+    let mut buffer: Vec<(u32, IvP)> = Vec::new();
+    let mut spill = SpillFile {
+        path: spill_path.to_path_buf(),
+        writer: None,
+    };
+
+    // pass 1: read intervals of all s-lines
+    let mut block_count = 0;
+    let mut records = mafreader.records();
+    while let Some(record) = records.next_with_offset() {
+        // point to the a-line, so seeking to it gets the score and tags
+        let (offset, record) = record?;
 
         let mut name_vec = Vec::new();
         for (ord, sline) in enumerate(record.slines) {
@@ -34,60 +137,331 @@ pub fn build_index(
             } else {
                 return Err(WGAError::DuplicateName(name));
             }
-            let start = sline.start;
-            let end = sline.start + sline.align_size;
-            let size = sline.size;
-            let strand = sline.strand;
-
-            // idx.entry(name.clone()).or_insert(MafIndexItem {
-            //     ivls: Vec::new(),
-            //     size,
-            //     ord,
-            // });
-
-            if !idx.contains_key(&name) {
-                idx.insert(
-                    name.clone(),
-                    MafIndexItem {
-                        ivls: Vec::new(),
-                        size,
+            let id = match ids.get(&name) {
+                Some(&id) => {
+                    // compare ord if same
+                    if seqs[id as usize].ord != ord {
+                        return Err(WGAError::InvalidRecord(
+                            "There is a different order between Records!".to_string(),
+                        ));
+                    }
+                    id
+                }
+                None => {
+                    let id = u32::try_from(seqs.len())
+                        .map_err(|_| WGAError::Other(anyhow!("too many sequences to index")))?;
+                    ids.insert(name.clone(), id);
+                    seqs.push(IndexedSeq {
+                        name,
+                        size: sline.size,
                         ord,
-                    },
-                );
-            } else {
-                // compare ord if same
-                if idx
-                    .get(&name)
-                    .ok_or(WGAError::Other(anyhow!("not excepted")))?
-                    .ord
-                    != ord
-                {
-                    return Err(WGAError::Other(anyhow!(
-                        "There is a different order between Records!"
-                    )));
+                        count: 0,
+                    });
+                    id
                 }
-            }
-
-            idx.get_mut(&name)
-                .ok_or(WGAError::Other(anyhow!("not excepted")))?
-                .ivls
-                .push(IvP {
-                    start,
-                    end,
-                    strand,
+            };
+            seqs[id as usize].count += 1;
+            buffer.push((
+                id,
+                IvP {
+                    start: sline.start,
+                    end: sline.start + sline.align_size,
+                    strand: sline.strand,
                     offset,
-                });
+                },
+            ));
+            if buffer.len() >= cap {
+                spill.write(&buffer)?;
+                buffer.clear();
+            }
+        }
+        block_count += 1;
+        if block_count % PROGRESS_BLOCKS == 0 {
+            info!("indexed {} blocks, {} sequences", block_count, seqs.len());
         }
     }
-    // write index to file if not empty
-    if !idx.is_empty() {
-        serde_json::to_writer(idx_wtr, &idx)?
+    drop(ids);
+    info!(
+        "indexed {} blocks, {} sequences, {} intervals",
+        block_count,
+        seqs.len(),
+        seqs.iter().map(|seq| seq.count).sum::<usize>()
+    );
+
+    // pass 2: write intervals of sequences, in order of appearance
+    write!(idx_wtr, "{{\"version\":{},\"source\":", INDEX_VERSION)?;
+    serde_json::to_writer(&mut idx_wtr, &source)?;
+    write!(idx_wtr, ",\"index\":{{")?;
+    if spill.writer.is_none() {
+        let mut groups = seqs.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+        for (id, ivl) in buffer {
+            groups[id as usize].push(ivl);
+        }
+        write_index_items(&mut idx_wtr, &seqs, 0, groups)?;
     } else {
-        return Err(WGAError::EmptyRecord);
+        spill.write(&buffer)?;
+        drop(buffer);
+        if let Some(writer) = &mut spill.writer {
+            writer.flush()?;
+        }
+        info!(
+            "intervals over memory cap are spilled to {}",
+            spill_path.display()
+        );
+        // batches of sequences with intervals within the cap
+        let mut batch_start = 0;
+        while batch_start < seqs.len() {
+            let mut batch_end = batch_start;
+            let mut batch_count = 0;
+            while batch_end < seqs.len()
+                && (batch_end == batch_start || batch_count + seqs[batch_end].count <= cap)
+            {
+                batch_count += seqs[batch_end].count;
+                batch_end += 1;
+            }
+            let groups = spill.read_ids(batch_start..batch_end)?;
+            write_index_items(&mut idx_wtr, &seqs, batch_start, groups)?;
+            info!("written index of {}/{} sequences", batch_end, seqs.len());
+            batch_start = batch_end;
+        }
     }
+    write!(idx_wtr, "}}}}")?;
+    idx_wtr.flush()?;
     Ok(())
 }
 
+// write `"name":{item}` of sequences from `first`, separated by `,`
+fn write_index_items(
+    writer: &mut dyn Write,
+    seqs: &[IndexedSeq],
+    first: usize,
+    groups: Vec<Vec<IvP>>,
+) -> Result<(), WGAError> {
+    for (i, ivls) in groups.into_iter().enumerate() {
+        let seq = &seqs[first + i];
+        if first + i > 0 {
+            write!(writer, ",")?;
+        }
+        serde_json::to_writer(&mut *writer, &seq.name)?;
+        write!(writer, ":")?;
+        let item = MafIndexItem {
+            ivls,
+            size: seq.size,
+            ord: seq.ord,
+        };
+        serde_json::to_writer(&mut *writer, &item)?;
+    }
+    Ok(())
+}
+
+/// Load index `{maf_path}.index` and check it against the MAF file, None if not exist.
+/// A stale index is rebuilt if `rebuild`, otherwise an error is returned
+pub fn load_index(maf_path: &str, rebuild: bool) -> Result<Option<MafIndex>, WGAError> {
+    let index_path = format!("{}.index", maf_path);
+    if !Path::new(&index_path).exists() {
+        return Ok(None);
+    }
+    let stale_reason = match read_index_file(&index_path)? {
+        Ok(index_file) => match index_file.source.diff(&SourceMeta::from_path(maf_path)?) {
+            None => return Ok(Some(index_file.index)),
+            Some(reason) => reason,
+        },
+        Err(reason) => reason,
+    };
+    let err = WGAError::StaleIndex(index_path.clone(), stale_reason);
+    if !rebuild {
+        return Err(err);
+    }
+    warn!(target: WarnCategory::Index.target(), "{}, rebuilding", err);
+    let source = SourceMeta::from_path(maf_path)?;
+    let mut mafreader = MAFReader::from_path(maf_path)?;
+    build_index(
+        &mut mafreader,
+        Box::new(BufWriter::new(File::create(&index_path)?)),
+        source,
+        Path::new(&format!("{}.tmp", index_path)),
+        INDEX_MAX_MEM_MB << 20,
+    )?;
+    load_index(maf_path, false)
+}
+
+// read an index file, the inner error is the reason if it is not of `INDEX_VERSION`
+fn read_index_file(index_path: &str) -> Result<Result<MafIndexFile, String>, WGAError> {
+    let reader = BufReader::new(File::open(index_path)?);
+    let version = match serde_json::from_reader::<_, MafIndexFile>(reader) {
+        Ok(index_file) if index_file.version == INDEX_VERSION => return Ok(Ok(index_file)),
+        Ok(index_file) => index_file.version,
+        // layout of another version, read the version only to tell why
+        Err(_) => {
+            let reader = BufReader::new(File::open(index_path)?);
+            match serde_json::from_reader::<_, IndexVersion>(reader) {
+                Ok(head) if head.version != INDEX_VERSION => head.version,
+                _ => return Ok(Err("unknown format".to_string())),
+            }
+        }
+    };
+    Ok(Err(match version {
+        0 => format!(
+            "no format version, built by an older wgatools, expected version {}",
+            INDEX_VERSION
+        ),
+        v if v > INDEX_VERSION => format!(
+            "format version {} is newer than version {} supported, upgrade wgatools to read it",
+            v, INDEX_VERSION
+        ),
+        v => format!(
+            "format version {} is older than version {}",
+            v, INDEX_VERSION
+        ),
+    }))
+}
+
+/// Check index `index_path` of `maf_path`: format version, source file, and each interval
+/// points to an `a` line whose block has the s-line of its name at the indexed order and span
+pub fn check_index(maf_path: &str, index_path: &str) -> Result<(), WGAError> {
+    if !Path::new(index_path).exists() {
+        return Err(WGAError::FileNotExist(PathBuf::from(index_path)));
+    }
+    let index_file = read_index_file(index_path)?
+        .map_err(|reason| WGAError::StaleIndex(index_path.to_string(), reason))?;
+    if let Some(reason) = index_file.source.diff(&SourceMeta::from_path(maf_path)?) {
+        return Err(WGAError::StaleIndex(index_path.to_string(), reason));
+    }
+
+    // intervals grouped by offset, so each block is read once in file order
+    let mut blocks: HashMap<u64, Vec<(&str, &MafIndexItem, &IvP)>> = HashMap::new();
+    for (name, item) in &index_file.index {
+        for ivp in &item.ivls {
+            blocks
+                .entry(ivp.offset)
+                .or_default()
+                .push((name.as_str(), item, ivp));
+        }
+    }
+    let mut offsets = blocks.keys().copied().collect::<Vec<_>>();
+    offsets.sort_unstable();
+
+    let mut raw = BufReader::new(File::open(maf_path)?);
+    let mut mafreader = MAFReader::from_path(maf_path)?;
+    let mut line = String::new();
+    let (mut total, mut bad) = (0, 0);
+    for offset in offsets {
+        let ivls = &blocks[&offset];
+        total += ivls.len();
+        // the offset is at the start of an `a` line
+        let mut prev = [b'\n'];
+        if offset > 0 {
+            raw.seek(SeekFrom::Start(offset - 1))?;
+            raw.read_exact(&mut prev)?;
+        }
+        line.clear();
+        raw.seek(SeekFrom::Start(offset))?;
+        raw.read_line(&mut line)?;
+        if prev[0] != b'\n' || !line.starts_with('a') {
+            bad += ivls.len();
+            if bad <= CHECK_REPORT_MAX {
+                warn!(target: WarnCategory::Index.target(), "offset {} does not point to an `a` line", offset);
+            }
+            continue;
+        }
+        mafreader.seek(offset)?;
+        let rec = mafreader.records().next().ok_or(WGAError::EmptyRecord)??;
+        for (name, item, ivp) in ivls {
+            let matched = rec.slines.get(item.ord).is_some_and(|sline| {
+                sline.name == *name
+                    && sline.size == item.size
+                    && sline.strand == ivp.strand
+                    && sline.start == ivp.start
+                    && sline.start + sline.align_size == ivp.end
+            });
+            if !matched {
+                bad += 1;
+                if bad <= CHECK_REPORT_MAX {
+                    warn!(
+                        target: WarnCategory::Index.target(),
+                        "s-line {} of block at offset {} is not {}:{}-{}",
+                        item.ord, offset, name, ivp.start, ivp.end
+                    );
+                }
+            }
+        }
+    }
+    if bad > 0 {
+        return Err(WGAError::StaleIndex(
+            index_path.to_string(),
+            format!("{} of {} intervals do not match their s-lines", bad, total),
+        ));
+    }
+    info!(
+        "index `{}` is valid: version {}, {} sequences, {} intervals",
+        index_path,
+        index_file.version,
+        index_file.index.len(),
+        total
+    );
+    Ok(())
+}
+
+/// Size, modified time and MD5 of the head of the MAF file an index is built from
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourceMeta {
+    pub size: u64,
+    pub mtime_ns: u64,
+    pub md5: String,
+}
+
+impl SourceMeta {
+    pub fn from_path(path: &str) -> Result<Self, WGAError> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let mtime_ns = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or_default();
+        let mut head = Vec::new();
+        file.take(CHECKSUM_BYTES).read_to_end(&mut head)?;
+        Ok(SourceMeta {
+            size: metadata.len(),
+            mtime_ns,
+            md5: format!("{:x}", md5::compute(&head)),
+        })
+    }
+
+    // reason of staleness if current file is different
+    fn diff(&self, current: &SourceMeta) -> Option<String> {
+        if self.size != current.size {
+            Some(format!(
+                "file size changed from {} to {}",
+                self.size, current.size
+            ))
+        } else if self.md5 != current.md5 {
+            Some("checksum of file head changed".to_string())
+        } else if self.mtime_ns != current.mtime_ns {
+            Some("modified time changed".to_string())
+        } else {
+            None
+        }
+    }
+}
+
+/// Index file of MAF with its format version and source
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MafIndexFile {
+    /// 0 for files without version, built before versioning
+    #[serde(default)]
+    pub version: u32,
+    pub source: SourceMeta,
+    pub index: MafIndex,
+}
+
+// format version of an index file of any layout
+#[derive(Deserialize)]
+struct IndexVersion {
+    #[serde(default)]
+    version: u32,
+}
+
 pub type MafIndex = HashMap<String, MafIndexItem>;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -104,3 +478,61 @@ pub struct IvP {
     pub strand: Strand,
     pub offset: u64,
 }
+
+/// Sequence names matching `query`: the exact name, else names containing it
+/// ignoring case, else names with its characters in order (fuzzy),
+/// shorter names first and then in natural order
+pub fn match_seq_names<'a, I>(names: I, query: &str) -> Vec<&'a str>
+where
+    I: IntoIterator<Item = &'a str> + Clone,
+{
+    if let Some(name) = names.clone().into_iter().find(|name| *name == query) {
+        return vec![name];
+    }
+    let query = query.to_lowercase();
+    let mut matched = names
+        .clone()
+        .into_iter()
+        .filter(|name| name.to_lowercase().contains(&query))
+        .collect::<Vec<_>>();
+    if matched.is_empty() {
+        matched = names
+            .into_iter()
+            .filter(|name| {
+                let mut chars = name.chars().map(|c| c.to_ascii_lowercase());
+                query.chars().all(|q| chars.any(|c| c == q))
+            })
+            .collect();
+    }
+    matched.sort_by(|a, b| a.len().cmp(&b.len()).then_with(|| natord::compare(a, b)));
+    matched
+}
+
+/// Resolve a sequence name in index by `match_seq_names`, error with candidates if ambiguous,
+/// None if nothing matches
+pub fn resolve_seq_name(mafidx: &MafIndex, query: &str) -> Result<Option<String>, WGAError> {
+    let matched = match_seq_names(mafidx.keys().map(String::as_str), query);
+    match matched.as_slice() {
+        [] => Ok(None),
+        [name] => Ok(Some(name.to_string())),
+        _ => {
+            let shown = matched
+                .iter()
+                .take(CANDIDATE_REPORT_MAX)
+                .copied()
+                .collect::<Vec<_>>()
+                .join(", ");
+            let more = match matched.len() > CANDIDATE_REPORT_MAX {
+                true => format!(" and {} more", matched.len() - CANDIDATE_REPORT_MAX),
+                false => String::new(),
+            };
+            Err(WGAError::InvalidArgs(format!(
+                "sequence name `{}` is ambiguous, {} candidates: {}{}",
+                query,
+                matched.len(),
+                shown,
+                more
+            )))
+        }
+    }
+}
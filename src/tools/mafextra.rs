@@ -1,17 +1,17 @@
-use crate::errors::{ParseGenomeRegionErrKind, WGAError};
-use crate::parser::maf::{MAFReader, MAFWriter};
-use crate::tools::index::{IvP, MafIndex};
-use crate::utils::parse_str2u64;
-use csv::ReaderBuilder;
-use regex::Regex;
+use crate::errors::WGAError;
+use crate::parser::maf::{MAFReader, MAFRecord, MAFWriter};
+use crate::parser::region::{parse_region_specs, read_bed_regions, GenomeRegion};
+use crate::tools::index::{resolve_seq_name, IvP, MafIndex};
+use crate::utils::par_write_ordered;
+use log::info;
 use rust_lapper::{Interval, Lapper};
-use serde::{Deserialize, Serialize};
 use std::cmp::{max, min};
-use std::fmt::Display;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use std::io::Seek;
 use std::io::{BufReader, Write};
+use std::sync::Mutex;
 
 // fn maf_extract_iter<R: Read>(
 //     _regions: &Option<Vec<String>>,
@@ -22,20 +22,30 @@ use std::io::{BufReader, Write};
 //     todo!()
 // }
 
-pub fn maf_extract_idx<R: Read + Send + Seek>(
+pub fn maf_extract_idx(
     regions: &Option<Vec<String>>,
     region_file: &Option<String>,
-    mafreader: &mut MAFReader<R>,
+    maf_path: &str,
     mafindex: MafIndex,
     writer: &mut dyn Write,
+    fuzzy: bool,
 ) -> Result<Vec<GenomeRegion>, WGAError> {
-    let input_regions = get_input_regions(regions, region_file)?;
-    let mut sub_maf_wtr = MAFWriter::new(writer);
+    let mut input_regions = get_input_regions(regions, region_file)?;
+    if fuzzy {
+        // names not matching any sequence are kept to be reported as failed
+        for region in input_regions.iter_mut() {
+            match resolve_seq_name(&mafindex, &region.name)? {
+                Some(name) if name != region.name => {
+                    info!("sequence name `{}` resolved to `{}`", region.name, name);
+                    region.name = name;
+                }
+                _ => {}
+            }
+        }
+    }
     let header = "#maf version=1.6 cmd=maf_extract";
-    sub_maf_wtr.write_header(header.to_owned())?;
-    let failed_regions =
-        extract_sub_blocks_with_idx(mafindex, input_regions, mafreader, &mut sub_maf_wtr)?;
-    Ok(failed_regions)
+    MAFWriter::new(&mut *writer).write_header(header.to_owned())?;
+    extract_sub_blocks_with_idx(&mafindex, input_regions, maf_path, writer)
 }
 
 fn get_input_regions(
@@ -53,87 +63,17 @@ fn get_input_regions(
 
     // read input region_vec
     if let Some(regions) = regions {
-        for region in regions {
-            let genome_region = GenomeRegion::try_from(region.to_string())?;
-            input_regions.push(genome_region);
-        }
+        input_regions.extend(parse_region_specs(regions)?);
     }
 
     // read input region_file
     if let Some(region_file) = region_file {
         let reader = BufReader::new(File::open(region_file)?);
-        let regions = read_genome_region(reader)?;
-        input_regions.extend(regions);
+        input_regions.extend(read_bed_regions(reader)?);
     }
     Ok(input_regions)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GenomeRegion {
-    name: String,
-    start: u64,
-    end: u64,
-}
-
-impl TryFrom<String> for GenomeRegion {
-    type Error = WGAError;
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        let re = Regex::new(r"^([a-zA-Z0-9.@_#-]+):([0-9]+)-([0-9]+)$")?;
-        match re.captures(&value) {
-            Some(caps) => {
-                let name = caps
-                    .get(1)
-                    .ok_or(WGAError::UnexceptedRegexError("name".to_string()))?
-                    .as_str()
-                    .to_string();
-                let start = caps
-                    .get(2)
-                    .ok_or(WGAError::UnexceptedRegexError("start".to_string()))?
-                    .as_str();
-                let start = parse_str2u64(start)?;
-                let end = caps
-                    .get(3)
-                    .ok_or(WGAError::UnexceptedRegexError("end".to_string()))?
-                    .as_str();
-                let end = parse_str2u64(end)?;
-                if start > end {
-                    return Err(WGAError::ParseGenomeRegion(
-                        ParseGenomeRegionErrKind::StartGTEnd(start, end),
-                    ));
-                }
-                Ok(GenomeRegion { name, start, end })
-            }
-            None => Err(WGAError::ParseGenomeRegion(
-                ParseGenomeRegionErrKind::FormatNotMatch(value),
-            )),
-        }
-    }
-}
-
-impl Display for GenomeRegion {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}:{}-{}", self.name, self.start, self.end)
-    }
-}
-
-fn read_genome_region<R: Read>(reader: R) -> Result<Vec<GenomeRegion>, WGAError> {
-    let mut rdr = ReaderBuilder::new()
-        .delimiter(b'\t')
-        .has_headers(false)
-        .from_reader(reader);
-    let mut regions = Vec::new();
-    for result in rdr.deserialize() {
-        let record: GenomeRegion = result?;
-        if record.start > record.end {
-            return Err(WGAError::ParseGenomeRegion(
-                ParseGenomeRegionErrKind::StartGTEnd(record.start, record.end),
-            ));
-        }
-        regions.push(record);
-    }
-    Ok(regions)
-}
-
 type Iv = Interval<u64, u64>;
 
 fn ivp2iv(ivp: &IvP) -> Iv {
@@ -144,61 +84,141 @@ fn ivp2iv(ivp: &IvP) -> Iv {
     }
 }
 
-fn extract_sub_blocks_with_idx<R: Read + Send + Seek, W: Write>(
-    mafidx: MafIndex,
+/// extract sub-blocks for each region in parallel and write them in the order of input regions
+/// with bounded memory, readers are reused by workers to seek independently
+fn extract_sub_blocks_with_idx(
+    mafidx: &MafIndex,
     regions: Vec<GenomeRegion>,
-    mafreader: &mut MAFReader<R>,
-    mafwriter: &mut MAFWriter<W>,
+    maf_path: &str,
+    writer: &mut dyn Write,
 ) -> Result<Vec<GenomeRegion>, WGAError> {
-    let mut failed_regions = Vec::new();
-    // TODO: parallel genearte sub-maf-blocks
-    for givl in regions.into_iter() {
-        match mafidx.get(&givl.name) {
-            Some(item) => {
-                let hit_ivps = &item.ivls;
-                let hit_givls = hit_ivps.iter().map(ivp2iv).collect::<Vec<Iv>>();
-                let lapper = Lapper::new(hit_givls);
-                let find = lapper.find(givl.start, givl.end).collect::<Vec<&Iv>>();
-                let find_num = find.len();
-                let ord = item.ord;
-                match find_num {
-                    0 => {
-                        failed_regions.push(givl);
-                        continue;
+    // build interval trees once for all regions
+    let lappers = build_lappers(mafidx);
+    // idle readers, a worker takes one or opens a new one if none left
+    let readers = Mutex::new(Vec::new());
+    let failed_regions = Mutex::new(Vec::new());
+
+    par_write_ordered(
+        regions.into_iter().map(Ok::<_, WGAError>),
+        writer,
+        |idx, givl| {
+            let idle = readers.lock().unwrap_or_else(|e| e.into_inner()).pop();
+            let mut mafreader = match idle {
+                Some(mafreader) => mafreader,
+                None => MAFReader::from_path(maf_path)?,
+            };
+            let sub_blocks = extract_region(&lappers, &givl, &mut mafreader)?;
+            readers
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(mafreader);
+
+            let mut buf = Vec::new();
+            match sub_blocks {
+                Some(sub_blocks) => {
+                    let mut mafwriter = MAFWriter::new(&mut buf);
+                    for mafrec in sub_blocks {
+                        mafwriter.write_record(&mafrec)?;
                     }
-                    _ => {
-                        for block in find {
-                            let offset = block.val;
-                            mafreader.inner.seek(std::io::SeekFrom::Start(offset))?;
-                            let mut mafrec =
-                                mafreader.records().next().ok_or(WGAError::EmptyRecord)??;
+                }
+                None => failed_regions
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push((idx, givl)),
+            }
+            Ok(buf)
+        },
+    )?;
+
+    let mut failed_regions = failed_regions
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner());
+    failed_regions.sort_by_key(|(idx, _)| *idx);
+    Ok(failed_regions.into_iter().map(|(_, givl)| givl).collect())
+}
 
-                            let b_start = block.start;
-                            let b_end = block.stop;
+/// extract sub-blocks of a single region with prebuilt interval trees of index,
+/// empty if no block hit
+pub fn extract_region_records(
+    maf_path: &str,
+    lappers: &Lappers,
+    givl: &GenomeRegion,
+) -> Result<Vec<MAFRecord>, WGAError> {
+    let mut mafreader = MAFReader::from_path(maf_path)?;
+    Ok(extract_region(lappers, givl, &mut mafreader)?.unwrap_or_default())
+}
 
-                            let g_start = givl.start;
-                            let g_end = givl.end;
+/// Interval trees of blocks and the s-line order for each sequence in index
+pub type Lappers<'a> = HashMap<&'a str, (Lapper<u64, u64>, usize)>;
+
+/// build an interval tree and the s-line order for each sequence in index
+pub fn build_lappers(mafidx: &MafIndex) -> Lappers<'_> {
+    mafidx
+        .iter()
+        .map(|(name, item)| {
+            let hit_givls = item.ivls.iter().map(ivp2iv).collect::<Vec<Iv>>();
+            (name.as_str(), (Lapper::new(hit_givls), item.ord))
+        })
+        .collect()
+}
 
-                            if g_start <= b_start && g_end >= b_end {
-                                mafwriter.write_record(&mafrec)?;
-                                continue;
-                            }
+/// extract sub-blocks of a region, None if no block hit
+fn extract_region<R: Read + Send + Seek>(
+    lappers: &Lappers,
+    givl: &GenomeRegion,
+    mafreader: &mut MAFReader<R>,
+) -> Result<Option<Vec<MAFRecord>>, WGAError> {
+    let (lapper, ord) = match lappers.get(givl.name.as_str()) {
+        Some((lapper, ord)) => (lapper, *ord),
+        None => return Ok(None),
+    };
+    let find = lapper.find(givl.start, givl.end).collect::<Vec<&Iv>>();
+    if find.is_empty() {
+        return Ok(None);
+    }
+    let mut sub_blocks = Vec::with_capacity(find.len());
+    for block in find {
+        let offset = block.val;
+        mafreader.seek(offset)?;
+        let mut mafrec = mafreader.records().next().ok_or(WGAError::EmptyRecord)??;
 
-                            let r_start = max(b_start, g_start);
-                            let r_end = min(b_end, g_end);
+        let b_start = block.start;
+        let b_end = block.stop;
 
-                            mafrec.slice_block(r_start, r_end, ord);
+        let g_start = givl.start;
+        let g_end = givl.end;
 
-                            mafwriter.write_record(&mafrec)?;
-                        }
-                    }
-                }
-            }
-            None => {
-                failed_regions.push(givl);
-                continue;
-            }
-        };
+        if g_start <= b_start && g_end >= b_end {
+            sub_blocks.push(mafrec);
+            continue;
+        }
+
+        let r_start = max(b_start, g_start);
+        let r_end = min(b_end, g_end);
+
+        mafrec.slice_block(r_start, r_end, ord)?;
+
+        sub_blocks.push(mafrec);
+    }
+    Ok(Some(sub_blocks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::EXIT_IO;
+
+    #[test]
+    fn worker_reader_error_is_io() {
+        let regions = vec!["chr1:1-10".parse::<GenomeRegion>().unwrap()];
+        let err = extract_sub_blocks_with_idx(
+            &MafIndex::new(),
+            regions,
+            "test/not_exist.maf",
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, WGAError::FileNotExist(_)));
+        assert_eq!(err.exit_code(), EXIT_IO);
     }
-    Ok(failed_regions)
 }
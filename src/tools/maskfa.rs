@@ -0,0 +1,163 @@
+use crate::errors::WGAError;
+use crate::log::WarnCategory;
+use crate::parser::cigar::parse_cigar_to_blocks;
+use crate::parser::common::{FastaMaskMode, Strand};
+use crate::parser::maf::MAFReader;
+use crate::parser::paf::PAFReader;
+use crate::utils::merge_intervals;
+use log::{info, warn};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Read, Write};
+
+/// Aligned intervals on target sequences, sorted and merged
+#[derive(Debug, Default)]
+pub struct TargetCoverage {
+    intervals: HashMap<String, Vec<(u64, u64)>>,
+}
+
+impl TargetCoverage {
+    fn add(&mut self, name: &str, start: u64, end: u64) {
+        if end > start {
+            match self.intervals.get_mut(name) {
+                Some(ivs) => ivs.push((start, end)),
+                None => {
+                    self.intervals.insert(name.to_string(), vec![(start, end)]);
+                }
+            }
+        }
+    }
+
+    fn merge(mut self) -> Self {
+        for ivs in self.intervals.values_mut() {
+            *ivs = merge_intervals(std::mem::take(ivs));
+        }
+        self
+    }
+
+    /// Target bases aligned to query bases in gap-free blocks of PAF records
+    pub fn from_paf<R: Read + Send>(reader: &mut PAFReader<R>) -> Result<Self, WGAError> {
+        let mut cov = TargetCoverage::default();
+        for rec in reader.records() {
+            let rec = rec?;
+            for block in parse_cigar_to_blocks(&rec)? {
+                cov.add(block.target_name, block.target_start, block.target_end);
+            }
+        }
+        Ok(cov.merge())
+    }
+
+    /// Target bases of the first s-line aligned to a base of any other s-line in MAF records
+    pub fn from_maf<R: Read + Send>(reader: &mut MAFReader<R>) -> Result<Self, WGAError> {
+        let mut cov = TargetCoverage::default();
+        for rec in reader.records() {
+            let rec = rec?;
+            let Some((target, others)) = rec.slines.split_first() else {
+                continue;
+            };
+            let others = others
+                .iter()
+                .map(|sline| sline.seq.as_bytes())
+                .collect::<Vec<_>>();
+            // walk columns on target strand, the run of covered bases is [run_start, pos)
+            let mut pos = target.start;
+            let mut run_start = None;
+            let mut runs = Vec::new();
+            for (col, base) in target.seq.bytes().enumerate() {
+                if base == b'-' {
+                    continue;
+                }
+                let covered = others
+                    .iter()
+                    .any(|seq| seq.get(col).is_some_and(|b| *b != b'-'));
+                match (covered, run_start) {
+                    (true, None) => run_start = Some(pos),
+                    (false, Some(start)) => {
+                        runs.push((start, pos));
+                        run_start = None;
+                    }
+                    _ => {}
+                }
+                pos += 1;
+            }
+            if let Some(start) = run_start {
+                runs.push((start, pos));
+            }
+            for (start, end) in runs {
+                match target.strand {
+                    Strand::Positive => cov.add(&target.name, start, end),
+                    Strand::Negative => cov.add(
+                        &target.name,
+                        target.size.saturating_sub(end),
+                        target.size.saturating_sub(start),
+                    ),
+                }
+            }
+        }
+        Ok(cov.merge())
+    }
+}
+
+/// Mask bases of target FASTA not covered by alignments, or covered ones if `aligned`,
+/// the line layout of FASTA is kept
+pub fn mask_fasta<B: BufRead>(
+    fasta: B,
+    cov: &TargetCoverage,
+    mode: FastaMaskMode,
+    aligned: bool,
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    let empty = Vec::new();
+    let mut seen = HashSet::new();
+    // aligned intervals of current sequence, index of the first one not passed and position
+    let mut ivs = &empty;
+    let mut iv_idx = 0;
+    let mut pos = 0;
+    let (mut total, mut masked) = (0u64, 0u64);
+    for line in fasta.lines() {
+        let line = line?;
+        if let Some(header) = line.strip_prefix('>') {
+            let name = header.split_whitespace().next().unwrap_or_default();
+            ivs = cov.intervals.get(name).unwrap_or(&empty);
+            seen.insert(name.to_string());
+            iv_idx = 0;
+            pos = 0;
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+        let mut seq = line.into_bytes();
+        for (i, base) in seq.iter_mut().enumerate() {
+            let base_pos = pos + i as u64;
+            while ivs.get(iv_idx).is_some_and(|iv| iv.1 <= base_pos) {
+                iv_idx += 1;
+            }
+            let covered = ivs.get(iv_idx).is_some_and(|iv| iv.0 <= base_pos);
+            if covered == aligned {
+                *base = match mode {
+                    FastaMaskMode::Hard => b'N',
+                    FastaMaskMode::Soft => base.to_ascii_lowercase(),
+                };
+                masked += 1;
+            }
+        }
+        pos += seq.len() as u64;
+        total += seq.len() as u64;
+        writer.write_all(&seq)?;
+        writeln!(writer)?;
+    }
+
+    let mut missing = cov
+        .intervals
+        .keys()
+        .filter(|name| !seen.contains(*name))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        missing.sort_unstable();
+        warn!(
+            target: WarnCategory::Input.target(),
+            "aligned target sequences not found in FASTA: {}",
+            missing.into_iter().cloned().collect::<Vec<_>>().join(",")
+        );
+    }
+    info!("masked {} of {} bases", masked, total);
+    Ok(())
+}
@@ -1,13 +1,26 @@
+pub mod annotate;
+pub mod bedpe;
 pub mod caller;
 pub mod chunk;
+pub mod consensus;
+pub mod diff;
 pub mod dotplot;
 pub mod filter;
 pub mod index;
 pub mod mafextra;
+pub mod maskfa;
 pub mod pafcov;
+pub mod pmafstat;
+#[cfg(feature = "polish")]
+pub mod polish;
+pub mod posmap;
 pub mod pseudomaf;
 pub mod rename;
+pub mod report;
+pub mod sample;
+pub mod segments;
 pub mod stat;
 pub mod trimovp;
 pub mod tview;
 pub mod validate;
+pub mod vcflift;
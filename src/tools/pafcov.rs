@@ -2,17 +2,23 @@ use rayon::iter::{ParallelBridge, ParallelIterator};
 
 use crate::{
     errors::WGAError,
-    parser::{cigar::update_cov_vec, common::AlignRecord, paf::PAFReader},
+    parser::{
+        cigar::update_cov_vec,
+        common::{AlignRecord, GroupBy},
+        paf::PAFReader,
+    },
 };
 use std::{
     collections::HashMap,
     io::{Read, Write},
 };
 
-// main function of PAF Coverage
+// main function of PAF Coverage, per-base BED of each target,
+// or a summary of each group of targets if grouped by PanSN level
 pub fn pafcov<R: Read + Send>(
     mut reader: PAFReader<R>,
     writer: &mut dyn Write,
+    group_by: GroupBy,
 ) -> Result<(), WGAError> {
     // let mut cov_map: HashMap<String, Vec<usize>> = HashMap::new();
     // for rec in reader.records() {
@@ -52,6 +58,10 @@ pub fn pafcov<R: Read + Send>(
             Ok(acc)
         })?;
 
+    if group_by != GroupBy::Sequence {
+        return write_group_cov(cov_map, writer, group_by);
+    }
+
     // Output in BED format
     for (target, coverage) in cov_map {
         for (pos, count) in coverage.iter().enumerate() {
@@ -81,3 +91,34 @@ pub fn pafcov<R: Read + Send>(
     // }
     Ok(())
 }
+
+// size, covered bases, coverage and mean depth of each group of targets
+fn write_group_cov(
+    cov_map: HashMap<String, Vec<usize>>,
+    writer: &mut dyn Write,
+    group_by: GroupBy,
+) -> Result<(), WGAError> {
+    // size, covered bases and sum of depth
+    let mut groups: HashMap<&str, (u64, u64, u64)> = HashMap::new();
+    for (target, coverage) in &cov_map {
+        let group = groups.entry(group_by.group(target)).or_default();
+        group.0 += coverage.len() as u64;
+        group.1 += coverage.iter().filter(|count| **count > 0).count() as u64;
+        group.2 += coverage.iter().map(|count| *count as u64).sum::<u64>();
+    }
+    let mut groups = groups.into_iter().collect::<Vec<_>>();
+    groups.sort_by(|a, b| natord::compare(a.0, b.0));
+    writeln!(writer, "group\tsize\tcovered\tcoverage\tmean_depth")?;
+    for (group, (size, covered, depth)) in groups {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{:.4}\t{:.4}",
+            group,
+            size,
+            covered,
+            covered as f64 / size.max(1) as f64,
+            depth as f64 / size.max(1) as f64
+        )?;
+    }
+    Ok(())
+}
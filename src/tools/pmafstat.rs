@@ -0,0 +1,172 @@
+use crate::{
+    errors::WGAError,
+    log::empty_input,
+    parser::{
+        common::AlignRecord,
+        maf::{MAFReader, MAFRecord},
+    },
+};
+use rayon::prelude::*;
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Aligned and matched bases of a genome in a window
+#[derive(Debug, Default, Clone, Copy)]
+struct WindowCount {
+    aligned: u64,
+    matched: u64,
+}
+
+/// Window counts of all genomes on a target
+struct TargetWindows {
+    name: String,
+    size: u64,
+    genomes: HashMap<String, Vec<WindowCount>>,
+}
+
+// main function of window-averaged statistics for pseudo MAFs from `pafpseudo`
+pub fn pmaf_stat(
+    input_dir: &Path,
+    writer: &mut dyn Write,
+    window: u64,
+    min_cov: f64,
+) -> Result<(), WGAError> {
+    // each target is a `.maf` file in dir
+    let mut maf_paths = std::fs::read_dir(input_dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<PathBuf>, _>>()?;
+    maf_paths.retain(|path| path.extension().is_some_and(|ext| ext == "maf"));
+
+    let targets = maf_paths
+        .par_iter()
+        .map(|path| count_pmaf_windows(path, window))
+        .collect::<Result<Vec<_>, WGAError>>()?;
+    // pseudo MAFs without records are skipped
+    let mut targets = targets.into_iter().flatten().collect::<Vec<_>>();
+    targets.sort_by(|a, b| natord::compare(&a.name, &b.name));
+
+    writer.write_all(b"target\tstart\tend\tmetric\tvalue\n")?;
+    for target in targets {
+        let genome_count = target.genomes.len();
+        let window_count = target.size.div_ceil(window) as usize;
+        for idx in 0..window_count {
+            let start = idx as u64 * window;
+            let end = (start + window).min(target.size);
+            let window_len = (end - start) as f64;
+
+            // genomes covering enough bases in this window
+            let aligned = target
+                .genomes
+                .values()
+                .map(|counts| counts[idx])
+                .filter(|count| covers_window(count, window_len, min_cov))
+                .collect::<Vec<WindowCount>>();
+
+            let avg_divergence = match aligned.len() {
+                0 => "NA".to_string(),
+                n => {
+                    let divergence_sum = aligned
+                        .iter()
+                        .map(|count| 1.0 - count.matched as f64 / count.aligned as f64)
+                        .sum::<f64>();
+                    format!("{:.6}", divergence_sum / n as f64)
+                }
+            };
+            let aligned_fraction = match genome_count {
+                0 => "NA".to_string(),
+                n => format!("{:.6}", aligned.len() as f64 / n as f64),
+            };
+            writeln!(
+                writer,
+                "{}\t{}\t{}\tavg_divergence\t{}",
+                target.name, start, end, avg_divergence
+            )?;
+            writeln!(
+                writer,
+                "{}\t{}\t{}\taligned_fraction\t{}",
+                target.name, start, end, aligned_fraction
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// a genome counts as aligned in a window if it covers at least `min_cov` of it
+fn covers_window(count: &WindowCount, window_len: f64, min_cov: f64) -> bool {
+    count.aligned > 0 && count.aligned as f64 / window_len >= min_cov
+}
+
+// count aligned and matched bases in each window for every query row of a pseudo MAF
+fn count_pmaf_windows(path: &Path, window: u64) -> Result<Option<TargetWindows>, WGAError> {
+    let mut mafreader = MAFReader::from_path(path)?;
+    let mut target: Option<TargetWindows> = None;
+    for rec in mafreader.records() {
+        let rec = rec?;
+        let target = target.get_or_insert_with(|| TargetWindows {
+            name: rec.target_name().to_string(),
+            size: rec.target_length(),
+            genomes: HashMap::new(),
+        });
+        count_block_windows(&rec, target, window);
+    }
+    if target.is_none() {
+        empty_input(&path.to_string_lossy())?;
+    }
+    Ok(target)
+}
+
+fn count_block_windows(rec: &MAFRecord, target: &mut TargetWindows, window: u64) {
+    let window_count = target.size.div_ceil(window) as usize;
+    let t_sline = &rec.slines[0];
+    for q_sline in &rec.slines[1..] {
+        let counts = target
+            .genomes
+            .entry(q_sline.name.clone())
+            .or_insert_with(|| vec![WindowCount::default(); window_count]);
+        let mut t_pos = t_sline.start;
+        for (t_base, q_base) in t_sline.seq.bytes().zip(q_sline.seq.bytes()) {
+            // columns not on target are skipped
+            if t_base == b'-' {
+                continue;
+            }
+            let idx = (t_pos / window) as usize;
+            t_pos += 1;
+            // `1`/`0` for match/mismatch if pseudo MAF is generated without FASTA
+            let matched = match q_base {
+                b'-' => continue,
+                b'1' => true,
+                b'0' => false,
+                base => base.eq_ignore_ascii_case(&t_base),
+            };
+            if let Some(count) = counts.get_mut(idx) {
+                count.aligned += 1;
+                count.matched += matched as u64;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_cov_includes_boundary() {
+        let full = WindowCount {
+            aligned: 100,
+            matched: 90,
+        };
+        let half = WindowCount {
+            aligned: 50,
+            matched: 50,
+        };
+        assert!(covers_window(&full, 100.0, 1.0));
+        assert!(covers_window(&half, 100.0, 0.5));
+        assert!(!covers_window(&half, 100.0, 0.51));
+        // uncovered windows never count, even with `--min-cov 0`
+        assert!(!covers_window(&WindowCount::default(), 100.0, 0.0));
+    }
+}
@@ -0,0 +1,437 @@
+use crate::errors::WGAError;
+use crate::log::WarnCategory;
+use crate::parser::common::Strand;
+use crate::parser::maf::{MAFReader, MAFRecord, MAFSLine, MAFWriter};
+use crate::utils::{par_write_ordered, reverse_complement, IndexedFasta};
+use bio::alignment::pairwise::Aligner;
+use bio::alignment::AlignmentOperation;
+use log::{info, warn};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// affine gap scores of the re-alignment, same as minimap2 default
+const MATCH_SCORE: i32 = 2;
+const MISMATCH_SCORE: i32 = -4;
+const GAP_OPEN: i32 = -4;
+const GAP_EXTEND: i32 = -2;
+
+/// Counts of re-aligned gap pairs
+#[derive(Debug, Default)]
+struct PolishStat {
+    intra: usize,
+    between: usize,
+    too_long: usize,
+}
+
+/// Re-align unaligned gap pairs of pairwise MAF blocks no longer than `max_gap`:
+/// an insertion adjacent to a deletion within a block, and the gaps between consecutive
+/// blocks of the same target and query if both FASTA are given, which joins the blocks.
+/// Blocks with more than two s-lines are written unchanged.
+pub fn polish_maf<R: Read + Send>(
+    mut mafreader: MAFReader<R>,
+    writer: &mut dyn Write,
+    max_gap: usize,
+    fastas: Option<(&str, &str)>,
+) -> Result<(), WGAError> {
+    let header = format!("#maf version=1.6 polish_max_gap={}", max_gap);
+    MAFWriter::new(&mut *writer).write_header(header)?;
+
+    let mut stat = PolishStat::default();
+    match fastas {
+        // blocks are sorted to be joined, so all of them are read first
+        Some((target_path, query_path)) => {
+            let records = mafreader.records().collect::<Result<Vec<_>, _>>()?;
+            let mut records = join_blocks(records, target_path, query_path, max_gap, &mut stat)?;
+            let counts = records
+                .par_iter_mut()
+                .map(|rec| polish_block(rec, max_gap))
+                .collect::<Vec<_>>();
+            for (polished, too_long) in counts {
+                stat.intra += polished;
+                stat.too_long += too_long;
+            }
+            let mut mafwtr = MAFWriter::new(writer);
+            for rec in &records {
+                mafwtr.write_record(rec)?;
+            }
+        }
+        // blocks are polished on their own, so they are streamed in input order
+        None => {
+            let (intra, too_long) = (AtomicUsize::new(0), AtomicUsize::new(0));
+            par_write_ordered(mafreader.records(), writer, |_, mut rec| {
+                let (polished, long) = polish_block(&mut rec, max_gap);
+                intra.fetch_add(polished, Ordering::Relaxed);
+                too_long.fetch_add(long, Ordering::Relaxed);
+                let mut buf = Vec::new();
+                MAFWriter::new(&mut buf).write_record(&rec)?;
+                Ok(buf)
+            })?;
+            stat.intra = intra.into_inner();
+            stat.too_long = too_long.into_inner();
+        }
+    }
+    info!(
+        "re-aligned {} gap pairs within blocks and {} between blocks, {} longer than {} are kept",
+        stat.intra, stat.between, stat.too_long, max_gap
+    );
+    Ok(())
+}
+
+// re-align gap pairs within a pairwise block, returns counts of polished and too long ones
+fn polish_block(rec: &mut MAFRecord, max_gap: usize) -> (usize, usize) {
+    if rec.slines.len() != 2 {
+        return (0, 0);
+    }
+    let t_seq = rec.slines[0].seq.as_bytes();
+    let q_seq = rec.slines[1].seq.as_bytes();
+    let aligned = |col: usize| t_seq[col] != b'-' && q_seq[col] != b'-';
+
+    let mut t_out = Vec::with_capacity(t_seq.len());
+    let mut q_out = Vec::with_capacity(q_seq.len());
+    let (mut polished, mut too_long) = (0, 0);
+    let mut col = 0;
+    while col < t_seq.len() {
+        if aligned(col) {
+            t_out.push(t_seq[col]);
+            q_out.push(q_seq[col]);
+            col += 1;
+            continue;
+        }
+        // a run of columns with gap on either side
+        let run_end = (col..t_seq.len())
+            .find(|&c| aligned(c))
+            .unwrap_or(t_seq.len());
+        let t_bases = ungap(&t_seq[col..run_end]);
+        let q_bases = ungap(&q_seq[col..run_end]);
+        // a pure insertion or deletion has nothing to re-align
+        if t_bases.is_empty() || q_bases.is_empty() {
+            t_out.extend_from_slice(&t_seq[col..run_end]);
+            q_out.extend_from_slice(&q_seq[col..run_end]);
+        } else if t_bases.len() > max_gap || q_bases.len() > max_gap {
+            t_out.extend_from_slice(&t_seq[col..run_end]);
+            q_out.extend_from_slice(&q_seq[col..run_end]);
+            too_long += 1;
+        } else {
+            let (t_aln, q_aln) = align_pair(&t_bases, &q_bases);
+            t_out.extend(t_aln);
+            q_out.extend(q_aln);
+            polished += 1;
+        }
+        col = run_end;
+    }
+
+    // only gap characters are moved, bases and coordinates are kept
+    rec.slines[0].seq = String::from_utf8(t_out).expect("bases are ASCII");
+    rec.slines[1].seq = String::from_utf8(q_out).expect("bases are ASCII");
+    // tags of the original alignment are outdated
+    if polished > 0 {
+        rec.tags.clear();
+    }
+    (polished, too_long)
+}
+
+// sort pairwise blocks by target and join consecutive ones of the same target, query and strand
+// by the alignment of sequences between them
+fn join_blocks(
+    records: Vec<MAFRecord>,
+    target_path: &str,
+    query_path: &str,
+    max_gap: usize,
+    stat: &mut PolishStat,
+) -> Result<Vec<MAFRecord>, WGAError> {
+    let t_fasta = IndexedFasta::from_path(target_path)?;
+    let q_fasta = IndexedFasta::from_path(query_path)?;
+
+    let (mut pairwise, others): (Vec<_>, Vec<_>) =
+        records.into_iter().partition(|rec| rec.slines.len() == 2);
+    pairwise.sort_by(|a, b| join_key(a).cmp(&join_key(b)));
+
+    // fetch sequences between consecutive blocks sequentially, then align them in parallel
+    let mut gaps = Vec::new();
+    let mut missing_names = HashSet::new();
+    for (idx, pair) in pairwise.windows(2).enumerate() {
+        let (prev, next) = (&pair[0], &pair[1]);
+        let same_seqs = prev.slines.iter().zip(&next.slines).all(|(a, b)| {
+            a.name == b.name && a.strand == b.strand && a.start + a.align_size <= b.start
+        });
+        if !same_seqs {
+            continue;
+        }
+        let missing = [(&t_fasta, &prev.slines[0]), (&q_fasta, &prev.slines[1])]
+            .into_iter()
+            .filter(|(fasta, sline)| !fasta.contains(&sline.name))
+            .map(|(_, sline)| sline.name.clone())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            for name in missing {
+                if missing_names.insert(name.clone()) {
+                    warn!(
+                        target: WarnCategory::Input.target(),
+                        "sequence `{}` not found in FASTA, its gaps between blocks are kept",
+                        name
+                    );
+                }
+            }
+            continue;
+        }
+        let t_len = next.slines[0].start - prev.slines[0].start - prev.slines[0].align_size;
+        let q_len = next.slines[1].start - prev.slines[1].start - prev.slines[1].align_size;
+        if t_len as usize > max_gap || q_len as usize > max_gap {
+            stat.too_long += 1;
+            continue;
+        }
+        let t_bases = fetch_between(&t_fasta, &prev.slines[0], &next.slines[0])?;
+        let q_bases = fetch_between(&q_fasta, &prev.slines[1], &next.slines[1])?;
+        if let (Some(t_bases), Some(q_bases)) = (t_bases, q_bases) {
+            gaps.push((idx, t_bases, q_bases));
+        }
+    }
+    let mut aligned_gaps = gaps
+        .into_par_iter()
+        .map(|(idx, t_bases, q_bases)| {
+            let (t_aln, q_aln) = match (t_bases.is_empty(), q_bases.is_empty()) {
+                (false, false) => align_pair(&t_bases, &q_bases),
+                // a pure insertion or deletion, or contiguous blocks
+                _ => pad_gaps(t_bases, q_bases),
+            };
+            (idx, t_aln, q_aln)
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .peekable();
+
+    let mut joined: Vec<MAFRecord> = Vec::with_capacity(pairwise.len() + others.len());
+    for (idx, rec) in pairwise.into_iter().enumerate() {
+        // gap before this block is indexed by the previous one
+        let gap = match idx {
+            0 => None,
+            _ => aligned_gaps.next_if(|(gap_idx, _, _)| *gap_idx == idx - 1),
+        };
+        match (gap, joined.last_mut()) {
+            (Some((_, t_aln, q_aln)), Some(last)) => {
+                if !t_aln.is_empty() {
+                    stat.between += 1;
+                }
+                for ((sline, next_sline), aln) in
+                    last.slines.iter_mut().zip(rec.slines).zip([t_aln, q_aln])
+                {
+                    sline.align_size = next_sline.start + next_sline.align_size - sline.start;
+                    sline
+                        .seq
+                        .push_str(std::str::from_utf8(&aln).expect("bases are ASCII"));
+                    sline.seq.push_str(&next_sline.seq);
+                }
+                last.score += rec.score;
+                last.tags.clear();
+            }
+            _ => joined.push(rec),
+        }
+    }
+    joined.extend(others);
+    Ok(joined)
+}
+
+fn join_key(rec: &MAFRecord) -> (&str, &str, bool, bool, u64) {
+    let (t, q) = (&rec.slines[0], &rec.slines[1]);
+    (
+        &t.name,
+        &q.name,
+        t.strand == Strand::Negative,
+        q.strand == Strand::Negative,
+        t.start,
+    )
+}
+
+// global alignment of a pair of sequences, case is ignored for scoring
+fn align_pair(t_bases: &[u8], q_bases: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let score = |a: u8, b: u8| match a.eq_ignore_ascii_case(&b) {
+        true => MATCH_SCORE,
+        false => MISMATCH_SCORE,
+    };
+    let mut aligner =
+        Aligner::with_capacity(t_bases.len(), q_bases.len(), GAP_OPEN, GAP_EXTEND, score);
+    let alignment = aligner.global(t_bases, q_bases);
+
+    let mut t_aln = Vec::with_capacity(alignment.operations.len());
+    let mut q_aln = Vec::with_capacity(alignment.operations.len());
+    let (mut t_idx, mut q_idx) = (0, 0);
+    for op in alignment.operations {
+        match op {
+            AlignmentOperation::Match | AlignmentOperation::Subst => {
+                t_aln.push(t_bases[t_idx]);
+                q_aln.push(q_bases[q_idx]);
+                t_idx += 1;
+                q_idx += 1;
+            }
+            // `Ins` consumes the first sequence only
+            AlignmentOperation::Ins => {
+                t_aln.push(t_bases[t_idx]);
+                q_aln.push(b'-');
+                t_idx += 1;
+            }
+            AlignmentOperation::Del => {
+                t_aln.push(b'-');
+                q_aln.push(q_bases[q_idx]);
+                q_idx += 1;
+            }
+            // no clipping in global mode
+            AlignmentOperation::Xclip(_) | AlignmentOperation::Yclip(_) => {}
+        }
+    }
+    (t_aln, q_aln)
+}
+
+// align a sequence against nothing
+fn pad_gaps(mut t_bases: Vec<u8>, mut q_bases: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+    t_bases.resize(t_bases.len().max(q_bases.len()), b'-');
+    q_bases.resize(t_bases.len(), b'-');
+    (t_bases, q_bases)
+}
+
+fn ungap(seq: &[u8]) -> Vec<u8> {
+    seq.iter().filter(|&&b| b != b'-').copied().collect()
+}
+
+// fetch bases between two s-lines on their strand, None if the sequence is not in FASTA
+fn fetch_between(
+    fasta: &IndexedFasta,
+    prev: &MAFSLine,
+    next: &MAFSLine,
+) -> Result<Option<Vec<u8>>, WGAError> {
+    let start = prev.start + prev.align_size;
+    if start == next.start {
+        return Ok(Some(Vec::new()));
+    }
+    // coordinates of negative strand are on the reverse complement
+    let (fwd_start, fwd_end) = match prev.strand {
+        Strand::Positive => (start, next.start),
+        Strand::Negative => (prev.size - next.start, prev.size - start),
+    };
+    let Some(seq) = fasta.fetch(&prev.name, fwd_start as usize, fwd_end as usize - 1)? else {
+        return Ok(None);
+    };
+    let seq = match prev.strand {
+        Strand::Positive => seq,
+        Strand::Negative => reverse_complement(&seq)?,
+    };
+    Ok(Some(seq.into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REF_FA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test/fasta/ref.fa");
+
+    fn read_blocks(maf: &str) -> Vec<MAFRecord> {
+        MAFReader::new(maf.as_bytes())
+            .unwrap()
+            .records()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    fn pair_block(target: &str, query: &str) -> MAFRecord {
+        let size = |seq: &str| seq.chars().filter(|c| *c != '-').count();
+        read_blocks(&format!(
+            "##maf version=1\na score=0\ns t 0 {} + 1000 {}\ns q 0 {} + 1000 {}\n\n",
+            size(target),
+            target,
+            size(query),
+            query
+        ))
+        .remove(0)
+    }
+
+    fn seqs(rec: &MAFRecord) -> (&str, &str) {
+        (&rec.slines[0].seq, &rec.slines[1].seq)
+    }
+
+    #[test]
+    fn polish_insertion_next_to_deletion() {
+        let mut rec = pair_block("ACG--TTACG", "ACGTT--ACG");
+        assert_eq!(polish_block(&mut rec, 10), (1, 0));
+        assert_eq!(seqs(&rec), ("ACGTTACG", "ACGTTACG"));
+        // coordinates are kept
+        assert_eq!(rec.slines[0].align_size, 8);
+
+        // gaps longer than `max_gap` are kept
+        let mut rec = pair_block("ACG--TTACG", "ACGTT--ACG");
+        assert_eq!(polish_block(&mut rec, 1), (0, 1));
+        assert_eq!(seqs(&rec), ("ACG--TTACG", "ACGTT--ACG"));
+    }
+
+    #[test]
+    fn pure_indels_unchanged() {
+        for (target, query) in [("ACG--ACG", "ACGTTACG"), ("ACGTTACG", "ACG--ACG")] {
+            let mut rec = pair_block(target, query);
+            assert_eq!(polish_block(&mut rec, 10), (0, 0));
+            assert_eq!(seqs(&rec), (target, query));
+        }
+    }
+
+    #[test]
+    fn align_pair_orientation() {
+        // base only in target is a deletion, gap in query
+        let (t_aln, q_aln) = align_pair(b"ACGTACGT", b"ACGACGT");
+        assert_eq!(
+            (&t_aln[..], &q_aln[..]),
+            (&b"ACGTACGT"[..], &b"ACG-ACGT"[..])
+        );
+        let (t_aln, q_aln) = align_pair(b"ACGACGT", b"ACGTACGT");
+        assert_eq!(
+            (&t_aln[..], &q_aln[..]),
+            (&b"ACG-ACGT"[..], &b"ACGTACGT"[..])
+        );
+    }
+
+    #[test]
+    fn fetch_between_on_negative_strand() {
+        let fasta = IndexedFasta::from_path(REF_FA).unwrap();
+        // chr1 AACCGGTTACGTTGCAAGCT, its reverse complement AGCTTGCAACGTAACCGGTT
+        let recs = read_blocks(
+            "##maf version=1\na score=0\ns chr1 2 3 - 20 CTT\n\na score=0\ns chr1 8 2 - 20 AC\n\n",
+        );
+        let bases = fetch_between(&fasta, &recs[0].slines[0], &recs[1].slines[0]).unwrap();
+        assert_eq!(bases.unwrap(), b"GCA");
+        // nothing between contiguous blocks
+        let recs = read_blocks(
+            "##maf version=1\na score=0\ns chr1 2 3 - 20 CTT\n\na score=0\ns chr1 5 2 - 20 GC\n\n",
+        );
+        let bases = fetch_between(&fasta, &recs[0].slines[0], &recs[1].slines[0]).unwrap();
+        assert!(bases.unwrap().is_empty());
+    }
+
+    #[test]
+    fn join_contiguous_and_gapped_blocks() {
+        // chr1 AACCGGTTACGTTGCAAGCT, chr2 GATTACAGATTACA, blocks are joined after sorting
+        let recs = read_blocks(
+            "##maf version=1
+a score=1
+s chr1 4 4 + 20 GGTT
+s chr2 4 4 + 14 ACAG
+
+a score=2
+s chr1 0 4 + 20 AACC
+s chr2 0 4 + 14 GATT
+
+a score=3
+s chr1 10 2 + 20 GT
+s chr2 9 2 + 14 TT
+
+",
+        );
+        let mut stat = PolishStat::default();
+        let joined = join_blocks(recs, REF_FA, REF_FA, 10, &mut stat).unwrap();
+        assert_eq!(joined.len(), 1);
+        let rec = &joined[0];
+        // contiguous blocks are joined as is, `AC` and `A` between the last two are aligned
+        assert_eq!(seqs(rec), ("AACCGGTTACGT", "GATTACAGA-TT"));
+        assert_eq!(rec.slines[0].align_size, 12);
+        assert_eq!(rec.slines[1].align_size, 11);
+        assert_eq!(rec.score, 6);
+        assert_eq!(stat.between, 1);
+    }
+}
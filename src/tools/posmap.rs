@@ -0,0 +1,287 @@
+use crate::errors::WGAError;
+use crate::log::WarnCategory;
+use crate::parser::chain::ChainReader;
+use crate::parser::cigar::{parse_chain_to_blocks, parse_cigar_to_blocks, parse_maf_seq_to_blocks};
+use crate::parser::common::{AlignRecord, Block, LiftFrom, Strand};
+use crate::parser::maf::{MAFReader, MAFRecord};
+use crate::parser::paf::PAFReader;
+use crate::tools::index::MafIndex;
+use log::{info, warn};
+use std::fmt::Display;
+use std::io::{Read, Write};
+
+const POS_MAP_HEADER: &[u8] = b"at\tname\tpos\tstrand\ttarget_name\ttarget_start\ttarget_end\tquery_name\tquery_start\tquery_end\n";
+
+type BlocksFn<T> = fn(&T) -> Result<Vec<Block<'_>>, WGAError>;
+
+/// A position to map, `pos` is 1-based on `side` of alignments
+#[derive(Debug)]
+pub struct MapPos {
+    pub side: LiftFrom,
+    pub name: String,
+    pub pos: u64,
+}
+
+impl TryFrom<&str> for MapPos {
+    type Error = WGAError;
+    /// Parse `target:name:pos` or `query:name:pos`, the name may contain `:`
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let invalid = || {
+            WGAError::InvalidArgs(format!(
+                "invalid position `{}`, expect `target:name:pos` or `query:name:pos` with 1-based pos",
+                value
+            ))
+        };
+        let (side, rest) = value.split_once(':').ok_or_else(invalid)?;
+        let (name, pos) = rest.rsplit_once(':').ok_or_else(invalid)?;
+        let side = match side {
+            "target" => LiftFrom::Target,
+            "query" => LiftFrom::Query,
+            _ => return Err(invalid()),
+        };
+        let pos = pos.parse::<u64>().map_err(|_| invalid())?;
+        if name.is_empty() || pos == 0 {
+            return Err(invalid());
+        }
+        Ok(MapPos {
+            side,
+            name: name.to_string(),
+            pos,
+        })
+    }
+}
+
+impl Display for MapPos {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let side = match self.side {
+            LiftFrom::Target => "target",
+            LiftFrom::Query => "query",
+        };
+        write!(f, "{}:{}:{}", side, self.name, self.pos)
+    }
+}
+
+impl MapPos {
+    // whether the position is in the span of record on its side
+    fn hit<T: AlignRecord>(&self, rec: &T) -> bool {
+        let pos = self.pos - 1;
+        match self.side {
+            LiftFrom::Target => {
+                rec.target_name() == self.name
+                    && rec.target_start() <= pos
+                    && pos < rec.target_end()
+            }
+            LiftFrom::Query => {
+                rec.query_name() == self.name && rec.query_start() <= pos && pos < rec.query_end()
+            }
+        }
+    }
+
+    // a TSV line of the mapped position with the record span, `.` if it is in a gap of record
+    fn map_line<T: AlignRecord>(&self, rec: &T, blocks: &[Block]) -> String {
+        let pos = self.pos - 1;
+        let mapped = blocks.iter().find_map(|block| {
+            let (start, end) = match self.side {
+                LiftFrom::Target => (block.target_start, block.target_end),
+                LiftFrom::Query => (block.query_start, block.query_end),
+            };
+            if pos < start || pos >= end {
+                return None;
+            }
+            // query of block is on the forward strand, so a `-` block maps in reverse
+            let offset = pos - start;
+            Some(match (self.side, block.strand) {
+                (LiftFrom::Target, Strand::Positive) => block.query_start + offset,
+                (LiftFrom::Target, Strand::Negative) => block.query_end - 1 - offset,
+                (LiftFrom::Query, Strand::Positive) => block.target_start + offset,
+                (LiftFrom::Query, Strand::Negative) => block.target_end - 1 - offset,
+            })
+        });
+        let dest = match self.side {
+            LiftFrom::Target => rec.query_name(),
+            LiftFrom::Query => rec.target_name(),
+        };
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self,
+            dest,
+            mapped.map_or(".".to_string(), |pos| (pos + 1).to_string()),
+            rec.query_strand(),
+            rec.target_name(),
+            rec.target_start(),
+            rec.target_end(),
+            rec.query_name(),
+            rec.query_start(),
+            rec.query_end()
+        )
+    }
+}
+
+/// Mapped lines of each position, in order of records
+struct PosHits<'a> {
+    positions: &'a [MapPos],
+    lines: Vec<Vec<String>>,
+}
+
+impl<'a> PosHits<'a> {
+    fn new(positions: &'a [MapPos]) -> Self {
+        PosHits {
+            positions,
+            lines: vec![Vec::new(); positions.len()],
+        }
+    }
+
+    // map positions hit by a record, blocks are parsed only if any position is hit
+    fn add_record<T: AlignRecord>(&mut self, rec: &T, parse: BlocksFn<T>) -> Result<(), WGAError> {
+        let mut blocks = None;
+        for (pos, lines) in self.positions.iter().zip(self.lines.iter_mut()) {
+            if !pos.hit(rec) {
+                continue;
+            }
+            if blocks.is_none() {
+                blocks = Some(parse(rec)?);
+            }
+            lines.push(pos.map_line(rec, blocks.as_deref().unwrap_or_default()));
+        }
+        Ok(())
+    }
+
+    // map positions hit by a MAF block, a query position picks the s-line of its name as query
+    fn add_maf_record(&mut self, rec: &mut MAFRecord) -> Result<(), WGAError> {
+        self.add_record(rec, parse_maf_seq_to_blocks)?;
+        let query_idx = rec.query_idx;
+        for (pos, lines) in self.positions.iter().zip(self.lines.iter_mut()) {
+            if !matches!(pos.side, LiftFrom::Query) || pos.name == rec.query_name() {
+                continue;
+            }
+            match rec.get_query_idx_byname(&pos.name) {
+                Some(idx) if idx > 0 => rec.set_query_idx(idx),
+                _ => continue,
+            }
+            if pos.hit(rec) {
+                lines.push(pos.map_line(rec, &parse_maf_seq_to_blocks(rec)?));
+            }
+            rec.set_query_idx(query_idx);
+        }
+        Ok(())
+    }
+
+    fn write(self, writer: &mut dyn Write) -> Result<(), WGAError> {
+        writer.write_all(POS_MAP_HEADER)?;
+        let mut unmapped = 0;
+        for (pos, lines) in self.positions.iter().zip(self.lines) {
+            if lines.is_empty() {
+                unmapped += 1;
+                writeln!(writer, "{}\t.\t.\t.\t.\t.\t.\t.\t.\t.", pos)?;
+            }
+            for line in lines {
+                writeln!(writer, "{}", line)?;
+            }
+        }
+        if unmapped > 0 {
+            warn!(
+                target: WarnCategory::Result.target(),
+                "{} of {} positions not in any record",
+                unmapped,
+                self.positions.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Map positions through all records of MAF, `query_name` picks the query of target positions
+pub fn pos_map_maf<R: Read + Send>(
+    reader: &mut MAFReader<R>,
+    positions: &[MapPos],
+    query_name: Option<&str>,
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    let mut hits = PosHits::new(positions);
+    for rec in reader.records() {
+        let mut rec = rec?;
+        if let Some(qname) = query_name {
+            rec.set_query_idx_byname(qname)?;
+        }
+        hits.add_maf_record(&mut rec)?;
+    }
+    hits.write(writer)
+}
+
+/// Map positions through MAF with index, only blocks containing a position are read
+pub fn pos_map_maf_idx(
+    maf_path: &str,
+    mafidx: &MafIndex,
+    positions: &[MapPos],
+    query_name: Option<&str>,
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    let mut mafreader = MAFReader::from_path(maf_path)?;
+    let mut lines = Vec::with_capacity(positions.len());
+    let mut seeks = 0;
+    // map one position at a time, so a block containing several positions is not mapped twice
+    for pos in positions.chunks(1) {
+        let mut hits = PosHits::new(pos);
+        for offset in index_offsets(mafidx, &pos[0]) {
+            mafreader.seek(offset)?;
+            let mut rec = mafreader.records().next().ok_or(WGAError::EmptyRecord)??;
+            if let Some(qname) = query_name {
+                rec.set_query_idx_byname(qname)?;
+            }
+            hits.add_maf_record(&mut rec)?;
+            seeks += 1;
+        }
+        lines.extend(hits.lines);
+    }
+    info!("read {} blocks by index", seeks);
+    PosHits { positions, lines }.write(writer)
+}
+
+// offsets of indexed blocks containing the position on any strand, in order of file
+fn index_offsets(mafidx: &MafIndex, pos: &MapPos) -> Vec<u64> {
+    let Some(item) = mafidx.get(&pos.name) else {
+        return Vec::new();
+    };
+    let pos = pos.pos - 1;
+    let mut offsets = item
+        .ivls
+        .iter()
+        .filter(|ivp| {
+            let pos = match ivp.strand {
+                Strand::Positive => pos,
+                Strand::Negative => item.size.saturating_sub(pos + 1),
+            };
+            ivp.start <= pos && pos < ivp.end
+        })
+        .map(|ivp| ivp.offset)
+        .collect::<Vec<_>>();
+    offsets.sort_unstable();
+    offsets.dedup();
+    offsets
+}
+
+/// Map positions through all records of PAF
+pub fn pos_map_paf<R: Read + Send>(
+    reader: &mut PAFReader<R>,
+    positions: &[MapPos],
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    let mut hits = PosHits::new(positions);
+    for rec in reader.records() {
+        hits.add_record(&rec?, parse_cigar_to_blocks)?;
+    }
+    hits.write(writer)
+}
+
+/// Map positions through all records of Chain
+pub fn pos_map_chain<R: Read + Send>(
+    reader: &mut ChainReader<R>,
+    positions: &[MapPos],
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    let mut hits = PosHits::new(positions);
+    for rec in reader.records()? {
+        hits.add_record(&rec?, parse_chain_to_blocks)?;
+    }
+    hits.write(writer)
+}
@@ -1,17 +1,20 @@
 use crate::{
     errors::WGAError,
+    log::{profile, Stage},
     parser::{
         cigar::gen_pesudo_maf_by_cigar,
         common::{AlignRecord, Strand},
+        maf::MafWriteConfig,
         paf::{PAFReader, PafRecord},
     },
-    utils::reverse_complement,
+    utils::{create_output_writer, reverse_complement, write_repeat},
 };
 use rayon::prelude::*;
 use rust_htslib::faidx;
 use std::{
     collections::HashMap,
-    io::{BufWriter, Read, Write},
+    io::{Read, Write},
+    path::Path,
 };
 
 // main function of generate pesudo MAF from PAF
@@ -65,7 +68,7 @@ pub fn generate_pesudo_maf<R: Read + Send>(
             let mut out_path = out_dir.to_string();
             out_path.push_str(&format!("/{}.maf", target_name));
             let fa_path = fa_path.clone();
-            let mut writer = std::fs::File::create(out_path)?;
+            let mut writer = create_output_writer(Path::new(&out_path))?;
             write_pmaf(&mut writer, rec_vec, &target_name, &fa_path)?;
             Ok::<(), WGAError>(())
         })
@@ -93,7 +96,27 @@ fn write_pmaf(
             .unwrap_or_else(|e| e);
         query_rec_vec.insert(idx, rec);
     }
-    let mut writer = BufWriter::new(writer);
+    // s-lines of names and sizes, padded to the widest with `--maf-pretty`
+    let pretty = MafWriteConfig::global().pretty;
+    let sizes = query_groupby_map.values().filter_map(|recs| recs.first());
+    let name_w = sizes
+        .clone()
+        .map(|rec| rec.query_name.len())
+        .chain([target_name.len()])
+        .max()
+        .unwrap_or(0);
+    let size_w = sizes
+        .flat_map(|rec| [rec.query_length, rec.target_length])
+        .map(|size| size.to_string().len())
+        .max()
+        .unwrap_or(0);
+    let sline_head = |name: &str, size: u64| match pretty {
+        true => format!(
+            "s {:<name_w$} 0 {:>size_w$} + {:>size_w$} ",
+            name, size, size
+        ),
+        false => format!("s\t{}\t0\t{}\t+\t{}\t", name, size, size),
+    };
     // start output
     // writeln!(writer, "a score=0")?;
     writer.write_all(b"a score=0\n")?;
@@ -113,11 +136,7 @@ fn write_pmaf(
             target_size = rec.target_length();
             // let target_start = rec.target_start();
             if first_flag {
-                write!(
-                    writer,
-                    "s\t{}\t0\t{}\t+\t{}\t",
-                    target_name, target_size, target_size
-                )?;
+                writer.write_all(sline_head(target_name, target_size).as_bytes())?;
                 let whole_t_seq = get_sline_seq(fa_path, target_name, (0, target_size), true)?;
                 // writeln!(writer, "{}", whole_t_seq)?;
                 writer.write_all(whole_t_seq.as_bytes())?;
@@ -129,11 +148,7 @@ fn write_pmaf(
                 // start write query s-line
                 let query_name = rec.query_name();
                 let query_size = rec.query_length();
-                write!(
-                    writer,
-                    "s\t{}\t0\t{}\t+\t{}\t",
-                    query_name, query_size, query_size
-                )?;
+                writer.write_all(sline_head(query_name, query_size).as_bytes())?;
                 // fill the head with '-'
                 // for _ in 0..target_start {
                 //     write!(writer, "-")?;
@@ -152,10 +167,7 @@ fn write_pmaf(
                 //   -----  ---
                 // we need to fill the gap between two query recs
                 let gap_len = rec.target_start() - last_target_end;
-                for _ in 0..gap_len {
-                    // write!(writer, "-")?;
-                    writer.write_all(b"-")?;
-                }
+                write_repeat(writer, b'-', gap_len)?;
             } else {
                 // for this case
                 // --------------
@@ -196,16 +208,14 @@ fn write_pmaf(
         }
         // fill the tail with '-'
         let tail_len = target_size - last_target_end;
-        for _ in 0..tail_len {
-            // write!(writer, "-")?;
-            writer.write_all(b"-")?;
-        }
+        write_repeat(writer, b'-', tail_len)?;
         // new line
         writeln!(writer)?;
     }
 
     // final new line
     writeln!(writer)?;
+    writer.flush()?;
     Ok(())
 }
 
@@ -222,7 +232,9 @@ fn get_sline_seq(
             let fa_reader = faidx::Reader::from_path(path)?;
             let q_start = region.0 as usize;
             let q_end = region.1 as usize - 1; // SHIT! FAIDX
-            let raw_q_seq = fa_reader.fetch_seq_string(name, q_start, q_end)?;
+            let raw_q_seq = profile(Stage::Fetch, || {
+                fa_reader.fetch_seq_string(name, q_start, q_end)
+            })?;
             Ok(raw_q_seq)
         }
         None => {
@@ -1,23 +1,348 @@
 use crate::{
     errors::WGAError,
-    parser::maf::{MAFReader, MAFWriter},
+    parser::{
+        maf::{MAFReader, MAFRecord, MAFWriter},
+        paf::{paf_writer, PAFReader},
+    },
 };
-use std::io::{Read, Write};
-// filter maf
+use csv::ReaderBuilder;
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read, Write},
+};
+
+// blocks renamed before writing anything, so wrong prefixes fail early
+const PREVALIDATE_BLOCKS: usize = 1000;
+
+/// Rules to rename sequences, applied in order: map, regex, prefix
+#[derive(Debug, Default)]
+pub struct Renamer {
+    prefixs: Vec<String>,
+    // prefix of each genome, the part of name before the first `.`
+    genome_prefixs: HashMap<String, String>,
+    name_map: HashMap<String, String>,
+    regex: Option<Substitution>,
+    // s-line indices to apply map and regex, None for all
+    indices: Option<Vec<usize>>,
+    desc: Vec<String>,
+}
+
+/// A sed-like substitution `s/pattern/replacement/[g]`
+#[derive(Debug)]
+struct Substitution {
+    re: Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl Renamer {
+    pub fn new(
+        prefixs: &[String],
+        genome_prefixs: &[String],
+        map_file: &Option<String>,
+        regex: &Option<String>,
+        indices: &Option<Vec<usize>>,
+    ) -> Result<Self, WGAError> {
+        let mut desc = Vec::new();
+        if let Some(map_file) = map_file {
+            desc.push(format!("map={}", map_file));
+        }
+        if let Some(regex) = regex {
+            desc.push(format!("regex={}", regex));
+        }
+        if !prefixs.is_empty() {
+            desc.push(format!("rename={}", prefixs.join(";")));
+        }
+        if !genome_prefixs.is_empty() {
+            desc.push(format!("genome_prefix={}", genome_prefixs.join(";")));
+        }
+        Ok(Renamer {
+            prefixs: prefixs.to_vec(),
+            genome_prefixs: genome_prefixs
+                .iter()
+                .map(|pair| match pair.split_once('=') {
+                    Some((genome, prefix)) if !genome.is_empty() => {
+                        Ok((genome.to_string(), prefix.to_string()))
+                    }
+                    _ => Err(WGAError::InvalidArgs(format!(
+                        "`--genome-prefix` should be `genome=prefix`, got `{}`",
+                        pair
+                    ))),
+                })
+                .collect::<Result<_, _>>()?,
+            name_map: match map_file {
+                Some(map_file) => read_name_map(BufReader::new(File::open(map_file)?))?,
+                None => HashMap::new(),
+            },
+            regex: regex.as_deref().map(parse_substitution).transpose()?,
+            indices: indices.clone(),
+            desc,
+        })
+    }
+
+    /// apply map and regex to the sequence at s-line index, target is 0
+    fn substitute(&self, idx: usize, name: &str) -> String {
+        let selected = self
+            .indices
+            .as_ref()
+            .is_none_or(|indices| indices.contains(&idx));
+        let mut new_name = name.to_string();
+        if !selected {
+            return new_name;
+        }
+        if let Some(mapped) = self.name_map.get(&new_name) {
+            new_name = mapped.clone();
+        }
+        if let Some(sub) = &self.regex {
+            new_name = if sub.global {
+                sub.re.replace_all(&new_name, sub.replacement.as_str())
+            } else {
+                sub.re.replace(&new_name, sub.replacement.as_str())
+            }
+            .into_owned();
+        }
+        new_name
+    }
+
+    /// prefix of the genome of a name, error if the genome is not given
+    fn genome_prefix(&self, name: &str) -> Result<&str, WGAError> {
+        let genome = name.split_once('.').map_or(name, |(genome, _)| genome);
+        self.genome_prefixs
+            .get(genome)
+            .map(String::as_str)
+            .ok_or_else(|| {
+                WGAError::InvalidRecord(format!(
+                    "genome `{}` of `{}` is not in `--genome-prefix`",
+                    genome, name
+                ))
+            })
+    }
+
+    /// rename all s-lines of a MAF record
+    fn rename_maf_record(&self, rec: &mut MAFRecord) -> Result<(), WGAError> {
+        for (idx, sline) in rec.slines.iter_mut().enumerate() {
+            let mut new_name = self.substitute(idx, &sline.name);
+            if !self.genome_prefixs.is_empty() {
+                new_name = format!("{}{}", self.genome_prefix(&new_name)?, new_name);
+            }
+            sline.set_name(new_name);
+        }
+        if !self.prefixs.is_empty() {
+            let prefixs = self.prefixs.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+            rec.rename(&prefixs)?;
+        }
+        Ok(())
+    }
+}
+
+// rename maf, the first blocks are renamed before writing anything
 pub fn rename_maf<R: Read + Send>(
     mut reader: MAFReader<R>,
     writer: &mut dyn Write,
-    prefixs: Vec<&str>,
+    renamer: &Renamer,
 ) -> Result<(), WGAError> {
+    let rename = |rec: Result<MAFRecord, WGAError>| {
+        let mut rec = rec?;
+        renamer.rename_maf_record(&mut rec)?;
+        Ok::<_, WGAError>(rec)
+    };
+    let mut records = reader.records();
+    let head = records
+        .by_ref()
+        .take(PREVALIDATE_BLOCKS)
+        .map(rename)
+        .collect::<Result<Vec<_>, WGAError>>()?;
     // init a MAFWriter
     let mut mafwtr = MAFWriter::new(writer);
     // write header
-    let header = format!("#maf version=1.6 rename={}", prefixs.join(";"));
+    let header = format!("#maf version=1.6 {}", renamer.desc.join(" "));
     mafwtr.write_header(header)?;
+    for rec in head {
+        mafwtr.write_record(&rec)?;
+    }
+    for rec in records.map(rename) {
+        mafwtr.write_record(&rec?)?;
+    }
+    Ok(())
+}
+
+// rename paf, target is 0 and query is 1
+pub fn rename_paf<R: Read + Send>(
+    mut reader: PAFReader<R>,
+    writer: &mut dyn Write,
+    renamer: &Renamer,
+) -> Result<(), WGAError> {
+    // prefixes are checked before reading
+    let prefixs = match renamer.prefixs.as_slice() {
+        [] => None,
+        [t_prefix, q_prefix] => Some((t_prefix, q_prefix)),
+        prefixs => {
+            return Err(WGAError::SLineCountNotMatch(
+                prefixs.len(),
+                2,
+                "target,query of PAF".to_string(),
+            ))
+        }
+    };
+    let mut pafwtr = paf_writer(writer);
     for rec in reader.records() {
         let mut rec = rec?;
-        rec.rename(&prefixs)?;
-        mafwtr.write_record(&rec)?;
+        rec.target_name = renamer.substitute(0, &rec.target_name);
+        rec.query_name = renamer.substitute(1, &rec.query_name);
+        if !renamer.genome_prefixs.is_empty() {
+            rec.target_name = format!(
+                "{}{}",
+                renamer.genome_prefix(&rec.target_name)?,
+                rec.target_name
+            );
+            rec.query_name = format!(
+                "{}{}",
+                renamer.genome_prefix(&rec.query_name)?,
+                rec.query_name
+            );
+        }
+        if let Some((t_prefix, q_prefix)) = prefixs {
+            rec.target_name = format!("{}{}", t_prefix, rec.target_name);
+            rec.query_name = format!("{}{}", q_prefix, rec.query_name);
+        }
+        pafwtr.serialize(rec)?;
     }
     Ok(())
 }
+
+// read a two-column TSV of `old\tnew`
+fn read_name_map<R: Read>(reader: R) -> Result<HashMap<String, String>, WGAError> {
+    let mut rdr = ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .from_reader(reader);
+    let mut name_map = HashMap::new();
+    for result in rdr.deserialize() {
+        let (old, new): (String, String) = result?;
+        name_map.insert(old, new);
+    }
+    Ok(name_map)
+}
+
+// parse `s/pattern/replacement/[g]`, any char after `s` is the delimiter,
+// `\1` in replacement refers to the capture group
+fn parse_substitution(expr: &str) -> Result<Substitution, WGAError> {
+    let invalid = || WGAError::InvalidSubstitution(expr.to_string());
+    let mut chars = expr.chars();
+    if chars.next() != Some('s') {
+        return Err(invalid());
+    }
+    let delim = chars.next().ok_or_else(invalid)?;
+
+    // split by unescaped delimiter
+    let mut parts = vec![String::new()];
+    let mut escaped = false;
+    for c in chars {
+        if escaped {
+            if c != delim {
+                parts.last_mut().ok_or_else(invalid)?.push('\\');
+            }
+            parts.last_mut().ok_or_else(invalid)?.push(c);
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delim {
+            parts.push(String::new());
+        } else {
+            parts.last_mut().ok_or_else(invalid)?.push(c);
+        }
+    }
+    let (pattern, replacement, flags) = match parts.as_slice() {
+        [pattern, replacement, flags] => (pattern, replacement, flags.as_str()),
+        _ => return Err(invalid()),
+    };
+    let global = match flags {
+        "" => false,
+        "g" => true,
+        _ => return Err(invalid()),
+    };
+    // literal `$` is escaped for regex crate
+    let replacement = replacement.replace('$', "$$");
+    let group_re = Regex::new(r"\\([0-9])")?;
+    Ok(Substitution {
+        re: Regex::new(pattern)?,
+        replacement: group_re.replace_all(&replacement, "$${$1}").into_owned(),
+        global,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAF: &str = "a score=0\n\
+                       s hg.chr1 0 4 + 100 ACGT\n\
+                       s mm.chr1_chr1 0 4 + 100 ACGT\n\n";
+
+    fn renamer(prefixs: &[&str], genome_prefixs: &[&str], regex: Option<&str>) -> Renamer {
+        let strings = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        Renamer::new(
+            &strings(prefixs),
+            &strings(genome_prefixs),
+            &None,
+            &regex.map(String::from),
+            &None,
+        )
+        .unwrap()
+    }
+
+    fn rename(maf: &str, renamer: &Renamer) -> Result<String, WGAError> {
+        let mut out = Vec::new();
+        let result = rename_maf(MAFReader::new(maf.as_bytes())?, &mut out, renamer);
+        result.map(|_| String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn substitute_first_or_all() {
+        let sub = renamer(&[], &[], Some("s/chr/Chr/"));
+        assert_eq!(sub.substitute(1, "mm.chr1_chr1"), "mm.Chr1_chr1");
+        let sub = renamer(&[], &[], Some("s|chr|Chr|g"));
+        assert_eq!(sub.substitute(1, "mm.chr1_chr1"), "mm.Chr1_Chr1");
+        // capture groups and escaped delimiter
+        let sub = renamer(&[], &[], Some(r"s/(\w+)\.chr(\d)/\1\/\2/"));
+        assert_eq!(sub.substitute(0, "hg.chr1"), "hg/1");
+    }
+
+    #[test]
+    fn bad_substitution() {
+        for expr in ["x/a/b/", "s/a/b", "s/a/b/q", "s"] {
+            assert!(matches!(
+                parse_substitution(expr),
+                Err(WGAError::InvalidSubstitution(_))
+            ));
+        }
+        assert!(matches!(
+            parse_substitution("s/(/b/"),
+            Err(WGAError::RegexBuildError(_))
+        ));
+    }
+
+    #[test]
+    fn genome_prefix_after_substitution() {
+        let renamer = renamer(&[], &["hg=HG#1#", "mm=MM#1#"], Some("s/chr/Chr/"));
+        let out = rename(MAF, &renamer).unwrap();
+        assert!(out.contains("s\tHG#1#hg.Chr1\t"));
+        assert!(out.contains("s\tMM#1#mm.Chr1_chr1\t"));
+    }
+
+    #[test]
+    fn prevalidate_before_writing() {
+        // the 3rd block has a genome without prefix, nothing is written
+        let maf = format!(
+            "{}{}a score=0\ns hg.chr2 0 4 + 100 ACGT\ns rn.chr2 0 4 + 100 ACGT\n\n",
+            MAF, MAF
+        );
+        let renamer = renamer(&[], &["hg=HG#1#", "mm=MM#1#"], None);
+        let mut out = Vec::new();
+        let result = rename_maf(MAFReader::new(maf.as_bytes()).unwrap(), &mut out, &renamer);
+        assert!(matches!(result, Err(WGAError::InvalidRecord(_))));
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,330 @@
+use crate::{
+    errors::WGAError,
+    parser::common::{AlignRecord, Block},
+    tools::{
+        dotplot::{rec_dot_data, AllPlotdata, DOTPLOT_SPEC},
+        stat::{PairStats, Statistic},
+    },
+    utils::union_length,
+};
+use minijinja::{context, Environment};
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{collections::HashMap, io::Write};
+
+const REPORT_TEMP: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>wgatools report: {{ title }}</title>
+    <script src="https://cdn.jsdelivr.net/npm/vega@5"></script>
+    <script src="https://cdn.jsdelivr.net/npm/vega-lite@5"></script>
+    <script src="https://cdn.jsdelivr.net/npm/vega-embed@6"></script>
+    <style>
+        body { font-family: sans-serif; margin: 2em; }
+        table { border-collapse: collapse; margin-bottom: 2em; }
+        th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: right; }
+        th { background: #eee; }
+        td.name { text-align: left; }
+    </style>
+</head>
+
+<body>
+    <h1>wgatools report: {{ title }}</h1>
+
+    <h2>Summary</h2>
+    <table>
+        <tr><th>Format</th><td>{{ summary.format }}</td></tr>
+        <tr><th>Records</th><td>{{ summary.records }}</td></tr>
+        <tr><th>Pairs</th><td>{{ summary.pairs }}</td></tr>
+        <tr><th>Target size</th><td>{{ summary.target_size }}</td></tr>
+        <tr><th>Target covered</th><td>{{ summary.covered_size }} ({{ summary.covered_pct }}%)</td></tr>
+        <tr><th>Aligned size</th><td>{{ summary.aligned_size }}</td></tr>
+        <tr><th>Identity</th><td>{{ summary.identity }}</td></tr>
+    </table>
+
+    <h2>Statistics</h2>
+    <table>
+        <tr>
+            <th>ref_name</th><th>ref_size</th><th>query_name</th><th>query_size</th>
+            <th>aligned_size</th><th>identity</th><th>similarity</th>
+            <th>ins_event</th><th>ins_size</th><th>del_event</th><th>del_size</th><th>inv_event</th>
+        </tr>
+        {% for stat in stats %}
+        <tr>
+            <td class="name">{{ stat.ref_name }}</td><td>{{ stat.ref_size }}</td>
+            <td class="name">{{ stat.query_name }}</td><td>{{ stat.query_size }}</td>
+            <td>{{ stat.aligned_size }}</td><td>{{ stat.identity|round(4) }}</td>
+            <td>{{ stat.similarity|round(4) }}</td>
+            <td>{{ stat.ins_event }}</td><td>{{ stat.ins_size }}</td>
+            <td>{{ stat.del_event }}</td><td>{{ stat.del_size }}</td><td>{{ stat.inv_event }}</td>
+        </tr>
+        {% endfor %}
+    </table>
+
+    <h2>Coverage</h2>
+    <table>
+        <tr><th>target</th><th>size</th><th>covered</th><th>covered_pct</th><th>mean_depth</th></tr>
+        {% for cov in coverage %}
+        <tr>
+            <td class="name">{{ cov.target }}</td><td>{{ cov.size }}</td><td>{{ cov.covered }}</td>
+            <td>{{ cov.covered_pct }}</td><td>{{ cov.mean_depth }}</td>
+        </tr>
+        {% endfor %}
+    </table>
+    <div id="coverage"></div>
+
+    <h2>Dotplot</h2>
+    <div id="dotplot"></div>
+
+    <script>
+        vegaEmbed('#coverage', {{ coverage_json | safe }});
+        vegaEmbed('#dotplot', {{ dotplot_json | safe }});
+    </script>
+</body>
+</html>
+"#;
+
+const COVERAGE_SPEC: &str = r#"
+{
+    "$schema": "https://vega.github.io/schema/vega-lite/v5.json",
+    "width": 800,
+    "height": 80,
+    "data": {
+        "values": []
+    },
+    "mark": {
+        "type": "area",
+        "interpolate": "step-after",
+        "tooltip": true
+    },
+    "encoding": {
+        "x": {
+            "field": "start",
+            "type": "quantitative",
+            "title": null
+        },
+        "y": {
+            "field": "depth",
+            "type": "quantitative",
+            "title": "depth"
+        },
+        "row": {
+            "field": "target",
+            "header": {
+                "labelAngle": 0
+            },
+            "title": null
+        }
+    },
+    "resolve": {"scale": {"x": "independent"}}
+}"#;
+
+/// Mean depth of a bin on target
+#[derive(Debug, Serialize)]
+struct CoverageBin<'a> {
+    target: &'a str,
+    start: u64,
+    end: u64,
+    depth: f64,
+}
+
+/// Coverage of a target in table
+#[derive(Debug, Serialize)]
+struct CoverageRow {
+    target: String,
+    size: u64,
+    covered: u64,
+    covered_pct: String,
+    mean_depth: String,
+}
+
+/// Aligned bases of a target by gapless blocks
+struct TargetCov {
+    size: u64,
+    bin_size: u64,
+    // aligned bases in each bin
+    bin_sum: Vec<u64>,
+    ivs: Vec<(u64, u64)>,
+}
+
+impl TargetCov {
+    fn new(size: u64, bins: usize) -> Self {
+        let bin_size = size.div_ceil(bins as u64).max(1);
+        TargetCov {
+            size,
+            bin_size,
+            bin_sum: vec![0; size.div_ceil(bin_size) as usize],
+            ivs: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, start: u64, end: u64) {
+        let end = end.min(self.size);
+        if start >= end {
+            return;
+        }
+        let mut pos = start;
+        while pos < end {
+            let bin = pos / self.bin_size;
+            let bin_end = ((bin + 1) * self.bin_size).min(end);
+            self.bin_sum[bin as usize] += bin_end - pos;
+            pos = bin_end;
+        }
+        self.ivs.push((start, end));
+    }
+
+    fn merge(&mut self, mut other: TargetCov) {
+        for (acc, sum) in self.bin_sum.iter_mut().zip(other.bin_sum) {
+            *acc += sum;
+        }
+        self.ivs.append(&mut other.ivs);
+    }
+
+    // number of target bases covered by any block
+    fn covered(&mut self) -> u64 {
+        union_length(std::mem::take(&mut self.ivs))
+    }
+}
+
+/// Statistics, coverage and dotplot data collected in one pass
+#[derive(Default)]
+struct ReportData {
+    records: usize,
+    pair_stats: PairStats,
+    coverage: HashMap<String, TargetCov>,
+    dots: Vec<AllPlotdata>,
+}
+
+impl ReportData {
+    fn merge(&mut self, mut other: ReportData) {
+        self.records += other.records;
+        self.pair_stats.merge(other.pair_stats);
+        for (target, cov) in other.coverage {
+            match self.coverage.get_mut(&target) {
+                Some(acc) => acc.merge(cov),
+                None => {
+                    self.coverage.insert(target, cov);
+                }
+            }
+        }
+        self.dots.append(&mut other.dots);
+    }
+}
+
+/// Write a HTML report of statistics, target coverage and overview dotplot of records,
+/// vega scripts are loaded from CDN so the page needs network access to render,
+/// gapless blocks of a record are split by `blocks`
+pub fn report<T, I>(
+    records: I,
+    writer: &mut dyn Write,
+    title: &str,
+    format: &str,
+    bins: usize,
+    blocks: for<'a> fn(&'a T) -> Result<Vec<Block<'a>>, WGAError>,
+) -> Result<(), WGAError>
+where
+    T: AlignRecord + Send,
+    I: Iterator<Item = Result<T, WGAError>> + Send,
+{
+    let data = records
+        .par_bridge()
+        .try_fold(ReportData::default, |mut acc, rec| {
+            let rec = rec?;
+            acc.records += 1;
+            acc.pair_stats.add(&rec)?;
+            let cov = acc
+                .coverage
+                .entry(rec.target_name().to_string())
+                .or_insert_with(|| TargetCov::new(rec.target_length(), bins));
+            for block in blocks(&rec)? {
+                cov.add(block.target_start, block.target_end);
+            }
+            acc.dots.push(rec_dot_data(&rec, false)?);
+            Ok::<ReportData, WGAError>(acc)
+        })
+        .try_reduce(ReportData::default, |mut acc, other| {
+            acc.merge(other);
+            Ok(acc)
+        })?;
+
+    let stats = data.pair_stats.finish();
+    let mut coverage = data.coverage.into_iter().collect::<Vec<_>>();
+    coverage.sort_by(|a, b| natord::compare(&a.0, &b.0));
+
+    // coverage table and binned depth track
+    let mut coverage_rows = Vec::new();
+    let mut coverage_bins = Vec::new();
+    for (target, cov) in coverage.iter_mut() {
+        let covered = cov.covered();
+        let aligned = cov.bin_sum.iter().sum::<u64>();
+        coverage_rows.push(CoverageRow {
+            target: target.clone(),
+            size: cov.size,
+            covered,
+            covered_pct: format!("{:.2}", percent(covered, cov.size)),
+            mean_depth: format!("{:.2}", aligned as f64 / cov.size.max(1) as f64),
+        });
+        for (i, sum) in cov.bin_sum.iter().enumerate() {
+            let start = i as u64 * cov.bin_size;
+            let end = (start + cov.bin_size).min(cov.size);
+            coverage_bins.push(CoverageBin {
+                target,
+                start,
+                end,
+                depth: *sum as f64 / (end - start) as f64,
+            });
+        }
+    }
+
+    let target_size = coverage_rows.iter().map(|row| row.size).sum::<u64>();
+    let covered_size = coverage_rows.iter().map(|row| row.covered).sum::<u64>();
+    let aligned_size = stats.iter().map(|stat| stat.aligned_size).sum::<usize>();
+    let summary = json!({
+        "format": format,
+        "records": data.records,
+        "pairs": stats.len(),
+        "target_size": target_size,
+        "covered_size": covered_size,
+        "covered_pct": format!("{:.2}", percent(covered_size, target_size)),
+        "aligned_size": aligned_size,
+        "identity": format!("{:.4}", identity(&stats)),
+    });
+
+    let mut coverage_spec: Value = serde_json::from_str(COVERAGE_SPEC)?;
+    coverage_spec["data"]["values"] = serde_json::to_value(&coverage_bins)?;
+    let mut dotplot_spec: Value = serde_json::from_str(DOTPLOT_SPEC)?;
+    dotplot_spec["data"]["values"] = serde_json::to_value(&data.dots)?;
+
+    let mut env = Environment::new();
+    env.add_template("report", REPORT_TEMP)?;
+    let template = env.get_template("report")?;
+    let rendered = template.render(context! {
+        title => title,
+        summary => summary,
+        stats => stats,
+        coverage => coverage_rows,
+        coverage_json => serde_json::to_string(&coverage_spec)?,
+        dotplot_json => serde_json::to_string(&dotplot_spec)?,
+    })?;
+    writeln!(writer, "{}", rendered)?;
+    Ok(())
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => part as f64 * 100.0 / total as f64,
+    }
+}
+
+// matched bases over aligned bases of all pairs
+fn identity(stats: &[Statistic]) -> f64 {
+    let aligned = stats.iter().map(|stat| stat.aligned_size).sum::<usize>();
+    let matched = stats.iter().map(|stat| stat.matched).sum::<usize>();
+    match aligned {
+        0 => 0.0,
+        _ => matched as f64 / aligned as f64,
+    }
+}
@@ -0,0 +1,116 @@
+use crate::errors::WGAError;
+use crate::log::record_skipped;
+use crate::parser::chain::ChainReader;
+use crate::parser::common::AlignRecord;
+use crate::parser::maf::{MAFReader, MAFWriter};
+use crate::parser::paf::{paf_writer, PAFReader};
+use crate::parser::region::GenomeRegion;
+use log::info;
+use std::io::{Read, Write};
+
+/// How records are picked by `sample`, in order of regions, fraction and head
+#[derive(Debug, Default)]
+pub struct SampleOpts {
+    /// Keep records overlapping any region on target
+    pub regions: Vec<GenomeRegion>,
+    /// Keep each record with this probability
+    pub fraction: Option<f64>,
+    /// Random seed of `fraction`, the same seed keeps the same records
+    pub seed: u64,
+    /// Stop after this number of records kept
+    pub head: Option<usize>,
+}
+
+impl SampleOpts {
+    // pick the record of index `idx` in input
+    fn pick<T: AlignRecord>(&self, idx: u64, rec: &T) -> bool {
+        if !self.regions.is_empty()
+            && !self.regions.iter().any(|region| {
+                region.overlaps(rec.target_name(), rec.target_start(), rec.target_end())
+            })
+        {
+            return false;
+        }
+        match self.fraction {
+            Some(fraction) => unit_random(self.seed, idx) < fraction,
+            None => true,
+        }
+    }
+}
+
+// a random number in [0, 1) by SplitMix64 of seed and index, so sampling is reproducible
+pub(crate) fn unit_random(seed: u64, idx: u64) -> f64 {
+    let mut z = seed
+        .wrapping_add(idx.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+        .wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// write picked records until `head` are kept, input after it is not read
+fn sample_records<T, I, F>(records: I, opts: &SampleOpts, mut write: F) -> Result<(), WGAError>
+where
+    T: AlignRecord,
+    I: Iterator<Item = Result<T, WGAError>>,
+    F: FnMut(T) -> Result<(), WGAError>,
+{
+    let mut kept = 0;
+    let mut records = records.enumerate();
+    // the record after `head` is not read either
+    while opts.head.is_none_or(|head| kept < head) {
+        let (idx, rec) = match records.next() {
+            Some(next) => next,
+            None => break,
+        };
+        let rec = rec?;
+        if !opts.pick(idx as u64, &rec) {
+            record_skipped();
+            continue;
+        }
+        write(rec)?;
+        kept += 1;
+    }
+    if opts.head.is_some_and(|head| kept >= head) {
+        info!("stop reading after {} records kept", kept);
+    }
+    info!("kept {} records", kept);
+    Ok(())
+}
+
+/// Subset records of MAF, the header is kept
+pub fn sample_maf<R: Read + Send>(
+    reader: &mut MAFReader<R>,
+    writer: &mut dyn Write,
+    opts: &SampleOpts,
+) -> Result<(), WGAError> {
+    let mut mafwtr = MAFWriter::new(writer);
+    let header = match reader.header.starts_with('#') {
+        true => reader.header.clone(),
+        false => "##maf version=1".to_string(),
+    };
+    mafwtr.write_header(header)?;
+    sample_records(reader.records(), opts, |rec| mafwtr.write_record(&rec))
+}
+
+/// Subset records of PAF
+pub fn sample_paf<R: Read + Send>(
+    reader: &mut PAFReader<R>,
+    writer: &mut dyn Write,
+    opts: &SampleOpts,
+) -> Result<(), WGAError> {
+    let mut pafwtr = paf_writer(writer);
+    sample_records(reader.records(), opts, |rec| Ok(pafwtr.serialize(rec)?))?;
+    pafwtr.flush()?;
+    Ok(())
+}
+
+/// Subset records of Chain
+pub fn sample_chain<R: Read + Send>(
+    reader: &mut ChainReader<R>,
+    writer: &mut dyn Write,
+    opts: &SampleOpts,
+) -> Result<(), WGAError> {
+    sample_records(reader.records()?, opts, |rec| rec.write_to(writer))
+}
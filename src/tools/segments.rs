@@ -0,0 +1,156 @@
+use crate::{
+    errors::WGAError,
+    parser::{
+        chain::ChainReader,
+        cigar::{parse_chain_to_blocks, parse_cigar_to_blocks, parse_maf_seq_to_blocks},
+        common::{AlignRecord, Block, Strand},
+        maf::MAFReader,
+        paf::PAFReader,
+    },
+    utils::par_write_ordered,
+};
+use std::io::{Read, Write};
+
+/// A gap-free matched segment, query start is on the forward strand of query,
+/// so a segment on `-` strand matches `target_start` to `query_start + len - 1`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub target_start: u64,
+    pub query_start: u64,
+    pub len: u64,
+    pub strand: Strand,
+}
+
+/// Gap-free segments of a record, with its index in input
+#[derive(Debug, Clone)]
+pub struct RecordSegments {
+    pub record: usize,
+    pub target_name: String,
+    pub target_length: u64,
+    pub query_name: String,
+    pub query_length: u64,
+    pub segments: Vec<Segment>,
+}
+
+impl RecordSegments {
+    fn new<T: AlignRecord>(record: usize, rec: &T, blocks: Vec<Block>) -> Self {
+        RecordSegments {
+            record,
+            target_name: rec.target_name().to_string(),
+            target_length: rec.target_length(),
+            query_name: rec.query_name().to_string(),
+            query_length: rec.query_length(),
+            segments: blocks
+                .into_iter()
+                .map(|block| Segment {
+                    target_start: block.target_start,
+                    query_start: block.query_start,
+                    len: block.target_end - block.target_start,
+                    strand: block.strand,
+                })
+                .collect(),
+        }
+    }
+
+    // one TSV line per segment
+    fn write_lines(&self, writer: &mut dyn Write) -> Result<(), WGAError> {
+        for seg in &self.segments {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                self.record,
+                self.target_name,
+                seg.target_start,
+                self.query_name,
+                seg.query_start,
+                seg.len,
+                seg.strand
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Iterate over segments of MAF records, `query_name` picks the query of multi-sequence blocks
+pub fn maf_segments<'a, R: Read + Send>(
+    reader: &'a mut MAFReader<R>,
+    query_name: Option<&'a str>,
+) -> impl Iterator<Item = Result<RecordSegments, WGAError>> + 'a {
+    reader.records().enumerate().map(move |(idx, rec)| {
+        let mut rec = rec?;
+        if let Some(qname) = query_name {
+            rec.set_query_idx_byname(qname)?;
+        }
+        Ok(RecordSegments::new(
+            idx,
+            &rec,
+            parse_maf_seq_to_blocks(&rec)?,
+        ))
+    })
+}
+
+/// Iterate over segments of PAF records, from the `cg` tag
+pub fn paf_segments<R: Read + Send>(
+    reader: &mut PAFReader<R>,
+) -> impl Iterator<Item = Result<RecordSegments, WGAError>> + '_ {
+    reader.records().enumerate().map(|(idx, rec)| {
+        let rec = rec?;
+        Ok(RecordSegments::new(idx, &rec, parse_cigar_to_blocks(&rec)?))
+    })
+}
+
+/// Iterate over segments of chain records, from data lines
+pub fn chain_segments<R: Read + Send>(
+    reader: &mut ChainReader<R>,
+) -> Result<impl Iterator<Item = Result<RecordSegments, WGAError>>, WGAError> {
+    Ok(reader.records()?.enumerate().map(|(idx, rec)| {
+        let rec = rec?;
+        Ok(RecordSegments::new(idx, &rec, parse_chain_to_blocks(&rec)?))
+    }))
+}
+
+const SEGMENTS_HEADER: &[u8] =
+    b"record\ttarget_name\ttarget_start\tquery_name\tquery_start\tlen\tstrand\n";
+
+/// Write segments of MAF records as TSV, in input order
+pub fn write_maf_segments<R: Read + Send>(
+    reader: &mut MAFReader<R>,
+    writer: &mut dyn Write,
+    query_name: Option<&str>,
+) -> Result<(), WGAError> {
+    writer.write_all(SEGMENTS_HEADER)?;
+    par_write_ordered(reader.records(), writer, |idx, mut rec| {
+        if let Some(qname) = query_name {
+            rec.set_query_idx_byname(qname)?;
+        }
+        let mut buf = Vec::new();
+        RecordSegments::new(idx, &rec, parse_maf_seq_to_blocks(&rec)?).write_lines(&mut buf)?;
+        Ok(buf)
+    })
+}
+
+/// Write segments of PAF records as TSV, in input order
+pub fn write_paf_segments<R: Read + Send>(
+    reader: &mut PAFReader<R>,
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    writer.write_all(SEGMENTS_HEADER)?;
+    par_write_ordered(reader.records(), writer, |idx, rec| {
+        let mut buf = Vec::new();
+        RecordSegments::new(idx, &rec, parse_cigar_to_blocks(&rec)?).write_lines(&mut buf)?;
+        Ok(buf)
+    })
+}
+
+/// Write segments of chain records as TSV, in input order
+pub fn write_chain_segments<R: Read + Send>(
+    reader: &mut ChainReader<R>,
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    writer.write_all(SEGMENTS_HEADER)?;
+    par_write_ordered(reader.records()?, writer, |idx, rec| {
+        let mut buf = Vec::new();
+        RecordSegments::new(idx, &rec, parse_chain_to_blocks(&rec)?).write_lines(&mut buf)?;
+        Ok(buf)
+    })
+}
@@ -1,16 +1,24 @@
 use crate::{
     errors::WGAError,
+    log::{profile, Stage},
     parser::{
-        common::{AlignRecord, RecStat},
-        maf::MAFReader,
+        cigar::parse_cigar_to_units,
+        common::{AlignRecord, GroupBy, RecStat, Strand},
+        maf::{MAFReader, MAFRecord},
         paf::PAFReader,
+        region::read_named_bed_regions,
     },
+    tools::sample::unit_random,
+    utils::{merge_intervals, par_write_ordered},
 };
+use log::info;
 use rayon::prelude::*;
+use rust_lapper::{Interval, Lapper};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    io::{Read, Write},
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{BufRead, Read, Write},
+    ops::AddAssign,
 };
 
 /// Pair of reference and query as KEY
@@ -53,66 +61,460 @@ pub struct Statistic {
 struct PairStat {
     pair: Pair,
     ref_start: u64,
+    ref_end: u64,
     query_start: u64,
+    query_end: u64,
+    strand: Strand,
     rec_stat: RecStat,
 }
 
+/// Interval of a block in a pair, for detailed JSON output
+#[derive(Debug, Serialize)]
+struct BlockInterval {
+    ref_start: u64,
+    ref_end: u64,
+    query_start: u64,
+    query_end: u64,
+    strand: Strand,
+}
+
+/// Detailed statistic of a pair, nested by target and query in JSON output
+#[derive(Debug, Serialize, Default)]
+struct DetailedStatistic {
+    ref_size: u64,
+    query_size: u64,
+    aligned_size: usize,
+    identity: f32,
+    inv_event: usize,
+    block_count: usize,
+    blocks: Vec<BlockInterval>,
+    #[serde(skip)]
+    matched: usize,
+}
+
+/// Records picked by `stat --sample` with their probability, totals are extrapolated from them
+#[derive(Debug, Clone, Copy)]
+pub struct StatSample {
+    pub fraction: f64,
+    pub seed: u64,
+}
+
+/// Binned counts of ins/del lengths
+#[derive(Debug, Clone)]
+struct GapHist {
+    ins: Vec<u64>,
+    del: Vec<u64>,
+}
+
+impl GapHist {
+    fn new(bin_count: usize) -> Self {
+        GapHist {
+            ins: vec![0; bin_count],
+            del: vec![0; bin_count],
+        }
+    }
+
+    fn merge(&mut self, other: &GapHist) {
+        for (a, b) in self.ins.iter_mut().zip(&other.ins) {
+            *a += b;
+        }
+        for (a, b) in self.del.iter_mut().zip(&other.del) {
+            *a += b;
+        }
+    }
+}
+
+/// N bases and longest gap runs of target and query in a block
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlockQc {
+    pub target_n: u64,
+    pub query_n: u64,
+    pub target_max_gap: u64,
+    pub query_max_gap: u64,
+}
+
+impl BlockQc {
+    /// QC of target and current query of the block
+    pub fn new(rec: &MAFRecord) -> Self {
+        let (target_n, target_max_gap) = seq_qc(rec.target_seq());
+        let (query_n, query_max_gap) = seq_qc(rec.query_seq());
+        BlockQc {
+            target_n,
+            query_n,
+            target_max_gap,
+            query_max_gap,
+        }
+    }
+}
+
+// count of N and longest run of `-` in an aligned sequence
+fn seq_qc(seq: &str) -> (u64, u64) {
+    let (mut n_count, mut max_gap, mut gap) = (0, 0, 0);
+    for b in seq.bytes() {
+        match b {
+            b'-' => {
+                gap += 1;
+                max_gap = max_gap.max(gap);
+                continue;
+            }
+            b'N' | b'n' => n_count += 1,
+            _ => {}
+        }
+        gap = 0;
+    }
+    (n_count, max_gap)
+}
+
+/// Thresholds of block QC, blocks over any of them fail
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QcThresholds {
+    pub max_n: Option<u64>,
+    pub max_gap_run: Option<u64>,
+}
+
+impl QcThresholds {
+    /// If no threshold is set
+    pub fn is_empty(&self) -> bool {
+        self.max_n.is_none() && self.max_gap_run.is_none()
+    }
+
+    /// If the block is within all thresholds
+    pub fn pass(&self, qc: &BlockQc) -> bool {
+        self.max_n
+            .is_none_or(|max| qc.target_n <= max && qc.query_n <= max)
+            && self
+                .max_gap_run
+                .is_none_or(|max| qc.target_max_gap <= max && qc.query_max_gap <= max)
+    }
+}
+
+/// Write QC of each MAF block as TSV in input order: N bases and longest gap runs
+pub fn stat_qc<R: Read + Send>(
+    mut reader: MAFReader<R>,
+    writer: &mut dyn Write,
+    query_name: Option<&str>,
+) -> Result<(), WGAError> {
+    writer.write_all(
+        b"target_name\ttarget_start\ttarget_end\tquery_name\tquery_start\tquery_end\tstrand\t\
+          target_n\tquery_n\ttarget_max_gap\tquery_max_gap\n",
+    )?;
+    par_write_ordered(reader.records(), writer, |_, mut rec| {
+        if let Some(qname) = query_name {
+            rec.set_query_idx_byname(qname)?;
+        }
+        let qc = BlockQc::new(&rec);
+        let mut buf = Vec::new();
+        writeln!(
+            buf,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            rec.target_name(),
+            rec.target_start(),
+            rec.target_end(),
+            rec.query_name(),
+            rec.query_start(),
+            rec.query_end(),
+            rec.query_strand(),
+            qc.target_n,
+            qc.query_n,
+            qc.target_max_gap,
+            qc.query_max_gap
+        )?;
+        Ok(buf)
+    })
+}
+
 // stat for maf
+#[allow(clippy::too_many_arguments)]
 pub fn stat_maf<R: Read + Send>(
     mut reader: MAFReader<R>,
     writer: &mut dyn Write,
     each: bool,
     query_name: Option<&str>,
+    json_detailed: bool,
+    gap_bins: Option<&[u64]>,
+    per_sline: bool,
+    group_by: GroupBy,
+    sample: Option<StatSample>,
+) -> Result<(), WGAError> {
+    if per_sline {
+        return stat_per_sline(reader.records(), writer, json_detailed);
+    }
+    let records = reader.records().map(|result_rec| {
+        let mut rec = result_rec?;
+        if let Some(qname) = query_name {
+            rec.set_query_idx_byname(qname)?;
+        }
+        Ok(rec)
+    });
+    match gap_bins {
+        Some(gap_bins) => gap_hist_records(records, writer, gap_bins, group_by),
+        None => stat_records(records, writer, each, json_detailed, group_by, sample),
+    }
+}
+
+// stat for paf
+pub fn stat_paf<R: Read + Send>(
+    mut reader: PAFReader<R>,
+    writer: &mut dyn Write,
+    each: bool,
+    json_detailed: bool,
+    gap_bins: Option<&[u64]>,
+    group_by: GroupBy,
+    sample: Option<StatSample>,
 ) -> Result<(), WGAError> {
-    let pair_stat_vec = reader
-        .records()
+    let records = reader.records();
+    match gap_bins {
+        Some(gap_bins) => gap_hist_records(records, writer, gap_bins, group_by),
+        None => stat_records(records, writer, each, json_detailed, group_by, sample),
+    }
+}
+
+// histograms of ins/del length from CIGAR for each pair of groups and all pairs,
+// bins are `[edge_i, edge_i+1)` and the last bin is open, lengths below the first edge are skipped
+fn gap_hist_records<T, I>(
+    records: I,
+    writer: &mut dyn Write,
+    gap_bins: &[u64],
+    group_by: GroupBy,
+) -> Result<(), WGAError>
+where
+    T: AlignRecord + Send,
+    I: Iterator<Item = Result<T, WGAError>> + Send,
+{
+    let bin_count = gap_bins.len();
+    let pair_hists = records
         .par_bridge()
-        .try_fold(Vec::new, |mut acc, result_rec| {
-            let mut rec = result_rec?;
-            if let Some(qname) = query_name {
-                rec.set_query_idx_byname(qname)?;
+        .try_fold(
+            HashMap::new,
+            |mut acc: HashMap<(String, String), GapHist>, rec| {
+                let rec = rec?;
+                let hist = acc
+                    .entry((
+                        group_by.group(rec.target_name()).to_string(),
+                        group_by.group(rec.query_name()).to_string(),
+                    ))
+                    .or_insert_with(|| GapHist::new(bin_count));
+                for (op, len) in parse_cigar_to_units(&rec)? {
+                    let bins = match op {
+                        'I' => &mut hist.ins,
+                        'D' => &mut hist.del,
+                        _ => continue,
+                    };
+                    // index of the last edge <= len
+                    if let Some(idx) = gap_bins.partition_point(|&edge| edge <= len).checked_sub(1)
+                    {
+                        bins[idx] += 1;
+                    }
+                }
+                Ok::<_, WGAError>(acc)
+            },
+        )
+        .try_reduce(HashMap::new, |mut acc, map| {
+            for (pair, hist) in map {
+                match acc.get_mut(&pair) {
+                    Some(acc_hist) => acc_hist.merge(&hist),
+                    None => {
+                        acc.insert(pair, hist);
+                    }
+                }
+            }
+            Ok(acc)
+        })?;
+
+    let mut pairs = pair_hists.into_iter().collect::<Vec<_>>();
+    pairs.sort_by(|a, b| {
+        natord::compare(&a.0 .0, &b.0 .0).then_with(|| natord::compare(&a.0 .1, &b.0 .1))
+    });
+    // aggregated histogram of all pairs as `*`
+    let mut all_hist = GapHist::new(bin_count);
+    for (_, hist) in &pairs {
+        all_hist.merge(hist);
+    }
+    pairs.push((("*".to_string(), "*".to_string()), all_hist));
+
+    writer.write_all(b"ref_name\tquery_name\ttype\tbin_start\tbin_end\tcount\n")?;
+    for ((ref_name, query_name), hist) in pairs {
+        for (gap_type, bins) in [("ins", &hist.ins), ("del", &hist.del)] {
+            for (idx, count) in bins.iter().enumerate() {
+                let bin_end = match gap_bins.get(idx + 1) {
+                    Some(edge) => edge.to_string(),
+                    None => "inf".to_string(),
+                };
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    ref_name, query_name, gap_type, gap_bins[idx], bin_end, count
+                )?;
             }
-            acc.push(stat_rec(&rec)?);
-            Ok::<Vec<PairStat>, WGAError>(acc)
+        }
+    }
+    Ok(())
+}
+
+// statistics of the target and each query s-line of every block, as `each` with all queries,
+// rows are in input order of blocks and s-lines within a target
+fn stat_per_sline<I>(
+    records: I,
+    writer: &mut dyn Write,
+    json_detailed: bool,
+) -> Result<(), WGAError>
+where
+    I: Iterator<Item = Result<MAFRecord, WGAError>> + Send,
+{
+    let mut indexed_stats = records
+        .enumerate()
+        .par_bridge()
+        .try_fold(Vec::new, |mut acc, (rec_idx, rec)| {
+            let mut rec = rec?;
+            for query_idx in 1..rec.slines.len() {
+                rec.set_query_idx(query_idx);
+                acc.push(((rec_idx, query_idx), stat_rec(&rec)?));
+            }
+            Ok::<Vec<_>, WGAError>(acc)
         })
         .try_reduce(Vec::new, |mut acc, mut vec| {
             acc.append(&mut vec);
             Ok(acc)
         })?;
-
-    write_style_result(pair_stat_vec, writer, each)
+    indexed_stats.sort_by_key(|(idx, _)| *idx);
+    let pair_stat_vec = indexed_stats
+        .into_iter()
+        .map(|(_, pair_stat)| pair_stat)
+        .collect();
+    profile(Stage::Write, || {
+        write_style_result(pair_stat_vec, writer, true, json_detailed)
+    })
 }
 
-// stat for paf
-pub fn stat_paf<R: Read + Send>(
-    mut reader: PAFReader<R>,
+// per-record stats are only kept for `each` and `json_detailed`, otherwise
+// records are aggregated into pairs as they stream to keep memory bounded,
+// only records picked by `sample` are walked and totals are extrapolated from them
+fn stat_records<T, I>(
+    records: I,
     writer: &mut dyn Write,
     each: bool,
-) -> Result<(), WGAError> {
-    let pair_stat_vec = reader
-        .records()
+    json_detailed: bool,
+    group_by: GroupBy,
+    sample: Option<StatSample>,
+) -> Result<(), WGAError>
+where
+    T: AlignRecord + Send,
+    I: Iterator<Item = Result<T, WGAError>> + Send,
+{
+    if each || json_detailed {
+        let pair_stat_vec = records
+            .par_bridge()
+            .try_fold(Vec::new, |mut acc, rec| {
+                acc.push(stat_rec(&rec?)?);
+                Ok::<Vec<PairStat>, WGAError>(acc)
+            })
+            .try_reduce(Vec::new, |mut acc, mut vec| {
+                acc.append(&mut vec);
+                Ok(acc)
+            })?;
+        return profile(Stage::Write, || {
+            write_style_result(pair_stat_vec, writer, each, json_detailed)
+        });
+    }
+
+    let (mut total, mut picked) = (0u64, 0u64);
+    let records = records.filter(|_| {
+        let pick = sample.is_none_or(|s| unit_random(s.seed, total) < s.fraction);
+        total += 1;
+        picked += pick as u64;
+        pick
+    });
+    let mut pair_stats = records
         .par_bridge()
-        .try_fold(Vec::new, |mut acc, rec| {
-            acc.push(stat_rec(&rec?)?);
-            Ok::<Vec<PairStat>, WGAError>(acc)
+        .try_fold(PairStats::default, |mut acc, rec| {
+            acc.add(&rec?)?;
+            Ok::<PairStats, WGAError>(acc)
         })
-        .try_reduce(Vec::new, |mut acc, mut vec| {
-            acc.append(&mut vec);
+        .try_reduce(PairStats::default, |mut acc, other| {
+            acc.merge(other);
             Ok(acc)
         })?;
+    if let Some(sample) = sample {
+        info!(
+            "{} of {} records sampled, totals are extrapolated",
+            picked, total
+        );
+        pair_stats.extrapolate(sample.fraction);
+    }
+    write_result(group_final(pair_stats.finish(), group_by), writer)
+}
+
+/// Statistics aggregated by pair while records stream
+#[derive(Default)]
+pub(crate) struct PairStats(HashMap<Pair, Statistic>);
+
+impl PairStats {
+    pub(crate) fn add<T: AlignRecord>(&mut self, rec: &T) -> Result<(), WGAError> {
+        add_pair_stat(&mut self.0, stat_rec(rec)?);
+        Ok(())
+    }
+
+    pub(crate) fn merge(&mut self, other: PairStats) {
+        for (pair, stat) in other.0 {
+            match self.0.get_mut(&pair) {
+                Some(acc_stat) => *acc_stat += &stat,
+                None => {
+                    self.0.insert(pair, stat);
+                }
+            }
+        }
+    }
 
-    write_style_result(pair_stat_vec, writer, each)
+    /// Scale counts and sizes by `1 / fraction`, as only this fraction of records is added
+    pub(crate) fn extrapolate(&mut self, fraction: f64) {
+        let scale = |x: usize| (x as f64 / fraction).round() as usize;
+        for stat in self.0.values_mut() {
+            for x in [
+                &mut stat.aligned_size,
+                &mut stat.matched,
+                &mut stat.mismatched,
+                &mut stat.ins_event,
+                &mut stat.del_event,
+                &mut stat.ins_size,
+                &mut stat.del_size,
+                &mut stat.inv_event,
+                &mut stat.inv_ins_event,
+                &mut stat.inv_ins_size,
+                &mut stat.inv_del_event,
+                &mut stat.inv_del_size,
+            ] {
+                *x = scale(*x);
+            }
+            stat.inv_size = (stat.inv_size as f64 / fraction) as f32;
+        }
+    }
+
+    /// Finished statistics sorted by ref_name
+    pub(crate) fn finish(self) -> Vec<Statistic> {
+        let mut final_stat = finish_merged(self.0);
+        final_stat.sort_by(|a, b| natord::compare(&a.ref_name, &b.ref_name));
+        final_stat
+    }
 }
 
 fn write_style_result(
     pair_stat_vec: Vec<PairStat>,
     writer: &mut dyn Write,
     each: bool,
+    json_detailed: bool,
 ) -> Result<(), WGAError> {
-    let mut final_stat = match each {
+    if json_detailed {
+        return write_json_detailed(pair_stat_vec, writer);
+    }
+    let final_stat = match each {
         true => split_final(pair_stat_vec),
         false => merge_final_from_pair(pair_stat_vec),
     };
+    write_result(final_stat, writer)
+}
+
+// write statistics sorted by ref_name as TSV
+fn write_result(mut final_stat: Vec<Statistic>, writer: &mut dyn Write) -> Result<(), WGAError> {
     final_stat.sort_by(|a, b| natord::compare(&a.ref_name, &b.ref_name));
     let mut wtr = csv::WriterBuilder::new()
         .delimiter(b'\t')
@@ -125,6 +527,50 @@ fn write_style_result(
     Ok(())
 }
 
+// nest by target -> query, keep each block's interval
+fn write_json_detailed(
+    pair_stat_vec: Vec<PairStat>,
+    writer: &mut dyn Write,
+) -> Result<(), WGAError> {
+    let mut detailed: BTreeMap<String, BTreeMap<String, DetailedStatistic>> = BTreeMap::new();
+    for pair_stat in pair_stat_vec {
+        let pair = pair_stat.pair;
+        let rec_stat = pair_stat.rec_stat;
+        let stat = detailed
+            .entry(pair.ref_name)
+            .or_default()
+            .entry(pair.query_name)
+            .or_default();
+        stat.ref_size = pair.ref_size;
+        stat.query_size = pair.query_size;
+        stat.aligned_size += rec_stat.aligned_size;
+        stat.matched += rec_stat.matched;
+        stat.inv_event += rec_stat.inv_event;
+        stat.block_count += 1;
+        stat.blocks.push(BlockInterval {
+            ref_start: pair_stat.ref_start,
+            ref_end: pair_stat.ref_end,
+            query_start: pair_stat.query_start,
+            query_end: pair_stat.query_end,
+            strand: pair_stat.strand,
+        });
+    }
+    // calculate the identity and sort blocks by target start
+    for query_map in detailed.values_mut() {
+        for stat in query_map.values_mut() {
+            // pairs aligned only by insertions have no identity, 0 rather than NaN for JSON
+            stat.identity = match stat.aligned_size {
+                0 => 0.0,
+                aligned_size => stat.matched as f32 / aligned_size as f32,
+            };
+            stat.blocks.sort_by_key(|b| b.ref_start);
+        }
+    }
+    serde_json::to_writer(&mut *writer, &detailed)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
 // not merge
 fn split_final(pair_stat_vec: Vec<PairStat>) -> Vec<Statistic> {
     // init final_stat
@@ -140,21 +586,8 @@ fn split_final(pair_stat_vec: Vec<PairStat>) -> Vec<Statistic> {
             query_name: pair.query_name,
             query_size: pair.query_size,
             query_start: pair_stat.query_start,
-            ..Default::default()
+            ..Statistic::from(rec_stat)
         };
-        stat.aligned_size = rec_stat.aligned_size;
-        stat.matched = rec_stat.matched;
-        stat.mismatched = rec_stat.mismatched;
-        stat.ins_event = rec_stat.ins_event;
-        stat.del_event = rec_stat.del_event;
-        stat.ins_size = rec_stat.ins_size;
-        stat.del_size = rec_stat.del_size;
-        stat.inv_ins_event = rec_stat.inv_ins_event;
-        stat.inv_ins_size = rec_stat.inv_ins_size;
-        stat.inv_del_event = rec_stat.inv_del_event;
-        stat.inv_del_size = rec_stat.inv_del_size;
-        stat.inv_event = rec_stat.inv_event;
-        stat.inv_size = rec_stat.inv_size;
         stat.identity = stat.matched as f32 / stat.aligned_size as f32;
         stat.similarity = (stat.matched + stat.mismatched) as f32 / stat.aligned_size as f32;
         // push to final_stat
@@ -165,61 +598,137 @@ fn split_final(pair_stat_vec: Vec<PairStat>) -> Vec<Statistic> {
 
 // merge blocks in aggregation by ref_name
 fn merge_final_from_pair(pair_stat_vec: Vec<PairStat>) -> Vec<Statistic> {
-    // init final_stat
-    let mut final_stat = Vec::new();
-
     let mut pair_stat_map = HashMap::new();
     for pair_stat in pair_stat_vec {
-        let pair = pair_stat.pair;
-        let rec_stat = pair_stat.rec_stat;
-        pair_stat_map.entry(pair).or_insert(Vec::new()).push((
-            rec_stat,
-            pair_stat.ref_start,
-            pair_stat.query_start,
-        ));
+        add_pair_stat(&mut pair_stat_map, pair_stat);
     }
-    for (pair, rec_stats) in pair_stat_map {
-        // for rach record, init a Statistic
-        let mut stat = Statistic {
-            ref_name: pair.ref_name,
+    finish_merged(pair_stat_map)
+}
+
+// aggregate a record into the statistic of its pair
+fn add_pair_stat(pair_stat_map: &mut HashMap<Pair, Statistic>, pair_stat: PairStat) {
+    let pair = pair_stat.pair;
+    let rec_stat = pair_stat.rec_stat;
+    let stat = pair_stat_map
+        .entry(pair)
+        .or_insert_with_key(|pair| Statistic {
+            ref_name: pair.ref_name.clone(),
             ref_size: pair.ref_size,
             ref_start: pair.ref_size,
-            query_name: pair.query_name,
+            query_name: pair.query_name.clone(),
             query_size: pair.query_size,
             query_start: pair.query_size,
             ..Default::default()
-        };
-        // aggregate by each record
-        for (rec_stat, r_s, q_s) in rec_stats {
-            stat.aligned_size += rec_stat.aligned_size;
-            stat.matched += rec_stat.matched;
-            stat.mismatched += rec_stat.mismatched;
-            stat.ins_event += rec_stat.ins_event;
-            stat.del_event += rec_stat.del_event;
-            stat.ins_size += rec_stat.ins_size;
-            stat.del_size += rec_stat.del_size;
-            stat.inv_ins_event += rec_stat.inv_ins_event;
-            stat.inv_ins_size += rec_stat.inv_ins_size;
-            stat.inv_del_event += rec_stat.inv_del_event;
-            stat.inv_del_size += rec_stat.inv_del_size;
-            stat.inv_event += rec_stat.inv_event;
-            stat.inv_size += rec_stat.inv_size;
-            // judge if ref_start and query_start is the smallest
-            if r_s < stat.ref_start {
-                stat.ref_start = r_s;
-            }
-            if q_s < stat.query_start {
-                stat.query_start = q_s;
+        });
+    *stat += &Statistic {
+        ref_start: pair_stat.ref_start,
+        query_start: pair_stat.query_start,
+        ..Statistic::from(rec_stat)
+    };
+}
+
+// counts of a record, names and sizes are left to the pair
+impl From<RecStat> for Statistic {
+    fn from(rec_stat: RecStat) -> Self {
+        Statistic {
+            aligned_size: rec_stat.aligned_size,
+            matched: rec_stat.matched,
+            mismatched: rec_stat.mismatched,
+            ins_event: rec_stat.ins_event,
+            del_event: rec_stat.del_event,
+            ins_size: rec_stat.ins_size,
+            del_size: rec_stat.del_size,
+            inv_ins_event: rec_stat.inv_ins_event,
+            inv_ins_size: rec_stat.inv_ins_size,
+            inv_del_event: rec_stat.inv_del_event,
+            inv_del_size: rec_stat.inv_del_size,
+            inv_event: rec_stat.inv_event,
+            inv_size: rec_stat.inv_size,
+            ..Default::default()
+        }
+    }
+}
+
+// merge partial statistics of the same pair: counts are summed and starts are the smallest
+impl AddAssign<&Statistic> for Statistic {
+    fn add_assign(&mut self, other: &Statistic) {
+        self.aligned_size += other.aligned_size;
+        self.matched += other.matched;
+        self.mismatched += other.mismatched;
+        self.ins_event += other.ins_event;
+        self.del_event += other.del_event;
+        self.ins_size += other.ins_size;
+        self.del_size += other.del_size;
+        self.inv_ins_event += other.inv_ins_event;
+        self.inv_ins_size += other.inv_ins_size;
+        self.inv_del_event += other.inv_del_event;
+        self.inv_del_size += other.inv_del_size;
+        self.inv_event += other.inv_event;
+        self.inv_size += other.inv_size;
+        self.ref_start = self.ref_start.min(other.ref_start);
+        self.query_start = self.query_start.min(other.query_start);
+    }
+}
+
+// calculate the unaligned size, identity and similarity of aggregated pairs
+fn finish_merged(pair_stat_map: HashMap<Pair, Statistic>) -> Vec<Statistic> {
+    pair_stat_map
+        .into_values()
+        .map(|mut stat| {
+            // extrapolated aligned size could exceed the sequence
+            stat.unaligned_size = stat.ref_size.saturating_sub(stat.aligned_size as u64);
+            stat.identity = stat.matched as f32 / stat.aligned_size as f32;
+            stat.similarity = (stat.matched + stat.mismatched) as f32 / stat.aligned_size as f32;
+            stat
+        })
+        .collect()
+}
+
+// aggregate pairs by groups of names, the size of a group is the sum of its sequences
+// in any pair, and starts are 0 as they are on different sequences
+fn group_final(final_stat: Vec<Statistic>, group_by: GroupBy) -> Vec<Statistic> {
+    if group_by == GroupBy::Sequence {
+        return final_stat;
+    }
+    let mut seen = HashSet::new();
+    let mut group_sizes: HashMap<&str, u64> = HashMap::new();
+    for stat in &final_stat {
+        for (name, size) in [
+            (&stat.ref_name, stat.ref_size),
+            (&stat.query_name, stat.query_size),
+        ] {
+            if seen.insert(name) {
+                *group_sizes.entry(group_by.group(name)).or_default() += size;
             }
         }
-        // calculate the identity and similarity
-        stat.unaligned_size = stat.ref_size - stat.aligned_size as u64;
-        stat.identity = stat.matched as f32 / stat.aligned_size as f32;
-        stat.similarity = (stat.matched + stat.mismatched) as f32 / stat.aligned_size as f32;
-        // push to final_stat
-        final_stat.push(stat);
     }
-    final_stat
+    let mut groups: HashMap<(&str, &str), Statistic> = HashMap::new();
+    for stat in &final_stat {
+        let ref_group = group_by.group(&stat.ref_name);
+        let query_group = group_by.group(&stat.query_name);
+        let acc = groups
+            .entry((ref_group, query_group))
+            .or_insert_with(|| Statistic {
+                ref_name: ref_group.to_string(),
+                ref_size: group_sizes[ref_group],
+                query_name: query_group.to_string(),
+                query_size: group_sizes[query_group],
+                ..Default::default()
+            });
+        *acc += stat;
+    }
+    let mut grouped = groups
+        .into_values()
+        .map(|mut stat| {
+            stat.unaligned_size = stat.ref_size.saturating_sub(stat.aligned_size as u64);
+            stat.identity = stat.matched as f32 / stat.aligned_size as f32;
+            stat.similarity = (stat.matched + stat.mismatched) as f32 / stat.aligned_size as f32;
+            stat
+        })
+        .collect::<Vec<_>>();
+    // queries of a group are kept in order, as rows are sorted by ref_name only
+    grouped.sort_by(|a, b| natord::compare(&a.query_name, &b.query_name));
+    grouped
 }
 
 // stat a record to generate a PairStat
@@ -245,6 +754,336 @@ fn stat_rec<T: AlignRecord>(rec: &T) -> Result<PairStat, WGAError> {
         pair,
         rec_stat,
         ref_start,
+        ref_end: rec.target_end(),
         query_start,
+        query_end: rec.query_end(),
+        strand: rec.query_strand(),
     })
 }
+
+/// Classes of target regions from the 4th column of BED, regions of a class are merged
+pub struct Strata {
+    classes: Vec<String>,
+    class_sizes: Vec<u64>,
+    regions: HashMap<String, Lapper<u64, usize>>,
+}
+
+impl Strata {
+    /// Read classes from BED, `track`/`browser`/`#` lines are skipped
+    pub fn from_bed<R: BufRead>(reader: R) -> Result<Self, WGAError> {
+        let mut class_idx: HashMap<String, usize> = HashMap::new();
+        let mut classes = Vec::new();
+        let mut intervals: HashMap<(String, usize), Vec<(u64, u64)>> = HashMap::new();
+        for (region, class) in read_named_bed_regions(reader)? {
+            let class = match class {
+                Some(class) if !class.is_empty() => class,
+                _ => {
+                    return Err(WGAError::InvalidRecord(format!(
+                        "invalid BED record without class in the 4th column: {}\t{}\t{}",
+                        region.name, region.start, region.end
+                    )))
+                }
+            };
+            let idx = *class_idx.entry(class.clone()).or_insert_with(|| {
+                classes.push(class);
+                classes.len() - 1
+            });
+            if region.start < region.end {
+                intervals
+                    .entry((region.name, idx))
+                    .or_default()
+                    .push((region.start, region.end));
+            }
+        }
+
+        let mut class_sizes = vec![0; classes.len()];
+        let mut ivs: HashMap<String, Vec<Interval<u64, usize>>> = HashMap::new();
+        for ((name, idx), regions) in intervals {
+            let target_ivs = ivs.entry(name).or_default();
+            for (start, stop) in merge_intervals(regions) {
+                class_sizes[idx] += stop - start;
+                target_ivs.push(Interval {
+                    start,
+                    stop,
+                    val: idx,
+                });
+            }
+        }
+        Ok(Strata {
+            classes,
+            class_sizes,
+            regions: ivs
+                .into_iter()
+                .map(|(name, ivs)| (name, Lapper::new(ivs)))
+                .collect(),
+        })
+    }
+
+    // overlapped bases of each class with `[start, end)` on target
+    fn overlaps<'a>(
+        &'a self,
+        name: &str,
+        start: u64,
+        end: u64,
+    ) -> impl Iterator<Item = (usize, u64)> + 'a {
+        self.regions
+            .get(name)
+            .into_iter()
+            .flat_map(move |lapper| lapper.find(start, end))
+            .map(move |iv| (iv.val, iv.stop.min(end) - iv.start.max(start)))
+    }
+}
+
+/// Alignment columns counted in a class
+#[derive(Debug, Clone, Copy, Default)]
+struct ClassCount {
+    matched: u64,
+    mismatched: u64,
+    ins_event: u64,
+    del_event: u64,
+    del_size: u64,
+}
+
+/// Statistic of a class, Serialize for output
+#[derive(Debug, Serialize)]
+struct StrataStatistic {
+    class: String,
+    class_size: u64,
+    covered_size: u64,
+    coverage: f32,
+    aligned_size: u64,
+    identity: f32,
+    matched: u64,
+    mismatched: u64,
+    ins_event: u64,
+    del_event: u64,
+    del_size: u64,
+    variants_per_kb: f32,
+}
+
+/// Counts of classes and aligned target ranges while records stream
+struct StrataStats<'a> {
+    strata: &'a Strata,
+    counts: Vec<ClassCount>,
+    aligned: HashMap<String, Vec<(u64, u64)>>,
+}
+
+impl<'a> StrataStats<'a> {
+    fn new(strata: &'a Strata) -> Self {
+        StrataStats {
+            strata,
+            counts: vec![ClassCount::default(); strata.classes.len()],
+            aligned: HashMap::new(),
+        }
+    }
+
+    // walk CIGAR units on target, an insertion is counted at the target base before it,
+    // or the first base if it leads the record
+    fn add<T: AlignRecord>(&mut self, rec: &T) -> Result<(), WGAError> {
+        let name = rec.target_name();
+        let (start, size) = (rec.target_start(), rec.target_length());
+        // MAF target may be on `-` strand, so ranges are flipped to the forward strand
+        let forward = |s: u64, e: u64| match rec.target_strand() {
+            Strand::Positive => (s, e),
+            Strand::Negative => (size.saturating_sub(e), size.saturating_sub(s)),
+        };
+        let mut pos = start;
+        let mut aligned = Vec::new();
+        for (op, len) in parse_cigar_to_units(rec)? {
+            match op {
+                'M' | '=' | 'X' => {
+                    let (s, e) = forward(pos, pos + len);
+                    for (class, bases) in self.strata.overlaps(name, s, e) {
+                        match op {
+                            'X' => self.counts[class].mismatched += bases,
+                            _ => self.counts[class].matched += bases,
+                        }
+                    }
+                    aligned.push((s, e));
+                    pos += len;
+                }
+                'D' => {
+                    let (s, e) = forward(pos, pos + len);
+                    let (first, _) = forward(pos, pos + 1);
+                    for (class, bases) in self.strata.overlaps(name, s, e) {
+                        self.counts[class].del_size += bases;
+                    }
+                    for (class, _) in self.strata.overlaps(name, first, first + 1) {
+                        self.counts[class].del_event += 1;
+                    }
+                    pos += len;
+                }
+                'I' => {
+                    let anchor = pos.saturating_sub(1).max(start);
+                    let (anchor, _) = forward(anchor, anchor + 1);
+                    for (class, _) in self.strata.overlaps(name, anchor, anchor + 1) {
+                        self.counts[class].ins_event += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.aligned
+            .entry(name.to_string())
+            .or_default()
+            .extend(aligned);
+        Ok(())
+    }
+
+    fn merge(&mut self, other: StrataStats) {
+        for (acc, count) in self.counts.iter_mut().zip(other.counts) {
+            acc.matched += count.matched;
+            acc.mismatched += count.mismatched;
+            acc.ins_event += count.ins_event;
+            acc.del_event += count.del_event;
+            acc.del_size += count.del_size;
+        }
+        for (name, mut ranges) in other.aligned {
+            self.aligned.entry(name).or_default().append(&mut ranges);
+        }
+    }
+
+    // covered bases are unique target bases aligned by any record
+    fn finish(self) -> Vec<StrataStatistic> {
+        let mut covered = vec![0; self.strata.classes.len()];
+        for (name, ranges) in self.aligned {
+            for (start, end) in merge_intervals(ranges) {
+                for (class, bases) in self.strata.overlaps(&name, start, end) {
+                    covered[class] += bases;
+                }
+            }
+        }
+        let ratio = |a: u64, b: u64| match b {
+            0 => 0.0,
+            _ => a as f32 / b as f32,
+        };
+        self.strata
+            .classes
+            .iter()
+            .zip(&self.strata.class_sizes)
+            .zip(self.counts)
+            .zip(covered)
+            .map(|(((class, &class_size), count), covered_size)| {
+                let aligned_size = count.matched + count.mismatched;
+                let variants = count.mismatched + count.ins_event + count.del_event;
+                StrataStatistic {
+                    class: class.clone(),
+                    class_size,
+                    covered_size,
+                    coverage: ratio(covered_size, class_size),
+                    aligned_size,
+                    identity: ratio(count.matched, aligned_size),
+                    matched: count.matched,
+                    mismatched: count.mismatched,
+                    ins_event: count.ins_event,
+                    del_event: count.del_event,
+                    del_size: count.del_size,
+                    variants_per_kb: ratio(variants * 1000, aligned_size),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Statistics of each class of `strata` in order of BED: size, coverage, identity and
+/// variant density of alignment columns intersected with the class on target
+pub fn stat_strata<T, I>(
+    records: I,
+    strata: &Strata,
+    writer: &mut dyn Write,
+) -> Result<(), WGAError>
+where
+    T: AlignRecord + Send,
+    I: Iterator<Item = Result<T, WGAError>> + Send,
+{
+    let stats = records
+        .par_bridge()
+        .try_fold(
+            || StrataStats::new(strata),
+            |mut acc, rec| {
+                acc.add(&rec?)?;
+                Ok::<_, WGAError>(acc)
+            },
+        )
+        .try_reduce(
+            || StrataStats::new(strata),
+            |mut acc, other| {
+                acc.merge(other);
+                Ok(acc)
+            },
+        )?;
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(true)
+        .from_writer(writer);
+    for stat in stats.finish() {
+        wtr.serialize(stat)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_pair_stats() {
+        let maf =
+            std::fs::read(format!("{}/test/maf/lines.maf", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let records = MAFReader::new(&maf[..])
+            .unwrap()
+            .records()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let mut whole = PairStats::default();
+        let mut parts = [PairStats::default(), PairStats::default()];
+        for (rec, part) in records.iter().zip(parts.iter_mut()) {
+            whole.add(rec).unwrap();
+            part.add(rec).unwrap();
+        }
+        let [mut merged, other] = parts;
+        merged.merge(other);
+        let json = |stats: PairStats| serde_json::to_string(&stats.finish()).unwrap();
+        let merged = json(merged);
+        assert_eq!(merged, json(whole));
+        // both blocks are counted from the smallest start
+        assert!(merged.contains("\"aligned_size\":44"));
+        assert!(merged.contains("\"ref_start\":27578828"));
+    }
+
+    #[test]
+    fn detailed_json_without_aligned_size() {
+        let pair_stat = PairStat {
+            pair: Pair {
+                ref_name: "t".to_string(),
+                ref_size: 100,
+                query_name: "q".to_string(),
+                query_size: 100,
+            },
+            ref_start: 10,
+            ref_end: 10,
+            query_start: 0,
+            query_end: 5,
+            strand: Strand::Positive,
+            rec_stat: RecStat::default(),
+        };
+        let mut out = Vec::new();
+        write_json_detailed(vec![pair_stat], &mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains("\"identity\":0.0"));
+        assert!(!json.contains("null"));
+    }
+
+    #[test]
+    fn strata_from_bed() {
+        let bed =
+            "track name=strata\nchr1\t0\t10\tgene\nchr1\t5\t20\tgene\nchr2\t0\t5\trepeat\t0\n";
+        let strata = Strata::from_bed(bed.as_bytes()).unwrap();
+        assert_eq!(strata.classes, ["gene", "repeat"]);
+        assert_eq!(strata.class_sizes, [20, 5]);
+        // class is required
+        assert!(Strata::from_bed("chr1\t0\t10\n".as_bytes()).is_err());
+        assert!(Strata::from_bed("chr1\t0\t10\t\n".as_bytes()).is_err());
+    }
+}
@@ -1,18 +1,21 @@
-use crate::tools::index::MafIndex;
+use crate::tools::index::{load_index, match_seq_names, MafIndex};
+use crate::utils::get_input_reader;
 use crate::{errors::WGAError, parser::maf::MAFReader};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, MouseButton, MouseEvent,
+        MouseEventKind,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use itertools::enumerate;
 use ratatui::{prelude::*, widgets::*};
-use regex::Regex;
 use rust_lapper::{Interval, Lapper};
 use std::path::PathBuf;
 use std::{
     fs::File,
-    io::{self, BufReader, Read, Seek},
+    io::{self, BufRead, Read, Seek},
     rc::Rc,
     time::{Duration, Instant},
 };
@@ -58,6 +61,12 @@ impl Scroll<'_> {
         self.scroll = 0;
         self.scroll_state = self.scroll_state.position(self.scroll);
     }
+
+    /// scroll to put column `col` at the center of view with `width` columns
+    fn scroll_center(&mut self, col: usize, width: usize) -> Result<(), WGAError> {
+        self.scroll_init();
+        self.scroll_right(col.saturating_sub(width / 2))
+    }
 }
 
 type Iv = Interval<u64, u64>;
@@ -67,23 +76,27 @@ struct Navigation {
     input: String,
     cursor_position: usize,
     input_valid: bool,
+    // candidate names matching the typed name, and their indices in all names
     cddt_name: Vec<String>,
+    cddt_idx: Vec<usize>,
     select_name_idx: usize,
     cddt_region: Vec<String>,
     select_region_idx: usize,
+    all_names: Vec<String>,
     all_regions: Vec<Vec<Iv>>,
     select_region: bool,
 }
 
 impl Navigation {
     fn select_up(&mut self) {
-        if self.show {
+        if self.show && !self.cddt_name.is_empty() {
             if !self.select_region {
                 if self.select_name_idx == 0 {
                     self.select_name_idx = self.cddt_name.len();
                 }
                 self.select_name_idx = self.select_name_idx.saturating_sub(1);
-                self.cddt_region = ivvec2strvec(&self.all_regions[self.select_name_idx]);
+                self.cddt_region =
+                    ivvec2strvec(&self.all_regions[self.cddt_idx[self.select_name_idx]]);
                 self.select_region_idx = 0;
                 self.update_input();
             } else {
@@ -97,7 +110,7 @@ impl Navigation {
     }
 
     fn select_down(&mut self) {
-        if self.show {
+        if self.show && !self.cddt_name.is_empty() {
             if !self.select_region {
                 if self.select_name_idx + 1 == self.cddt_name.len() {
                     self.select_name_idx = 0;
@@ -105,7 +118,8 @@ impl Navigation {
                     self.select_name_idx = self.select_name_idx.saturating_add(1);
                 }
                 // update regions
-                self.cddt_region = ivvec2strvec(&self.all_regions[self.select_name_idx]);
+                self.cddt_region =
+                    ivvec2strvec(&self.all_regions[self.cddt_idx[self.select_name_idx]]);
                 self.select_region_idx = 0;
                 self.update_input();
             } else {
@@ -120,14 +134,49 @@ impl Navigation {
     }
 
     fn update_input(&mut self) {
-        let name = &self.cddt_name[self.select_name_idx];
-        let region = &self.cddt_region[self.select_region_idx];
+        let (Some(name), Some(region)) = (
+            self.cddt_name.get(self.select_name_idx),
+            self.cddt_region.get(self.select_region_idx),
+        ) else {
+            return;
+        };
         let start = region.split('-').collect::<Vec<&str>>()[0];
         let replace_text = format!("{}:{}", name, start);
         self.input.replace_range(6.., &replace_text);
         self.cursor_position = self.input.len();
     }
 
+    /// typed name before `:` of input
+    fn typed_name(&self) -> &str {
+        let typed = &self.input[6..];
+        typed.rsplit_once(':').map_or(typed, |(name, _)| name)
+    }
+
+    /// list names matching the typed name exactly, by substring or fuzzily as candidates
+    fn filter_names(&mut self) {
+        let typed = self.typed_name();
+        self.cddt_idx = if typed.is_empty() {
+            (0..self.all_names.len()).collect()
+        } else {
+            let matched = match_seq_names(self.all_names.iter().map(String::as_str), typed);
+            matched
+                .iter()
+                .filter_map(|name| self.all_names.iter().position(|x| x == name))
+                .collect()
+        };
+        self.cddt_name = self
+            .cddt_idx
+            .iter()
+            .map(|&idx| self.all_names[idx].clone())
+            .collect();
+        self.select_name_idx = 0;
+        self.select_region_idx = 0;
+        self.cddt_region = self
+            .cddt_idx
+            .first()
+            .map_or_else(Vec::new, |&idx| ivvec2strvec(&self.all_regions[idx]));
+    }
+
     fn move_cursor_left(&mut self) {
         let cursor_moved_left = self.cursor_position.saturating_sub(1);
         self.cursor_position = self.clamp_cursor(cursor_moved_left);
@@ -142,6 +191,7 @@ impl Navigation {
         self.input.insert(self.cursor_position, new_char);
 
         self.move_cursor_right();
+        self.filter_names();
     }
 
     fn delete_char(&mut self) {
@@ -157,6 +207,7 @@ impl Navigation {
             // By leaving the selected one out, it is forgotten and therefore deleted.
             self.input = before_char_to_delete.chain(after_char_to_delete).collect();
             self.move_cursor_left();
+            self.filter_names();
         }
     }
 
@@ -165,6 +216,14 @@ impl Navigation {
     }
 }
 
+/// A variant site loaded from VCF
+struct Variant {
+    chrom: String,
+    // 1-based as VCF
+    pos: u64,
+    alleles: String,
+}
+
 struct MafViewApp<'a, R: Read + Send + Seek> {
     fixed: Vec<Line<'a>>,
     scroll: Scroll<'a>,
@@ -172,6 +231,13 @@ struct MafViewApp<'a, R: Read + Send + Seek> {
     #[allow(dead_code)]
     wait: bool,
     filerdr: MAFReader<R>,
+    variants: Vec<Variant>,
+    variant_idx: Option<usize>,
+    // areas of sequence names and sequences in last draw, to locate mouse events
+    name_area: Rect,
+    seq_area: Rect,
+    // name of the highlighted sequence row
+    highlight: Option<String>,
 }
 
 impl MafViewApp<'_, File> {
@@ -196,28 +262,26 @@ impl MafViewApp<'_, File> {
             input: "Goto: ".to_string(),
             cursor_position: 6,
             input_valid: true,
-            cddt_name: cddt_names,
+            cddt_idx: (0..cddt_names.len()).collect(),
+            cddt_name: cddt_names.clone(),
             select_name_idx: 0,
             cddt_region: ivvec2strvec(cddt_regions),
             select_region_idx: 0,
+            all_names: cddt_names,
             all_regions,
             select_region: false,
         }
     }
 
-    fn new(input: &String) -> Result<Self, WGAError> {
+    fn new(input: &String, vcf: Option<&str>, rebuild_index: bool) -> Result<Self, WGAError> {
         // creat reader
         let mut mafreader = MAFReader::from_path(input)?;
         // init scroll, fixed
         let mut scroll = Scroll::default();
         let mut fixed = vec![Line::from("pos:"), Line::from("|")];
         // read index
-        let index_file_path = &format!("{}.index", input);
-        let index_file = match File::open(index_file_path) {
-            Ok(index_file) => index_file,
-            Err(_) => return Err(WGAError::FileNotExist(PathBuf::from(index_file_path))),
-        };
-        let mafindex: MafIndex = serde_json::from_reader(BufReader::new(index_file))?;
+        let mafindex = load_index(input, rebuild_index)?
+            .ok_or_else(|| WGAError::FileNotExist(PathBuf::from(format!("{}.index", input))))?;
         // create navigation
         let mut navigation = Self::gen_navigation(mafindex);
 
@@ -252,21 +316,29 @@ impl MafViewApp<'_, File> {
         scroll.para_lines = para_lines;
         navigation.update_input();
 
+        let variants = match vcf {
+            Some(vcf) => read_variants(vcf)?,
+            None => Vec::new(),
+        };
+
         let app = Self {
             fixed,
             scroll,
             navigation,
             wait: false,
             filerdr: mafreader,
+            variants,
+            variant_idx: None,
+            name_area: Rect::default(),
+            seq_area: Rect::default(),
+            highlight: None,
         };
 
         Ok(app)
     }
 
     fn update(&mut self) -> Result<(), WGAError> {
-        self.filerdr
-            .inner
-            .seek(std::io::SeekFrom::Start(self.scroll.seek))?;
+        self.filerdr.seek(self.scroll.seek)?;
         // new mafrec
         let mafrec = self
             .filerdr
@@ -324,11 +396,140 @@ impl MafViewApp<'_, File> {
         self.navigation.show = false;
         Ok(())
     }
+
+    /// set seek/destination/ref name if `start`(0-based) of `name` is in index
+    fn locate(&mut self, name: &str, start: u64) -> bool {
+        let name_idx = match self.navigation.all_names.iter().position(|i| i == name) {
+            Some(name_idx) => name_idx,
+            None => return false,
+        };
+        let cddt_regions: &Vec<Iv> = &self.navigation.all_regions[name_idx];
+        let lapper = Lapper::new(cddt_regions.clone());
+        match lapper.find(start, start + 1).next() {
+            Some(dest_block) => {
+                self.scroll.seek = dest_block.val;
+                self.scroll.destpos = start;
+                self.scroll.ref_name = name.to_string();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 2 for borders
+    fn view_width(&self) -> usize {
+        self.seq_area.width.saturating_sub(2) as usize
+    }
+
+    /// scroll by wheel, center the clicked column of ruler or highlight the clicked row
+    fn handle_mouse(&mut self, mouse: MouseEvent, step: usize) -> Result<(), WGAError> {
+        match mouse.kind {
+            MouseEventKind::ScrollUp if self.navigation.show => self.navigation.select_up(),
+            MouseEventKind::ScrollDown if self.navigation.show => self.navigation.select_down(),
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollLeft => self.scroll.scroll_left(step),
+            MouseEventKind::ScrollDown | MouseEventKind::ScrollRight => {
+                self.scroll.scroll_right(step)?
+            }
+            MouseEventKind::Down(MouseButton::Left) if !self.navigation.show => {
+                let (col, row) = (mouse.column, mouse.row);
+                let in_seq = in_rect(self.seq_area, col, row);
+                let in_name = in_rect(self.name_area, col, row);
+                // rows and columns inside borders
+                let line_idx = match (in_seq, in_name) {
+                    (true, _) => row.checked_sub(self.seq_area.y + 1),
+                    (_, true) => row.checked_sub(self.name_area.y + 1),
+                    _ => None,
+                };
+                match line_idx.map(usize::from) {
+                    // axis and indicator lines of ruler
+                    Some(0 | 1) if in_seq => {
+                        if let Some(col) = col.checked_sub(self.seq_area.x + 1) {
+                            let col = self.scroll.scroll + col as usize;
+                            self.scroll.scroll_center(col, self.view_width())?;
+                        }
+                    }
+                    Some(idx) if idx >= 2 && idx < self.fixed.len() => {
+                        let name = line_text(&self.fixed[idx]);
+                        // click the highlighted row again to clear
+                        self.highlight = match self.highlight.as_deref() == Some(name.as_str()) {
+                            true => None,
+                            false => Some(name),
+                        };
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// jump to the next/previous variant and center it, variants not in index are skipped
+    fn goto_variant(&mut self, forward: bool) -> Result<(), WGAError> {
+        let count = self.variants.len();
+        if count == 0 {
+            return Ok(());
+        }
+        let mut idx = self.variant_idx;
+        for _ in 0..count {
+            let next_idx = match (idx, forward) {
+                (None, true) => 0,
+                (None, false) => count - 1,
+                (Some(i), true) => (i + 1) % count,
+                (Some(i), false) => (i + count - 1) % count,
+            };
+            idx = Some(next_idx);
+            let variant = &self.variants[next_idx];
+            let (chrom, start) = (variant.chrom.clone(), variant.pos.saturating_sub(1));
+            if self.locate(&chrom, start) {
+                self.update()?;
+                self.scroll.scroll_left(self.view_width() / 2);
+                self.variant_idx = idx;
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
 }
 
-pub fn tview(input: &String, step: usize) -> Result<(), WGAError> {
+// read CHROM/POS/REF/ALT of variants from a plain or compressed VCF
+fn read_variants(path: &str) -> Result<Vec<Variant>, WGAError> {
+    let reader = get_input_reader(&Some(path.to_string()))?;
+    let mut variants = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        let fields = line.split('\t').take(5).collect::<Vec<&str>>();
+        let (chrom, pos, ref_allele, alt_allele) = match fields.as_slice() {
+            [chrom, pos, _id, ref_allele, alt_allele] => (chrom, pos, ref_allele, alt_allele),
+            _ => {
+                return Err(WGAError::InvalidRecord(format!(
+                    "invalid VCF record: {}",
+                    line
+                )))
+            }
+        };
+        variants.push(Variant {
+            chrom: chrom.to_string(),
+            pos: pos.parse::<u64>().map_err(|e| {
+                WGAError::InvalidRecord(format!("invalid VCF POS `{}`: {}", pos, e))
+            })?,
+            alleles: format!("{}>{}", ref_allele, alt_allele),
+        });
+    }
+    Ok(variants)
+}
+
+pub fn tview(
+    input: &String,
+    step: usize,
+    vcf: Option<&str>,
+    rebuild_index: bool,
+) -> Result<(), WGAError> {
     // creat app and fill init data
-    let app = MafViewApp::new(input)?;
+    let app = MafViewApp::new(input, vcf, rebuild_index)?;
 
     // setup terminal
     enable_raw_mode()?;
@@ -378,8 +579,9 @@ fn run_app<B: Backend>(
 
         let timeout = tick_rate.saturating_sub(last_tick.elapsed());
         if crossterm::event::poll(timeout)? {
-            if let Event::Key(key) = event::read()? {
-                match key.code {
+            match event::read()? {
+                Event::Mouse(mouse) => app.handle_mouse(mouse, step)?,
+                Event::Key(key) => match key.code {
                     KeyCode::Left => {
                         if app.navigation.show {
                             app.navigation.move_cursor_left();
@@ -400,10 +602,8 @@ fn run_app<B: Backend>(
                     KeyCode::Down => {
                         app.navigation.select_down();
                     }
-                    KeyCode::Esc => {
-                        if app.navigation.show {
-                            app.navigation.show = false;
-                        }
+                    KeyCode::Esc if app.navigation.show => {
+                        app.navigation.show = false;
                     }
                     KeyCode::Char(input_char) => {
                         if app.navigation.show {
@@ -412,25 +612,24 @@ fn run_app<B: Backend>(
                             return Ok(());
                         } else if input_char == 'g' {
                             app.navigation.show = true;
+                        } else if input_char == 'n' {
+                            app.goto_variant(true)?;
+                        } else if input_char == 'p' {
+                            app.goto_variant(false)?;
                         }
                     }
-                    KeyCode::Backspace => {
-                        if app.navigation.show {
-                            app.navigation.delete_char();
-                        }
+                    KeyCode::Backspace if app.navigation.show => {
+                        app.navigation.delete_char();
                     }
-                    KeyCode::Tab => {
-                        if app.navigation.show {
-                            app.navigation.select_region = !app.navigation.select_region;
-                        }
+                    KeyCode::Tab if app.navigation.show => {
+                        app.navigation.select_region = !app.navigation.select_region;
                     }
-                    KeyCode::Enter => {
-                        if app.navigation.show && app.navigation.input_valid {
-                            app.update()?;
-                        }
+                    KeyCode::Enter if app.navigation.show && app.navigation.input_valid => {
+                        app.update()?;
                     }
                     _ => {}
-                }
+                },
+                _ => {}
             }
         }
         if last_tick.elapsed() >= tick_rate {
@@ -458,11 +657,42 @@ fn main_ui(f: &mut Frame, app: &mut MafViewApp<'_, File>) {
             .border_type(BorderType::Rounded)
     };
 
-    let seqname_para = Paragraph::new(app.fixed.clone()).block(create_block("seq name"));
+    let mut fixed = app.fixed.clone();
+    let mut para_lines = app.scroll.para_lines.clone();
+    let highlight_idx = app
+        .highlight
+        .as_ref()
+        .and_then(|name| (2..fixed.len()).find(|&idx| line_text(&fixed[idx]) == *name));
+    if let Some(idx) = highlight_idx {
+        let style = Style::default().add_modifier(Modifier::REVERSED);
+        fixed[idx].patch_style(style);
+        para_lines[idx].patch_style(style);
+    }
+
+    let seqname_para = Paragraph::new(fixed).block(create_block("seq name"));
     f.render_widget(seqname_para, main_layout[0]);
 
-    let paragraph = Paragraph::new(app.scroll.para_lines.clone())
-        .block(create_block("Press ◄ ► to scroll"))
+    app.name_area = main_layout[0];
+    app.seq_area = main_layout[1];
+    let scroll_title = match app.variant_idx {
+        Some(idx) => {
+            let variant = &app.variants[idx];
+            format!(
+                "Press ◄ ► to scroll, n/p for next/previous variant [{}/{}] {}:{} {}",
+                idx + 1,
+                app.variants.len(),
+                variant.chrom,
+                variant.pos,
+                variant.alleles
+            )
+        }
+        None if !app.variants.is_empty() => {
+            "Press ◄ ► to scroll, n/p for next/previous variant".to_string()
+        }
+        None => "Press ◄ ► to scroll".to_string(),
+    };
+    let paragraph = Paragraph::new(para_lines)
+        .block(create_block(scroll_title.as_str()))
         .scroll((0, app.scroll.scroll as u16));
     f.render_widget(paragraph, main_layout[1]);
     f.render_stateful_widget(
@@ -531,9 +761,14 @@ fn main_ui(f: &mut Frame, app: &mut MafViewApp<'_, File>) {
         );
 
         let message = if app.navigation.input_valid {
-            "Press ▲ ▼ to select, <Tab> to switch between name and region, <Esc> to exit, <Enter> to jump"
+            "Press ▲ ▼ to select, <Tab> to switch between name and region, <Esc> to exit, <Enter> to jump".to_string()
+        } else if app.navigation.cddt_name.len() > 1 {
+            format!(
+                "Ambiguous name, {} candidates, press ▲ ▼ to select",
+                app.navigation.cddt_name.len()
+            )
         } else {
-            "Invalid input, please re-select or enter"
+            "Invalid input, please re-select or enter".to_string()
         };
         let msg = Paragraph::new(message).light_blue();
         f.render_widget(msg, msg_area);
@@ -554,7 +789,7 @@ fn get_axis_idc_len(
     let mut base_count = 0;
     let mut walk_size = 0;
     for base in seq.chars() {
-        if base_count <= destpos - (start - 1) {
+        if base_count <= destpos.saturating_sub(start - 1) {
             walk_size += 1
         }
         len_count += 1;
@@ -584,6 +819,17 @@ fn get_axis_idc_len(
     (axis_text, indicator_text, len_count, walk_size)
 }
 
+fn in_rect(area: Rect, col: u16, row: u16) -> bool {
+    (area.x..area.right()).contains(&col) && (area.y..area.bottom()).contains(&row)
+}
+
+fn line_text(line: &Line) -> String {
+    line.spans
+        .iter()
+        .map(|span| span.content.as_ref())
+        .collect()
+}
+
 fn ivvec2strvec(invec: &[Iv]) -> Vec<String> {
     invec
         .iter()
@@ -592,40 +838,34 @@ fn ivvec2strvec(invec: &[Iv]) -> Vec<String> {
 }
 
 fn input_valid_update(app: &mut MafViewApp<'_, File>) -> Result<(), WGAError> {
-    let re = Regex::new(r"^[a-zA-Z0-9.\-@_#]+:[0-9]+?$")?; // NO ERROR
-    match re.is_match(&app.navigation.input[6..]) {
-        true => {
-            let name = &app.navigation.input[6..].split(':').collect::<Vec<&str>>()[0];
-            match app.navigation.cddt_name.iter().position(|i| i == name) {
-                Some(name_idx) => {
-                    let raw_start = &app.navigation.input[6..].split(':').collect::<Vec<&str>>()[1];
-                    let start = match raw_start.parse::<usize>() {
-                        Ok(i) => i,
-                        Err(_) => {
-                            app.navigation.input_valid = false;
-                            0
-                        }
-                    };
-                    let cddt_regions: &Vec<Iv> = &app.navigation.all_regions[name_idx];
-                    let lapper = Lapper::new(cddt_regions.clone());
-                    let find = lapper
-                        .find(start as u64, start as u64 + 1)
-                        .collect::<Vec<&Iv>>();
-                    if find.is_empty() {
-                        app.navigation.input_valid = false;
-                    } else {
-                        let dest_block = find[0];
-                        app.scroll.seek = dest_block.val;
-                        app.scroll.destpos = start as u64;
-                        app.scroll.ref_name = name.to_string();
-                    }
-                }
-                None => {
-                    app.navigation.input_valid = false;
-                }
+    // `name:start` or `name` for its first block, name is matched as the candidates
+    let typed = &app.navigation.input[6..];
+    let (name, start) = match typed.rsplit_once(':') {
+        Some((name, start)) => (name, start.parse::<u64>().ok()),
+        None => (typed, None),
+    };
+    let matched = match_seq_names(app.navigation.all_names.iter().map(String::as_str), name);
+    let name_idx = match matched.as_slice() {
+        [name] => app.navigation.all_names.iter().position(|x| x == name),
+        _ => None,
+    };
+    let start = match (name_idx, typed.contains(':')) {
+        (Some(name_idx), false) => app.navigation.all_regions[name_idx]
+            .iter()
+            .map(|iv| iv.start)
+            .min(),
+        (Some(_), true) => start,
+        (None, _) => None,
+    };
+    match (name_idx, start) {
+        (Some(name_idx), Some(start)) => {
+            let name = app.navigation.all_names[name_idx].clone();
+            // name or position not in index
+            if !app.locate(&name, start) {
+                app.navigation.input_valid = false;
             }
         }
-        false => {
+        _ => {
             app.navigation.input_valid = false;
         }
     }
@@ -1,80 +1,235 @@
 use crate::{
     errors::WGAError,
     parser::{
-        common::AlignRecord,
-        paf::{PAFReader, PafRecord},
+        common::{AlignRecord, ValidateOutFormat},
+        paf::{paf_writer, PAFReader, PafRecord},
     },
 };
 use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashSet;
 use std::fmt;
 use std::io::{Read, Write};
 
-/// Check query&target start&end position by CIGAR
-/// query_start + Match/Mismatch + INS_size = query_end
-/// ref_start + Match/Mismatch + DEL_size = ref_end
+// Check query&target start&end position by CIGAR
+// query_start + Match/Mismatch + INS_size = query_end
+// ref_start + Match/Mismatch + DEL_size = ref_end
+
+/// Error classes of a PAF record
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum IssueKind {
+    /// query_end differs from the one by CIGAR, fixable
+    QueryEnd,
+    /// target_end differs from the one by CIGAR, fixable
+    TargetEnd,
+    /// query start > end or end > length
+    QueryOutOfRange,
+    /// target start > end or end > length
+    TargetOutOfRange,
+    /// CIGAR is missing or can not be parsed
+    InvalidCigar,
+}
+
+const ISSUE_HEADER: [&str; 10] = [
+    "record",
+    "kind",
+    "query_name",
+    "query_start",
+    "query_end",
+    "target_name",
+    "target_start",
+    "target_end",
+    "detail",
+    "fixed",
+];
+
+/// An error of a record, one row in JSON/TSV output
+#[derive(Debug, Serialize)]
+struct Issue {
+    /// 1-based ordinal of the record
+    record: usize,
+    kind: IssueKind,
+    query_name: String,
+    query_start: u64,
+    query_end: u64,
+    target_name: String,
+    target_start: u64,
+    target_end: u64,
+    /// expected value or error message
+    detail: String,
+    fixed: bool,
+}
+
+impl Issue {
+    fn new(record: usize, kind: IssueKind, rec: &PafRecord, detail: String, fixed: bool) -> Self {
+        Issue {
+            record,
+            kind,
+            query_name: rec.query_name.clone(),
+            query_start: rec.query_start,
+            query_end: rec.query_end,
+            target_name: rec.target_name.clone(),
+            target_start: rec.target_start,
+            target_end: rec.target_end,
+            detail,
+            fixed,
+        }
+    }
+
+    // `name:start-end` of the side with error
+    fn uid(&self) -> String {
+        match self.kind {
+            IssueKind::TargetEnd | IssueKind::TargetOutOfRange => {
+                format!(
+                    "{}:{}-{}",
+                    self.target_name, self.target_start, self.target_end
+                )
+            }
+            _ => format!(
+                "{}:{}-{}",
+                self.query_name, self.query_start, self.query_end
+            ),
+        }
+    }
+}
+
+/// Summary in JSON output
+#[derive(Serialize)]
+struct ValidateSummary<'a> {
+    total: usize,
+    invalid: usize,
+    issues: &'a [Issue],
+}
 
 #[derive(Default)]
 struct Validations {
     total: usize,
-    query_invalid: usize,
-    query_inv_list: Vec<String>,
-    ref_invalid: usize,
-    ref_inv_list: Vec<String>,
-    fix_paf_recs: Vec<PafRecord>,
+    issues: Vec<Issue>,
+    fix_paf_recs: Vec<(usize, PafRecord)>,
+}
+
+impl Validations {
+    fn of_kind(&self, kind: IssueKind) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(move |issue| issue.kind == kind)
+    }
+
+    // number of records with any error
+    fn invalid(&self) -> usize {
+        self.issues
+            .iter()
+            .map(|issue| issue.record)
+            .collect::<HashSet<_>>()
+            .len()
+    }
 }
 
 impl fmt::Display for Validations {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Total records: {}", self.total)?;
-        writeln!(f, "Query invalid records: {}", self.query_invalid)?;
-        writeln!(f, "Target invalid records: {}", self.ref_invalid)?;
+        writeln!(
+            f,
+            "Query invalid records: {}",
+            self.of_kind(IssueKind::QueryEnd).count()
+        )?;
+        writeln!(
+            f,
+            "Target invalid records: {}",
+            self.of_kind(IssueKind::TargetEnd).count()
+        )?;
         writeln!(f, "Query invalid list:")?;
-        for query_uid in self.query_inv_list.iter() {
-            writeln!(f, "{}", query_uid)?;
+        for issue in self.of_kind(IssueKind::QueryEnd) {
+            writeln!(f, "{}", issue.uid())?;
         }
         writeln!(f, "Target invalid list:")?;
-        for ref_uid in self.ref_inv_list.iter() {
-            writeln!(f, "{}", ref_uid)?;
+        for issue in self.of_kind(IssueKind::TargetEnd) {
+            writeln!(f, "{}", issue.uid())?;
+        }
+        // errors which can not be fixed are only shown if present
+        for (kind, label) in [
+            (IssueKind::QueryOutOfRange, "Query out of range"),
+            (IssueKind::TargetOutOfRange, "Target out of range"),
+            (IssueKind::InvalidCigar, "Invalid CIGAR"),
+        ] {
+            let issues = self.of_kind(kind).collect::<Vec<_>>();
+            if issues.is_empty() {
+                continue;
+            }
+            writeln!(f, "{} records: {}", label, issues.len())?;
+            for issue in issues {
+                writeln!(f, "{}\t{}", issue.uid(), issue.detail)?;
+            }
         }
         Ok(())
     }
 }
 
-/// Validate PAF records in parallel
+/// Validate PAF records in parallel, error if any record is invalid in `strict` mode
 pub fn parallel_validatepaf<R: Read + Send>(
     mut reader: PAFReader<R>,
     writer: &mut dyn Write,
     fix_writer: Option<Box<dyn Write>>,
     fix_flag: bool,
+    out_format: ValidateOutFormat,
+    strict: bool,
 ) -> Result<(), WGAError> {
     let validations = reader
         .records()
+        .enumerate()
         .par_bridge()
-        .try_fold(Validations::default, |vd, rec| {
+        .try_fold(Validations::default, |vd, (idx, rec)| {
             let rec = rec?;
-            process_record(vd, rec, fix_flag)
+            process_record(vd, idx + 1, rec, fix_flag)
         })
         .try_reduce(Validations::default, |mut vd1, vd2| {
             vd1.total += vd2.total;
-            vd1.query_invalid += vd2.query_invalid;
-            vd1.query_inv_list.extend(vd2.query_inv_list);
-            vd1.ref_invalid += vd2.ref_invalid;
-            vd1.ref_inv_list.extend(vd2.ref_inv_list);
+            vd1.issues.extend(vd2.issues);
             vd1.fix_paf_recs.extend(vd2.fix_paf_recs);
             Ok(vd1)
         });
-    process_validations(validations?, writer, fix_writer)?;
+    let mut validations = validations?;
+    // keep input order
+    validations.issues.sort_by_key(|issue| issue.record);
+    validations.fix_paf_recs.sort_by_key(|(idx, _)| *idx);
+    let (invalid, total) = (validations.invalid(), validations.total);
+    process_validations(validations, writer, fix_writer, out_format)?;
+    if strict && invalid > 0 {
+        return Err(WGAError::ValidateFailed(invalid, total));
+    }
     Ok(())
 }
 
 /// process record
 fn process_record(
     mut vd: Validations,
+    idx: usize,
     mut rec: PafRecord,
     fix_flag: bool,
 ) -> Result<Validations, WGAError> {
     vd.total += 1;
-    let rec_stat = rec.get_stat().unwrap();
+    let rec_stat = match rec.get_stat() {
+        Ok(rec_stat) => rec_stat,
+        Err(err) => {
+            let issue = Issue::new(idx, IssueKind::InvalidCigar, &rec, err.to_string(), false);
+            vd.issues.push(issue);
+            if fix_flag {
+                vd.fix_paf_recs.push((idx, rec));
+            }
+            return Ok(vd);
+        }
+    };
+
+    // check ranges of the original record
+    if rec.query_start() > rec.query_end() || rec.query_end() > rec.query_length() {
+        let detail = format!("query length {}", rec.query_length());
+        let issue = Issue::new(idx, IssueKind::QueryOutOfRange, &rec, detail, false);
+        vd.issues.push(issue);
+    }
+    if rec.target_start() > rec.target_end() || rec.target_end() > rec.target_length() {
+        let detail = format!("target length {}", rec.target_length());
+        let issue = Issue::new(idx, IssueKind::TargetOutOfRange, &rec, detail, false);
+        vd.issues.push(issue);
+    }
 
     // check query end
     let exp_query_end = rec.query_start()
@@ -82,38 +237,28 @@ fn process_record(
         + rec_stat.mismatched as u64
         + rec_stat.ins_size as u64
         + rec_stat.inv_ins_size as u64;
-    if exp_query_end != rec.query_end() {
-        vd.query_invalid += 1;
-        let query_uid = format!(
-            "{}:{}-{}",
-            rec.query_name(),
-            rec.query_start(),
-            rec.query_end()
-        );
-        vd.query_inv_list.push(query_uid);
-        rec.query_end = exp_query_end;
-    }
-
     // check ref end
     let exp_ref_end = rec.target_start()
         + rec_stat.matched as u64
         + rec_stat.mismatched as u64
         + rec_stat.del_size as u64
         + rec_stat.inv_del_size as u64;
+
+    if exp_query_end != rec.query_end() {
+        let detail = exp_query_end.to_string();
+        let issue = Issue::new(idx, IssueKind::QueryEnd, &rec, detail, fix_flag);
+        vd.issues.push(issue);
+    }
     if exp_ref_end != rec.target_end() {
-        vd.ref_invalid += 1;
-        let ref_uid = format!(
-            "{}:{}-{}",
-            rec.target_name(),
-            rec.target_start(),
-            rec.target_end()
-        );
-        vd.ref_inv_list.push(ref_uid);
-        rec.target_end = exp_ref_end;
+        let detail = exp_ref_end.to_string();
+        let issue = Issue::new(idx, IssueKind::TargetEnd, &rec, detail, fix_flag);
+        vd.issues.push(issue);
     }
+    rec.query_end = exp_query_end;
+    rec.target_end = exp_ref_end;
 
     if fix_flag {
-        vd.fix_paf_recs.push(rec);
+        vd.fix_paf_recs.push((idx, rec));
     }
 
     Ok(vd)
@@ -124,18 +269,39 @@ fn process_validations(
     validations: Validations,
     writer: &mut dyn Write,
     fix_writer: Option<Box<dyn Write>>,
+    out_format: ValidateOutFormat,
 ) -> Result<(), WGAError> {
-    writeln!(writer, "{}", validations)?;
+    match out_format {
+        ValidateOutFormat::Text => writeln!(writer, "{}", validations)?,
+        ValidateOutFormat::Json => {
+            let summary = ValidateSummary {
+                total: validations.total,
+                invalid: validations.invalid(),
+                issues: &validations.issues,
+            };
+            writeln!(writer, "{}", serde_json::to_string(&summary)?)?;
+        }
+        ValidateOutFormat::Tsv => {
+            // header is written even if there is no issue
+            let mut wtr = csv::WriterBuilder::new()
+                .delimiter(b'\t')
+                .has_headers(false)
+                .from_writer(&mut *writer);
+            wtr.write_record(ISSUE_HEADER)?;
+            for issue in &validations.issues {
+                wtr.serialize(issue)?;
+            }
+            wtr.flush()?;
+        }
+    }
+    writer.flush()?;
     // write fix output
     if let Some(writer) = fix_writer {
-        let mut pafwtr = csv::WriterBuilder::new()
-            .delimiter(b'\t')
-            .flexible(true)
-            .has_headers(false)
-            .from_writer(writer);
-        for rec in validations.fix_paf_recs {
+        let mut pafwtr = paf_writer(writer);
+        for (_, rec) in validations.fix_paf_recs {
             pafwtr.serialize(rec)?;
         }
+        pafwtr.flush()?;
     }
     Ok(())
 }
@@ -0,0 +1,308 @@
+use crate::errors::WGAError;
+use crate::log::RecordCounter;
+use crate::parser::common::{LiftFrom, Strand};
+use crate::tools::segments::RecordSegments;
+use crate::utils::{parse_str2u64, reverse_complement, IndexedFasta};
+use log::info;
+use rust_lapper::{Interval, Lapper};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+// INFO field of records in reject file
+const LIFT_FAIL_HEADER: &str = "##INFO=<ID=LIFT_FAIL,Number=1,Type=String,Description=\"Reason of failed lift: NO_HIT, PARTIAL_HIT, MULTIPLE_HITS, SYMBOLIC_ON_MINUS, COMPLEX_ON_MINUS, NO_ANCHOR, NO_DEST_SEQ or REF_MISMATCH\">";
+
+/// Destination of a gap-free segment, `start` is on the forward strand
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LiftDest {
+    name: usize,
+    start: u64,
+    strand: Strand,
+}
+
+/// Gap-free segments indexed by source sequence, with destination sequences in order of appearance
+pub struct LiftMap {
+    segments: HashMap<String, Lapper<u64, LiftDest>>,
+    dest_names: Vec<String>,
+    dest_sizes: Vec<u64>,
+}
+
+impl LiftMap {
+    /// Build from segments of alignment records
+    pub fn new<I>(records: I, from: LiftFrom) -> Result<Self, WGAError>
+    where
+        I: Iterator<Item = Result<RecordSegments, WGAError>>,
+    {
+        let mut ivs: HashMap<String, Vec<Interval<u64, LiftDest>>> = HashMap::new();
+        let mut dest_idx: HashMap<String, usize> = HashMap::new();
+        let mut dest_names = Vec::new();
+        let mut dest_sizes = Vec::new();
+        for rec in records {
+            let rec = rec?;
+            let (src, dest, dest_size) = match from {
+                LiftFrom::Query => (rec.query_name, rec.target_name, rec.target_length),
+                LiftFrom::Target => (rec.target_name, rec.query_name, rec.query_length),
+            };
+            let name = *dest_idx.entry(dest.clone()).or_insert_with(|| {
+                dest_names.push(dest);
+                dest_sizes.push(dest_size);
+                dest_names.len() - 1
+            });
+            let src_ivs = ivs.entry(src).or_default();
+            for seg in rec.segments {
+                let (src_start, dest_start) = match from {
+                    LiftFrom::Query => (seg.query_start, seg.target_start),
+                    LiftFrom::Target => (seg.target_start, seg.query_start),
+                };
+                src_ivs.push(Interval {
+                    start: src_start,
+                    stop: src_start + seg.len,
+                    val: LiftDest {
+                        name,
+                        start: dest_start,
+                        strand: seg.strand,
+                    },
+                });
+            }
+        }
+        Ok(LiftMap {
+            segments: ivs
+                .into_iter()
+                .map(|(name, ivs)| (name, Lapper::new(ivs)))
+                .collect(),
+            dest_names,
+            dest_sizes,
+        })
+    }
+
+    /// Lift 0-based half-open `[start, end)` on `name` within a single segment
+    fn lift(
+        &self,
+        name: &str,
+        start: u64,
+        end: u64,
+    ) -> Result<(usize, u64, u64, Strand), &'static str> {
+        let lapper = self.segments.get(name).ok_or("NO_HIT")?;
+        let mut hits = lapper.find(start, end.max(start + 1)).peekable();
+        if hits.peek().is_none() {
+            return Err("NO_HIT");
+        }
+        let mut within = hits.filter(|iv| iv.start <= start && end <= iv.stop);
+        let iv = within.next().ok_or("PARTIAL_HIT")?;
+        if within.next().is_some() {
+            return Err("MULTIPLE_HITS");
+        }
+        let dest = &iv.val;
+        let (dest_start, dest_end) = match dest.strand {
+            Strand::Positive => (dest.start + start - iv.start, dest.start + end - iv.start),
+            Strand::Negative => (dest.start + iv.stop - end, dest.start + iv.stop - start),
+        };
+        Ok((dest.name, dest_start, dest_end, dest.strand))
+    }
+}
+
+/// Counts of lifted and rejected records
+#[derive(Debug, Default)]
+struct LiftStat {
+    lifted: usize,
+    rejected: HashMap<&'static str, usize>,
+}
+
+/// Lift VCF records by `liftmap` and check REF against destination FASTA,
+/// records failed to lift are written to `reject` with reason in INFO `LIFT_FAIL`
+pub fn lift_vcf<B: BufRead>(
+    vcf_reader: B,
+    liftmap: &LiftMap,
+    fasta: &str,
+    writer: &mut dyn Write,
+    mut reject: Option<&mut dyn Write>,
+) -> Result<(), WGAError> {
+    let fasta = IndexedFasta::from_path(fasta)?;
+
+    let mut stat = LiftStat::default();
+    // VCF is the input of command
+    let mut counter = RecordCounter::new(None);
+    for line in vcf_reader.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with("##contig=") {
+            // contigs of source are kept in reject file only
+            if let Some(reject) = reject.as_mut() {
+                writeln!(reject, "{}", line)?;
+            }
+            continue;
+        }
+        if line.starts_with("#CHROM") {
+            for (name, size) in liftmap.dest_names.iter().zip(&liftmap.dest_sizes) {
+                writeln!(writer, "##contig=<ID={},length={}>", name, size)?;
+            }
+            writeln!(writer, "{}", line)?;
+            if let Some(reject) = reject.as_mut() {
+                writeln!(reject, "{}", LIFT_FAIL_HEADER)?;
+                writeln!(reject, "{}", line)?;
+            }
+            continue;
+        }
+        if line.starts_with('#') {
+            writeln!(writer, "{}", line)?;
+            if let Some(reject) = reject.as_mut() {
+                writeln!(reject, "{}", line)?;
+            }
+            continue;
+        }
+        counter.add();
+
+        let mut fields = line.split('\t').map(str::to_string).collect::<Vec<_>>();
+        if fields.len() < 5 {
+            return Err(WGAError::InvalidRecord(format!(
+                "invalid VCF record: {}",
+                line
+            )));
+        }
+        match lift_fields(&mut fields, liftmap, &fasta)? {
+            Ok(()) => {
+                stat.lifted += 1;
+                writeln!(writer, "{}", fields.join("\t"))?;
+            }
+            Err(reason) => {
+                *stat.rejected.entry(reason).or_default() += 1;
+                if let Some(reject) = reject.as_mut() {
+                    let mut fields = line.split('\t').map(str::to_string).collect::<Vec<_>>();
+                    set_info(&mut fields, "LIFT_FAIL", reason);
+                    writeln!(reject, "{}", fields.join("\t"))?;
+                }
+            }
+        }
+    }
+
+    let rejected = stat.rejected.values().sum::<usize>();
+    info!(
+        "lifted records: {}, rejected records: {}",
+        stat.lifted, rejected
+    );
+    let mut reasons = stat.rejected.into_iter().collect::<Vec<_>>();
+    reasons.sort_unstable();
+    for (reason, count) in reasons {
+        info!("rejected by {}: {}", reason, count);
+    }
+    Ok(())
+}
+
+// lift CHROM, POS, REF, ALT and INFO END of a record in place,
+// the outer error is for IO and the inner one is the reason of reject
+fn lift_fields(
+    fields: &mut Vec<String>,
+    liftmap: &LiftMap,
+    fasta: &IndexedFasta,
+) -> Result<Result<(), &'static str>, WGAError> {
+    let pos = parse_str2u64(&fields[1])?;
+    let ref_len = fields[3].len() as u64;
+    let start = pos.saturating_sub(1);
+    let info_end = get_info(fields.get(7), "END")
+        .map(parse_str2u64)
+        .transpose()?;
+    let end = info_end.map_or(start + ref_len, |end| end.max(start + ref_len));
+
+    let (dest, dest_start, dest_end, strand) = match liftmap.lift(&fields[0], start, end) {
+        Ok(lifted) => lifted,
+        Err(reason) => return Ok(Err(reason)),
+    };
+    let dest_name = &liftmap.dest_names[dest];
+    if !fasta.contains(dest_name) {
+        return Ok(Err("NO_DEST_SEQ"));
+    }
+
+    let alts = fields[4].split(',').collect::<Vec<_>>();
+    let symbolic = alts
+        .iter()
+        .any(|alt| alt.starts_with('<') || alt.contains('[') || alt.contains(']') || *alt == "*");
+    let (new_pos, new_ref, new_alts) = match strand {
+        Strand::Positive => (dest_start + 1, fields[3].clone(), fields[4].clone()),
+        Strand::Negative if symbolic => return Ok(Err("SYMBOLIC_ON_MINUS")),
+        // SNP and MNP are reverse complemented in place
+        Strand::Negative if alts.iter().all(|alt| alt.len() == fields[3].len()) => {
+            let new_alts = alts
+                .iter()
+                .map(|alt| reverse_complement(alt))
+                .collect::<Result<Vec<_>, _>>();
+            match (reverse_complement(&fields[3]), new_alts) {
+                (Ok(new_ref), Ok(new_alts)) => (dest_start + 1, new_ref, new_alts.join(",")),
+                _ => return Ok(Err("COMPLEX_ON_MINUS")),
+            }
+        }
+        // indels share the first base, which is the base before the span on destination
+        Strand::Negative => {
+            let anchor = fields[3].chars().next();
+            if anchor.is_none() || !alts.iter().all(|alt| alt.starts_with(anchor.unwrap())) {
+                return Ok(Err("COMPLEX_ON_MINUS"));
+            }
+            if dest_start == 0 {
+                return Ok(Err("NO_ANCHOR"));
+            }
+            let Some(base) =
+                fasta.fetch(dest_name, dest_start as usize - 1, dest_start as usize - 1)?
+            else {
+                return Ok(Err("NO_DEST_SEQ"));
+            };
+            let new_alts = alts
+                .iter()
+                .map(|alt| reverse_complement(&alt[1..]).map(|rc| format!("{}{}", base, rc)))
+                .collect::<Result<Vec<_>, _>>();
+            match (reverse_complement(&fields[3][1..]), new_alts) {
+                (Ok(rc), Ok(new_alts)) => {
+                    (dest_start, format!("{}{}", base, rc), new_alts.join(","))
+                }
+                _ => return Ok(Err("COMPLEX_ON_MINUS")),
+            }
+        }
+    };
+
+    // bases until the end of sequence are fetched if `end` is out of range
+    let Some(dest_ref) = fasta.fetch(
+        dest_name,
+        new_pos as usize - 1,
+        (new_pos as usize - 1 + new_ref.len()).saturating_sub(1),
+    )?
+    else {
+        return Ok(Err("NO_DEST_SEQ"));
+    };
+    if !dest_ref.eq_ignore_ascii_case(&new_ref) {
+        return Ok(Err("REF_MISMATCH"));
+    }
+
+    fields[0] = dest_name.clone();
+    fields[1] = new_pos.to_string();
+    fields[3] = new_ref;
+    fields[4] = new_alts;
+    if info_end.is_some() {
+        // END is the last base of the span, so the end of half-open interval
+        set_info(fields, "END", &dest_end.to_string());
+    }
+    Ok(Ok(()))
+}
+
+// value of a key in INFO column
+fn get_info<'a>(info: Option<&'a String>, key: &str) -> Option<&'a str> {
+    info?
+        .split(';')
+        .find_map(|kv| kv.strip_prefix(key)?.strip_prefix('='))
+}
+
+// set a key in INFO column, the column is added if missing
+fn set_info(fields: &mut Vec<String>, key: &str, value: &str) {
+    if fields.len() < 8 {
+        fields.resize(8, ".".to_string());
+    }
+    let info = &fields[7];
+    let mut kvs = match info.as_str() {
+        "." | "" => Vec::new(),
+        _ => info
+            .split(';')
+            .filter(|kv| kv.split('=').next() != Some(key))
+            .map(str::to_string)
+            .collect::<Vec<_>>(),
+    };
+    kvs.push(format!("{}={}", key, value));
+    fields[7] = kvs.join(";");
+}
@@ -1,43 +1,648 @@
 use crate::{
     cli::Cli,
-    converter::{chain2maf, chain2paf, maf2chain, maf2paf, maf2sam, paf2chain, paf2maf},
+    converter::{
+        axt2maf, axt2paf, chain2bigchain, chain2chain, chain2maf, chain2paf, delta2maf, delta2paf,
+        maf2aln, maf2bigmaf, maf2blocks, maf2chain, maf2chain_liftover, maf2chain_split, maf2paf,
+        maf2sam, paf2blocks, paf2chain, paf2maf, BIGCHAIN_AS, BIGLINK_AS, BIGMAF_AS,
+    },
     errors::WGAError,
+    log::{profile, Stage, WarnCategory},
     parser::{
+        axt::AxtReader,
         chain::ChainReader,
-        common::{DotplotMode, DotplotoutFormat, FileFormat},
+        cigar::{parse_cigar_to_blocks, parse_maf_seq_to_blocks},
+        common::{
+            AlnOutFormat, BestHit, BlocksOutFormat, ChunkStrategy, CigarStyle, DotplotMode,
+            DotplotoutFormat, FastaMaskMode, FileFormat, GroupBy, LiftFrom, MaskMode, OddChar,
+            PafSortBy, PanSN, UnknownBase, ValidateOutFormat,
+        },
+        delta::DeltaReader,
         maf::MAFReader,
         paf::PAFReader,
+        region::{parse_region_specs, parse_single_region},
     },
     tools::{
-        caller::{call_var_maf, call_var_paf},
+        annotate::annotate_vcf,
+        bedpe::{write_chain_bedpe, write_maf_bedpe, write_paf_bedpe},
+        caller::{
+            call_var_maf, call_var_mafs, call_var_paf, index_contigs, CallCheckpoint, CallOpts,
+        },
         chunk::chunk_maf,
-        dotplot::dotplot,
-        filter::{filter_chain, filter_maf, filter_paf, filter_paf_align_pair},
-        index::{build_index, MafIndex},
+        consensus::{consensus_maf, consensus_vcf},
+        diff::{diff_alignments, diff_blocks, DiffBlock},
+        dotplot::{dotplot, PlotWindow},
+        filter::{
+            filter_chain, filter_maf, filter_paf, filter_paf_align_pair, report_paf_align_pair,
+            Mask,
+        },
+        index::{build_index, check_index, load_index, MafIndex, SourceMeta},
         mafextra::maf_extract_idx,
+        maskfa::{mask_fasta, TargetCoverage},
         pafcov::pafcov,
+        pmafstat::pmaf_stat,
+        posmap::{pos_map_chain, pos_map_maf, pos_map_maf_idx, pos_map_paf, MapPos},
         pseudomaf::generate_pesudo_maf,
-        rename::rename_maf,
-        stat::{stat_maf, stat_paf}, // trimovp::trim_ovp,
+        rename::{rename_maf, rename_paf, Renamer},
+        report::report,
+        sample::{sample_chain, sample_maf, sample_paf, SampleOpts},
+        segments::{
+            chain_segments, maf_segments, paf_segments, write_chain_segments, write_maf_segments,
+            write_paf_segments,
+        },
+        stat::{stat_maf, stat_paf, stat_qc, stat_strata, StatSample, Strata}, // trimovp::trim_ovp,
         validate::parallel_validatepaf,
+        vcflift::{lift_vcf, LiftMap},
     },
 };
 use clap::CommandFactory;
 use clap_complete::{generate, Shell};
 use log::{info, warn};
-use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Read, Stdin, Write};
+use noodles::bgzf;
+use rayon::prelude::*;
+use regex::Regex;
+use rust_htslib::faidx;
+use std::collections::{HashMap, HashSet};
+use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Stdin, Write};
+use std::num::NonZeroUsize;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::{fs::File, path::PathBuf};
 
 // TODO : define a pub type WResult = Result<(), WGAError>;
 
 const BUFFER_SIZE: usize = 32 * 1024;
 
+// buffer size of output writers, set by `--buffer-size` before any writer is created
+static WRITE_BUFFER_SIZE: AtomicUsize = AtomicUsize::new(1024 * 1024);
+
+// threads of compressing output and decompressing BGZF input, set by `--threads`
+static CODEC_THREADS: AtomicUsize = AtomicUsize::new(1);
+
+/// Compression level of output by default
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 6;
+
+// compression level of `.gz`/`.bz2`/`.xz`/`.zst` output, set by `--compression-level`
+static COMPRESSION_LEVEL: AtomicI32 = AtomicI32::new(DEFAULT_COMPRESSION_LEVEL);
+
+// write output files to temporary files renamed on success, unset by `--no-atomic`
+static ATOMIC_OUTPUT: AtomicBool = AtomicBool::new(true);
+
+// output files of the command, committed or discarded at its end
+static OUTPUTS: OutputRegistry = OutputRegistry::new();
+
+// bytes written at once by `write_repeat`
+const REPEAT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Records held in memory by ordered parallel writing, in two chunks
+pub const ORDERED_IN_FLIGHT: usize = 8192;
+
+// records between progress logs of ordered parallel writing
+const PROGRESS_RECORDS: usize = 1_000_000;
+
+// max bytes read ahead to see the whole first line for format detection
+const DETECT_PEEK_MAX: usize = 1024 * 1024;
+
 const MAGIC_MAX_LEN: usize = 6;
 // compressed file magic number, ref: https://docs.rs/infer/latest/infer/archive/index.html
 const GZ_MAGIC: [u8; 3] = [0x1f, 0x8b, 0x08];
 const BZ_MAGIC: [u8; 3] = [0x42, 0x5a, 0x68];
 const XZ_MAGIC: [u8; 6] = [0xfd, 0x37, 0x7a, 0x58, 0x5A, 0x00];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+// gzip header with the extra field of BGZF block size
+const BGZF_HEADER_LEN: usize = 16;
+// uncompressed bytes of a BGZF block, so a compressed one always fits in 64 KiB
+const BGZF_BLOCK_DATA_LEN: usize = 65280;
+// BGZF blocks compressed at once by each worker of parallel BGZF
+const BGZF_BLOCKS_PER_WORKER: usize = 4;
+// empty BGZF block at the end of file
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Set the buffer size of output writers in bytes
+pub fn set_write_buffer_size(size: usize) {
+    WRITE_BUFFER_SIZE.store(size, Ordering::Relaxed);
+}
+
+/// Buffer size of output writers in bytes
+pub fn write_buffer_size() -> usize {
+    WRITE_BUFFER_SIZE.load(Ordering::Relaxed)
+}
+
+/// Set the threads of compressing gz/xz output and decompressing BGZF input
+pub fn set_codec_threads(threads: usize) {
+    CODEC_THREADS.store(threads, Ordering::Relaxed);
+}
+
+// worker threads of compression, None to compress in the current thread
+fn codec_workers() -> Option<NonZeroUsize> {
+    NonZeroUsize::new(CODEC_THREADS.load(Ordering::Relaxed)).filter(|n| n.get() > 1)
+}
+
+/// Set the compression level of `.gz`/`.bz2`/`.xz`/`.zst` output, checked by the codec of each output
+pub fn set_compression_level(level: i32) {
+    COMPRESSION_LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// Set if output files are written to temporary files and renamed on success
+pub fn set_atomic_output(atomic: bool) {
+    ATOMIC_OUTPUT.store(atomic, Ordering::Relaxed);
+}
+
+// if output files are written atomically by default
+fn atomic_output() -> bool {
+    ATOMIC_OUTPUT.load(Ordering::Relaxed)
+}
+
+// an output being written, renamed from `tmp_path` if atomic
+struct PendingOutput {
+    id: usize,
+    tmp_path: Option<PathBuf>,
+    path: PathBuf,
+    // None until its writer is finished
+    finished: Option<std::io::Result<()>>,
+}
+
+// output files being written, with temporary files and states of finishing
+struct OutputRegistry {
+    pending: Mutex<Vec<PendingOutput>>,
+    // ids of output writers in `pending`
+    ids: AtomicUsize,
+}
+
+impl OutputRegistry {
+    const fn new() -> Self {
+        OutputRegistry {
+            pending: Mutex::new(Vec::new()),
+            ids: AtomicUsize::new(0),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<PendingOutput>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // register an output as pending, returns its id
+    fn register(&self, tmp_path: Option<PathBuf>, path: &Path) -> usize {
+        let id = self.ids.fetch_add(1, Ordering::Relaxed);
+        self.lock().push(PendingOutput {
+            id,
+            tmp_path,
+            path: path.to_path_buf(),
+            finished: None,
+        });
+        id
+    }
+
+    fn set_finished(&self, id: usize, result: std::io::Result<()>) {
+        if let Some(pending) = self.lock().iter_mut().find(|pending| pending.id == id) {
+            pending.finished = Some(result);
+        }
+    }
+
+    // rename temporary files, or discard all outputs if any of them failed to finish
+    fn commit(&self) -> Result<(), WGAError> {
+        let mut pending = std::mem::take(&mut *self.lock());
+        let failed = pending.iter().position(|pending| {
+            pending
+                .finished
+                .as_ref()
+                .is_none_or(|finished| finished.is_err())
+        });
+        if let Some(idx) = failed {
+            pending.iter().for_each(discard_output);
+            let failed = pending.swap_remove(idx);
+            let err = match failed.finished {
+                Some(Err(e)) => e,
+                _ => std::io::Error::other("writer is not finished"),
+            };
+            return Err(WGAError::Io(std::io::Error::new(
+                err.kind(),
+                format!("failed to write `{}`: {}", failed.path.display(), err),
+            )));
+        }
+        for pending in pending {
+            if let Some(tmp_path) = &pending.tmp_path {
+                std::fs::rename(tmp_path, &pending.path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn discard(&self) {
+        let pending = std::mem::take(&mut *self.lock());
+        pending.iter().for_each(discard_output);
+    }
+}
+
+/// Writer of output whose buffers and encoders are finished explicitly
+trait FinishWrite: Write + Send {
+    /// Flush buffers and write the end of compressed stream, errors are not swallowed as in drop
+    fn finish(self: Box<Self>) -> std::io::Result<()>;
+}
+
+impl FinishWrite for File {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FinishWrite for std::io::Stdout {
+    fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: FinishWrite> FinishWrite for BufWriter<W> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        let inner = self.into_inner().map_err(|e| e.into_error())?;
+        Box::new(inner).finish()
+    }
+}
+
+impl<W: FinishWrite> FinishWrite for xz2::write::XzEncoder<W> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        Box::new((*self).finish()?).finish()
+    }
+}
+
+impl<W: FinishWrite> FinishWrite for flate2::write::GzEncoder<W> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        Box::new((*self).finish()?).finish()
+    }
+}
+
+impl<W: FinishWrite> FinishWrite for bzip2::write::BzEncoder<W> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        Box::new((*self).finish()?).finish()
+    }
+}
+
+impl<W: FinishWrite> FinishWrite for zstd::stream::write::Encoder<'static, W> {
+    fn finish(self: Box<Self>) -> std::io::Result<()> {
+        Box::new((*self).finish()?).finish()
+    }
+}
+
+impl<W: FinishWrite> FinishWrite for ParBgzfWriter<W> {
+    fn finish(mut self: Box<Self>) -> std::io::Result<()> {
+        self.compress(true)?;
+        self.inner.write_all(&BGZF_EOF)?;
+        Box::new(self.inner).finish()
+    }
+}
+
+/// Writer of BGZF blocks compressed in parallel at any level, which is still gzip
+struct ParBgzfWriter<W: Write> {
+    inner: W,
+    level: flate2::Compression,
+    pool: rayon::ThreadPool,
+    // uncompressed bytes of blocks not written yet
+    buf: Vec<u8>,
+    batch_len: usize,
+}
+
+impl<W: Write> ParBgzfWriter<W> {
+    fn new(inner: W, workers: NonZeroUsize, level: u32) -> std::io::Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(workers.get())
+            .build()
+            .map_err(std::io::Error::other)?;
+        let batch_len = BGZF_BLOCK_DATA_LEN * BGZF_BLOCKS_PER_WORKER * workers.get();
+        Ok(ParBgzfWriter {
+            inner,
+            level: flate2::Compression::new(level),
+            pool,
+            buf: Vec::with_capacity(batch_len),
+            batch_len,
+        })
+    }
+
+    // compress and write full blocks in parallel, or all bytes if `all`
+    fn compress(&mut self, all: bool) -> std::io::Result<()> {
+        let len = match all {
+            true => self.buf.len(),
+            false => self.buf.len() / BGZF_BLOCK_DATA_LEN * BGZF_BLOCK_DATA_LEN,
+        };
+        let level = self.level;
+        let blocks = self.pool.install(|| {
+            self.buf[..len]
+                .par_chunks(BGZF_BLOCK_DATA_LEN)
+                .map(|data| bgzf_block(data, level))
+                .collect::<std::io::Result<Vec<_>>>()
+        })?;
+        for block in blocks {
+            self.inner.write_all(&block)?;
+        }
+        self.buf.drain(..len);
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ParBgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= self.batch_len {
+            self.compress(false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.compress(true)?;
+        self.inner.flush()
+    }
+}
+
+// one BGZF block of `data`: gzip header with block size in extra field, deflated data and trailer
+fn bgzf_block(data: &[u8], level: flate2::Compression) -> std::io::Result<Vec<u8>> {
+    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), level);
+    encoder.write_all(data)?;
+    let cdata = encoder.finish()?;
+    let mut crc = flate2::Crc::new();
+    crc.update(data);
+    let block_size = u16::try_from(BGZF_HEADER_LEN + 2 + cdata.len() + 8 - 1)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let mut block = Vec::with_capacity(block_size as usize + 1);
+    block.extend_from_slice(&BGZF_EOF[..BGZF_HEADER_LEN]);
+    block.extend_from_slice(&block_size.to_le_bytes());
+    block.extend_from_slice(&cdata);
+    block.extend_from_slice(&crc.sum().to_le_bytes());
+    block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    Ok(block)
+}
+
+/// Writer of an output file or stdout. Buffers and encoders are finished when it is dropped,
+/// errors of that are returned by `commit_outputs`, so a truncated output is never committed
+pub struct OutputWriter {
+    inner: Option<Box<dyn FinishWrite>>,
+    registry: &'static OutputRegistry,
+    id: usize,
+}
+
+impl OutputWriter {
+    // register the output as pending in `registry`
+    fn new(
+        inner: Box<dyn FinishWrite>,
+        tmp_path: Option<PathBuf>,
+        path: &Path,
+        registry: &'static OutputRegistry,
+    ) -> Self {
+        OutputWriter {
+            inner: Some(inner),
+            registry,
+            id: registry.register(tmp_path, path),
+        }
+    }
+
+    /// Flush buffers and finish encoders, the error is also kept for `commit_outputs`
+    pub fn finish(&mut self) -> Result<(), WGAError> {
+        let Some(inner) = self.inner.take() else {
+            return Ok(());
+        };
+        let result = inner.finish();
+        let err = result.as_ref().err().map(|e| e.to_string());
+        self.registry.set_finished(self.id, result);
+        match err {
+            Some(err) => Err(WGAError::Io(std::io::Error::other(err))),
+            None => Ok(()),
+        }
+    }
+
+    // writer not finished yet
+    fn inner(&mut self) -> std::io::Result<&mut Box<dyn FinishWrite>> {
+        self.inner
+            .as_mut()
+            .ok_or_else(|| std::io::Error::other("output is already finished"))
+    }
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner()?.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.inner()?.write_all(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner()?.flush()
+    }
+}
+
+impl Drop for OutputWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+// create an output file, if `atomic` a temporary file beside it is created instead,
+// existing files other than regular ones, e.g. `/dev/null` or a named pipe, are opened directly
+fn create_output_file(path: &Path, atomic: bool) -> Result<(File, Option<PathBuf>), WGAError> {
+    let special = std::fs::metadata(path).is_ok_and(|meta| !meta.is_file());
+    let file_name = path.file_name().filter(|_| !special);
+    let Some(file_name) = file_name.filter(|_| atomic) else {
+        return Ok((File::create(path)?, None));
+    };
+    let tmp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    Ok((File::create(&tmp_path)?, Some(tmp_path)))
+}
+
+/// Create a buffered writer of an output file, which is renamed from a temporary file
+/// by `commit_outputs` if atomic, or removed by `discard_outputs`
+pub fn create_output_writer(path: &Path) -> Result<OutputWriter, WGAError> {
+    create_output_writer_in(path, &OUTPUTS)
+}
+
+fn create_output_writer_in(
+    path: &Path,
+    registry: &'static OutputRegistry,
+) -> Result<OutputWriter, WGAError> {
+    let (file, tmp_path) = create_output_file(path, atomic_output())?;
+    Ok(OutputWriter::new(
+        Box::new(buffered_writer(file)),
+        tmp_path,
+        path,
+        registry,
+    ))
+}
+
+/// Rename temporary files to their output files, after all writers are finished.
+/// Outputs are discarded if any of them failed to finish
+pub fn commit_outputs() -> Result<(), WGAError> {
+    OUTPUTS.commit()
+}
+
+/// Remove temporary files of output on error, so no partial output is left
+pub fn discard_outputs() {
+    OUTPUTS.discard()
+}
+
+fn discard_output(pending: &PendingOutput) {
+    if let Some(tmp_path) = &pending.tmp_path {
+        if std::fs::remove_file(tmp_path).is_ok() {
+            info!("remove partial output of `{}`", pending.path.display());
+        }
+    }
+}
+
+/// Buffered writer of output file, large buffer to reduce write syscalls on e.g. NFS
+pub fn buffered_writer<W: Write>(inner: W) -> BufWriter<W> {
+    BufWriter::with_capacity(write_buffer_size(), inner)
+}
+
+/// Write `byte` `count` times in large chunks, e.g. `-` padding of MAF sequences
+pub fn write_repeat(writer: &mut dyn Write, byte: u8, count: u64) -> std::io::Result<()> {
+    let chunk = [byte; REPEAT_CHUNK_SIZE];
+    let mut rest = count as usize;
+    while rest > 0 {
+        let len = rest.min(REPEAT_CHUNK_SIZE);
+        writer.write_all(&chunk[..len])?;
+        rest -= len;
+    }
+    Ok(())
+}
+
+/// Sort and merge overlapping or adjacent intervals `[start, end)`
+pub fn merge_intervals(mut ivs: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ivs.sort_unstable();
+    ivs.dedup_by(|next, last| {
+        let overlapped = next.0 <= last.1;
+        if overlapped {
+            last.1 = last.1.max(next.1);
+        }
+        overlapped
+    });
+    ivs
+}
+
+/// Total length of the union of intervals `[start, end)`
+pub fn union_length(ivs: Vec<(u64, u64)>) -> u64 {
+    merge_intervals(ivs)
+        .iter()
+        .map(|(start, end)| end - start)
+        .sum()
+}
+
+/// Map records into bytes in parallel and write them in input order.
+/// Records are read chunk by chunk to keep memory bounded, `f` gets the global index of record.
+pub fn par_write_ordered<T, E, I, F>(
+    records: I,
+    writer: &mut dyn Write,
+    f: F,
+) -> Result<(), WGAError>
+where
+    T: Send,
+    I: Iterator<Item = Result<T, E>>,
+    WGAError: From<E>,
+    F: Fn(usize, T) -> Result<Vec<u8>, WGAError> + Sync,
+{
+    par_write_bounded(records, writer, ORDERED_IN_FLIGHT, f)
+}
+
+/// Same as [`par_write_ordered`] with at most `in_flight` records in memory: the next chunk
+/// is read while the current one is mapped, so a slow input such as a pipe is not waited for
+/// by idle threads and a fast one is not buffered without bound
+pub fn par_write_bounded<T, E, I, F>(
+    records: I,
+    writer: &mut dyn Write,
+    in_flight: usize,
+    f: F,
+) -> Result<(), WGAError>
+where
+    T: Send,
+    I: Iterator<Item = Result<T, E>>,
+    WGAError: From<E>,
+    F: Fn(usize, T) -> Result<Vec<u8>, WGAError> + Sync,
+{
+    par_map_bounded(records, in_flight, f, |bufs| {
+        profile(Stage::Write, || {
+            for buf in bufs {
+                writer.write_all(&buf)?;
+            }
+            Ok::<_, WGAError>(())
+        })
+    })?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Map records in parallel chunk by chunk and collect the results in input order,
+/// so only the mapped values are kept, not the records, e.g. before sorting
+pub fn par_collect_ordered<T, U, E, I, F>(records: I, f: F) -> Result<Vec<U>, WGAError>
+where
+    T: Send,
+    U: Send,
+    I: Iterator<Item = Result<T, E>>,
+    WGAError: From<E>,
+    F: Fn(usize, T) -> Result<U, WGAError> + Sync,
+{
+    let mut collected = Vec::new();
+    par_map_bounded(records, ORDERED_IN_FLIGHT, f, |values| {
+        collected.extend(values);
+        Ok(())
+    })?;
+    Ok(collected)
+}
+
+// map chunks of at most `in_flight / 2` records in parallel while the next chunk is read,
+// mapped values of each chunk are passed to `sink` in input order
+fn par_map_bounded<T, U, E, I, F, S>(
+    records: I,
+    in_flight: usize,
+    f: F,
+    mut sink: S,
+) -> Result<(), WGAError>
+where
+    T: Send,
+    U: Send,
+    I: Iterator<Item = Result<T, E>>,
+    WGAError: From<E>,
+    F: Fn(usize, T) -> Result<U, WGAError> + Sync,
+    S: FnMut(Vec<U>) -> Result<(), WGAError>,
+{
+    let chunk_size = (in_flight / 2).max(1);
+    let mut records = records.enumerate();
+    let mut read_chunk = || {
+        records
+            .by_ref()
+            .take(chunk_size)
+            .map(|(idx, rec)| Ok((idx, rec?)))
+            .collect::<Result<Vec<_>, WGAError>>()
+    };
+    let mut chunk = read_chunk()?;
+    let mut processed = 0;
+    while !chunk.is_empty() {
+        let count = chunk.len();
+        let mut values = Ok(Vec::new());
+        // the iterator may not be Send, so it is read in this thread
+        let next = rayon::in_place_scope(|scope| {
+            scope.spawn(|_| {
+                // indexed parallel collect keeps the order
+                values = chunk
+                    .into_par_iter()
+                    .map(|(idx, rec)| f(idx, rec))
+                    .collect::<Result<Vec<U>, WGAError>>();
+            });
+            read_chunk()
+        });
+        // errors of earlier records first
+        sink(values?)?;
+        if (processed + count) / PROGRESS_RECORDS > processed / PROGRESS_RECORDS {
+            info!("processed {} records", processed + count);
+        }
+        processed += count;
+        chunk = next?;
+    }
+    Ok(())
+}
 
 type RdrWtr = (Box<dyn BufRead + Send>, Box<dyn Write>);
 fn prepare_rdr_wtr(
@@ -79,24 +684,76 @@ pub fn parse_str2f64(s: &str) -> Result<f64, WGAError> {
     }
 }
 
+/// Reverse complement a sequence, error on characters out of IUPAC nucleotide codes
 pub fn reverse_complement(input: &str) -> Result<String, WGAError> {
-    let mut output = String::with_capacity(input.len());
-    for c in input.chars().rev() {
-        match c {
-            'A' => output.push('T'),
-            'C' => output.push('G'),
-            'G' => output.push('C'),
-            'T' => output.push('A'),
-            'N' => output.push('N'),
-            'a' => output.push('t'),
-            'c' => output.push('g'),
-            'g' => output.push('c'),
-            't' => output.push('a'),
-            'n' => output.push('n'),
-            _ => return Err(WGAError::InvalidBase(c.to_string())),
-        }
+    reverse_complement_with(input, UnknownBase::Error)
+}
+
+/// Reverse complement a sequence of IUPAC nucleotide codes with case kept,
+/// other characters are handled by `policy`
+pub fn reverse_complement_with(input: &str, policy: UnknownBase) -> Result<String, WGAError> {
+    input
+        .chars()
+        .rev()
+        .map(|c| match complement_base(c) {
+            Some(comp) => Ok(comp),
+            None => unknown_base(c, policy),
+        })
+        .collect()
+}
+
+/// Check characters of a sequence are IUPAC nucleotide codes, other characters are handled by `policy`
+pub fn check_bases(input: String, policy: UnknownBase) -> Result<String, WGAError> {
+    if input.chars().all(|c| complement_base(c).is_some()) {
+        return Ok(input);
+    }
+    input
+        .chars()
+        .map(|c| match complement_base(c) {
+            Some(_) => Ok(c),
+            None => unknown_base(c, policy),
+        })
+        .collect()
+}
+
+/// If a character is a IUPAC nucleotide code
+pub fn is_iupac_base(c: char) -> bool {
+    complement_base(c).is_some()
+}
+
+// complement of a IUPAC nucleotide code, `U` is complemented as `A`
+fn complement_base(c: char) -> Option<char> {
+    let comp = match c.to_ascii_uppercase() {
+        'A' => 'T',
+        'C' => 'G',
+        'G' => 'C',
+        'T' => 'A',
+        'U' => 'A',
+        'R' => 'Y',
+        'Y' => 'R',
+        'K' => 'M',
+        'M' => 'K',
+        'B' => 'V',
+        'V' => 'B',
+        'D' => 'H',
+        'H' => 'D',
+        'S' => 'S',
+        'W' => 'W',
+        'N' => 'N',
+        _ => return None,
+    };
+    match c.is_ascii_lowercase() {
+        true => Some(comp.to_ascii_lowercase()),
+        false => Some(comp),
+    }
+}
+
+fn unknown_base(c: char, policy: UnknownBase) -> Result<char, WGAError> {
+    match policy {
+        UnknownBase::Error => Err(WGAError::InvalidBase(c.to_string())),
+        UnknownBase::ReplaceWithN => Ok('N'),
+        UnknownBase::PassThrough => Ok(c),
     }
-    Ok(output)
 }
 
 fn get_magic_num(path: &str) -> Result<[u8; MAGIC_MAX_LEN], WGAError> {
@@ -113,6 +770,17 @@ fn is_gzipped(path: &str) -> Result<bool, WGAError> {
     Ok(gz_or_not || Path::new(path).extension().is_some_and(|ext| ext == "gz"))
 }
 
+// BGZF is gzip with a `BC` extra subfield, so blocks can be inflated independently
+fn is_bgzf(path: &str) -> Result<bool, WGAError> {
+    let mut buffer = [0u8; BGZF_HEADER_LEN];
+    let mut fp = File::open(path)?;
+    let len = fp.read(&mut buffer)?;
+    Ok(len == BGZF_HEADER_LEN
+        && buffer[..3] == GZ_MAGIC
+        && buffer[3] & 0x04 != 0
+        && &buffer[12..14] == b"BC")
+}
+
 fn is_bzipped(path: &str) -> Result<bool, WGAError> {
     let buffer = get_magic_num(path)?;
     let bz_or_not =
@@ -120,6 +788,12 @@ fn is_bzipped(path: &str) -> Result<bool, WGAError> {
     Ok(bz_or_not || Path::new(path).extension().is_some_and(|ext| ext == "bz2"))
 }
 
+fn is_zstd(path: &str) -> Result<bool, WGAError> {
+    let buffer = get_magic_num(path)?;
+    let zstd_or_not = buffer[..ZSTD_MAGIC.len()] == ZSTD_MAGIC;
+    Ok(zstd_or_not || Path::new(path).extension().is_some_and(|ext| ext == "zst"))
+}
+
 fn is_xz(path: &str) -> Result<bool, WGAError> {
     let buffer = get_magic_num(path)?;
     let xz_or_not = buffer[0] == XZ_MAGIC[0]
@@ -131,8 +805,81 @@ fn is_xz(path: &str) -> Result<bool, WGAError> {
     Ok(xz_or_not || Path::new(path).extension().is_some_and(|ext| ext == "xz"))
 }
 
+/// Indexed FASTA whose sequence names are checked before fetching,
+/// as htslib crashes on a missing sequence
+pub struct IndexedFasta {
+    reader: faidx::Reader,
+    // in the order of index
+    names: Vec<String>,
+    name_set: HashSet<String>,
+}
+
+impl IndexedFasta {
+    /// Open a FASTA with its `.fai`, which is built if absent
+    pub fn from_path(path: &str) -> Result<Self, WGAError> {
+        let reader = faidx::Reader::from_path(path)?;
+        let names = (0..reader.n_seqs() as i32)
+            .map(|i| reader.seq_name(i))
+            .collect::<Result<Vec<_>, _>>()?;
+        let name_set = names.iter().cloned().collect();
+        Ok(IndexedFasta {
+            reader,
+            names,
+            name_set,
+        })
+    }
+
+    /// Sequence names in the order of index
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.name_set.contains(name)
+    }
+
+    /// Bases of `name` in 0-based `[start, end]`, None if the sequence is not in FASTA.
+    /// Bases until the end of sequence are fetched if `end` is out of range
+    pub fn fetch(&self, name: &str, start: usize, end: usize) -> Result<Option<String>, WGAError> {
+        if !self.contains(name) {
+            return Ok(None);
+        }
+        // `end` is a signed integer in htslib
+        let end = end.min(i64::MAX as usize);
+        Ok(Some(self.reader.fetch_seq_string(name, start, end)?))
+    }
+}
+
+/// Read sequence sizes from the first two columns of a `.sizes` file, or of `.fai`
+/// if `path` is an indexed FASTA
+pub fn read_seq_sizes(path: &str) -> Result<HashMap<String, u64>, WGAError> {
+    Ok(read_seq_size_list(path)?.into_iter().collect())
+}
+
+/// Read sequence sizes as [`read_seq_sizes`], in order of the file
+pub fn read_seq_size_list(path: &str) -> Result<Vec<(String, u64)>, WGAError> {
+    let fai_path = format!("{}.fai", path);
+    let sizes_path = if Path::new(&fai_path).exists() {
+        fai_path.as_str()
+    } else {
+        path
+    };
+    let reader = get_input_reader(&Some(sizes_path.to_string()))?;
+    let mut sizes = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        if let (Some(name), Some(size)) = (fields.next(), fields.next()) {
+            sizes.push((name.to_string(), parse_str2u64(size)?));
+        }
+    }
+    Ok(sizes)
+}
+
 pub fn get_input_reader(input: &Option<String>) -> Result<Box<dyn BufRead + Send>, WGAError> {
-    let reader: Box<dyn BufRead + Send> = if let Some(path) = input {
+    // "-" for stdin in pipelines
+    let path = input.as_deref().filter(|path| *path != "-");
+    let reader: Box<dyn BufRead + Send> = if let Some(path) = path {
         match File::open(path) {
             Ok(file) => {
                 if is_xz(path)? {
@@ -142,19 +889,30 @@ pub fn get_input_reader(input: &Option<String>) -> Result<Box<dyn BufRead + Send
                         xz2::read::XzDecoder::new_multi_decoder(file),
                     ))
                 } else if is_gzipped(path)? {
-                    // decode gzip compressed file
-                    Box::new(BufReader::with_capacity(
-                        BUFFER_SIZE,
-                        flate2::read::MultiGzDecoder::new(file),
-                    ))
+                    match codec_workers() {
+                        // decode BGZF blocks in parallel
+                        Some(workers) if is_bgzf(path)? => {
+                            Box::new(bgzf::MultithreadedReader::with_worker_count(workers, file))
+                        }
+                        // decode gzip compressed file
+                        _ => Box::new(BufReader::with_capacity(
+                            BUFFER_SIZE,
+                            flate2::read::MultiGzDecoder::new(file),
+                        )),
+                    }
                 } else if is_bzipped(path)? {
                     // decode bzip2 compressed file
                     Box::new(BufReader::with_capacity(
                         BUFFER_SIZE,
                         bzip2::read::MultiBzDecoder::new(file),
                     ))
+                } else if is_zstd(path)? {
+                    // decode zstd compressed file, frames are decoded one by one
+                    Box::new(BufReader::with_capacity(
+                        BUFFER_SIZE,
+                        zstd::stream::read::Decoder::new(file)?,
+                    ))
                 } else {
-                    // stdin flag "-" covered
                     Box::new(BufReader::with_capacity(BUFFER_SIZE, file))
                 }
             }
@@ -178,52 +936,110 @@ fn stdin_reader() -> Result<Stdin, WGAError> {
 }
 
 fn get_output_writer(outputpath: &str, rewrite: bool) -> Result<Box<dyn Write>, WGAError> {
+    open_output_writer(outputpath, rewrite, atomic_output(), &OUTPUTS)
+}
+
+// writer of output registered in `registry`, written to a temporary file renamed on success if `atomic`
+fn open_output_writer(
+    outputpath: &str,
+    rewrite: bool,
+    atomic: bool,
+    registry: &'static OutputRegistry,
+) -> Result<Box<dyn Write>, WGAError> {
     // check if output file exists
     check_outfile(outputpath, rewrite)?;
 
-    // if output is stdout, return stdout writer directly
+    // if output is stdout, return stdout writer directly,
+    // small buffer for terminal so output shows up timely
     if outputpath == "-" {
-        return Ok(Box::new(BufWriter::new(stdout())));
+        let writer = match atty::is(atty::Stream::Stdout) {
+            true => BufWriter::new(stdout()),
+            false => buffered_writer(stdout()),
+        };
+        return Ok(Box::new(OutputWriter::new(
+            Box::new(writer),
+            None,
+            Path::new(outputpath),
+            registry,
+        )));
     }
 
-    let file = File::create(outputpath)?;
-    // TODO: add compression level option
-    let compression_level: u32 = 6;
-
-    let writer: Box<dyn Write> = if Path::new(outputpath)
-        .extension()
-        .is_some_and(|ext| ext == "xz")
-    {
-        // encode file to xz format
-        Box::new(BufWriter::with_capacity(
-            BUFFER_SIZE,
-            xz2::write::XzEncoder::new(file, compression_level),
-        ))
-    } else if Path::new(outputpath)
+    // compression level is checked before the output file is created
+    let ext = Path::new(outputpath)
         .extension()
-        .is_some_and(|ext| ext == "gz")
-    {
-        // encode file to gzip format
-        Box::new(BufWriter::with_capacity(
-            BUFFER_SIZE,
-            flate2::write::GzEncoder::new(file, flate2::Compression::new(compression_level)),
-        ))
-    } else if Path::new(outputpath)
-        .extension()
-        .is_some_and(|ext| ext == "bz2")
-    {
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+    let compression_level = COMPRESSION_LEVEL.load(Ordering::Relaxed);
+    let level_in = |range: std::ops::RangeInclusive<i32>| match range.contains(&compression_level) {
+        true => Ok(compression_level),
+        false => Err(WGAError::InvalidArgs(format!(
+            "`--compression-level` of `.{}` output should be in {}..={}, got {}",
+            ext,
+            range.start(),
+            range.end(),
+            compression_level
+        ))),
+    };
+    let compression_level = match ext {
+        "xz" | "gz" | "bz2" => level_in(1..=9)?,
+        "zst" => level_in(zstd::compression_level_range())?,
+        _ => compression_level,
+    };
+    let (file, tmp_path) = create_output_file(Path::new(outputpath), atomic)?;
+
+    let writer: Box<dyn FinishWrite> = match ext {
+        "xz" => {
+            // encode file to xz format, compressed bytes are buffered too
+            let encoder = match codec_workers() {
+                Some(workers) => xz2::write::XzEncoder::new_stream(
+                    buffered_writer(file),
+                    xz2::stream::MtStreamBuilder::new()
+                        .threads(workers.get() as u32)
+                        .preset(compression_level as u32)
+                        .check(xz2::stream::Check::Crc64)
+                        .encoder()
+                        .map_err(std::io::Error::from)?,
+                ),
+                None => xz2::write::XzEncoder::new(buffered_writer(file), compression_level as u32),
+            };
+            Box::new(buffered_writer(encoder))
+        }
+        "gz" => match codec_workers() {
+            // encode file to BGZF blocks in parallel, which is still gzip
+            Some(workers) => Box::new(ParBgzfWriter::new(
+                buffered_writer(file),
+                workers,
+                compression_level as u32,
+            )?),
+            // encode file to gzip format
+            None => Box::new(buffered_writer(flate2::write::GzEncoder::new(
+                buffered_writer(file),
+                flate2::Compression::new(compression_level as u32),
+            ))),
+        },
         // encode file to bzip2 format
-        Box::new(BufWriter::with_capacity(
-            BUFFER_SIZE,
-            bzip2::write::BzEncoder::new(file, bzip2::Compression::new(compression_level)),
-        ))
-    } else if outputpath != "-" {
-        Box::new(BufWriter::with_capacity(BUFFER_SIZE, file))
-    } else {
-        Box::new(BufWriter::new(stdout()))
+        "bz2" => Box::new(buffered_writer(bzip2::write::BzEncoder::new(
+            buffered_writer(file),
+            bzip2::Compression::new(compression_level as u32),
+        ))),
+        "zst" => {
+            // encode file to zstd format, in parallel by worker threads of zstd
+            let mut encoder =
+                zstd::stream::write::Encoder::new(buffered_writer(file), compression_level)?;
+            if let Some(workers) = codec_workers() {
+                encoder.multithread(workers.get() as u32)?;
+            }
+            Box::new(buffered_writer(encoder))
+        }
+        _ => Box::new(buffered_writer(file)),
     };
 
-    Ok(writer)
+    Ok(Box::new(OutputWriter::new(
+        writer,
+        tmp_path,
+        Path::new(outputpath),
+        registry,
+    )))
 }
 
 /// check if output file exists and if rewrite
@@ -234,7 +1050,7 @@ fn check_outfile(output_file: &str, rewrite: bool) -> Result<(), WGAError> {
         if path.exists() {
             if rewrite {
                 // rewrite the file
-                warn!("file {} exist, will rewrite it", output_file);
+                warn!(target: WarnCategory::Overwrite.target(), "file {} exist, will rewrite it", output_file);
             } else {
                 // exit
                 return Err(WGAError::FileReWrite(output_file.to_string()));
@@ -245,63 +1061,271 @@ fn check_outfile(output_file: &str, rewrite: bool) -> Result<(), WGAError> {
 }
 
 /// Command: maf2paf
+#[allow(clippy::too_many_arguments)]
 pub fn wrap_maf2paf(
     input: &Option<String>,
     output: &str,
     query_name: Option<String>,
     rewrite: bool,
+    verify: bool,
+    sam_tags: bool,
+    odd_char: OddChar,
+    sort_by: PafSortBy,
+    pansn: Option<PanSN>,
+    cigar_style: CigarStyle,
+    in_flight: usize,
 ) -> Result<(), WGAError> {
+    if pansn.as_ref().is_some_and(|pansn| pansn.sep.is_empty()) {
+        return Err(WGAError::InvalidArgs(
+            "`--pansn-sep` should not be empty".to_string(),
+        ));
+    }
+    if in_flight == 0 {
+        return Err(WGAError::InvalidArgs(
+            "`--in-flight` should be greater than 0".to_string(),
+        ));
+    }
     // prepare reader and writer
     let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
-    let mut mafrdr = MAFReader::new(reader)?;
-    maf2paf(&mut mafrdr, &mut writer, query_name.as_deref())?;
+    let mut mafrdr = MAFReader::new(reader)?.with_odd_char(odd_char);
+    maf2paf(
+        &mut mafrdr,
+        &mut writer,
+        query_name.as_deref(),
+        verify,
+        sam_tags,
+        sort_by,
+        pansn.as_ref(),
+        cigar_style,
+        in_flight,
+    )?;
     Ok(())
 }
 
+/// Command: maf2aln
+pub fn wrap_maf2aln(
+    inputs: &[String],
+    output: &str,
+    rewrite: bool,
+    out_format: AlnOutFormat,
+    genome_sep: char,
+    genomes: &[String],
+    min_genomes: usize,
+) -> Result<(), WGAError> {
+    let mut writer = get_output_writer(output, rewrite)?;
+    // no input for STDIN
+    let inputs = match inputs.is_empty() {
+        true => vec![None],
+        false => inputs.iter().map(|path| Some(path.clone())).collect(),
+    };
+    let mut mafrdrs = Vec::with_capacity(inputs.len());
+    for input in &inputs {
+        info!("start read file: `{}`", input.as_deref().unwrap_or("stdin"));
+        let mafrdr = MAFReader::new(get_input_reader(input)?)?;
+        mafrdrs.push(match input {
+            Some(path) => mafrdr.with_source(path),
+            None => mafrdr,
+        });
+    }
+    maf2aln(
+        &mut mafrdrs,
+        &mut writer,
+        out_format,
+        genome_sep,
+        genomes,
+        min_genomes,
+    )
+}
+
 /// Command: maf2chain
+#[allow(clippy::too_many_arguments)]
 pub fn wrap_maf2chain(
     input: &Option<String>,
     output: &str,
     rewrite: bool,
     query_name: Option<String>,
+    query_regex: Option<&str>,
+    split: bool,
+    genome_sep: char,
+    liftover_ready: bool,
+    odd_char: OddChar,
 ) -> Result<(), WGAError> {
+    let query_regex = query_regex.map(Regex::new).transpose()?;
+    if split {
+        // `-o` is the dir of chain files
+        prepare_output_dir(output, rewrite)?;
+        let mut mafrdr = MAFReader::new(get_input_reader(input)?)?.with_odd_char(odd_char);
+        return maf2chain_split(
+            &mut mafrdr,
+            Path::new(output),
+            genome_sep,
+            query_regex.as_ref(),
+        );
+    }
     // prepare reader and writer
     let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
-    let mut mafrdr = MAFReader::new(reader)?;
-    maf2chain(&mut mafrdr, &mut writer, query_name.as_deref())?;
-    Ok(())
-}
-
-/// Command: maf2sam
-pub fn wrap_maf2sam(input: &Option<String>, output: &str, rewrite: bool) -> Result<(), WGAError> {
-    // prepare reader and writer
-    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
-    let mut mafrdr = MAFReader::new(reader)?;
-    maf2sam(&mut mafrdr, &mut writer)?;
+    let mut mafrdr = MAFReader::new(reader)?.with_odd_char(odd_char);
+    if liftover_ready {
+        return maf2chain_liftover(
+            &mut mafrdr,
+            &mut writer,
+            query_name.as_deref(),
+            query_regex.as_ref(),
+        );
+    }
+    maf2chain(
+        &mut mafrdr,
+        &mut writer,
+        query_name.as_deref(),
+        query_regex.as_ref(),
+    )?;
     Ok(())
 }
 
-/// Command: paf2chain
-pub fn wrap_paf2chain(input: &Option<String>, output: &str, rewrite: bool) -> Result<(), WGAError> {
+/// Command: blocks
+pub fn wrap_blocks(
+    input: &Option<String>,
+    format: FileFormat,
+    out_format: BlocksOutFormat,
+    output: &str,
+    query_name: Option<String>,
+    rewrite: bool,
+) -> Result<(), WGAError> {
     // prepare reader and writer
-    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let (mut reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let format = resolve_input_format(format, &mut reader, &[FileFormat::Maf, FileFormat::Paf])?;
+    match format {
+        FileFormat::Maf => {
+            let mut mafrdr = MAFReader::new(reader)?;
+            maf2blocks(&mut mafrdr, &mut writer, out_format, query_name.as_deref())?;
+        }
+        FileFormat::Paf => {
+            let mut pafrdr = PAFReader::new(reader);
+            paf2blocks(&mut pafrdr, &mut writer, out_format)?;
+        }
+        _ => {
+            return Err(WGAError::NotImplemented);
+        }
+    }
+    Ok(())
+}
+
+/// Command: segments
+pub fn wrap_segments(
+    input: &Option<String>,
+    format: FileFormat,
+    output: &str,
+    query_name: Option<String>,
+    rewrite: bool,
+) -> Result<(), WGAError> {
+    // prepare reader and writer
+    let (mut reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let format = resolve_input_format(
+        format,
+        &mut reader,
+        &[FileFormat::Maf, FileFormat::Paf, FileFormat::Chain],
+    )?;
+    match format {
+        FileFormat::Maf => {
+            let mut mafrdr = MAFReader::new(reader)?;
+            write_maf_segments(&mut mafrdr, &mut writer, query_name.as_deref())?;
+        }
+        FileFormat::Paf => {
+            let mut pafrdr = PAFReader::new(reader);
+            write_paf_segments(&mut pafrdr, &mut writer)?;
+        }
+        FileFormat::Chain => {
+            let mut chainrdr = ChainReader::new(reader);
+            write_chain_segments(&mut chainrdr, &mut writer)?;
+        }
+        _ => {
+            return Err(WGAError::NotImplemented);
+        }
+    }
+    Ok(())
+}
+
+/// Command: bedpe
+pub fn wrap_bedpe(
+    input: &Option<String>,
+    format: FileFormat,
+    output: &str,
+    split: bool,
+    query_name: Option<String>,
+    rewrite: bool,
+) -> Result<(), WGAError> {
+    // prepare reader and writer
+    let (mut reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let format = resolve_input_format(
+        format,
+        &mut reader,
+        &[FileFormat::Maf, FileFormat::Paf, FileFormat::Chain],
+    )?;
+    match format {
+        FileFormat::Maf => {
+            let mut mafrdr = MAFReader::new(reader)?;
+            write_maf_bedpe(&mut mafrdr, &mut writer, query_name.as_deref(), split)?;
+        }
+        FileFormat::Paf => {
+            let mut pafrdr = PAFReader::new(reader);
+            write_paf_bedpe(&mut pafrdr, &mut writer, split)?;
+        }
+        FileFormat::Chain => {
+            let mut chainrdr = ChainReader::new(reader);
+            write_chain_bedpe(&mut chainrdr, &mut writer, split)?;
+        }
+        _ => {
+            return Err(WGAError::NotImplemented);
+        }
+    }
+    Ok(())
+}
+
+/// Command: maf2sam
+pub fn wrap_maf2sam(input: &Option<String>, output: &str, rewrite: bool) -> Result<(), WGAError> {
+    // prepare reader and writer
+    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let mut mafrdr = MAFReader::new(reader)?;
+    maf2sam(&mut mafrdr, &mut writer)?;
+    Ok(())
+}
+
+/// Command: paf2chain
+pub fn wrap_paf2chain(input: &Option<String>, output: &str, rewrite: bool) -> Result<(), WGAError> {
+    // prepare reader and writer
+    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
     let mut pafrdr = PAFReader::new(reader);
     paf2chain(&mut pafrdr, &mut writer)?;
     Ok(())
 }
 
 /// Command: paf2maf
+#[allow(clippy::too_many_arguments)]
 pub fn wrap_paf2maf(
     input: &Option<String>,
     output: &str,
     target_fa_path: &str,
     query_fa_path: &str,
     rewrite: bool,
+    verify: bool,
+    unknown_base: UnknownBase,
+    keep_tags: &[String],
+    target_regions: &[String],
 ) -> Result<(), WGAError> {
+    let regions = parse_region_specs(target_regions)?;
     // prepare reader and writer
     let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
     let mut pafrdr = PAFReader::new(reader);
-    paf2maf(&mut pafrdr, &mut writer, target_fa_path, query_fa_path)?;
+    paf2maf(
+        &mut pafrdr,
+        &mut writer,
+        target_fa_path,
+        query_fa_path,
+        verify,
+        unknown_base,
+        keep_tags,
+        &regions,
+    )?;
     Ok(())
 }
 
@@ -312,25 +1336,197 @@ pub fn wrap_chain2maf(
     target_fa_path: &str,
     query_fa_path: &str,
     rewrite: bool,
+    unknown_base: UnknownBase,
+    target_regions: &[String],
 ) -> Result<(), WGAError> {
+    let regions = parse_region_specs(target_regions)?;
     // prepare reader and writer
     let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
     let mut chainrdr = ChainReader::new(reader);
-    chain2maf(&mut chainrdr, &mut writer, target_fa_path, query_fa_path)?;
+    chain2maf(
+        &mut chainrdr,
+        &mut writer,
+        target_fa_path,
+        query_fa_path,
+        unknown_base,
+        &regions,
+    )?;
+    Ok(())
+}
+
+/// Command: chain2bigchain
+pub fn wrap_chain2bigchain(
+    input: &Option<String>,
+    output: &str,
+    link: &str,
+    as_dir: Option<&str>,
+    rewrite: bool,
+) -> Result<(), WGAError> {
+    // prepare reader and writers
+    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let mut link_writer = get_output_writer(link, rewrite)?;
+    if let Some(dir) = as_dir {
+        write_schema(dir, "bigChain.as", BIGCHAIN_AS, rewrite)?;
+        write_schema(dir, "bigLink.as", BIGLINK_AS, rewrite)?;
+    }
+    let mut chainrdr = ChainReader::new(reader);
+    chain2bigchain(&mut chainrdr, &mut writer, &mut link_writer)?;
+    Ok(())
+}
+
+/// Command: maf2bigmaf
+pub fn wrap_maf2bigmaf(
+    input: &Option<String>,
+    output: &str,
+    ref_db: Option<&str>,
+    as_dir: Option<&str>,
+    rewrite: bool,
+) -> Result<(), WGAError> {
+    // prepare reader and writer
+    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    if let Some(dir) = as_dir {
+        write_schema(dir, "bigMaf.as", BIGMAF_AS, rewrite)?;
+    }
+    let mut mafrdr = MAFReader::new(reader)?;
+    maf2bigmaf(&mut mafrdr, &mut writer, ref_db)?;
+    Ok(())
+}
+
+// write autoSql schema for `bedToBigBed -as`
+fn write_schema(dir: &str, name: &str, schema: &str, rewrite: bool) -> Result<(), WGAError> {
+    let path = Path::new(dir).join(name);
+    let mut writer = get_output_writer(&path.to_string_lossy(), rewrite)?;
+    writer.write_all(schema.as_bytes())?;
+    info!("autoSql schema is written to `{}`", path.display());
     Ok(())
 }
 
 /// Command: chain2paf
-pub fn wrap_chain2paf(input: &Option<String>, output: &str, rewrite: bool) -> Result<(), WGAError> {
+pub fn wrap_chain2paf(
+    input: &Option<String>,
+    output: &str,
+    rewrite: bool,
+    sort_by: PafSortBy,
+    fa_paths: Option<(&String, &String)>,
+) -> Result<(), WGAError> {
     // prepare reader and writer
     let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
     let mut chainrdr = ChainReader::new(reader);
-    chain2paf(&mut chainrdr, &mut writer)?;
+    let fa_paths = fa_paths.map(|(t, q)| (t.as_str(), q.as_str()));
+    chain2paf(&mut chainrdr, &mut writer, sort_by, fa_paths)?;
     Ok(())
 }
 
-/// Command: build maf index
-pub fn wrap_build_index(input: &String, outputpath: &str) -> Result<(), WGAError> {
+pub fn wrap_axt2maf(
+    input: &Option<String>,
+    output: &str,
+    target_sizes: &str,
+    query_sizes: &str,
+    rewrite: bool,
+    in_flight: usize,
+) -> Result<(), WGAError> {
+    if in_flight == 0 {
+        return Err(WGAError::InvalidArgs(
+            "`--in-flight` should be greater than 0".to_string(),
+        ));
+    }
+    let t_sizes = read_seq_sizes(target_sizes)?;
+    let q_sizes = read_seq_sizes(query_sizes)?;
+    // prepare reader and writer
+    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let mut axtrdr = AxtReader::new(reader);
+    axt2maf(&mut axtrdr, &mut writer, &t_sizes, &q_sizes, in_flight)?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn wrap_axt2paf(
+    input: &Option<String>,
+    output: &str,
+    target_sizes: &str,
+    query_sizes: &str,
+    rewrite: bool,
+    sort_by: PafSortBy,
+    cigar_style: CigarStyle,
+    in_flight: usize,
+) -> Result<(), WGAError> {
+    if in_flight == 0 {
+        return Err(WGAError::InvalidArgs(
+            "`--in-flight` should be greater than 0".to_string(),
+        ));
+    }
+    let t_sizes = read_seq_sizes(target_sizes)?;
+    let q_sizes = read_seq_sizes(query_sizes)?;
+    // prepare reader and writer
+    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let mut axtrdr = AxtReader::new(reader);
+    axt2paf(
+        &mut axtrdr,
+        &mut writer,
+        &t_sizes,
+        &q_sizes,
+        sort_by,
+        cigar_style,
+        in_flight,
+    )?;
+    Ok(())
+}
+
+pub fn wrap_delta2maf(
+    input: &Option<String>,
+    output: &str,
+    target_fa_path: Option<&str>,
+    query_fa_path: Option<&str>,
+    rewrite: bool,
+    unknown_base: UnknownBase,
+) -> Result<(), WGAError> {
+    // prepare reader and writer
+    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let mut deltardr = DeltaReader::new(reader)?;
+    delta2maf(
+        &mut deltardr,
+        &mut writer,
+        target_fa_path,
+        query_fa_path,
+        unknown_base,
+    )?;
+    Ok(())
+}
+
+pub fn wrap_delta2paf(
+    input: &Option<String>,
+    output: &str,
+    rewrite: bool,
+    sort_by: PafSortBy,
+) -> Result<(), WGAError> {
+    // prepare reader and writer
+    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let mut deltardr = DeltaReader::new(reader)?;
+    delta2paf(&mut deltardr, &mut writer, sort_by)?;
+    Ok(())
+}
+
+/// Command: chain2chain
+pub fn wrap_chain2chain(
+    input: &Option<String>,
+    output: &str,
+    rewrite: bool,
+    split: Option<u64>,
+) -> Result<(), WGAError> {
+    // prepare reader and writer
+    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let mut chainrdr = ChainReader::new(reader);
+    chain2chain(&mut chainrdr, &mut writer, split)?;
+    Ok(())
+}
+
+/// Command: build or check maf index
+pub fn wrap_build_index(
+    input: &String,
+    outputpath: &str,
+    max_mem: usize,
+    check: bool,
+) -> Result<(), WGAError> {
     let outputpath = match outputpath {
         "-" => {
             // add .idx suffix to input file
@@ -340,196 +1536,947 @@ pub fn wrap_build_index(input: &String, outputpath: &str) -> Result<(), WGAError
         }
         path => path.to_owned(),
     };
+    if check {
+        return check_index(input, &outputpath);
+    }
 
+    let source = SourceMeta::from_path(input)?;
     let mut mafreader = MAFReader::from_path(input)?;
 
     // NOTE: new index file will always overwrite old one
     let idx_wtr = get_output_writer(&outputpath, true)?;
-    build_index(&mut mafreader, idx_wtr)
+    let spill_path = format!("{}.tmp", outputpath);
+    build_index(
+        &mut mafreader,
+        idx_wtr,
+        source,
+        Path::new(&spill_path),
+        max_mem << 20,
+    )
+}
+
+/// Command: maf extract
+pub fn wrap_maf_extract(
+    input: &Option<String>,
+    regions: &Option<Vec<String>>,
+    region_file: &Option<String>,
+    output: &str,
+    rewrite: bool,
+    rebuild_index: bool,
+    fuzzy: bool,
+) -> Result<(), WGAError> {
+    // judge regions and region_file
+    if regions.is_none() && region_file.is_none() {
+        return Err(WGAError::EmptyRegion);
+    }
+
+    // init writer and check if output file exists
+    let output_name = match output {
+        "-" => "stdout",
+        path => path,
+    };
+    info!("start write file: `{}`", output_name);
+    let mut writer = get_output_writer(output, rewrite)?;
+
+    match input {
+        // if input if from file, use index
+        Some(path) => {
+            if path == "-" {
+                return Err(WGAError::StdinNotAllowed);
+            }
+            // check input file and header before extracting in parallel
+            MAFReader::from_path(path)?;
+            let mafindex = load_index(path, rebuild_index)?
+                .ok_or_else(|| WGAError::FileNotExist(PathBuf::from(format!("{}.index", path))))?;
+            let failed_regions =
+                maf_extract_idx(regions, region_file, path, mafindex, &mut writer, fuzzy)?;
+            for region in failed_regions {
+                let err = WGAError::FailedRegion(region);
+                warn!(target: WarnCategory::Result.target(), "{}", err);
+            }
+            Ok(())
+        }
+        // if input is from stdin, raise error
+        None => Err(WGAError::StdinNotAllowed),
+    }
+}
+
+/// Command: maf call
+pub fn wrap_maf_call(
+    inputs: &[String],
+    output: &str,
+    rewrite: bool,
+    target_fa_path: Option<&str>,
+    samples: Option<&[String]>,
+    opts: &CallOpts,
+) -> Result<(), WGAError> {
+    let CallOpts {
+        diploid,
+        ploidy,
+        gvcf,
+        block_info,
+        odd_char,
+        ..
+    } = *opts;
+    let (checkpoint, split_by_chrom) = (opts.checkpoint.as_deref(), opts.split_by_chrom.as_deref());
+    if opts.chunk_strategy != ChunkStrategy::None && opts.chunk_size == 0 {
+        return Err(WGAError::InvalidArgs(
+            "`chunk-size` should be greater than 0".to_string(),
+        ));
+    }
+
+    if gvcf && inputs.len() > 1 {
+        return Err(WGAError::InvalidArgs(
+            "`--gvcf` does not support multiple input".to_string(),
+        ));
+    }
+    if block_info && inputs.len() > 1 {
+        return Err(WGAError::InvalidArgs(
+            "`--block-info` does not support multiple input".to_string(),
+        ));
+    }
+    if opts.min_qual.is_some() && inputs.len() > 1 {
+        return Err(WGAError::InvalidArgs(
+            "`--min-qline-qual` does not support multiple input".to_string(),
+        ));
+    }
+    if split_by_chrom.is_some() && inputs.len() > 1 {
+        return Err(WGAError::InvalidArgs(
+            "`--split-by-chrom` does not support multiple input".to_string(),
+        ));
+    }
+    if checkpoint.is_some() && inputs.len() > 1 {
+        return Err(WGAError::InvalidArgs(
+            "`--checkpoint` does not support multiple input".to_string(),
+        ));
+    }
+    if opts.bnd_distance.is_some() && inputs.len() > 1 {
+        return Err(WGAError::InvalidArgs(
+            "`--between` does not support multiple input".to_string(),
+        ));
+    }
+    if diploid && inputs.len() != 2 {
+        return Err(WGAError::InvalidArgs(format!(
+            "`--diploid` needs two MAF files of haplotype 1 and 2, got {}",
+            inputs.len()
+        )));
+    }
+    if diploid && ploidy != 2 {
+        return Err(WGAError::InvalidArgs(
+            "`--diploid` writes diploid GT, it can't be used with `--ploidy 1`".to_string(),
+        ));
+    }
+    // contigs of VCF header in order of target FASTA index, its `.fai` is built if absent
+    let contigs = match target_fa_path {
+        Some(path) => {
+            faidx::Reader::from_path(path)?;
+            Some(read_seq_size_list(path)?)
+        }
+        None => None,
+    };
+    if inputs.len() > 1 {
+        // haplotypes of one sample named `sample` by default
+        let samples = match (diploid, samples) {
+            (true, Some([sample])) => vec![sample.clone()],
+            (true, Some(samples)) => {
+                return Err(WGAError::InvalidArgs(format!(
+                    "got {} sample names for `--diploid`, expected 1",
+                    samples.len()
+                )))
+            }
+            (true, None) => vec!["sample".to_string()],
+            (false, _) => call_sample_names(inputs, samples)?,
+        };
+        let mafreaders = inputs
+            .iter()
+            .map(|path| {
+                if path == "-" {
+                    return Err(WGAError::InvalidArgs(
+                        "STDIN is not supported for multiple input".to_string(),
+                    ));
+                }
+                Ok(MAFReader::new(get_input_reader(&Some(path.clone()))?)?
+                    .with_source(path)
+                    .with_odd_char(odd_char))
+            })
+            .collect::<Result<Vec<_>, WGAError>>()?;
+        let mut writer = get_output_writer(output, rewrite)?;
+        return call_var_mafs(mafreaders, contigs, &samples, &mut writer, opts);
+    }
+
+    let input = inputs.first().cloned();
+    let sample = call_sample_names(inputs, samples)?.pop();
+
+    let mut checkpoint = checkpoint
+        .map(|path| call_checkpoint(path, input.as_deref(), output))
+        .transpose()?;
+    if let Some(dir) = split_by_chrom {
+        // VCFs are written into the dir instead of `-o`
+        prepare_output_dir(dir, rewrite)?;
+    }
+
+    // prepare reader and writer, output of a resumed run is appended after the last target done
+    let (reader, mut writer) = match &checkpoint {
+        Some(checkpoint) if checkpoint.header_written() => {
+            info!(
+                "resume from checkpoint, {} targets done",
+                checkpoint.done.len()
+            );
+            (
+                get_input_reader(&input)?,
+                resume_output_writer(output, checkpoint.output_bytes)?,
+            )
+        }
+        // partial output is kept to resume from, so it is written in place
+        Some(_) => {
+            info!("start write file: `{}`", output);
+            (
+                get_input_reader(&input)?,
+                open_output_writer(output, rewrite, false, &OUTPUTS)?,
+            )
+        }
+        None => prepare_rdr_wtr(&input, output, rewrite)?,
+    };
+
+    // contigs of MAF index if input is not stdin, or targets of blocks
+    let contigs = match (contigs, &input) {
+        (Some(contigs), _) => Some(contigs),
+        (None, Some(path)) if path != "-" => {
+            load_index(path, opts.rebuild_index)?.map(index_contigs)
+        }
+        (None, _) => None,
+    };
+    if contigs.is_none() {
+        info!("no MAF index or target FASTA, contigs of VCF header are targets of blocks");
+    }
+
+    // get mafreader
+    let mut mafreader = MAFReader::new(reader)?.with_odd_char(odd_char);
+
+    call_var_maf(
+        &mut mafreader,
+        contigs,
+        &mut writer,
+        sample.as_deref(),
+        opts,
+        checkpoint.as_mut(),
+    )?;
+    Ok(())
+}
+
+// checkpoint of `call`, input and output should be files so they are the same when resumed,
+// and output is plain text to be truncated and appended
+fn call_checkpoint(
+    path: &str,
+    input: Option<&str>,
+    output: &str,
+) -> Result<CallCheckpoint, WGAError> {
+    let input = match input {
+        Some(input) if input != "-" => input,
+        _ => {
+            return Err(WGAError::InvalidArgs(
+                "`--checkpoint` needs an input MAF file".to_string(),
+            ))
+        }
+    };
+    if output == "-"
+        || Path::new(output)
+            .extension()
+            .is_some_and(|ext| ext == "gz" || ext == "xz" || ext == "bz2" || ext == "zst")
+    {
+        return Err(WGAError::InvalidArgs(
+            "`--checkpoint` needs an uncompressed output file".to_string(),
+        ));
+    }
+    CallCheckpoint::open(path, input, output)
+}
+
+// writer appending in place to output after the first `len` bytes, later bytes of an unfinished
+// write are dropped, it is finished and committed as other outputs
+fn resume_output_writer(output: &str, len: u64) -> Result<Box<dyn Write>, WGAError> {
+    let mut file = std::fs::OpenOptions::new().write(true).open(output)?;
+    if file.metadata()?.len() < len {
+        return Err(WGAError::InvalidArgs(format!(
+            "output `{}` is shorter than {} bytes in checkpoint",
+            output, len
+        )));
+    }
+    file.set_len(len)?;
+    file.seek(SeekFrom::End(0))?;
+    info!("append to file: `{}`", output);
+    Ok(Box::new(OutputWriter::new(
+        Box::new(buffered_writer(file)),
+        None,
+        Path::new(output),
+        &OUTPUTS,
+    )))
+}
+
+// sample names of `call` input, derived from file names of multiple input if not given
+fn call_sample_names(
+    inputs: &[String],
+    samples: Option<&[String]>,
+) -> Result<Vec<String>, WGAError> {
+    let samples = match samples {
+        Some(samples) if samples.len() != inputs.len().max(1) => {
+            return Err(WGAError::InvalidArgs(format!(
+                "got {} sample names for {} input",
+                samples.len(),
+                inputs.len().max(1)
+            )))
+        }
+        Some(samples) => samples.to_vec(),
+        None if inputs.len() > 1 => inputs.iter().map(|path| file_stem(path)).collect(),
+        None => Vec::new(),
+    };
+    let mut seen = HashSet::new();
+    if let Some(dup) = samples.iter().find(|sample| !seen.insert(*sample)) {
+        return Err(WGAError::InvalidArgs(format!(
+            "duplicate sample name `{}`, specify names by `--sample`",
+            dup
+        )));
+    }
+    Ok(samples)
+}
+
+// file name without directory, compression and MAF extensions
+fn file_stem(path: &str) -> String {
+    let mut name = Path::new(path)
+        .file_name()
+        .map_or(path.to_string(), |name| name.to_string_lossy().into_owned());
+    for ext in [".gz", ".bz2", ".xz", ".zst", ".maf"] {
+        if let Some(stem) = name.strip_suffix(ext) {
+            name = stem.to_string();
+        }
+    }
+    name
+}
+
+/// Command: paf call
+pub fn wrap_paf_call(
+    inputs: &[String],
+    t_fa_path: &str,
+    q_fa_path: &str,
+    output: &str,
+    rewrite: bool,
+    samples: Option<&[String]>,
+    opts: &CallOpts,
+) -> Result<(), WGAError> {
+    if inputs.len() > 1 {
+        return Err(WGAError::InvalidArgs(
+            "multiple input is only supported for MAF".to_string(),
+        ));
+    }
+    let input = inputs.first().cloned();
+    let sample = call_sample_names(inputs, samples)?.pop();
+
+    // prepare reader and writer
+    let (reader, mut writer) = prepare_rdr_wtr(&input, output, rewrite)?;
+
+    // check if fasta files exist
+    if !Path::new(t_fa_path).exists() {
+        return Err(WGAError::FileNotExist(PathBuf::from(t_fa_path)));
+    }
+    if !Path::new(q_fa_path).exists() {
+        return Err(WGAError::FileNotExist(PathBuf::from(q_fa_path)));
+    }
+
+    // check if fasta index files exist, if not create them
+    if !Path::new(&format!("{}.fai", t_fa_path)).exists() {
+        return Err(WGAError::FileNotExist(PathBuf::from(format!(
+            "{}.fai",
+            t_fa_path
+        ))));
+    }
+    if !Path::new(&format!("{}.fai", q_fa_path)).exists() {
+        return Err(WGAError::FileNotExist(PathBuf::from(format!(
+            "{}.fai",
+            q_fa_path
+        ))));
+    }
+
+    // initialize PAF reader
+    let mut pafreader = PAFReader::new(reader);
+
+    call_var_paf(
+        &mut pafreader,
+        t_fa_path,
+        q_fa_path,
+        &mut writer,
+        sample.as_deref(),
+        opts,
+    )?;
+    Ok(())
+}
+
+/// Resolve `FileFormat::Auto` by peeking the input, and check it is one of `expected`.
+/// Bytes read ahead for a first line longer than the buffer are put back in front of `reader`
+pub fn resolve_input_format(
+    format: FileFormat,
+    reader: &mut Box<dyn BufRead + Send>,
+    expected: &[FileFormat],
+) -> Result<FileFormat, WGAError> {
+    if format != FileFormat::Auto {
+        return Ok(format);
+    }
+    buffer_first_line(reader)?;
+    detect_input_format(format, reader.as_mut(), expected)
+}
+
+// read ahead until the first non-empty line is complete in buffer, up to `DETECT_PEEK_MAX`
+fn buffer_first_line(reader: &mut Box<dyn BufRead + Send>) -> Result<(), WGAError> {
+    if has_first_line(reader.fill_buf()?) {
+        return Ok(());
+    }
+    let mut inner = std::mem::replace(reader, Box::new(std::io::empty()));
+    let mut ahead = Vec::new();
+    while !has_first_line(&ahead) && ahead.len() < DETECT_PEEK_MAX {
+        let buf = inner.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        let len = buf.len();
+        ahead.extend_from_slice(buf);
+        inner.consume(len);
+    }
+    *reader = Box::new(std::io::Cursor::new(ahead).chain(inner));
+    Ok(())
+}
+
+// `buf` has a non-whitespace byte followed by a newline
+fn has_first_line(buf: &[u8]) -> bool {
+    buf.iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .is_some_and(|start| buf[start..].contains(&b'\n'))
+}
+
+// peek the input only within its current buffer, e.g. STDIN which is read again later
+fn detect_input_format<R: BufRead + ?Sized>(
+    format: FileFormat,
+    reader: &mut R,
+    expected: &[FileFormat],
+) -> Result<FileFormat, WGAError> {
+    if format != FileFormat::Auto {
+        return Ok(format);
+    }
+    let expected_names = expected
+        .iter()
+        .map(|f| format!("`{}`", f))
+        .collect::<Vec<_>>()
+        .join(" or ");
+    // empty input is read as the first format, and reported after running
+    if reader.fill_buf()?.iter().all(u8::is_ascii_whitespace) {
+        info!("input is empty, read as `{}`", expected[0]);
+        return Ok(expected[0]);
+    }
+    match FileFormat::detect(reader)? {
+        FileFormat::Unknown => Err(WGAError::FormatDetect(expected_names)),
+        detected if expected.contains(&detected) => {
+            info!("detected input format: `{}`", detected);
+            Ok(detected)
+        }
+        detected => Err(WGAError::FormatNotExpected(
+            format!("`{}`", detected),
+            expected_names,
+        )),
+    }
+}
+
+/// Resolve `FileFormat::Auto` of call by peeking its first input, STDIN is peeked without consuming
+pub fn resolve_call_format(format: FileFormat, inputs: &[String]) -> Result<FileFormat, WGAError> {
+    if format != FileFormat::Auto {
+        return Ok(format);
+    }
+    let expected = [FileFormat::Maf, FileFormat::Paf];
+    match inputs.first().filter(|path| *path != "-") {
+        Some(path) => {
+            let mut reader = get_input_reader(&Some(path.clone()))?;
+            resolve_input_format(format, &mut reader, &expected)
+        }
+        None => detect_input_format(format, &mut stdin_reader()?.lock(), &expected),
+    }
+}
+
+/// A wrapper for report sub-cmd, stat, coverage and dotplot of MAF/PAF in one HTML
+pub fn wrap_report(
+    input: &Option<String>,
+    format: FileFormat,
+    output: &str,
+    query_name: Option<&str>,
+    bins: usize,
+    rewrite: bool,
+) -> Result<(), WGAError> {
+    if bins == 0 {
+        return Err(WGAError::InvalidArgs(
+            "`bins` should be positive".to_string(),
+        ));
+    }
+    let (mut reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let format = resolve_input_format(format, &mut reader, &[FileFormat::Maf, FileFormat::Paf])?;
+    let title = match input.as_deref() {
+        Some(path) if path != "-" => path,
+        _ => "STDIN",
+    };
+    match format {
+        FileFormat::Maf => {
+            let mut mafrdr = MAFReader::new(reader)?;
+            let records = mafrdr.records().map(|rec| {
+                let mut rec = rec?;
+                if let Some(qname) = query_name {
+                    rec.set_query_idx_byname(qname)?;
+                }
+                Ok(rec)
+            });
+            let format = format.to_string();
+            report(
+                records,
+                &mut writer,
+                title,
+                &format,
+                bins,
+                parse_maf_seq_to_blocks,
+            )
+        }
+        FileFormat::Paf => {
+            if query_name.is_some() {
+                warn!(target: WarnCategory::Args.target(), "`query_name` is only for MAF, will be ignored");
+            }
+            let mut pafrdr = PAFReader::new(reader);
+            let format = format.to_string();
+            report(
+                pafrdr.records(),
+                &mut writer,
+                title,
+                &format,
+                bins,
+                parse_cigar_to_blocks,
+            )
+        }
+        _ => Err(WGAError::NotImplemented),
+    }
+}
+
+/// A wrapper for stat sub-cmd, match format and call `stat_{maf,paf}`
+#[allow(clippy::too_many_arguments)]
+pub fn wrap_stat(
+    format: FileFormat,
+    input: &Option<String>,
+    output: &str,
+    query_name: Option<String>,
+    rewrite: bool,
+    each: bool,
+    json_detailed: bool,
+    gap_bins: Option<&[u64]>,
+    odd_char: OddChar,
+    per_sline: bool,
+    qc: bool,
+    strata: Option<&str>,
+    group_by: GroupBy,
+    sample: Option<StatSample>,
+) -> Result<(), WGAError> {
+    if sample.is_some_and(|s| !(s.fraction > 0.0 && s.fraction <= 1.0)) {
+        return Err(WGAError::InvalidArgs(
+            "`--sample` should be in (0, 1]".to_string(),
+        ));
+    }
+    if let Some(gap_bins) = gap_bins {
+        if gap_bins.is_empty() || gap_bins.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(WGAError::InvalidArgs(
+                "`gap_bins` should be ascending edges".to_string(),
+            ));
+        }
+        if each || json_detailed {
+            warn!(target: WarnCategory::Args.target(), "`gap_hist` is set, `each` and `json_detailed` will be ignored");
+        }
+    }
+    // prepare reader and writer
+    let (mut reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let format = resolve_input_format(format, &mut reader, &[FileFormat::Maf, FileFormat::Paf])?;
+    if json_detailed && each {
+        warn!(target: WarnCategory::Args.target(), "`json_detailed` is set, `each` will be ignored");
+    }
+    if group_by != GroupBy::Sequence && (each || json_detailed) && gap_bins.is_none() {
+        warn!(target: WarnCategory::Args.target(), "`each` or `json_detailed` is set, `group_by` will be ignored");
+    }
+    if per_sline {
+        if format != FileFormat::Maf {
+            return Err(WGAError::InvalidArgs(
+                "`per_sline` only supports MAF input".to_string(),
+            ));
+        }
+        if gap_bins.is_some() || query_name.is_some() {
+            warn!(target: WarnCategory::Args.target(), "`per_sline` is set, `gap_hist` and `query_name` will be ignored");
+        }
+    }
+    if qc {
+        if format != FileFormat::Maf {
+            return Err(WGAError::InvalidArgs(
+                "`qc` only supports MAF input".to_string(),
+            ));
+        }
+        if each || json_detailed || gap_bins.is_some() || per_sline {
+            warn!(
+                target: WarnCategory::Args.target(),
+                "`qc` is set, `each`, `json_detailed`, `gap_hist` and `per_sline` will be ignored"
+            );
+        }
+        let mafrdr = MAFReader::new(reader)?.with_odd_char(odd_char);
+        return stat_qc(mafrdr, &mut writer, query_name.as_deref());
+    }
+    if let Some(path) = strata {
+        if each || json_detailed || gap_bins.is_some() || per_sline {
+            warn!(
+                target: WarnCategory::Args.target(),
+                "`strata` is set, `each`, `json_detailed`, `gap_hist` and `per_sline` will be ignored"
+            );
+        }
+        info!("start read file: `{}`", path);
+        let strata = Strata::from_bed(get_input_reader(&Some(path.to_string()))?)?;
+        return match format {
+            FileFormat::Maf => {
+                let mut mafrdr = MAFReader::new(reader)?.with_odd_char(odd_char);
+                let records = mafrdr.records().map(|rec| {
+                    let mut rec = rec?;
+                    if let Some(qname) = query_name.as_deref() {
+                        rec.set_query_idx_byname(qname)?;
+                    }
+                    Ok(rec)
+                });
+                stat_strata(records, &strata, &mut writer)
+            }
+            FileFormat::Paf => stat_strata(PAFReader::new(reader).records(), &strata, &mut writer),
+            _ => Err(WGAError::NotImplemented),
+        };
+    }
+
+    // match format and call stat
+    match format {
+        FileFormat::Maf => {
+            let mafrdr = MAFReader::new(reader)?.with_odd_char(odd_char);
+            stat_maf(
+                mafrdr,
+                &mut writer,
+                each,
+                query_name.as_deref(),
+                json_detailed,
+                gap_bins,
+                per_sline,
+                group_by,
+                sample,
+            )?
+        }
+        FileFormat::Paf => {
+            let pafrdr = PAFReader::new(reader);
+            stat_paf(
+                pafrdr,
+                &mut writer,
+                each,
+                json_detailed,
+                gap_bins,
+                group_by,
+                sample,
+            )?
+        }
+        _ => {
+            return Err(WGAError::NotImplemented);
+        }
+    }
+    Ok(())
+}
+
+/// A wrapper for consensus sub-cmd, VCF needs target FASTA while MAF has target sequences
+#[allow(clippy::too_many_arguments)]
+pub fn wrap_consensus(
+    input: &Option<String>,
+    output: &str,
+    rewrite: bool,
+    target: Option<&str>,
+    maf: bool,
+    regions: &Option<Vec<String>>,
+    sample: Option<&str>,
+    haplotype: u8,
+) -> Result<(), WGAError> {
+    // prepare reader and writer
+    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    if maf {
+        if target.is_some() || regions.is_some() || sample.is_some() || haplotype != 1 {
+            warn!(target: WarnCategory::Args.target(), "`target`, `regions`, `sample` and `haplotype` only work for VCF, will be ignored");
+        }
+        let mut mafrdr = MAFReader::new(reader)?;
+        return consensus_maf(&mut mafrdr, &mut writer);
+    }
+    let target = target.ok_or(WGAError::InvalidArgs(
+        "target FASTA is necessary for VCF".to_string(),
+    ))?;
+    let regions = parse_region_specs(regions.as_deref().unwrap_or_default())?;
+    consensus_vcf(
+        reader,
+        target,
+        regions,
+        sample,
+        haplotype as usize,
+        &mut writer,
+    )
+}
+
+/// A wrapper for sample sub-cmd
+#[allow(clippy::too_many_arguments)]
+pub fn wrap_sample(
+    input: &Option<String>,
+    format: FileFormat,
+    output: &str,
+    rewrite: bool,
+    fraction: Option<f64>,
+    seed: u64,
+    head: Option<usize>,
+    regions: &Option<Vec<String>>,
+) -> Result<(), WGAError> {
+    if fraction.is_some_and(|f| !(f > 0.0 && f <= 1.0)) {
+        return Err(WGAError::InvalidArgs(
+            "`--fraction` should be in (0, 1]".to_string(),
+        ));
+    }
+    if fraction.is_none() && head.is_none() && regions.is_none() {
+        warn!(target: WarnCategory::Args.target(), "none of `--fraction`, `--head` and `--target-region` is set, all records are kept");
+    }
+    let opts = SampleOpts {
+        regions: parse_region_specs(regions.as_deref().unwrap_or_default())?,
+        fraction,
+        seed,
+        head,
+    };
+    // prepare reader and writer
+    let (mut reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let format = resolve_input_format(
+        format,
+        &mut reader,
+        &[FileFormat::Maf, FileFormat::Paf, FileFormat::Chain],
+    )?;
+    match format {
+        FileFormat::Maf => {
+            let mut mafrdr = MAFReader::new(reader)?;
+            sample_maf(&mut mafrdr, &mut writer, &opts)?;
+        }
+        FileFormat::Paf => {
+            let mut pafrdr = PAFReader::new(reader);
+            sample_paf(&mut pafrdr, &mut writer, &opts)?;
+        }
+        FileFormat::Chain => {
+            let mut chainrdr = ChainReader::new(reader);
+            sample_chain(&mut chainrdr, &mut writer, &opts)?;
+        }
+        _ => {
+            return Err(WGAError::NotImplemented);
+        }
+    }
+    Ok(())
+}
+
+/// A wrapper for annotate sub-cmd
+pub fn wrap_annotate(
+    input: &Option<String>,
+    maf: &str,
+    output: &str,
+    rewrite: bool,
+    query_name: Option<&str>,
+    window: u64,
+) -> Result<(), WGAError> {
+    // prepare reader and writer
+    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    info!("start read file: `{}`", maf);
+    let mut mafrdr = MAFReader::new(get_input_reader(&Some(maf.to_string()))?)?.with_source(maf);
+    annotate_vcf(reader, &mut mafrdr, &mut writer, query_name, window)
 }
 
-/// Command: maf extract
-pub fn wrap_maf_extract(
+/// A wrapper for vcf-lift sub-cmd, build segments of alignment and call `lift_vcf`
+#[allow(clippy::too_many_arguments)]
+pub fn wrap_vcf_lift(
     input: &Option<String>,
-    regions: &Option<Vec<String>>,
-    region_file: &Option<String>,
+    align: &str,
+    format: FileFormat,
+    fasta: &str,
+    from: LiftFrom,
+    query_name: Option<&str>,
+    reject: Option<&str>,
     output: &str,
     rewrite: bool,
 ) -> Result<(), WGAError> {
-    // judge regions and region_file
-    if regions.is_none() && region_file.is_none() {
-        return Err(WGAError::EmptyRegion);
-    }
-
-    // init writer and check if output file exists
-    let output_name = match output {
-        "-" => "stdout",
-        path => path,
+    // prepare reader and writer
+    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let mut reject_writer = reject
+        .map(|path| get_output_writer(path, rewrite))
+        .transpose()?;
+    info!("start read file: `{}`", align);
+    let mut align_reader = get_input_reader(&Some(align.to_string()))?;
+    let format = resolve_input_format(
+        format,
+        &mut align_reader,
+        &[FileFormat::Maf, FileFormat::Paf, FileFormat::Chain],
+    )?;
+    let liftmap = match format {
+        FileFormat::Maf => {
+            let mut mafrdr = MAFReader::new(align_reader)?.with_source(align);
+            LiftMap::new(maf_segments(&mut mafrdr, query_name), from)?
+        }
+        FileFormat::Paf => {
+            let mut pafrdr = PAFReader::new(align_reader).with_source(align);
+            LiftMap::new(paf_segments(&mut pafrdr), from)?
+        }
+        FileFormat::Chain => {
+            let mut chainrdr = ChainReader::new(align_reader).with_source(align);
+            LiftMap::new(chain_segments(&mut chainrdr)?, from)?
+        }
+        _ => return Err(WGAError::NotImplemented),
     };
-    info!("start write file: `{}`", output_name);
-    let mut writer = get_output_writer(output, rewrite)?;
+    lift_vcf(
+        reader,
+        &liftmap,
+        fasta,
+        &mut writer,
+        reject_writer
+            .as_mut()
+            .map(|wtr| wtr.as_mut() as &mut dyn Write),
+    )
+}
 
-    match input {
-        // if input if from file, use index
-        Some(path) => {
-            if path == "-" {
-                return Err(WGAError::StdinNotAllowed);
-            }
-            let mut mafreader = MAFReader::from_path(path)?;
-            let index_path = format!("{}.index", path);
-            let index_rdr = BufReader::new(File::open(index_path)?);
-            let mafindex: MafIndex = serde_json::from_reader(index_rdr)?;
-            let failed_regions =
-                maf_extract_idx(regions, region_file, &mut mafreader, mafindex, &mut writer)?;
-            for region in failed_regions {
-                let err = WGAError::FailedRegion(region);
-                warn!("{}", err);
-            }
-            Ok(())
+// load MAF index to seek blocks, None with a warning if it is missing or stale
+fn load_index_or_scan(path: &str) -> Result<Option<MafIndex>, WGAError> {
+    match load_index(path, false) {
+        Ok(Some(mafindex)) => Ok(Some(mafindex)),
+        Ok(None) => {
+            warn!(target: WarnCategory::Index.target(), "maf index not found, will scan the whole file");
+            Ok(None)
         }
-        // if input is from stdin, raise error
-        None => Err(WGAError::StdinNotAllowed),
+        Err(err @ WGAError::StaleIndex(..)) => {
+            warn!(target: WarnCategory::Index.target(), "{}, will scan the whole file", err);
+            Ok(None)
+        }
+        Err(err) => Err(err),
     }
 }
 
-/// Command: maf call
-#[allow(clippy::too_many_arguments)]
-pub fn wrap_maf_call(
+/// Command: pos-map
+pub fn wrap_pos_map(
     input: &Option<String>,
+    format: FileFormat,
+    at: &[String],
+    query_name: Option<&str>,
     output: &str,
     rewrite: bool,
-    snp: bool,
-    svlen: u64,
-    between: bool,
-    sample: Option<&str>,
-    query_name: Option<&str>,
 ) -> Result<(), WGAError> {
-    // prepare reader and writer
-    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    // parse positions before reading
+    let positions = at
+        .iter()
+        .map(|at| MapPos::try_from(at.as_str()))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    // get mafindex if input is not stdin
-    let mafindex = match input {
-        Some(path) => {
-            if path == "-" {
-                None
-            } else {
-                let index_path = format!("{}.index", path);
-                match File::open(index_path) {
-                    Err(_) => None,
-                    Ok(index_file) => {
-                        let index_rdr = BufReader::new(index_file);
-                        let mafindex: MafIndex = serde_json::from_reader(index_rdr)?;
-                        Some(mafindex)
-                    }
+    // prepare reader and writer
+    let (mut reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let format = resolve_input_format(
+        format,
+        &mut reader,
+        &[FileFormat::Maf, FileFormat::Paf, FileFormat::Chain],
+    )?;
+    match format {
+        FileFormat::Maf => {
+            // MAF index seeks blocks containing positions instead of scanning
+            let mafindex = match input {
+                Some(path) if path != "-" => load_index_or_scan(path)?,
+                _ => None,
+            };
+            match (input, mafindex) {
+                (Some(path), Some(mafindex)) => {
+                    pos_map_maf_idx(path, &mafindex, &positions, query_name, &mut writer)?
+                }
+                _ => {
+                    let mut mafrdr = MAFReader::new(reader)?;
+                    pos_map_maf(&mut mafrdr, &positions, query_name, &mut writer)?
                 }
             }
         }
-        None => None,
-    };
-    if mafindex.is_none() {
-        warn!("maf index not found, will not generate contig info");
+        FileFormat::Paf => {
+            let mut pafrdr = PAFReader::new(reader);
+            pos_map_paf(&mut pafrdr, &positions, &mut writer)?;
+        }
+        FileFormat::Chain => {
+            let mut chainrdr = ChainReader::new(reader);
+            pos_map_chain(&mut chainrdr, &positions, &mut writer)?;
+        }
+        _ => return Err(WGAError::NotImplemented),
     }
-
-    // get mafreader
-    let mut mafreader = MAFReader::new(reader)?;
-
-    call_var_maf(
-        &mut mafreader,
-        mafindex,
-        &mut writer,
-        snp,
-        svlen,
-        between,
-        sample,
-        query_name,
-    )?;
     Ok(())
 }
 
-#[allow(clippy::too_many_arguments)]
-/// Command: paf call
-pub fn wrap_paf_call(
+/// A wrapper for polish sub-cmd
+#[cfg(feature = "polish")]
+pub fn wrap_polish(
     input: &Option<String>,
-    t_fa_path: &str,
-    q_fa_path: &str,
     output: &str,
     rewrite: bool,
-    snp: bool,
-    svlen: u64,
-    between: bool,
-    sample: Option<&str>,
+    max_gap: usize,
+    target: Option<&str>,
+    query: Option<&str>,
 ) -> Result<(), WGAError> {
     // prepare reader and writer
     let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
-
-    // check if fasta files exist
-    if !Path::new(t_fa_path).exists() {
-        return Err(WGAError::FileNotExist(PathBuf::from(t_fa_path)));
-    }
-    if !Path::new(q_fa_path).exists() {
-        return Err(WGAError::FileNotExist(PathBuf::from(q_fa_path)));
-    }
-
-    // check if fasta index files exist, if not create them
-    if !Path::new(&format!("{}.fai", t_fa_path)).exists() {
-        return Err(WGAError::FileNotExist(PathBuf::from(format!(
-            "{}.fai",
-            t_fa_path
-        ))));
-    }
-    if !Path::new(&format!("{}.fai", q_fa_path)).exists() {
-        return Err(WGAError::FileNotExist(PathBuf::from(format!(
-            "{}.fai",
-            q_fa_path
-        ))));
-    }
-
-    // initialize PAF reader
-    let mut pafreader = PAFReader::new(reader);
-
-    call_var_paf(
-        &mut pafreader,
-        t_fa_path,
-        q_fa_path,
-        &mut writer,
-        snp,
-        svlen,
-        between,
-        sample,
-    )?;
-    Ok(())
+    let mafrdr = MAFReader::new(reader)?;
+    crate::tools::polish::polish_maf(mafrdr, &mut writer, max_gap, target.zip(query))
 }
 
-/// A wrapper for stat sub-cmd, match format and call `stat_{maf,paf}`
-pub fn wrap_stat(
+/// A wrapper for diff sub-cmd, read blocks of two alignments and call `diff_alignments`
+pub fn wrap_diff(
+    input_a: &str,
+    input_b: &str,
     format: FileFormat,
-    input: &Option<String>,
     output: &str,
-    query_name: Option<String>,
+    query_name: Option<&str>,
     rewrite: bool,
-    each: bool,
 ) -> Result<(), WGAError> {
     // prepare reader and writer
-    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let (reader_a, mut writer) = prepare_rdr_wtr(&Some(input_a.to_string()), output, rewrite)?;
+    let blocks_a = read_diff_blocks(reader_a, None, format, query_name)?;
+    info!("start read file: `{}`", input_b);
+    let reader_b = get_input_reader(&Some(input_b.to_string()))?;
+    let blocks_b = read_diff_blocks(reader_b, Some(input_b), format, query_name)?;
+    diff_alignments(blocks_a, blocks_b, &mut writer)?;
+    Ok(())
+}
 
-    // match format and call stat
+fn read_diff_blocks(
+    mut reader: Box<dyn BufRead + Send>,
+    source: Option<&str>,
+    format: FileFormat,
+    query_name: Option<&str>,
+) -> Result<Vec<DiffBlock>, WGAError> {
+    let format = resolve_input_format(format, &mut reader, &[FileFormat::Maf, FileFormat::Paf])?;
     match format {
         FileFormat::Maf => {
-            let mafrdr = MAFReader::new(reader)?;
-            stat_maf(mafrdr, &mut writer, each, query_name.as_deref())?
+            let mut mafrdr = MAFReader::new(reader)?;
+            if let Some(source) = source {
+                mafrdr = mafrdr.with_source(source);
+            }
+            let records = mafrdr.records().map(|result_rec| {
+                let mut rec = result_rec?;
+                if let Some(qname) = query_name {
+                    rec.set_query_idx_byname(qname)?;
+                }
+                Ok(rec)
+            });
+            diff_blocks(records)
         }
         FileFormat::Paf => {
-            let pafrdr = PAFReader::new(reader);
-            stat_paf(pafrdr, &mut writer, each)?
-        }
-        _ => {
-            return Err(WGAError::NotImplemented);
+            let mut pafrdr = PAFReader::new(reader);
+            if let Some(source) = source {
+                pafrdr = pafrdr.with_source(source);
+            }
+            diff_blocks(pafrdr.records())
         }
+        _ => Err(WGAError::NotImplemented),
     }
-    Ok(())
 }
 
 /// A wrapper for filter sub-cmd, match format and call `filter_{maf,paf}`
+#[allow(clippy::too_many_arguments)]
 pub fn wrap_filter(
     format: FileFormat,
     input: &Option<String>,
@@ -538,28 +2485,125 @@ pub fn wrap_filter(
     min_block_size: u64,
     min_query_size: u64,
     min_align_size: Option<u64>,
+    min_chain_score: f64,
+    min_target_span: u64,
+    dedup: bool,
+    pair_report: bool,
+    split_fraction: f64,
+    dup_redundancy: f64,
+    mask: Option<&str>,
+    mask_mode: MaskMode,
+    best_hit: Option<BestHit>,
 ) -> Result<(), WGAError> {
     // prepare reader and writer
-    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let (mut reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let format = resolve_input_format(
+        format,
+        &mut reader,
+        &[FileFormat::Maf, FileFormat::Paf, FileFormat::Chain],
+    )?;
+
+    if dedup && format != FileFormat::Maf {
+        return Err(WGAError::InvalidArgs(
+            "`--dedup` only supports MAF input".to_string(),
+        ));
+    }
+    if pair_report && format != FileFormat::Paf {
+        return Err(WGAError::InvalidArgs(
+            "`--pair-report` only supports PAF input".to_string(),
+        ));
+    }
+    if best_hit.is_some() {
+        if format != FileFormat::Paf {
+            return Err(WGAError::InvalidArgs(
+                "`--best-hit` only supports PAF input".to_string(),
+            ));
+        }
+        if pair_report || min_align_size.is_some() {
+            return Err(WGAError::InvalidArgs(
+                "`--best-hit` does not work with `--pair-report` or `--min-align-size`".to_string(),
+            ));
+        }
+    }
+    if format != FileFormat::Chain && (min_chain_score > 0.0 || min_target_span > 0) {
+        warn!(target: WarnCategory::Args.target(), "`min_chain_score` and `min_target_span` only work for chain, will be ignored");
+    }
+    let mask = match mask {
+        Some(path) => {
+            if pair_report || (format == FileFormat::Paf && min_align_size.is_some()) {
+                return Err(WGAError::InvalidArgs(
+                    "`--mask` does not work with `--pair-report` or `--min-align-size`".to_string(),
+                ));
+            }
+            match (mask_mode, format) {
+                (MaskMode::Trim, FileFormat::Chain) => {
+                    return Err(WGAError::InvalidArgs(
+                        "`--mask-mode trim` does not support chain input".to_string(),
+                    ))
+                }
+                (MaskMode::Softmask, FileFormat::Paf | FileFormat::Chain) => {
+                    return Err(WGAError::InvalidArgs(
+                        "`--mask-mode softmask` only supports MAF input".to_string(),
+                    ))
+                }
+                _ => {}
+            }
+            info!("start read file: `{}`", path);
+            let mask_reader = get_input_reader(&Some(path.to_string()))?;
+            Some(Mask::from_bed(mask_reader, mask_mode)?)
+        }
+        None => None,
+    };
 
     match format {
         FileFormat::Maf => {
             let mafrdr = MAFReader::new(reader)?;
-            filter_maf(mafrdr, &mut writer, min_block_size, min_query_size)?
+            filter_maf(
+                mafrdr,
+                &mut writer,
+                min_block_size,
+                min_query_size,
+                dedup,
+                mask.as_ref(),
+            )?
         }
         FileFormat::Paf => {
             let pafrdr = PAFReader::new(reader);
+            if pair_report {
+                return report_paf_align_pair(
+                    pafrdr,
+                    &mut writer,
+                    min_align_size.unwrap_or(0),
+                    split_fraction,
+                    dup_redundancy,
+                );
+            }
             match min_align_size {
                 Some(min_align_size) => {
-                    warn!("`min_align_size` is set, will not filter paf `min_block_size` and `min_query_size`");
+                    warn!(target: WarnCategory::Args.target(), "`min_align_size` is set, will not filter paf `min_block_size` and `min_query_size`");
                     filter_paf_align_pair(pafrdr, &mut writer, min_align_size)?
                 }
-                None => filter_paf(pafrdr, &mut writer, min_block_size, min_query_size)?,
+                None => filter_paf(
+                    pafrdr,
+                    &mut writer,
+                    min_block_size,
+                    min_query_size,
+                    mask.as_ref(),
+                    best_hit,
+                )?,
             }
         }
         FileFormat::Chain => {
             let chainrdr = ChainReader::new(reader);
-            filter_chain(chainrdr, &mut writer, min_block_size, min_query_size)?
+            filter_chain(
+                chainrdr,
+                &mut writer,
+                min_block_size,
+                min_query_size,
+                min_chain_score,
+                min_target_span,
+                mask.as_ref(),
+            )?
         }
         _ => {
             return Err(WGAError::NotImplemented);
@@ -568,44 +2612,80 @@ pub fn wrap_filter(
     Ok(())
 }
 
-/// A wrapper for filter sub-cmd, match format and call `filter_{maf,paf}`
-pub fn wrap_rename_maf(
+/// A wrapper for rename sub-cmd, match format and call `rename_{maf,paf}`
+#[allow(clippy::too_many_arguments)]
+pub fn wrap_rename(
     input: &Option<String>,
+    format: FileFormat,
     output: &str,
     rewrite: bool,
     prefixs: &[String],
+    genome_prefixs: &[String],
+    map_file: &Option<String>,
+    regex: &Option<String>,
+    indices: &Option<Vec<usize>>,
 ) -> Result<(), WGAError> {
+    // check rules before reading
+    let renamer = Renamer::new(prefixs, genome_prefixs, map_file, regex, indices)?;
     // prepare reader and writer
-    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
-    let mafrdr = MAFReader::new(reader)?;
-    let prefixs = prefixs.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
-    rename_maf(mafrdr, &mut writer, prefixs)?;
+    let (mut reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let format = resolve_input_format(format, &mut reader, &[FileFormat::Maf, FileFormat::Paf])?;
+    match format {
+        FileFormat::Maf => {
+            let mafrdr = MAFReader::new(reader)?;
+            rename_maf(mafrdr, &mut writer, &renamer)?;
+        }
+        FileFormat::Paf => {
+            let pafrdr = PAFReader::new(reader);
+            rename_paf(pafrdr, &mut writer, &renamer)?;
+        }
+        _ => {
+            return Err(WGAError::NotImplemented);
+        }
+    }
     Ok(())
 }
 
 /// A wrapper for PAF Converage count
-pub fn wrap_paf_cov(input: &Option<String>, output: &str, rewrite: bool) -> Result<(), WGAError> {
+pub fn wrap_paf_cov(
+    input: &Option<String>,
+    output: &str,
+    rewrite: bool,
+    group_by: GroupBy,
+) -> Result<(), WGAError> {
     let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
     let pafrdr = PAFReader::new(reader);
-    pafcov(pafrdr, &mut writer)?;
+    pafcov(pafrdr, &mut writer, group_by)?;
     Ok(())
 }
 
-/// A wrapper for PAF pesudo maf
-pub fn wrap_paf_pesudo_maf(
+/// A wrapper for maskfa sub-cmd, read coverage of alignment and mask target FASTA
+pub fn wrap_maskfa(
     input: &Option<String>,
+    format: FileFormat,
+    target: &str,
+    mode: FastaMaskMode,
+    aligned: bool,
     output: &str,
     rewrite: bool,
-    fa_path: &Option<String>,
-    target: &Option<String>,
 ) -> Result<(), WGAError> {
-    // get input name for INFO
-    let input_name = match input {
-        Some(path) => path,
-        None => "stdin",
+    // prepare reader and writer
+    let (mut reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let format = resolve_input_format(format, &mut reader, &[FileFormat::Maf, FileFormat::Paf])?;
+    let cov = match format {
+        FileFormat::Maf => TargetCoverage::from_maf(&mut MAFReader::new(reader)?)?,
+        FileFormat::Paf => TargetCoverage::from_paf(&mut PAFReader::new(reader))?,
+        _ => {
+            return Err(WGAError::NotImplemented);
+        }
     };
-    info!("start read file: `{}`", input_name);
+    info!("start read file: `{}`", target);
+    let fasta = get_input_reader(&Some(target.to_string()))?;
+    mask_fasta(fasta, &cov, mode, aligned, &mut writer)
+}
 
+/// Create output dir for one file per sequence or genome, stdout is not allowed
+fn prepare_output_dir(output: &str, rewrite: bool) -> Result<(), WGAError> {
     info!("start write file to dir: `{}`", output);
     if output == "-" {
         return Err(WGAError::StdoutNotAllowed);
@@ -621,11 +2701,30 @@ pub fn wrap_paf_pesudo_maf(
         }
         // if rewrite
         if rewrite {
-            warn!("output dir `{}` exists, will rewrite it", output);
+            warn!(target: WarnCategory::Overwrite.target(), "output dir `{}` exists, will rewrite it", output);
         } else {
             return Err(WGAError::FileReWrite(output.to_string()));
         }
     }
+    Ok(())
+}
+
+/// A wrapper for PAF pesudo maf
+pub fn wrap_paf_pesudo_maf(
+    input: &Option<String>,
+    output: &str,
+    rewrite: bool,
+    fa_path: &Option<String>,
+    target: &Option<String>,
+) -> Result<(), WGAError> {
+    // get input name for INFO
+    let input_name = match input {
+        Some(path) => path,
+        None => "stdin",
+    };
+    info!("start read file: `{}`", input_name);
+
+    prepare_output_dir(output, rewrite)?;
     // get a reader
     let reader = get_input_reader(input)?;
     let pafrdr = PAFReader::new(reader);
@@ -633,6 +2732,30 @@ pub fn wrap_paf_pesudo_maf(
     Ok(())
 }
 
+/// A wrapper for pmaf-stat sub-cmd
+pub fn wrap_pmaf_stat(
+    input_dir: &str,
+    output: &str,
+    rewrite: bool,
+    window: u64,
+    min_cov: f64,
+) -> Result<(), WGAError> {
+    info!("start read pseudo MAFs in dir: `{}`", input_dir);
+    let input_dir = Path::new(input_dir);
+    if !input_dir.exists() {
+        return Err(WGAError::FileNotExist(input_dir.to_path_buf()));
+    }
+    if !input_dir.is_dir() {
+        return Err(WGAError::NotDir(input_dir.to_path_buf()));
+    }
+    if window == 0 {
+        return Err(WGAError::InvalidArgs("window size must be > 0".to_string()));
+    }
+    let mut writer = get_output_writer(output, rewrite)?;
+    pmaf_stat(input_dir, &mut writer, window, min_cov)?;
+    Ok(())
+}
+
 // /// A wrapper for PAF trim overlap
 // pub fn wrap_paf_trim_overlap(
 //     input: &Option<String>,
@@ -654,9 +2777,9 @@ pub fn wrap_chunk(
 ) -> Result<(), WGAError> {
     // check length > 0
     if length == 0 {
-        return Err(WGAError::Other(anyhow::anyhow!(
-            "`length` should be greater than 0"
-        )));
+        return Err(WGAError::InvalidArgs(
+            "`length` should be greater than 0".to_string(),
+        ));
     }
 
     // prepare reader and writer
@@ -680,20 +2803,43 @@ pub fn wrap_dotplot(
     cutoff: Option<usize>,
     output: &str,
     query_name: Option<String>,
+    target_region: &Option<String>,
+    query_region: &Option<String>,
     rewrite: bool,
 ) -> Result<(), WGAError> {
+    // parse regions before reading
+    let window = PlotWindow {
+        target: target_region
+            .as_deref()
+            .map(parse_single_region)
+            .transpose()?,
+        query: query_region
+            .as_deref()
+            .map(parse_single_region)
+            .transpose()?,
+    };
+
     // prepare reader and writer
-    let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let (mut reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
+    let format = resolve_input_format(format, &mut reader, &[FileFormat::Maf, FileFormat::Paf])?;
+
+    // MAF index is only used to seek blocks in target region
+    let mafindex = match (format, input, &window.target) {
+        (FileFormat::Maf, Some(path), Some(_)) if path != "-" => {
+            load_index_or_scan(path)?.map(|mafindex| (path.as_str(), mafindex))
+        }
+        _ => None,
+    };
     // let mafrdr = MAFReader::new(reader)?;
     match mode {
         DotplotMode::BaseLevel => {
             if no_identity {
-                warn!("`no_identity` is set, but it's not supported in `BaseLevel` mode");
+                warn!(target: WarnCategory::Args.target(), "`no_identity` is set, but it's not supported in `BaseLevel` mode");
             }
         }
         DotplotMode::Overview => {
             if cutoff.is_some() {
-                warn!("`cutoff` is set, but it's not supported in `Overview` mode");
+                warn!(target: WarnCategory::Args.target(), "`cutoff` is set, but it's not supported in `Overview` mode");
             }
         }
     }
@@ -710,6 +2856,9 @@ pub fn wrap_dotplot(
         no_identity,
         cutoff,
         query_name.as_deref(),
+        &window,
+        mafindex.as_ref().map(|(path, mafindex)| (*path, mafindex)),
+        input.as_deref().filter(|path| *path != "-"),
     )?;
     Ok(())
 }
@@ -727,6 +2876,8 @@ pub fn wrap_validate(
     fix: &Option<String>,
     output: &str,
     rewrite: bool,
+    out_format: ValidateOutFormat,
+    strict: bool,
 ) -> Result<(), WGAError> {
     // prepare reader and writer
     let (reader, mut writer) = prepare_rdr_wtr(input, output, rewrite)?;
@@ -734,18 +2885,18 @@ pub fn wrap_validate(
 
     let fix_writer = match fix {
         Some(path) => {
-            warn!("`fix` is set, will try to fix the query|target postion of paf file.It does NOT represent the alignment behavior.");
+            warn!(target: WarnCategory::Args.target(), "`fix` is set, will try to fix the query|target postion of paf file.It does NOT represent the alignment behavior.");
             if path == "-" {
-                warn!("STDOUT mixed the validation information and new fixed paf records");
+                warn!(target: WarnCategory::Args.target(), "STDOUT mixed the validation information and new fixed paf records");
             }
             let input_path = match input {
                 Some(path) => path,
                 None => "stdin",
             };
             if path == input_path {
-                return Err(WGAError::Other(anyhow::anyhow!(
-                    "fixed file should not be the same as output file"
-                )));
+                return Err(WGAError::InvalidArgs(
+                    "fixed file should not be the same as output file".to_string(),
+                ));
             }
             let fix_writer = get_output_writer(path, true)?;
             Some(fix_writer)
@@ -754,7 +2905,157 @@ pub fn wrap_validate(
     };
 
     let fix_flag = fix.is_some();
-    parallel_validatepaf(pafrdr, &mut writer, fix_writer, fix_flag)?;
+    parallel_validatepaf(
+        pafrdr,
+        &mut writer,
+        fix_writer,
+        fix_flag,
+        out_format,
+        strict,
+    )?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_call_format_of_first_input() {
+        let fixture = |name: &str| format!("{}/test/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let maf = vec![fixture("test.maf"), fixture("testdotplot.paf")];
+        let paf = vec![fixture("testdotplot.paf")];
+        assert_eq!(
+            resolve_call_format(FileFormat::Auto, &maf).unwrap(),
+            FileFormat::Maf
+        );
+        assert_eq!(
+            resolve_call_format(FileFormat::Auto, &paf).unwrap(),
+            FileFormat::Paf
+        );
+        // an explicit format is kept
+        assert_eq!(
+            resolve_call_format(FileFormat::Paf, &maf).unwrap(),
+            FileFormat::Paf
+        );
+    }
+
+    #[test]
+    fn resolve_format_of_long_first_line() {
+        let paf = format!(
+            "\n\nq\t100\t0\t10\t+\tt\t100\t0\t10\t10\t10\t60\tcg:Z:10M\tzz:Z:{}\nrest\n",
+            "A".repeat(200)
+        );
+        // the buffer ends in the mandatory columns of the first line
+        let mut reader: Box<dyn BufRead + Send> = Box::new(BufReader::with_capacity(
+            16,
+            std::io::Cursor::new(paf.clone()),
+        ));
+        let expected = [FileFormat::Maf, FileFormat::Paf];
+        assert_eq!(
+            resolve_input_format(FileFormat::Auto, &mut reader, &expected).unwrap(),
+            FileFormat::Paf
+        );
+        // bytes read ahead are not consumed
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!(text, paf);
+    }
+
+    #[test]
+    fn union_length_of_overlapping_intervals() {
+        assert_eq!(union_length(vec![(10, 20), (0, 5), (15, 30), (5, 8)]), 28);
+        assert_eq!(union_length(Vec::new()), 0);
+    }
+
+    #[test]
+    fn par_bgzf_at_any_level() {
+        let data = (0..300_000u32)
+            .flat_map(|i| (i % 251).to_le_bytes())
+            .collect::<Vec<_>>();
+        for level in [1, 9] {
+            let path = std::env::temp_dir().join(format!(
+                "wgatools-bgzf-{}-{}.gz",
+                std::process::id(),
+                level
+            ));
+            let file = File::create(&path).unwrap();
+            let workers = NonZeroUsize::new(2).unwrap();
+            let mut writer = ParBgzfWriter::new(file, workers, level).unwrap();
+            // writes of any size, flushed in the middle of a block
+            for chunk in data.chunks(70_000) {
+                writer.write_all(chunk).unwrap();
+                writer.flush().unwrap();
+            }
+            Box::new(writer).finish().unwrap();
+            let path_str = path.to_string_lossy();
+            assert!(is_bgzf(&path_str).unwrap());
+            let mut read = Vec::new();
+            bgzf::Reader::new(File::open(&path).unwrap())
+                .read_to_end(&mut read)
+                .unwrap();
+            assert_eq!(read, data);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    // outputs of the test are in its own registry, not committed or discarded by other tests
+    #[test]
+    fn commit_outputs_after_finish() {
+        static REGISTRY: OutputRegistry = OutputRegistry::new();
+        let dir = std::env::temp_dir().join(format!("wgatools-output-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.txt");
+        let mut writer = create_output_writer_in(&path, &REGISTRY).unwrap();
+        writer.write_all(b"record\n").unwrap();
+        drop(writer);
+        // only the temporary file is there before commit
+        assert!(!path.exists());
+        REGISTRY.commit().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "record\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // the final flush fails on a full device, which is reported rather than committed
+        if Path::new("/dev/full").exists() {
+            let mut writer = create_output_writer_in(Path::new("/dev/full"), &REGISTRY).unwrap();
+            writer.write_all(b"record\n").unwrap();
+            assert!(writer.finish().is_err());
+            let mut writer = create_output_writer_in(&dir.join("other.txt"), &REGISTRY).unwrap();
+            writer.write_all(b"record\n").unwrap();
+            drop(writer);
+            assert!(REGISTRY.commit().is_err());
+            // other outputs are discarded too
+            assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        }
+
+        // outputs not committed are removed
+        let mut writer = open_output_writer(
+            &dir.join("out.gz").to_string_lossy(),
+            false,
+            true,
+            &REGISTRY,
+        )
+        .unwrap();
+        writer.write_all(b"record\n").unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        REGISTRY.discard();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // `.zst` output is compressed and read back
+        let zst = dir.join("out.zst").to_string_lossy().into_owned();
+        let mut writer = open_output_writer(&zst, false, true, &REGISTRY).unwrap();
+        writer.write_all(b"record\n").unwrap();
+        drop(writer);
+        REGISTRY.commit().unwrap();
+        assert_eq!(get_magic_num(&zst).unwrap()[..4], ZSTD_MAGIC);
+        let mut text = String::new();
+        get_input_reader(&Some(zst))
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "record\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
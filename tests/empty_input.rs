@@ -0,0 +1,41 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const EMPTY_MAF: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test/maf/empty.maf");
+
+// output path unique to each test, removed before running
+fn out_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("wgatools_{}_{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn filter_empty_maf(out: &Path, strict: bool) -> std::process::Output {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_wgatools"));
+    cmd.args(["filter", "-f", "maf", EMPTY_MAF, "-o"]).arg(out);
+    if strict {
+        cmd.arg("--strict");
+    }
+    cmd.output().unwrap()
+}
+
+#[test]
+fn empty_maf_writes_header_only() {
+    let out = out_path("empty_header_only.maf");
+    let output = filter_empty_maf(&out, false);
+    assert!(output.status.success());
+    let content = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_file(&out).unwrap();
+    assert!(content
+        .lines()
+        .all(|line| line.is_empty() || line.starts_with('#')));
+    assert!(content.starts_with("#maf"));
+}
+
+#[test]
+fn empty_maf_fails_with_strict() {
+    let out = out_path("empty_strict.maf");
+    let output = filter_empty_maf(&out, true);
+    assert!(!output.status.success());
+    assert!(!out.exists());
+}